@@ -0,0 +1,305 @@
+use fx::{
+    bypass::BypassCrossfade,
+    lfo::{Lfo, LfoWaveform},
+    stereo::equal_power_pan,
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::f32::consts::SQRT_2;
+use std::sync::Arc;
+
+/// The tempo used for syncing when the host doesn't report one, in beats per minute
+const DEFAULT_TEMPO: f64 = 120.0;
+
+/// All possible LFO waveforms for this auto-panner.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LfoWaveformParam {
+    Sine,
+    Triangle,
+    Square,
+    #[name = "Saw up"]
+    SawUp,
+    #[name = "Saw down"]
+    SawDown,
+}
+
+/// A matching from the waveform parameter to the implementation's waveform.
+fn waveform_param_to_lfo(waveform: LfoWaveformParam) -> LfoWaveform {
+    match waveform {
+        LfoWaveformParam::Sine => LfoWaveform::Sine,
+        LfoWaveformParam::Triangle => LfoWaveform::Triangle,
+        LfoWaveformParam::Square => LfoWaveform::Square,
+        LfoWaveformParam::SawUp => LfoWaveform::SawUp,
+        LfoWaveformParam::SawDown => LfoWaveform::SawDown,
+    }
+}
+
+/// Note lengths a sweep can be synced to, or free running at the rate in Hz.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyncParam {
+    Off,
+    #[name = "2 bars"]
+    TwoBars,
+    #[name = "1 bar"]
+    OneBar,
+    #[name = "1/2"]
+    Half,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/4 triplet"]
+    QuarterTriplet,
+    #[name = "1/8 triplet"]
+    EighthTriplet,
+}
+
+/// A matching from the sync parameter to the length of one sweep in quarter note beats, assuming
+/// 4/4 time, or `None` when free running.
+fn sync_param_to_beats(sync: SyncParam) -> Option<f64> {
+    match sync {
+        SyncParam::Off => None,
+        SyncParam::TwoBars => Some(8.0),
+        SyncParam::OneBar => Some(4.0),
+        SyncParam::Half => Some(2.0),
+        SyncParam::Quarter => Some(1.0),
+        SyncParam::Eighth => Some(0.5),
+        SyncParam::Sixteenth => Some(0.25),
+        SyncParam::QuarterTriplet => Some(2.0 / 3.0),
+        SyncParam::EighthTriplet => Some(1.0 / 3.0),
+    }
+}
+
+pub struct AutoPan {
+    params: Arc<AutoPanParams>,
+    lfo: Lfo,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct AutoPanParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "rate"]
+    pub rate: FloatParam,
+
+    #[id = "depth"]
+    pub depth: FloatParam,
+
+    #[id = "phase"]
+    pub phase: FloatParam,
+
+    #[id = "waveform"]
+    pub waveform: EnumParam<LfoWaveformParam>,
+
+    #[id = "sync"]
+    pub sync: EnumParam<SyncParam>,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for AutoPan {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(AutoPanParams::default()),
+            lfo: Lfo::new(DEFAULT_SAMPLE_RATE as f32),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for AutoPanParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // Only used when sync is off
+            rate: FloatParam::new(
+                "Rate",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // How far the sweep reaches, from 0 (centered) to 1 (hard left to hard right)
+            depth: FloatParam::new("Depth", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Shifts the sweep within its cycle, e.g. to start synced sweeps hard left on the beat
+            phase: FloatParam::new(
+                "Phase",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 360.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            waveform: EnumParam::new("Waveform", LfoWaveformParam::Sine),
+
+            sync: EnumParam::new("Sync", SyncParam::Off),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for AutoPan {
+    const NAME: &'static str = "Autopan v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.lfo.set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.lfo.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        // While the host plays, lock synced sweeps to its beat position so they stay on the grid
+        let transport = context.transport();
+        let tempo = transport.tempo.unwrap_or(DEFAULT_TEMPO);
+        let sync_beats = sync_param_to_beats(self.params.sync.value());
+        if let (Some(beats), true, Some(position)) =
+            (sync_beats, transport.playing, transport.pos_beats())
+        {
+            self.lfo.set_phase((position / beats).fract() as f32);
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let rate = self.params.rate.smoothed.next();
+            let depth = self.params.depth.smoothed.next();
+            let phase_offset = self.params.phase.smoothed.next() / 360.0;
+            let waveform = self.params.waveform.value();
+
+            let frequency = match sync_beats {
+                Some(beats) => (tempo / 60.0 / beats) as f32,
+                None => rate,
+            };
+            self.lfo.set_frequency(frequency);
+            self.lfo.set_waveform(waveform_param_to_lfo(waveform));
+
+            // Equal-power gains keep the loudness steady across the sweep, scaled so the center
+            // passes the input unchanged
+            let pan = depth * self.lfo.value_at(phase_offset);
+            self.lfo.advance();
+            let (gain_l, gain_r) = equal_power_pan(pan);
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+
+            let out_l = in_l * gain_l * SQRT_2;
+            let out_r = in_r * gain_r * SQRT_2;
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for AutoPan {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> =
+        Some("An equal-power auto-panner with tempo sync");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Tremolo,
+    ];
+}
+
+impl Vst3Plugin for AutoPan {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2__autopan";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] = &[
+        Vst3SubCategory::Fx,
+        Vst3SubCategory::Modulation,
+        Vst3SubCategory::Spatial,
+    ];
+}
+
+nih_export_vst3!(AutoPan);
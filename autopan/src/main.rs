@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use autopan::AutoPan;
+
+fn main() {
+    nih_export_standalone::<AutoPan>();
+}
\ No newline at end of file
@@ -0,0 +1,290 @@
+use fx::{
+    biquad::{BiquadFilterType, StereoBiquadFilter},
+    bypass::BypassCrossfade,
+    dynamics::EnvelopeFollower,
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+pub struct Autowah {
+    params: Arc<AutowahParams>,
+    envelope_follower: EnvelopeFollower,
+    biquad: StereoBiquadFilter,
+    sample_rate: f32,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct AutowahParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "dry-wet"]
+    pub dry_wet: FloatParam,
+
+    #[id = "sensitivity"]
+    pub sensitivity: FloatParam,
+
+    #[id = "min-frequency"]
+    pub min_frequency: FloatParam,
+
+    #[id = "max-frequency"]
+    pub max_frequency: FloatParam,
+
+    #[id = "resonance"]
+    pub resonance: FloatParam,
+
+    #[id = "attack"]
+    pub attack: FloatParam,
+
+    #[id = "release"]
+    pub release: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for Autowah {
+    fn default() -> Self {
+        let mut biquad = StereoBiquadFilter::new();
+        biquad.set_filter_type(BiquadFilterType::BandPass);
+        Self {
+            params: Arc::new(AutowahParams::default()),
+            envelope_follower: EnvelopeFollower::new(DEFAULT_SAMPLE_RATE as f32),
+            biquad,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for AutowahParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            dry_wet: FloatParam::new("Dry/wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Boosts the envelope before it's mapped to the cutoff, so quieter playing still
+            // sweeps the full range
+            sensitivity: FloatParam::new(
+                "Sensitivity",
+                util::db_to_gain(12.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(0.0),
+                    max: util::db_to_gain(40.0),
+                    factor: FloatRange::gain_skew_factor(0.0, 40.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            min_frequency: FloatParam::new(
+                "Min frequency",
+                300.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 5_000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            max_frequency: FloatParam::new(
+                "Max frequency",
+                3_000.0,
+                FloatRange::Skewed {
+                    min: 200.0,
+                    max: 20_000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            resonance: FloatParam::new(
+                "Resonance",
+                4.0,
+                FloatRange::Skewed {
+                    min: 0.5,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            attack: FloatParam::new(
+                "Attack",
+                5.0,
+                FloatRange::Skewed {
+                    min: 0.5,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            release: FloatParam::new(
+                "Release",
+                100.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2_000.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for Autowah {
+    const NAME: &'static str = "Autowah v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.sample_rate = _buffer_config.sample_rate;
+        self.envelope_follower
+            .set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.envelope_follower.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        self.envelope_follower.set_times(
+            self.params.attack.value() / 1000.0,
+            self.params.release.value() / 1000.0,
+        );
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let dry_wet = self.params.dry_wet.smoothed.next();
+            let sensitivity = self.params.sensitivity.smoothed.next();
+            let min_frequency = self.params.min_frequency.smoothed.next();
+            let max_frequency = self.params.max_frequency.smoothed.next();
+            let resonance = self.params.resonance.smoothed.next();
+
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+
+            // Sweep the band pass exponentially between the range bounds with the input's level
+            let envelope = self.envelope_follower.process((in_l + in_r) * 0.5);
+            let sweep = (envelope * sensitivity).min(1.0);
+            let cutoff = min_frequency * (max_frequency / min_frequency).powf(sweep);
+            self.biquad.set_biquads(
+                BiquadFilterType::BandPass,
+                cutoff / self.sample_rate,
+                resonance,
+                0.0,
+            );
+
+            let (wet_l, wet_r) = self.biquad.process((in_l, in_r));
+            let out_l = in_l * (1.0 - dry_wet) + wet_l * dry_wet;
+            let out_r = in_r * (1.0 - dry_wet) + wet_r * dry_wet;
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for Autowah {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("An envelope-following auto-wah");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Filter,
+    ];
+}
+
+impl Vst3Plugin for Autowah {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2__autowah";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Filter];
+}
+
+nih_export_vst3!(Autowah);
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use autowah::Autowah;
+
+fn main() {
+    nih_export_standalone::<Autowah>();
+}
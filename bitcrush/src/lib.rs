@@ -1,9 +1,159 @@
-use fx::digital::*;
+use fx::{
+    biquad::{BiquadFilter, BiquadFilterType},
+    bypass::BypassCrossfade,
+    digital::{
+        bitcrush_sample, floating_point_quantize, floating_point_quantize_interpolated,
+        DecimationMode, Decimator, Dither,
+    },
+    filters::DelayLine,
+    lfo::Lfo,
+    oversampling::Oversampler,
+    DEFAULT_SAMPLE_RATE,
+};
 use nih_plug::prelude::*;
 use std::sync::Arc;
 
+const OVERSAMPLING_FACTOR: usize = 4;
+
+const DITHER_SEEDS: [u32; 2] = [0xD1_7E01, 0xD1_7E02];
+
+/// The emphasis shelf, roughly matching the 50/15 µs emphasis of early digital audio
+const EMPHASIS_FREQUENCY_HZ: f32 = 5_000.0;
+const EMPHASIS_GAIN_DB: f32 = 10.0;
+const EMPHASIS_Q: f32 = 0.707;
+
+/// Where the vintage samplers' converter filters cut off, as a fraction of their sample rates
+const CONVERTER_FILTER_CUTOFF: f32 = 0.45;
+const CONVERTER_FILTER_Q: f32 = 0.707;
+
+/// How far the LFO pulls the crush down at full depth, in bits and in octaves of sample rate
+const MAX_BITS_MODULATION: f32 = 8.0;
+const MAX_DOWNSAMPLE_MODULATION_OCTAVES: f32 = 3.0;
+/// The bit depth and downsample ranges the modulated crush is clamped to
+const MIN_BITS: f32 = 1.0;
+const MAX_BITS: f32 = 16.0;
+const MAX_DOWNSAMPLE: f32 = 50.0;
+
+/// Ways of filling in the samples between kept ones when reducing the sample rate.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecimationModeParam {
+    Digital,
+    Smooth,
+}
+
+/// A matching from the decimation mode parameter to the implementation's decimation mode.
+fn decimation_mode_param_to_mode(mode: DecimationModeParam) -> DecimationMode {
+    match mode {
+        DecimationModeParam::Digital => DecimationMode::Digital,
+        DecimationModeParam::Smooth => DecimationMode::Smooth,
+    }
+}
+
+/// What the crush LFO modulates.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ModulationTargetParam {
+    Bits,
+    #[name = "Sample rate"]
+    SampleRate,
+    Both,
+}
+
+/// Classic hardware to emulate, as a base setting for the bits, downsampling, and emphasis.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VintagePresetParam {
+    Off,
+    #[name = "E-mu SP-1200"]
+    Sp1200,
+    #[name = "E-mu SP-12"]
+    Sp12,
+    #[name = "Akai S950"]
+    S950,
+    #[name = "Jungle break (S950 at 15 kHz)"]
+    JungleBreak,
+    #[name = "Ensoniq Mirage"]
+    Mirage,
+    #[name = "Sony PCM-F1"]
+    PcmF1,
+}
+
+/// The converter of a piece of vintage digital hardware.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct VintageConverter {
+    /// The sample rate, in Hz
+    sample_rate: f32,
+    bits: f32,
+    emphasis: bool,
+    /// Whether the input is low-passed before sampling; without it, the highs alias freely
+    anti_aliasing: bool,
+}
+
+/// A matching from the vintage preset parameter to the hardware's converter, or `None` when off.
+fn vintage_preset_to_converter(preset: VintagePresetParam) -> Option<VintageConverter> {
+    let converter = |sample_rate, bits, emphasis, anti_aliasing| {
+        Some(VintageConverter {
+            sample_rate,
+            bits,
+            emphasis,
+            anti_aliasing,
+        })
+    };
+    match preset {
+        VintagePresetParam::Off => None,
+        // Famously had no input filter, so its crunch is full of aliasing
+        VintagePresetParam::Sp1200 => converter(26_040.0, 12.0, false, false),
+        VintagePresetParam::Sp12 => converter(27_500.0, 12.0, false, false),
+        VintagePresetParam::S950 => converter(22_050.0, 12.0, false, true),
+        // Breaks sampled at a low rate to save memory, then pitched up
+        VintagePresetParam::JungleBreak => converter(15_000.0, 12.0, false, true),
+        VintagePresetParam::Mirage => converter(29_400.0, 8.0, false, true),
+        // Early digital recording with 50/15 µs emphasis
+        VintagePresetParam::PcmF1 => converter(44_056.0, 14.0, true, true),
+    }
+}
+
+/// Process input sample through both quantization stages
+fn crush_sample(
+    input_sample: f32,
+    bits: f32,
+    constant: f32,
+    dither: f32,
+    interpolate_constant: bool,
+) -> f32 {
+    // Dynamic range quantization
+    let output = bitcrush_sample(input_sample + dither, bits);
+
+    // Floating point error quantization, crossfading between grids while the constant sweeps
+    if interpolate_constant {
+        floating_point_quantize_interpolated(output, constant)
+    } else {
+        floating_point_quantize(output, constant)
+    }
+}
+
 pub struct Bitcrush {
     params: Arc<BitcrushParams>,
+    oversamplers: [Oversampler<OVERSAMPLING_FACTOR>; 2],
+    dry_delays: [DelayLine; 2],
+    decimators: [Decimator; 2],
+    dithers: [Dither; 2],
+    /// High shelves boosting the highs before crushing and cutting them after, per channel
+    pre_emphasis_filters: [BiquadFilter; 2],
+    de_emphasis_filters: [BiquadFilter; 2],
+    /// The vintage converter's low-passes before and after crushing, per channel
+    anti_aliasing_filters: [BiquadFilter; 2],
+    reconstruction_filters: [BiquadFilter; 2],
+    /// The vintage converter the converter filters are tuned for
+    converter: Option<VintageConverter>,
+    sample_rate: f32,
+    /// Pulses the bit depth and sample rate reduction
+    lfo: Lfo,
+    /// The bit depth of the previous frame, for telling when bits is being automated
+    previous_bits: f32,
+    /// The floating point constant of the previous frame, for telling when it's being automated
+    previous_constant: f32,
+    /// The latency last reported to the host, in samples
+    latency: u32,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -11,17 +161,65 @@ struct BitcrushParams {
     #[id = "gain"]
     pub gain: FloatParam,
 
+    #[id = "dry-wet"]
+    pub dry_wet: FloatParam,
+
     #[id = "bits"]
     pub bits: FloatParam,
 
     #[id = "constant"]
     pub constant: FloatParam,
+
+    #[id = "downsample"]
+    pub downsample: FloatParam,
+
+    #[id = "downsample-mode"]
+    pub downsample_mode: EnumParam<DecimationModeParam>,
+
+    #[id = "oversample"]
+    pub oversample: BoolParam,
+
+    #[id = "emphasis"]
+    pub emphasis: BoolParam,
+
+    #[id = "vintage-preset"]
+    pub vintage_preset: EnumParam<VintagePresetParam>,
+
+    #[id = "dither-link"]
+    pub dither_link: BoolParam,
+
+    #[id = "lfo-rate"]
+    pub lfo_rate: FloatParam,
+
+    #[id = "lfo-depth"]
+    pub lfo_depth: FloatParam,
+
+    #[id = "lfo-target"]
+    pub lfo_target: EnumParam<ModulationTargetParam>,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Bitcrush {
     fn default() -> Self {
         Self {
             params: Arc::new(BitcrushParams::default()),
+            oversamplers: [Oversampler::new(8, true); 2],
+            dry_delays: [DelayLine::new(1), DelayLine::new(1)],
+            decimators: [Decimator::new(), Decimator::new()],
+            dithers: DITHER_SEEDS.map(Dither::new),
+            pre_emphasis_filters: [BiquadFilter::new(), BiquadFilter::new()],
+            de_emphasis_filters: [BiquadFilter::new(), BiquadFilter::new()],
+            anti_aliasing_filters: [BiquadFilter::new(), BiquadFilter::new()],
+            reconstruction_filters: [BiquadFilter::new(), BiquadFilter::new()],
+            converter: None,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            lfo: Lfo::new(DEFAULT_SAMPLE_RATE as f32),
+            previous_bits: 16.0,
+            previous_constant: 16.0,
+            latency: 0,
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -43,12 +241,16 @@ impl Default for BitcrushParams {
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
 
+            dry_wet: FloatParam::new("Dry/wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             bits: FloatParam::new(
                 "Bits",
                 16.0,
                 FloatRange::Skewed {
-                    min: 1.0,
-                    max: 16.0,
+                    min: MIN_BITS,
+                    max: MAX_BITS,
                     factor: FloatRange::skew_factor(-1.0),
                 },
             )
@@ -66,6 +268,85 @@ impl Default for BitcrushParams {
             )
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Keeps every nth sample, reducing the sample rate; 1 leaves it unchanged
+            downsample: FloatParam::new(
+                "Downsample",
+                1.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: MAX_DOWNSAMPLE,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            downsample_mode: EnumParam::new("Downsample mode", DecimationModeParam::Digital),
+
+            oversample: BoolParam::new("Oversample", false),
+
+            // Boosts the highs before crushing and cuts them back after, like vintage samplers,
+            // which pushes the quantization noise down and out of the highs
+            emphasis: BoolParam::new("Emphasis", false),
+
+            // Emulates a classic sampler's converter as the base setting, which bits, downsample,
+            // downsample mode, and emphasis then adjust from their defaults
+            vintage_preset: EnumParam::new("Vintage preset", VintagePresetParam::Off),
+
+            // Uses the same dither noise in both channels, centering it, instead of independent
+            // noise per channel
+            dither_link: BoolParam::new("Dither link", false),
+
+            lfo_rate: FloatParam::new(
+                "LFO rate",
+                2.0,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // How much deeper the LFO pulls the crush, up to 8 bits or 3 octaves of sample rate
+            // below the static setting; 0 leaves the crush static
+            lfo_depth: FloatParam::new("LFO depth", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            lfo_target: EnumParam::new("LFO target", ModulationTargetParam::Both),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Bitcrush {
+    /// Tunes the converter filters to a vintage converter, starting them from silence.
+    fn set_converter(&mut self, converter: Option<VintageConverter>) {
+        self.converter = converter;
+        if let Some(converter) = converter {
+            let fc = (CONVERTER_FILTER_CUTOFF * converter.sample_rate / self.sample_rate).min(0.45);
+            for filter in self
+                .anti_aliasing_filters
+                .iter_mut()
+                .chain(self.reconstruction_filters.iter_mut())
+            {
+                filter.set_biquad(BiquadFilterType::LowPass, fc, CONVERTER_FILTER_Q, 0.0);
+                filter.reset();
+            }
+        }
+    }
+
+    /// Calculates the latency of the plugin with its current parameters, in samples.
+    fn current_latency(&self) -> u32 {
+        if self.params.oversample.value() {
+            self.oversamplers[0].latency().round() as u32
+        } else {
+            0
         }
     }
 }
@@ -109,12 +390,64 @@ impl Plugin for Bitcrush {
         // Resize buffers and perform other potentially expensive initialization operations here.
         // The `reset()` function is always called right after this function. You can remove this
         // function if you do not need it.
+
+        // The dry signal is delayed by the oversampler's latency so it lines up with the wet signal
+        let oversampling_latency = self.oversamplers[0].latency().round() as usize;
+        self.dry_delays = [
+            DelayLine::new(oversampling_latency.max(1)),
+            DelayLine::new(oversampling_latency.max(1)),
+        ];
+
+        self.latency = self.current_latency();
+        _context.set_latency_samples(self.latency);
+
+        let emphasis_fc = EMPHASIS_FREQUENCY_HZ / _buffer_config.sample_rate;
+        for (pre_emphasis, de_emphasis) in self
+            .pre_emphasis_filters
+            .iter_mut()
+            .zip(self.de_emphasis_filters.iter_mut())
+        {
+            pre_emphasis.set_biquad(
+                BiquadFilterType::HighShelf,
+                emphasis_fc,
+                EMPHASIS_Q,
+                EMPHASIS_GAIN_DB,
+            );
+            de_emphasis.set_biquad(
+                BiquadFilterType::HighShelf,
+                emphasis_fc,
+                EMPHASIS_Q,
+                -EMPHASIS_GAIN_DB,
+            );
+        }
+
+        self.sample_rate = _buffer_config.sample_rate;
+        self.lfo.set_sample_rate(_buffer_config.sample_rate);
+        self.set_converter(vintage_preset_to_converter(
+            self.params.vintage_preset.value(),
+        ));
+
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        for decimator in self.decimators.iter_mut() {
+            decimator.reset();
+        }
+        for filter in self
+            .pre_emphasis_filters
+            .iter_mut()
+            .chain(self.de_emphasis_filters.iter_mut())
+            .chain(self.anti_aliasing_filters.iter_mut())
+            .chain(self.reconstruction_filters.iter_mut())
+        {
+            filter.reset();
+        }
+        self.lfo.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
@@ -123,20 +456,154 @@ impl Plugin for Bitcrush {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        for channel_samples in buffer.iter_samples() {
+        // Oversampling adds latency, so let the host know whenever it's toggled
+        let latency = self.current_latency();
+        if latency != self.latency {
+            self.latency = latency;
+            _context.set_latency_samples(latency);
+        }
+
+        // Retune the converter filters whenever another vintage preset is picked
+        let converter = vintage_preset_to_converter(self.params.vintage_preset.value());
+        if converter != self.converter {
+            self.set_converter(converter);
+        }
+
+        let bypassed = self.params.bypass.value();
+        for mut channel_samples in buffer.iter_samples() {
             let gain = self.params.gain.smoothed.next();
+            let dry_wet = self.params.dry_wet.smoothed.next();
             let bits = self.params.bits.smoothed.next();
             let constant = self.params.constant.smoothed.next();
+            let oversample = self.params.oversample.value();
+            let emphasis = self.params.emphasis.value();
+            let downsample = self.params.downsample.smoothed.next();
+            let downsample_mode =
+                decimation_mode_param_to_mode(self.params.downsample_mode.value());
+
+            // A vintage converter holds each sample at its own rate and bit depth, which the
+            // knobs then adjust: bits takes away as many bits as it's set below 16, downsample
+            // reduces the converter's rate further, and emphasis flips the converter's emphasis
+            let (bits, emphasis, downsample) = match converter {
+                Some(converter) => (
+                    (converter.bits - (MAX_BITS - bits)).max(MIN_BITS),
+                    converter.emphasis != emphasis,
+                    ((self.sample_rate / converter.sample_rate).max(1.0) * downsample)
+                        .min(MAX_DOWNSAMPLE),
+                ),
+                None => (bits, emphasis, downsample),
+            };
+
+            // Pulse the crush deeper with the LFO, from the static setting at the LFO's low point
+            self.lfo.set_frequency(self.params.lfo_rate.smoothed.next());
+            let lfo_depth = self.params.lfo_depth.smoothed.next();
+            let modulation = lfo_depth * 0.5 * (1.0 + self.lfo.tick());
+            let target = self.params.lfo_target.value();
+            let bits = if lfo_depth > 0.0 && target != ModulationTargetParam::SampleRate {
+                (bits - modulation * MAX_BITS_MODULATION).clamp(MIN_BITS, MAX_BITS)
+            } else {
+                bits
+            };
+            let downsample = if lfo_depth > 0.0 && target != ModulationTargetParam::Bits {
+                (downsample * (modulation * MAX_DOWNSAMPLE_MODULATION_OCTAVES).exp2())
+                    .clamp(1.0, MAX_DOWNSAMPLE)
+            } else {
+                downsample
+            };
+
+            // Dither only while the bit depth moves, masking the jumps between quantization
+            // levels; static settings stay exactly as crushed as before
+            let is_automating = bits != self.previous_bits;
+            self.previous_bits = bits;
+            let dither_link = self.params.dither_link.value();
+            let linked_dither = self.dithers[0].clone();
+
+            // Likewise, only crossfade between floating point grids while the constant moves
+            let is_sweeping_constant = constant != self.previous_constant;
+            self.previous_constant = constant;
+
+            // Once fully bypassed, only the dry signal's latency compensation keeps running
+            let is_bypassed = self.bypass.is_bypassed(bypassed);
 
-            for sample in channel_samples {
-                // Dynamic range quantization
-                *sample = bitcrush_sample(*sample, bits);
+            let mut dry = [0.0; 2];
+            let mut out = [0.0; 2];
+            for (channel, sample) in channel_samples.iter_mut().enumerate() {
+                let input = *sample;
+                dry[channel] = if oversample {
+                    let delayed = self.dry_delays[channel].read();
+                    self.dry_delays[channel].write_and_advance(input);
+                    delayed
+                } else {
+                    input
+                };
 
-                // Floating point error quantization
-                *sample = floating_point_quantize(*sample, constant);
+                if !is_bypassed {
+                    let input = if emphasis {
+                        self.pre_emphasis_filters[channel].process(input)
+                    } else {
+                        input
+                    };
 
-                *sample *= gain;
+                    let input = match converter {
+                        Some(converter) if converter.anti_aliasing => {
+                            self.anti_aliasing_filters[channel].process(input)
+                        }
+                        _ => input,
+                    };
+
+                    // Reduce the sample rate first, so the crushing happens on the held steps
+                    let decimator = &mut self.decimators[channel];
+                    decimator.set_mode(downsample_mode);
+                    decimator.set_factor(downsample);
+                    let input = decimator.process(input);
+
+                    // Linked dither replays the first channel's noise in the others, without
+                    // disturbing their own noise for when it's unlinked
+                    let mut replayed_dither;
+                    let dither = if dither_link && channel > 0 {
+                        replayed_dither = linked_dither.clone();
+                        &mut replayed_dither
+                    } else {
+                        &mut self.dithers[channel]
+                    };
+                    let mut next_dither = || {
+                        if is_automating {
+                            dither.next(bits)
+                        } else {
+                            0.0
+                        }
+                    };
+                    let wet = if oversample {
+                        // Crush at the oversampled rate so the quantization harmonics are band-limited
+                        self.oversamplers[channel].process(input, |x| {
+                            crush_sample(x, bits, constant, next_dither(), is_sweeping_constant)
+                        })
+                    } else {
+                        crush_sample(input, bits, constant, next_dither(), is_sweeping_constant)
+                    };
+
+                    // Smooth the held steps like the converter's output filter
+                    let wet = if converter.is_some() {
+                        self.reconstruction_filters[channel].process(wet)
+                    } else {
+                        wet
+                    };
+
+                    // Undo the boost, taking the quantization noise in the highs down with it
+                    let wet = if emphasis {
+                        self.de_emphasis_filters[channel].process(wet)
+                    } else {
+                        wet
+                    };
+                    out[channel] = (dry[channel] * (1.0 - dry_wet) + wet * dry_wet) * gain;
+                }
             }
+
+            let (out_l, out_r) = self
+                .bypass
+                .tick(bypassed, (dry[0], dry[1]), (out[0], out[1]));
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
         }
 
         ProcessStatus::Normal
@@ -1,13 +1,74 @@
-use fx::{delay_line::StereoDelay, DEFAULT_SAMPLE_RATE};
+use atomic_float::AtomicF32;
+use fx::{
+    bypass::BypassCrossfade,
+    delay_line::{Interpolation, SpreadMode, StereoDelay, MAX_CHORUS_VOICES},
+    stereo::CorrelationMeter,
+    DEFAULT_SAMPLE_RATE,
+};
 use nih_plug::prelude::*;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 const MAX_DELAY_TIME_SECONDS: f32 = 5.0;
 const PARAMETER_MINIMUM: f32 = 0.01;
+/// How quickly the correlation meter follows the output, in seconds
+const CORRELATION_TIME_CONSTANT: f32 = 0.3;
+
+/// All interpolation qualities for reading the delay lines.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InterpolationParam {
+    #[name = "None (nearest)"]
+    None,
+    Linear,
+    Cubic,
+    #[name = "Sinc (8-tap)"]
+    Sinc,
+}
+
+/// A matching from the interpolation parameter to the implementation's interpolation.
+fn interpolation_param_to_interpolation(interpolation: InterpolationParam) -> Interpolation {
+    match interpolation {
+        InterpolationParam::None => Interpolation::None,
+        InterpolationParam::Linear => Interpolation::Linear,
+        InterpolationParam::Cubic => Interpolation::Cubic,
+        InterpolationParam::Sinc => Interpolation::Sinc,
+    }
+}
+
+/// Fixed phase relationships between the left and right LFOs, or free to follow the width.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StereoPhaseParam {
+    #[name = "Width"]
+    Free,
+    #[name = "0°"]
+    Degrees0,
+    #[name = "90°"]
+    Degrees90,
+    #[name = "120°"]
+    Degrees120,
+    #[name = "180°"]
+    Degrees180,
+}
+
+/// A matching from the stereo phase parameter to the right LFO's offset in cycles, or `None` to
+/// follow the width.
+fn stereo_phase_param_to_offset(phase: StereoPhaseParam) -> Option<f32> {
+    match phase {
+        StereoPhaseParam::Free => None,
+        StereoPhaseParam::Degrees0 => Some(0.0),
+        StereoPhaseParam::Degrees90 => Some(0.25),
+        StereoPhaseParam::Degrees120 => Some(1.0 / 3.0),
+        StereoPhaseParam::Degrees180 => Some(0.5),
+    }
+}
 
 pub struct Chorus {
     params: Arc<ChorusParams>,
     chorus: StereoDelay,
+    correlation_meter: CorrelationMeter,
+    /// The output's phase correlation, from -1 to 1, to be read by an editor
+    correlation: Arc<AtomicF32>,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -27,8 +88,29 @@ struct ChorusParams {
     #[id = "width"]
     pub width: FloatParam,
 
+    #[id = "stereo-phase"]
+    pub stereo_phase: EnumParam<StereoPhaseParam>,
+
+    #[id = "voices"]
+    pub voices: IntParam,
+
     #[id = "feedback"]
     pub feedback: FloatParam,
+
+    #[id = "cross-feedback"]
+    pub cross_feedback: BoolParam,
+
+    #[id = "spread"]
+    pub spread: FloatParam,
+
+    #[id = "mono-safe"]
+    pub mono_safe: BoolParam,
+
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<InterpolationParam>,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Chorus {
@@ -36,6 +118,12 @@ impl Default for Chorus {
         Self {
             params: Arc::new(ChorusParams::default()),
             chorus: StereoDelay::new(MAX_DELAY_TIME_SECONDS, DEFAULT_SAMPLE_RATE),
+            correlation_meter: CorrelationMeter::new(
+                DEFAULT_SAMPLE_RATE as f32,
+                CORRELATION_TIME_CONSTANT,
+            ),
+            correlation: Arc::new(AtomicF32::new(0.0)),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -105,6 +193,19 @@ impl Default for ChorusParams {
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
+            // Locks the left and right LFOs to a fixed phase relationship, overriding the width
+            stereo_phase: EnumParam::new("Stereo phase", StereoPhaseParam::Free),
+
+            // Extra voices are spread evenly around the LFO cycle, 120 degrees apart at 3 voices
+            voices: IntParam::new(
+                "Voices",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_CHORUS_VOICES as i32,
+                },
+            ),
+
             feedback: FloatParam::new(
                 "Feedback",
                 0.5,
@@ -115,10 +216,33 @@ impl Default for ChorusParams {
             )
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Feeds each channel's delayed signal into the other channel's delay line
+            cross_feedback: BoolParam::new("Cross feedback", false),
+
+            // Widens the wet signal with a Haas delay of up to 30 ms, or a side boost when mono-safe
+            spread: FloatParam::new("Spread", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            mono_safe: BoolParam::new("Mono-safe", false),
+
+            // Nearest gives modulation a gritty, stepped character, cubic is the smoothest
+            interpolation: EnumParam::new("Interpolation", InterpolationParam::Cubic),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
         }
     }
 }
 
+impl Chorus {
+    /// Returns the output's phase correlation for checking mono compatibility: 1 is mono, 0 is
+    /// unrelated channels, and -1 is out of phase.
+    pub fn correlation(&self) -> Arc<AtomicF32> {
+        self.correlation.clone()
+    }
+}
+
 impl Plugin for Chorus {
     const NAME: &'static str = "Chorus v0.0.4";
     const VENDOR: &'static str = "Renzo Ledesma";
@@ -157,10 +281,17 @@ impl Plugin for Chorus {
     ) -> bool {
         self.chorus
             .resize_buffers(MAX_DELAY_TIME_SECONDS, _buffer_config.sample_rate as usize);
+        self.correlation_meter
+            .set_time_constant(_buffer_config.sample_rate, CORRELATION_TIME_CONSTANT);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
         true
     }
 
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        self.chorus.reset();
+        self.correlation_meter.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
 
     fn process(
         &mut self,
@@ -168,6 +299,12 @@ impl Plugin for Chorus {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
         for mut channel_samples in buffer.iter_samples() {
             // Get parameters
             let gain = self.params.gain.smoothed.next();
@@ -175,7 +312,23 @@ impl Plugin for Chorus {
             let vibrato_width = self.params.lfo_amount.smoothed.next();
             let depth = self.params.depth.smoothed.next();
             let width = self.params.width.smoothed.next() * 0.5;
+            let phase_offset =
+                stereo_phase_param_to_offset(self.params.stereo_phase.value()).unwrap_or(width);
             let feedback = self.params.feedback.smoothed.next();
+            let spread = self.params.spread.smoothed.next();
+            let spread_mode = if self.params.mono_safe.value() {
+                SpreadMode::MidSide
+            } else {
+                SpreadMode::Haas
+            };
+            self.chorus.set_spread(spread, spread_mode);
+            self.chorus
+                .set_cross_feedback(self.params.cross_feedback.value());
+            self.chorus.set_voices(self.params.voices.value() as usize);
+            self.chorus
+                .set_interpolation(interpolation_param_to_interpolation(
+                    self.params.interpolation.value(),
+                ));
 
             // Process input
             let sample_l = *channel_samples.get_mut(0).unwrap();
@@ -185,14 +338,24 @@ impl Plugin for Chorus {
                 (sample_l, sample_r),
                 rate,
                 vibrato_width,
-                width,
+                phase_offset,
                 depth,
                 feedback,
             );
 
-            *channel_samples.get_mut(0).unwrap() = processed_l * gain;
-            *channel_samples.get_mut(1).unwrap() = processed_r * gain;
+            let (out_l, out_r) = self.bypass.tick(
+                bypassed,
+                (sample_l, sample_r),
+                (processed_l * gain, processed_r * gain),
+            );
+
+            self.correlation_meter.process((out_l, out_r));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
         }
+        self.correlation
+            .store(self.correlation_meter.correlation(), Ordering::Relaxed);
 
         ProcessStatus::Normal
     }
@@ -1,16 +1,64 @@
-use fx::{dynamics::DynamicRangeProcessor, DEFAULT_SAMPLE_RATE};
+use fx::{
+    bypass::BypassCrossfade,
+    dynamics::{DetectionTopology, DetectorCurve, Ducker, DynamicRangeProcessor},
+    waveshapers::{get_saturator_gain, get_saturator_output},
+    DEFAULT_SAMPLE_RATE,
+};
 use nih_plug::prelude::*;
 use std::sync::Arc;
 
+/// The saturator's drive at full input saturation, strong enough to round off transients while
+/// leaving signals well below full scale alone
+const MAX_INPUT_SATURATION_DRIVE: f32 = 0.5;
+
+/// The key filter's high pass is off at its lowest setting, in Hz
+const KEY_HIGH_PASS_OFF_HZ: f32 = 20.0;
+
+/// Shapes of the attack and release.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DetectorCurveParam {
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+
+/// A matching from the detector curve parameter to the implementation's detector curve.
+fn detector_curve_param_to_curve(curve: DetectorCurveParam) -> DetectorCurve {
+    match curve {
+        DetectorCurveParam::Linear => DetectorCurve::Linear,
+        DetectorCurveParam::Exponential => DetectorCurve::Exponential,
+        DetectorCurveParam::Logarithmic => DetectorCurve::Logarithmic,
+    }
+}
+
+/// Where the detector reads the signal from.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DetectionParam {
+    Feedforward,
+    Feedback,
+}
+
+/// A matching from the detection parameter to the implementation's detection topology.
+fn detection_param_to_topology(detection: DetectionParam) -> DetectionTopology {
+    match detection {
+        DetectionParam::Feedforward => DetectionTopology::Feedforward,
+        DetectionParam::Feedback => DetectionTopology::Feedback,
+    }
+}
+
 pub struct Compression {
     params: Arc<CompressionParams>,
     processor: DynamicRangeProcessor,
+    ducker: Ducker,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
 struct CompressionParams {
     #[id = "input-gain"]
     pub input_gain: FloatParam,
+    #[id = "input-saturation"]
+    pub input_saturation: FloatParam,
     #[id = "threshold"]
     pub threshold: FloatParam,
     #[id = "ratio"]
@@ -19,12 +67,38 @@ struct CompressionParams {
     pub attack: FloatParam,
     #[id = "release"]
     pub release: FloatParam,
+    #[id = "detector-curve"]
+    pub detector_curve: EnumParam<DetectorCurveParam>,
+    #[id = "detection"]
+    pub detection: EnumParam<DetectionParam>,
+    #[id = "key-high-pass"]
+    pub key_high_pass: FloatParam,
+    #[id = "key-bell-frequency"]
+    pub key_bell_frequency: FloatParam,
+    #[id = "key-bell-gain"]
+    pub key_bell_gain: FloatParam,
     #[id = "makeup-gain"]
     pub makeup_gain: FloatParam,
+    #[id = "gain-smoothing"]
+    pub gain_smoothing: FloatParam,
     #[id = "dry-wet"]
     pub dry_wet: FloatParam,
     #[id = "use-expander"]
     pub use_expander: BoolParam,
+    #[id = "use-gate"]
+    pub use_gate: BoolParam,
+    #[id = "range"]
+    pub range: FloatParam,
+    #[id = "hold"]
+    pub hold: FloatParam,
+    #[id = "duck-depth"]
+    pub duck_depth: FloatParam,
+    #[id = "warmth"]
+    pub warmth: FloatParam,
+    #[id = "delta"]
+    pub delta: BoolParam,
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Compression {
@@ -32,6 +106,8 @@ impl Default for Compression {
         Self {
             params: Arc::new(CompressionParams::default()),
             processor: DynamicRangeProcessor::new(DEFAULT_SAMPLE_RATE),
+            ducker: Ducker::new(DEFAULT_SAMPLE_RATE as f32),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -53,6 +129,16 @@ impl Default for CompressionParams {
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
 
+            // Soft clips the input before the detector and gain reduction, like a console
+            // compressor's input stage
+            input_saturation: FloatParam::new(
+                "Input saturation",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             threshold: FloatParam::new(
                 "Threshold",
                 0.0,
@@ -108,6 +194,52 @@ impl Default for CompressionParams {
                 .with_smoother(SmoothingStyle::Exponential(50.0))
                 .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
+            detector_curve: EnumParam::new("Detector curve", DetectorCurveParam::Exponential),
+
+            // Feedback detection reads the compressed output, for gentler, self-correcting
+            // compression; it doesn't affect the expander or gate
+            detection: EnumParam::new("Detection", DetectionParam::Feedforward),
+
+            // The key filter shapes only what the detector hears; the high pass is off at its
+            // minimum, and the bell at 0 dB
+            key_high_pass: FloatParam::new(
+                "Key high-pass",
+                KEY_HIGH_PASS_OFF_HZ,
+                FloatRange::Skewed {
+                    min: KEY_HIGH_PASS_OFF_HZ,
+                    max: 2_000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            key_bell_frequency: FloatParam::new(
+                "Key bell frequency",
+                6_000.0,
+                FloatRange::Skewed {
+                    min: 200.0,
+                    max: 16_000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            key_bell_gain: FloatParam::new(
+                "Key bell gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             makeup_gain: FloatParam::new(
                 "Makeup gain",
                 util::db_to_gain(0.0),
@@ -119,12 +251,75 @@ impl Default for CompressionParams {
                     factor: FloatRange::gain_skew_factor(-30.0, 30.0),
                 },
             )
-            .with_smoother(SmoothingStyle::Logarithmic(50.0))
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
 
+            // The makeup gain is smoothed by the processor rather than the parameter, so any
+            // gain it's given, set by hand or computed, changes without zipper noise
+            gain_smoothing: FloatParam::new(
+                "Gain smoothing",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             use_expander: BoolParam::new("Compress/Expand", false),
+
+            use_gate: BoolParam::new("Gate", false),
+
+            range: FloatParam::new(
+                "Range",
+                40.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 80.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            hold: FloatParam::new(
+                "Hold",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Each MIDI note on ducks the signal by this much, using the attack and release
+            duck_depth: FloatParam::new(
+                "Duck depth",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 60.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Saturates more the harder it compresses, like a vintage compressor's coloring
+            warmth: FloatParam::new("Warmth", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Outputs only what the compressor removes, for hearing exactly what's attenuated
+            delta: BoolParam::new("Delta", false),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
         }
     }
 }
@@ -147,7 +342,7 @@ impl Plugin for Compression {
         names: PortNames::const_default(),
     }];
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
     const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
 
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
@@ -170,6 +365,8 @@ impl Plugin for Compression {
         // function if you do not need it.
         let sample_rate = _buffer_config.sample_rate;
         self.processor.set_sample_rate(sample_rate as usize);
+        self.ducker.set_sample_rate(sample_rate);
+        self.bypass.set_sample_rate(sample_rate);
         self.processor.set_parameters(
             self.params.threshold.default_plain_value(),
             self.params.ratio.default_plain_value(),
@@ -177,22 +374,51 @@ impl Plugin for Compression {
             self.params.release.default_plain_value() / 1000.,
             self.params.use_expander.default_plain_value(),
         );
+        self.processor.set_gate_parameters(
+            self.params.use_gate.default_plain_value(),
+            self.params.range.default_plain_value(),
+            self.params.hold.default_plain_value() / 1000.,
+        );
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.processor.reset();
+        self.processor
+            .reset_makeup_gain(util::gain_to_db(self.params.makeup_gain.value()));
+        self.ducker.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        let mut next_event = context.next_event();
+
         // Iterating over each sample to process...
-        for mut channel_samples in buffer.iter_samples() {
+        for (sample_id, mut channel_samples) in buffer.iter_samples().enumerate() {
+            // Trigger the ducker on each note on, sample accurately
+            while let Some(event) = next_event {
+                if event.timing() > sample_id as u32 {
+                    break;
+                }
+                if let NoteEvent::NoteOn { .. } = event {
+                    self.ducker.trigger();
+                }
+                next_event = context.next_event();
+            }
+
             // Update processor's parameters
             let threshold = self.params.threshold.smoothed.next();
             let ratio = self.params.ratio.smoothed.next();
@@ -201,21 +427,79 @@ impl Plugin for Compression {
             let is_expander = self.params.use_expander.value();
             self.processor
                 .set_parameters(threshold, ratio, attack, release, is_expander);
+            self.processor
+                .set_detector_curve(detector_curve_param_to_curve(
+                    self.params.detector_curve.value(),
+                ));
+            self.processor
+                .set_detection_topology(detection_param_to_topology(self.params.detection.value()));
+            let key_high_pass = self.params.key_high_pass.smoothed.next();
+            self.processor
+                .set_key_high_pass((key_high_pass > KEY_HIGH_PASS_OFF_HZ).then_some(key_high_pass));
+            self.processor.set_key_bell(
+                self.params.key_bell_frequency.smoothed.next(),
+                self.params.key_bell_gain.smoothed.next(),
+            );
+
+            let is_gate = self.params.use_gate.value();
+            let range = self.params.range.smoothed.next();
+            let hold = self.params.hold.smoothed.next() * 0.001; // convert from ms to s
+            self.processor.set_gate_parameters(is_gate, range, hold);
+            self.processor
+                .set_warmth(self.params.warmth.smoothed.next());
+            self.ducker.set_times(attack, release);
 
             let input_gain = self.params.input_gain.smoothed.next();
-            let in_l = *channel_samples.get_mut(0).unwrap() * input_gain;
-            let in_r = *channel_samples.get_mut(1).unwrap() * input_gain;
+            let sample_l = *channel_samples.get_mut(0).unwrap();
+            let sample_r = *channel_samples.get_mut(1).unwrap();
+            let in_l = sample_l * input_gain;
+            let in_r = sample_r * input_gain;
+
+            // Saturate the input after the input gain, so the input gain drives it harder, dividing
+            // out the saturator's gain so quiet signals pass at unity and only peaks get rounded
+            let input_saturation = self.params.input_saturation.smoothed.next();
+            let input = if input_saturation > 0. {
+                let drive = input_saturation * MAX_INPUT_SATURATION_DRIVE;
+                let gain = get_saturator_gain(drive);
+                (
+                    get_saturator_output(drive, in_l) / gain,
+                    get_saturator_output(drive, in_r) / gain,
+                )
+            } else {
+                (in_l, in_r)
+            };
 
             // Process
-            let input = (in_l * input_gain, in_r * input_gain);
-            let makeup_gain = self.params.makeup_gain.smoothed.next();
-            let makeup_gain_db = util::gain_to_db_fast(makeup_gain);
+            self.processor
+                .set_makeup_smoothing(self.params.gain_smoothing.value() * 0.001);
+            let makeup_gain_db = util::gain_to_db_fast(self.params.makeup_gain.value());
             let frame_out = self.processor.process_input_frame(input, makeup_gain_db);
+            let makeup_gain = util::db_to_gain_fast(self.processor.makeup_gain());
+
+            // Apply MIDI ducking, independent of the input level
+            let duck_gain = self
+                .ducker
+                .next_gain(self.params.duck_depth.smoothed.next());
+            let frame_out = (frame_out.0 * duck_gain, frame_out.1 * duck_gain);
 
-            // Apply dry/wet, then output
+            // Apply dry/wet, then output, or output the delta: the part of the gained, saturated
+            // input the compressor took away, without the makeup gain so it doesn't count as a
+            // change
             let dry_wet_ratio = self.params.dry_wet.smoothed.next();
-            let out_l = in_l * (1. - dry_wet_ratio) + frame_out.0 * dry_wet_ratio;
-            let out_r = in_r * (1. - dry_wet_ratio) + frame_out.1 * dry_wet_ratio;
+            let (out_l, out_r) = if self.params.delta.value() {
+                (
+                    input.0 - frame_out.0 / makeup_gain,
+                    input.1 - frame_out.1 / makeup_gain,
+                )
+            } else {
+                (
+                    in_l * (1. - dry_wet_ratio) + frame_out.0 * dry_wet_ratio,
+                    in_r * (1. - dry_wet_ratio) + frame_out.1 * dry_wet_ratio,
+                )
+            };
+            let (out_l, out_r) = self
+                .bypass
+                .tick(bypassed, (sample_l, sample_r), (out_l, out_r));
 
             *channel_samples.get_mut(0).unwrap() = out_l;
             *channel_samples.get_mut(1).unwrap() = out_r;
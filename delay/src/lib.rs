@@ -1,16 +1,63 @@
-use fx::delay_line::DelayLine;
+use fx::biquad::{BiquadFilterType, StereoBiquadFilter};
+use fx::bypass::BypassCrossfade;
+use fx::delay_line::{DelayLine, Interpolation};
+use fx::dynamics::{DynamicRangeProcessor, TransientEnvelope};
+use fx::filters::MAX_DIFFUSER_STAGES;
+use fx::safety::{OutputSafety, DEFAULT_SAFETY_CEILING_DB};
 use fx::DEFAULT_SAMPLE_RATE;
 use nih_plug::prelude::*;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 const MAX_DELAY_TIME_SECONDS: f32 = 5.0;
+const DUCKING_THRESHOLD_DB: f32 = -40.0;
+const DUCKING_ATTACK_SECONDS: f32 = 0.01;
+const DUCKING_RELEASE_SECONDS: f32 = 0.25;
+const MAX_MODULATION_DEPTH_MS: f32 = 10.0;
+/// The wet filters' cutoff ranges; at the widest setting, each filter is switched off
+const MIN_WET_LOW_CUT_HZ: f32 = 20.0;
+const MAX_WET_HIGH_CUT_HZ: f32 = 20_000.0;
+const WET_FILTER_Q: f32 = 0.707;
+/// The feedback filter's highest cutoff, where it's switched off unless the envelope sweeps it
+const MAX_FEEDBACK_FILTER_CUTOFF_HZ: f32 = 20_000.0;
+/// How far the filter envelope opens the feedback filter above its cutoff at full amount
+const MAX_FILTER_ENVELOPE_OCTAVES: f32 = 5.0;
+
+/// All interpolation qualities for reading the delay lines.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InterpolationParam {
+    #[name = "None (nearest)"]
+    None,
+    Linear,
+    Cubic,
+    #[name = "Sinc (8-tap)"]
+    Sinc,
+}
+
+/// A matching from the interpolation parameter to the implementation's interpolation.
+fn interpolation_param_to_interpolation(interpolation: InterpolationParam) -> Interpolation {
+    match interpolation {
+        InterpolationParam::None => Interpolation::None,
+        InterpolationParam::Linear => Interpolation::Linear,
+        InterpolationParam::Cubic => Interpolation::Cubic,
+        InterpolationParam::Sinc => Interpolation::Sinc,
+    }
+}
 
 pub struct Delay {
     params: Arc<DelayParams>,
     delay_line_l: DelayLine,
     delay_line_r: DelayLine,
     should_update_delay_line: Arc<AtomicBool>,
+    ducking_processor: DynamicRangeProcessor,
+    filter_envelope: TransientEnvelope,
+    wet_low_cut: StereoBiquadFilter,
+    wet_high_cut: StereoBiquadFilter,
+    // Crossfade each wet filter in and out as it's switched on and off
+    wet_low_cut_fade: BypassCrossfade,
+    wet_high_cut_fade: BypassCrossfade,
+    output_safety: OutputSafety,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -23,6 +70,69 @@ struct DelayParams {
 
     #[id = "delay-time"]
     pub delay_time: FloatParam,
+
+    #[id = "dual-mono"]
+    pub dual_mono: BoolParam,
+
+    #[id = "right-delay-time"]
+    pub right_delay_time: FloatParam,
+
+    #[id = "damping"]
+    pub damping: FloatParam,
+
+    #[id = "resonator"]
+    pub resonator: BoolParam,
+
+    #[id = "resonance-tune"]
+    pub resonance_tune: IntParam,
+
+    #[id = "ducking"]
+    pub ducking: FloatParam,
+
+    #[id = "diffusion"]
+    pub diffusion: FloatParam,
+
+    #[id = "diffusion-stages"]
+    pub diffusion_stages: IntParam,
+
+    #[id = "feedback-filter-cutoff"]
+    pub feedback_filter_cutoff: FloatParam,
+
+    #[id = "filter-envelope-amount"]
+    pub filter_envelope_amount: FloatParam,
+
+    #[id = "filter-envelope-sensitivity"]
+    pub filter_envelope_sensitivity: FloatParam,
+
+    #[id = "filter-envelope-decay"]
+    pub filter_envelope_decay: FloatParam,
+
+    #[id = "wet-low-cut"]
+    pub wet_low_cut: FloatParam,
+
+    #[id = "wet-high-cut"]
+    pub wet_high_cut: FloatParam,
+
+    #[id = "mod-rate"]
+    pub mod_rate: FloatParam,
+
+    #[id = "mod-depth"]
+    pub mod_depth: FloatParam,
+
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<InterpolationParam>,
+
+    #[id = "hold"]
+    pub hold: BoolParam,
+
+    #[id = "output-safety"]
+    pub output_safety: BoolParam,
+
+    #[id = "output-ceiling"]
+    pub output_ceiling: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Delay {
@@ -39,6 +149,14 @@ impl Default for Delay {
                 DEFAULT_SAMPLE_RATE * MAX_DELAY_TIME_SECONDS as usize,
                 DEFAULT_SAMPLE_RATE,
             ),
+            ducking_processor: DynamicRangeProcessor::new(DEFAULT_SAMPLE_RATE),
+            filter_envelope: TransientEnvelope::new(DEFAULT_SAMPLE_RATE as f32),
+            wet_low_cut: StereoBiquadFilter::new(),
+            wet_high_cut: StereoBiquadFilter::new(),
+            wet_low_cut_fade: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+            wet_high_cut_fade: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+            output_safety: OutputSafety::new(),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -84,6 +202,276 @@ impl DelayParams {
             .with_smoother(SmoothingStyle::Linear(2.0))
             .with_unit(" ms")
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Unlinks the channels, so the time sets the left delay and the right time the right
+            dual_mono: BoolParam::new("Dual mono", false).with_callback(Arc::new({
+                let should_update_delay_line = should_update_delay_line.clone();
+                move |_| should_update_delay_line.store(true, Ordering::SeqCst)
+            })),
+
+            right_delay_time: FloatParam::new(
+                "Right time",
+                450.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: MAX_DELAY_TIME_SECONDS * 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_callback(Arc::new({
+                let should_update_delay_line = should_update_delay_line.clone();
+                move |_| should_update_delay_line.store(true, Ordering::SeqCst)
+            }))
+            .with_smoother(SmoothingStyle::Linear(2.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            damping: FloatParam::new(
+                "Damping",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 0.95,
+                },
+            )
+            .with_callback(Arc::new({
+                let should_update_delay_line = should_update_delay_line.clone();
+                move |_| should_update_delay_line.store(true, Ordering::SeqCst)
+            }))
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            resonator: BoolParam::new("Resonator", false).with_callback(Arc::new({
+                let should_update_delay_line = should_update_delay_line.clone();
+                move |_| should_update_delay_line.store(true, Ordering::SeqCst)
+            })),
+
+            // A3 by default
+            resonance_tune: IntParam::new(
+                "Resonance tune",
+                57,
+                IntRange::Linear { min: 24, max: 96 },
+            )
+            .with_callback(Arc::new({
+                let should_update_delay_line = should_update_delay_line.clone();
+                move |_| should_update_delay_line.store(true, Ordering::SeqCst)
+            }))
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+
+            // How much the dry input pushes down the echoes, from no ducking to full ducking
+            ducking: FloatParam::new("Ducking", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // How much each repeat is smeared by allpass filters in the feedback path
+            diffusion: FloatParam::new("Diffusion", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // How many allpass filters the diffusion chains; more stages smear each repeat more
+            // densely
+            diffusion_stages: IntParam::new(
+                "Diffusion stages",
+                MAX_DIFFUSER_STAGES as i32,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_DIFFUSER_STAGES as i32,
+                },
+            ),
+
+            // A low pass filter in the feedback path, off at its highest cutoff
+            feedback_filter_cutoff: FloatParam::new(
+                "Feedback filter cutoff",
+                MAX_FEEDBACK_FILTER_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: 100.0,
+                    max: MAX_FEEDBACK_FILTER_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // How far each new note sweeps the feedback filter open, up to 5 octaves; at 0 the
+            // filter stays at its cutoff
+            filter_envelope_amount: FloatParam::new(
+                "Filter envelope amount",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // How small a jump in the input's level counts as a new note
+            filter_envelope_sensitivity: FloatParam::new(
+                "Filter envelope sensitivity",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // How long the filter takes to close back to its cutoff
+            filter_envelope_decay: FloatParam::new(
+                "Filter envelope decay",
+                300.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 5_000.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // Tone controls on the echoes only, outside the feedback loop
+            wet_low_cut: FloatParam::new(
+                "Wet low cut",
+                MIN_WET_LOW_CUT_HZ,
+                FloatRange::Skewed {
+                    min: MIN_WET_LOW_CUT_HZ,
+                    max: 2_000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            wet_high_cut: FloatParam::new(
+                "Wet high cut",
+                MAX_WET_HIGH_CUT_HZ,
+                FloatRange::Skewed {
+                    min: 1_000.0,
+                    max: MAX_WET_HIGH_CUT_HZ,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // How fast and how far the delay time sweeps, for tape-style pitch wobble
+            mod_rate: FloatParam::new(
+                "Mod rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            mod_depth: FloatParam::new(
+                "Mod depth",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_MODULATION_DEPTH_MS,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            interpolation: EnumParam::new("Interpolation", InterpolationParam::Cubic),
+
+            // Loops the current echoes forever and keeps new input out of the loop
+            hold: BoolParam::new("Hold", false),
+
+            // Soft clips the final output under the ceiling, so runaway levels never reach the host
+            output_safety: BoolParam::new("Output safety", false),
+
+            output_ceiling: FloatParam::new(
+                "Output ceiling",
+                DEFAULT_SAFETY_CEILING_DB,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+///
+/// Runs a wet filter, crossfading between the filtered and unfiltered signal as it's switched on
+/// and off. The filter is skipped entirely once it's faded out, and starts from silence when it's
+/// switched back on rather than from the state it was left in.
+///
+fn filter_wet(
+    filter: &mut StereoBiquadFilter,
+    fade: &mut BypassCrossfade,
+    off: bool,
+    wet: (f32, f32),
+) -> (f32, f32) {
+    if fade.is_bypassed(off) {
+        return wet;
+    }
+    if fade.is_bypassed(true) {
+        filter.reset();
+    }
+    let filtered = filter.process(wet);
+    fade.tick(off, wet, filtered)
+}
+
+impl Delay {
+    ///
+    /// Tunes the wet signal's low and high cut filters.
+    ///
+    fn set_wet_filters(&mut self, low_cut: f32, high_cut: f32, sample_rate: f32) {
+        self.wet_low_cut.set_biquads(
+            BiquadFilterType::HighPass,
+            low_cut / sample_rate,
+            WET_FILTER_Q,
+            0.0,
+        );
+        self.wet_high_cut.set_biquads(
+            BiquadFilterType::LowPass,
+            (high_cut / sample_rate).min(0.49),
+            WET_FILTER_Q,
+            0.0,
+        );
+    }
+
+    ///
+    /// Sets the delay time of both delay lines, or with dual mono, the left and right delay
+    /// times separately. In resonator mode, both delay times are tuned to the resonance note
+    /// instead.
+    ///
+    fn set_delay_time(&mut self, delay_time_ms: f32, right_delay_time_ms: f32, sample_rate: f32) {
+        if self.params.resonator.value() {
+            let frequency = util::midi_note_to_freq(self.params.resonance_tune.value() as u8);
+            self.delay_line_l
+                .set_resonator_frequency(frequency, sample_rate);
+            self.delay_line_r
+                .set_resonator_frequency(frequency, sample_rate);
+        } else {
+            let right_delay_time_ms = if self.params.dual_mono.value() {
+                right_delay_time_ms
+            } else {
+                delay_time_ms
+            };
+            self.delay_line_l.set_delay_time(delay_time_ms, sample_rate);
+            self.delay_line_r
+                .set_delay_time(right_delay_time_ms, sample_rate);
         }
     }
 }
@@ -129,19 +517,50 @@ impl Plugin for Delay {
         // function if you do not need it.
         let fs = _buffer_config.sample_rate;
         self.delay_line_l
-            .resize_buffer((fs * MAX_DELAY_TIME_SECONDS) as usize);
-        self.delay_line_l
-            .set_delay_time(self.params.delay_time.value(), fs);
-        self.delay_line_r
-            .resize_buffer((fs * MAX_DELAY_TIME_SECONDS) as usize);
+            .resize_buffer_with_sample_rate((fs * MAX_DELAY_TIME_SECONDS) as usize, fs as usize);
         self.delay_line_r
-            .set_delay_time(self.params.delay_time.value(), fs);
+            .resize_buffer_with_sample_rate((fs * MAX_DELAY_TIME_SECONDS) as usize, fs as usize);
+        self.set_delay_time(
+            self.params.delay_time.value(),
+            self.params.right_delay_time.value(),
+            fs,
+        );
+        // The delay lines only output the wet signal, which is filtered before the dry/wet mix
+        self.delay_line_l.set_dry_wet(0.0, 1.0);
+        self.delay_line_r.set_dry_wet(0.0, 1.0);
+        self.set_wet_filters(
+            self.params.wet_low_cut.value(),
+            self.params.wet_high_cut.value(),
+            fs,
+        );
+        self.ducking_processor.set_sample_rate(fs as usize);
+        self.filter_envelope.set_sample_rate(fs);
+        self.wet_low_cut_fade.set_sample_rate(fs);
+        self.wet_high_cut_fade.set_sample_rate(fs);
+        self.bypass.set_sample_rate(fs);
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.delay_line_l.reset();
+        self.delay_line_r.reset();
+        self.ducking_processor.reset();
+        self.filter_envelope.reset();
+        self.wet_low_cut.reset();
+        self.wet_high_cut.reset();
+        self.wet_low_cut_fade
+            .reset(self.params.wet_low_cut.value() <= MIN_WET_LOW_CUT_HZ);
+        self.wet_high_cut_fade
+            .reset(self.params.wet_high_cut.value() >= MAX_WET_HIGH_CUT_HZ);
+        self.output_safety.reset();
+        self.bypass.reset(self.params.bypass.value());
+
+        // Start at the current feedback instead of ramping to it from the last session
+        let feedback = self.params.feedback.value();
+        self.delay_line_l.reset_feedback(feedback);
+        self.delay_line_r.reset_feedback(feedback);
     }
 
     fn process(
@@ -150,6 +569,12 @@ impl Plugin for Delay {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
         let sample_rate = _context.transport().sample_rate;
         if self
             .should_update_delay_line
@@ -158,44 +583,143 @@ impl Plugin for Delay {
         {
             // Set delay time and feedback if params have changed
             let delay_time_ms = self.params.delay_time.smoothed.next();
+            let right_delay_time_ms = self.params.right_delay_time.smoothed.next();
             let feedback = self.params.feedback.smoothed.next();
-            let dry_wet = self.params.dry_wet_ratio.smoothed.next();
+            let damping = self.params.damping.smoothed.next();
 
-            // Set both delay lines
-            self.delay_line_l.set_delay_time(delay_time_ms, sample_rate);
+            // Set both delay lines; damping goes first since it affects the resonator tuning
+            self.delay_line_l.set_damping(damping);
             self.delay_line_l.set_feedback(feedback);
-            self.delay_line_l.set_dry_wet(1.0 - dry_wet, dry_wet);
-            self.delay_line_r.set_delay_time(delay_time_ms, sample_rate);
+            self.delay_line_r.set_damping(damping);
             self.delay_line_r.set_feedback(feedback);
-            self.delay_line_r.set_dry_wet(1.0 - dry_wet, dry_wet);
+            self.set_delay_time(delay_time_ms, right_delay_time_ms, sample_rate);
         }
         for mut channel_samples in buffer.iter_samples() {
             // Set parameters while smoothing
-            if self.params.delay_time.smoothed.is_smoothing() {
-                let delay_time_ms = self.params.delay_time.smoothed.next();
-                self.delay_line_l.set_delay_time(delay_time_ms, sample_rate);
-                self.delay_line_r.set_delay_time(delay_time_ms, sample_rate);
+            if self.params.damping.smoothed.is_smoothing() {
+                let damping = self.params.damping.smoothed.next();
+                self.delay_line_l.set_damping(damping);
+                self.delay_line_r.set_damping(damping);
+                if self.params.resonator.value() {
+                    self.set_delay_time(
+                        self.params.delay_time.value(),
+                        self.params.right_delay_time.value(),
+                        sample_rate,
+                    );
+                }
             }
-            if self.params.feedback.smoothed.is_smoothing() {
-                let feedback = self.params.feedback.smoothed.next();
-                self.delay_line_l.set_feedback(feedback);
-                self.delay_line_r.set_feedback(feedback);
+            if self.params.delay_time.smoothed.is_smoothing()
+                || self.params.right_delay_time.smoothed.is_smoothing()
+            {
+                let delay_time_ms = self.params.delay_time.smoothed.next();
+                let right_delay_time_ms = self.params.right_delay_time.smoothed.next();
+                self.set_delay_time(delay_time_ms, right_delay_time_ms, sample_rate);
             }
-            if self.params.dry_wet_ratio.smoothed.is_smoothing() {
-                let dry_wet = self.params.dry_wet_ratio.smoothed.next();
-                self.delay_line_l.set_dry_wet(1.0 - dry_wet, dry_wet);
-                self.delay_line_r.set_dry_wet(1.0 - dry_wet, dry_wet);
+            // The delay lines smooth the feedback every sample themselves
+            let feedback = self.params.feedback.smoothed.next();
+            self.delay_line_l.set_feedback(feedback);
+            self.delay_line_r.set_feedback(feedback);
+            if self.params.wet_low_cut.smoothed.is_smoothing()
+                || self.params.wet_high_cut.smoothed.is_smoothing()
+            {
+                let low_cut = self.params.wet_low_cut.smoothed.next();
+                let high_cut = self.params.wet_high_cut.smoothed.next();
+                self.set_wet_filters(low_cut, high_cut, sample_rate);
             }
 
+            let interpolation =
+                interpolation_param_to_interpolation(self.params.interpolation.value());
+            self.delay_line_l.set_interpolation(interpolation);
+            self.delay_line_r.set_interpolation(interpolation);
+            let diffusion = self.params.diffusion.smoothed.next();
+            self.delay_line_l.set_diffusion(diffusion);
+            self.delay_line_r.set_diffusion(diffusion);
+            let diffusion_stages = self.params.diffusion_stages.value() as usize;
+            self.delay_line_l.set_diffusion_stages(diffusion_stages);
+            self.delay_line_r.set_diffusion_stages(diffusion_stages);
+            let mod_rate = self.params.mod_rate.smoothed.next();
+            let mod_depth = self.params.mod_depth.smoothed.next();
+            self.delay_line_l.set_modulation(mod_depth, mod_rate);
+            self.delay_line_r.set_modulation(mod_depth, mod_rate);
+            let hold = self.params.hold.value();
+            self.delay_line_l.set_hold(hold);
+            self.delay_line_r.set_hold(hold);
+
             // Process input
             let sample_l = *channel_samples.get_mut(0).unwrap();
             let sample_r = *channel_samples.get_mut(1).unwrap();
 
-            let processed_l = self.delay_line_l.process_with_delay(sample_l);
-            let processed_r = self.delay_line_r.process_with_delay(sample_r);
+            // Sweep the feedback filter open on each new note, closing back to its cutoff as the
+            // envelope decays
+            self.filter_envelope
+                .set_sensitivity(self.params.filter_envelope_sensitivity.value());
+            self.filter_envelope
+                .set_decay(self.params.filter_envelope_decay.value() / 1000.0);
+            let envelope = self.filter_envelope.process((sample_l + sample_r) * 0.5);
+            let cutoff = self.params.feedback_filter_cutoff.smoothed.next();
+            let amount = self.params.filter_envelope_amount.smoothed.next();
+            let feedback_filter = if cutoff >= MAX_FEEDBACK_FILTER_CUTOFF_HZ && amount <= 0.0 {
+                None
+            } else {
+                Some(cutoff * (amount * envelope * MAX_FILTER_ENVELOPE_OCTAVES).exp2())
+            };
+            self.delay_line_l.set_feedback_filter(feedback_filter);
+            self.delay_line_r.set_feedback_filter(feedback_filter);
+
+            // Follow the dry input's envelope with a compressor whose slope flattens as ducking
+            // increases, so loud input pushes the echoes down and at 0 they pass untouched
+            let ducking = self.params.ducking.smoothed.next();
+            self.ducking_processor.set_parameters(
+                DUCKING_THRESHOLD_DB,
+                1.0 - ducking,
+                DUCKING_ATTACK_SECONDS,
+                DUCKING_RELEASE_SECONDS,
+                false,
+            );
+            let duck_gain = self
+                .ducking_processor
+                .calculate_control_voltage((sample_l + sample_r) * 0.5, 0.0);
+
+            let wet_l = self
+                .delay_line_l
+                .process_with_ducked_delay(sample_l, duck_gain);
+            let wet_r = self
+                .delay_line_r
+                .process_with_ducked_delay(sample_r, duck_gain);
+
+            // Filter the echoes at the output only, so the filters don't compound on every repeat;
+            // each filter is off at its widest setting
+            let wet = filter_wet(
+                &mut self.wet_low_cut,
+                &mut self.wet_low_cut_fade,
+                self.params.wet_low_cut.value() <= MIN_WET_LOW_CUT_HZ,
+                (wet_l, wet_r),
+            );
+            let wet = filter_wet(
+                &mut self.wet_high_cut,
+                &mut self.wet_high_cut_fade,
+                self.params.wet_high_cut.value() >= MAX_WET_HIGH_CUT_HZ,
+                wet,
+            );
+
+            let dry_wet = self.params.dry_wet_ratio.smoothed.next();
+            let processed_l = sample_l * (1.0 - dry_wet) + wet.0 * dry_wet;
+            let processed_r = sample_r * (1.0 - dry_wet) + wet.1 * dry_wet;
+
+            // High feedback can build the echoes past full scale
+            let (out_l, out_r) = (processed_l, processed_r);
+            let (out_l, out_r) = self.output_safety.process_switched(
+                self.params.output_safety.value(),
+                self.params.output_ceiling.smoothed.next(),
+                (out_l, out_r),
+            );
+
+            let (out_l, out_r) = self
+                .bypass
+                .tick(bypassed, (sample_l, sample_r), (out_l, out_r));
 
-            *channel_samples.get_mut(0).unwrap() = processed_l;
-            *channel_samples.get_mut(1).unwrap() = processed_r;
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
         }
 
         ProcessStatus::Normal
@@ -3,8 +3,12 @@ use std::sync::Arc;
 
 use fx::{
     biquad::{BiquadFilterType, StereoBiquadFilter},
+    bypass::BypassCrossfade,
     dc_filter::DcFilter,
-    oversampling::HalfbandFilter,
+    dynamics::{LevelMatcher, LookaheadLimiter},
+    filters::DelayLine,
+    oversampling::StereoOversampler,
+    safety::{OutputSafety, DEFAULT_SAFETY_CEILING_DB},
     waveshapers::*,
     DEFAULT_SAMPLE_RATE,
 };
@@ -41,32 +45,254 @@ pub enum DistortionType {
     Wavefolding,
 }
 
-/// Process input sample through waveshaper algorithm of specified type
-pub fn distort_sample(distortion_type: &DistortionType, drive: f32, input_sample: f32) -> f32 {
+/// Hard clipper qualities available in plugin
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum ClipperQualityParam {
+    #[id = "naive"]
+    #[name = "Naive"]
+    Naive,
+
+    #[id = "antiderivative"]
+    #[name = "Antialiased"]
+    Antiderivative,
+}
+
+/// A matching from the clipper quality parameter to the implementation's clipper quality.
+fn clipper_quality_param_to_quality(quality: &ClipperQualityParam) -> ClipperQuality {
+    match quality {
+        ClipperQualityParam::Naive => ClipperQuality::Naive,
+        ClipperQualityParam::Antiderivative => ClipperQuality::Antiderivative,
+    }
+}
+
+/// How the drive knob maps to each distortion type's drive
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum DriveLawParam {
+    #[id = "linear"]
+    #[name = "Linear"]
+    Linear,
+
+    #[id = "exponential"]
+    #[name = "Exponential"]
+    Exponential,
+
+    #[id = "decibel"]
+    #[name = "Decibel"]
+    Decibel,
+}
+
+/// The lowest hard clipping threshold the decibel drive law reaches, in dB below the highest
+const HARD_CLIPPING_DECIBEL_RANGE: f32 = 24.0;
+
+///
+/// How far the exponential drive law bends the knob for a distortion type, tuned by ear. Positive
+/// values spend more of the knob's travel on low drives, for types that change the most there,
+/// and negative values spend more on high drives, for types whose gain races up near the top.
+///
+fn drive_curvature(distortion_type: &DistortionType) -> f32 {
+    match distortion_type {
+        // The saturator's gain grows without bound as the drive nears 1
+        DistortionType::Saturation
+        | DistortionType::FuzzyRectifier
+        | DistortionType::DoubleSoftClipper => -4.0,
+        // The drive is the threshold, so low drives clip the hardest
+        DistortionType::HardClipping => 3.0,
+        DistortionType::Dropout => 2.0,
+        DistortionType::ShockleyDiodeRectifier | DistortionType::Wavefolding => 0.0,
+    }
+}
+
+///
+/// Maps the drive knob to a distortion type's drive along a drive law, so each type can sweep
+/// evenly. Linear passes the knob through, as it always has, so existing settings sound the same.
+///
+/// # Arguments
+/// * `raw` - the drive knob's value, from 0 to `MAX_DRIVE`
+/// * `drive_law` - the response curve to map along
+/// * `distortion_type` - the distortion type the drive is for
+///
+fn map_drive(raw: f32, drive_law: &DriveLawParam, distortion_type: &DistortionType) -> f32 {
+    let position = (raw / MAX_DRIVE).clamp(0.0, 1.0);
+    match drive_law {
+        DriveLawParam::Linear => raw,
+        DriveLawParam::Exponential => {
+            let curvature = drive_curvature(distortion_type);
+            if curvature == 0.0 {
+                raw
+            } else {
+                MAX_DRIVE * (curvature * position).exp_m1() / curvature.exp_m1()
+            }
+        }
+        DriveLawParam::Decibel => match distortion_type {
+            // Even steps of the clipping threshold, in dB
+            DistortionType::HardClipping => {
+                MAX_DRIVE * 10.0_f32.powf((position - 1.0) * HARD_CLIPPING_DECIBEL_RANGE / 20.0)
+            }
+            // Even steps of the fold frequency, in octaves
+            DistortionType::Wavefolding => ((1.0 + 3.0 * MAX_DRIVE).powf(position) - 1.0) / 3.0,
+            // Even steps of the saturator's gain at rest, (1 + drive) / (1 - drive), in dB
+            _ => {
+                let gain = ((1.0 + MAX_DRIVE) / (1.0 - MAX_DRIVE)).powf(position);
+                (gain - 1.0) / (gain + 1.0)
+            }
+        },
+    }
+}
+
+/// Whether a distortion type's sharp corners or dense folds alias badly enough to be worth
+/// oversampling further.
+fn is_aliasing_prone(distortion_type: &DistortionType) -> bool {
+    matches!(
+        distortion_type,
+        DistortionType::HardClipping | DistortionType::Wavefolding
+    )
+}
+
+/// The highest drive, just short of 1, where the saturator's gain becomes infinite
+const MAX_DRIVE: f32 = 0.999;
+/// How far the stereo drive can offset one channel's drive
+const MAX_STEREO_DRIVE: f32 = 0.25;
+
+///
+/// Splits the drive between the channels, so the left and right distort slightly differently.
+/// A positive offset drives the right channel harder, and a negative one the left.
+///
+/// # Arguments
+/// * `drive` - the drive shared by both channels
+/// * `stereo_drive` - the offset added to one channel's drive; 0 drives both channels equally
+///
+fn stereo_drives(drive: f32, stereo_drive: f32) -> (f32, f32) {
+    let offset_l = (-stereo_drive).max(0.0);
+    let offset_r = stereo_drive.max(0.0);
+    (
+        (drive + offset_l).clamp(0.0, MAX_DRIVE),
+        (drive + offset_r).clamp(0.0, MAX_DRIVE),
+    )
+}
+
+/// Process input sample through waveshaper algorithm of specified type.
+/// The fold offset and fold gain only apply to wavefolding, and the hard clipper's state only
+/// applies to hard clipping.
+pub fn distort_sample(
+    distortion_type: &DistortionType,
+    drive: f32,
+    fold_offset: f32,
+    fold_gain: f32,
+    hard_clipper: &mut HardClipper,
+    input_sample: f32,
+) -> f32 {
     match distortion_type {
         DistortionType::Saturation => get_saturator_output(drive, input_sample),
-        DistortionType::HardClipping => get_hard_clipper_output(drive, input_sample),
+        DistortionType::HardClipping => hard_clipper.process(drive, input_sample),
         DistortionType::FuzzyRectifier => get_fuzzy_rectifier_output(drive, input_sample),
         DistortionType::ShockleyDiodeRectifier => {
             get_shockley_diode_rectifier_output(drive, input_sample)
         }
         DistortionType::Dropout => get_dropout_output(drive, input_sample),
         DistortionType::DoubleSoftClipper => get_double_soft_clipper_output(drive, input_sample),
-        DistortionType::Wavefolding => get_wavefolder_output(drive, input_sample),
+        DistortionType::Wavefolding => {
+            get_wavefolder_output(drive, fold_offset, fold_gain, input_sample)
+        }
     }
 }
 
 const FILTER_CUTOFF_HZ: f32 = 8000.0;
+const DEFAULT_DC_FILTER_CUTOFF_HZ: f32 = 7.0;
+const LEVEL_MATCH_TIME_CONSTANT: f32 = 0.3; // seconds
 const OVERSAMPLING_FACTOR: usize = 4;
+const HIGH_OVERSAMPLING_FACTOR: usize = 16;
+const SAFE_LOOKAHEAD_SECONDS: f32 = 0.001;
+const SAFE_CEILING_DB: f32 = -0.3;
+const ONE_KNOB_FILTER_Q: f32 = 1.5;
+const ONE_KNOB_LOW_PASS_RANGE_HZ: (f32, f32) = (200.0, 20_000.0);
+const ONE_KNOB_HIGH_PASS_RANGE_HZ: (f32, f32) = (20.0, 5_000.0);
+
+/// Maps the one-knob filter's position to a filter type and cutoff in Hz. Below the center the
+/// low-pass cutoff falls from 20 kHz to 200 Hz, above it the high-pass cutoff rises from 20 Hz
+/// to 5 kHz, and the center leaves the signal unfiltered.
+fn one_knob_filter(position: f32) -> Option<(BiquadFilterType, f32)> {
+    // Sweeps exponentially from `from` to `to` as `amount` goes from 0 to 1
+    let sweep = |from: f32, to: f32, amount: f32| from * (to / from).powf(amount);
+    if position < 0.5 {
+        let (min, max) = ONE_KNOB_LOW_PASS_RANGE_HZ;
+        Some((
+            BiquadFilterType::LowPass,
+            sweep(max, min, 1.0 - position * 2.0),
+        ))
+    } else if position > 0.5 {
+        let (min, max) = ONE_KNOB_HIGH_PASS_RANGE_HZ;
+        Some((
+            BiquadFilterType::HighPass,
+            sweep(min, max, position * 2.0 - 1.0),
+        ))
+    } else {
+        None
+    }
+}
+
+/// The pre- and post-filters' normalized cutoff at an oversampling factor. Past 4x, the cutoff is
+/// scaled down so the filters sound the same as they do at 4x.
+fn filter_fc(sample_rate: f32, oversample_factor: usize) -> f32 {
+    FILTER_CUTOFF_HZ / sample_rate * OVERSAMPLING_FACTOR as f32
+        / oversample_factor.max(OVERSAMPLING_FACTOR) as f32
+}
+
+/// The safety limiter's look-ahead at the original sample rate, in samples
+fn safe_lookahead(sample_rate: f32) -> usize {
+    (SAFE_LOOKAHEAD_SECONDS * sample_rate).round() as usize
+}
+
+/// Creates the safety limiter, running at the oversampled rate when oversampling, or detecting
+/// true peaks itself when not.
+fn safe_limiter(sample_rate: f32, oversample_factor: usize) -> LookaheadLimiter {
+    let mut limiter = LookaheadLimiter::new(
+        sample_rate * oversample_factor as f32,
+        safe_lookahead(sample_rate) * oversample_factor,
+    );
+    limiter.set_ceiling(SAFE_CEILING_DB);
+    limiter.set_true_peak(oversample_factor == 1);
+    limiter
+}
 
 pub struct Distortion {
     params: Arc<DistortionParams>,
-    upsampler: (HalfbandFilter, HalfbandFilter),
-    downsampler: (HalfbandFilter, HalfbandFilter),
+    oversampler: StereoOversampler<OVERSAMPLING_FACTOR>,
+    high_oversampler: StereoOversampler<HIGH_OVERSAMPLING_FACTOR>,
     prefilter: StereoBiquadFilter,
     postfilter: StereoBiquadFilter,
+    one_knob_filter: StereoBiquadFilter,
     dc_filters: (DcFilter, DcFilter),
+    /// DC filters after the waveshaper, at the oversampled rate
+    oversampled_dc_filters: (DcFilter, DcFilter),
+    /// Whether the base rate and the oversampled DC filters ran on the last sample, so each
+    /// starts from silence when it comes back into use
+    dc_filters_in_use: (bool, bool),
+    /// Hard clipper state for the left and right channels, each holding one clipper for type A
+    /// and one for type B
+    hard_clippers: ((HardClipper, HardClipper), (HardClipper, HardClipper)),
+    /// The last sample entering each channel's waveshaper, for delaying the other types by half
+    /// a sample to line up with antialiased hard clipping
+    shaper_inputs: (f32, f32),
     oversample_factor: usize,
+    /// The oversampling factor for aliasing-prone distortion types when auto oversampling is on,
+    /// and the factor currently in use
+    boosted_oversample_factor: usize,
+    active_oversample_factor: usize,
+    dry_delays: (DelayLine, DelayLine),
+    /// The last latency compensated dry frame, for delaying the dry signal by antialiased hard
+    /// clipping's extra half sample at the oversampled rate
+    previous_dry: (f32, f32),
+    /// Delays the wet signal at the regular oversampling factor to line up with the boosted
+    /// factor's longer latency, so switching between them doesn't shift the audio
+    wet_delays: (DelayLine, DelayLine),
+    wet_delay: usize,
+    latency: usize,
+    sample_rate: f32,
+    level_matcher: LevelMatcher,
+    safe_limiter: LookaheadLimiter,
+    boosted_safe_limiter: LookaheadLimiter,
+    output_safety: OutputSafety,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -83,14 +309,65 @@ struct DistortionParams {
     #[id = "drive"]
     pub drive: FloatParam,
 
+    #[id = "stereo-drive"]
+    pub stereo_drive: FloatParam,
+
+    #[id = "drive-law"]
+    pub drive_law: EnumParam<DriveLawParam>,
+
     #[id = "distortion-type"]
     pub distortion_type: EnumParam<DistortionType>,
 
+    #[id = "distortion-type-b"]
+    pub distortion_type_b: EnumParam<DistortionType>,
+
+    #[id = "morph"]
+    pub morph: FloatParam,
+
+    #[id = "clipper-quality"]
+    pub clipper_quality: EnumParam<ClipperQualityParam>,
+
+    #[id = "fold-offset"]
+    pub fold_offset: FloatParam,
+
+    #[id = "fold-gain"]
+    pub fold_gain: FloatParam,
+
     #[id = "enable-pre-filter"]
     pub enable_pre_filter: BoolParam,
 
     #[id = "enable-post-filter"]
     pub enable_post_filter: BoolParam,
+
+    #[id = "auto-level"]
+    pub auto_level: BoolParam,
+
+    #[id = "enable-dc-filter"]
+    pub enable_dc_filter: BoolParam,
+
+    #[id = "dc-filter-cutoff"]
+    pub dc_filter_cutoff: FloatParam,
+
+    #[id = "oversampled-dc-filter"]
+    pub oversampled_dc_filter: BoolParam,
+
+    #[id = "safe"]
+    pub safe: BoolParam,
+
+    #[id = "auto-oversampling"]
+    pub auto_oversampling: BoolParam,
+
+    #[id = "filter"]
+    pub filter: FloatParam,
+
+    #[id = "output-safety"]
+    pub output_safety: BoolParam,
+
+    #[id = "output-ceiling"]
+    pub output_ceiling: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Distortion {
@@ -108,12 +385,42 @@ impl Default for Distortion {
 
         Distortion {
             params: Arc::new(DistortionParams::default()),
-            upsampler: (HalfbandFilter::new(8, true), HalfbandFilter::new(8, true)),
-            downsampler: (HalfbandFilter::new(8, true), HalfbandFilter::new(8, true)),
+            oversampler: StereoOversampler::new(8, true),
+            high_oversampler: StereoOversampler::new(8, true),
             prefilter,
             postfilter,
+            one_knob_filter: StereoBiquadFilter::new(),
             dc_filters: (DcFilter::default(), DcFilter::default()),
+            oversampled_dc_filters: (DcFilter::default(), DcFilter::default()),
+            dc_filters_in_use: (false, false),
+            hard_clippers: (
+                (
+                    HardClipper::new(ClipperQuality::Naive),
+                    HardClipper::new(ClipperQuality::Naive),
+                ),
+                (
+                    HardClipper::new(ClipperQuality::Naive),
+                    HardClipper::new(ClipperQuality::Naive),
+                ),
+            ),
+            shaper_inputs: (0.0, 0.0),
             oversample_factor: 4,
+            boosted_oversample_factor: HIGH_OVERSAMPLING_FACTOR,
+            active_oversample_factor: 4,
+            dry_delays: (DelayLine::new(1), DelayLine::new(1)),
+            previous_dry: (0.0, 0.0),
+            wet_delays: (DelayLine::new(1), DelayLine::new(1)),
+            wet_delay: 0,
+            latency: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            level_matcher: LevelMatcher::new(DEFAULT_SAMPLE_RATE as f32, LEVEL_MATCH_TIME_CONSTANT),
+            safe_limiter: safe_limiter(DEFAULT_SAMPLE_RATE as f32, OVERSAMPLING_FACTOR),
+            boosted_safe_limiter: safe_limiter(
+                DEFAULT_SAMPLE_RATE as f32,
+                HIGH_OVERSAMPLING_FACTOR,
+            ),
+            output_safety: OutputSafety::new(),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -154,7 +461,18 @@ impl Default for DistortionParams {
                 0.5,
                 FloatRange::Linear {
                     min: 0.0,
-                    max: 0.999,
+                    max: MAX_DRIVE,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            stereo_drive: FloatParam::new(
+                "Stereo drive",
+                0.0,
+                FloatRange::Linear {
+                    min: -MAX_STEREO_DRIVE,
+                    max: MAX_STEREO_DRIVE,
                 },
             )
             .with_smoother(SmoothingStyle::Linear(50.0))
@@ -168,11 +486,130 @@ impl Default for DistortionParams {
             .with_smoother(SmoothingStyle::Linear(50.0))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
+            // Linear keeps existing settings sounding the same; the other laws remap the knob
+            // per distortion type so it sweeps evenly
+            drive_law: EnumParam::new("Drive law", DriveLawParam::Linear),
+
             distortion_type: EnumParam::new("Type", DistortionType::Saturation),
 
+            distortion_type_b: EnumParam::new("Type B", DistortionType::Saturation),
+
+            morph: FloatParam::new("Morph", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Antialiased hard clipping uses first-order antiderivative antialiasing
+            clipper_quality: EnumParam::new("Clipper quality", ClipperQualityParam::Naive),
+
+            fold_offset: FloatParam::new(
+                "Fold offset",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            fold_gain: FloatParam::new(
+                "Fold gain",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             enable_pre_filter: BoolParam::new("Enable pre-filter", true),
 
             enable_post_filter: BoolParam::new("Enable post-filter", true),
+
+            auto_level: BoolParam::new("Auto level", false),
+
+            enable_dc_filter: BoolParam::new("Enable DC filter", true),
+
+            dc_filter_cutoff: FloatParam::new(
+                "DC filter cutoff",
+                DEFAULT_DC_FILTER_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 100.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Moves the DC filter after the waveshaper, at the oversampled rate, so it also
+            // removes the DC the waveshaper itself adds
+            oversampled_dc_filter: BoolParam::new("Oversampled DC filter", false),
+
+            // Limits inter-sample peaks at the oversampled rate, before downsampling
+            safe: BoolParam::new("Safe", false),
+
+            // Oversamples hard clipping and wavefolding 4x further, at about 4x the CPU. The
+            // factor is picked on reset.
+            auto_oversampling: BoolParam::new("Auto oversampling", true),
+
+            // Low-pass below the center, high-pass above it, and no filtering at the center
+            filter: FloatParam::new("Filter", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Soft clips the final output under the ceiling, so runaway levels never reach the host
+            output_safety: BoolParam::new("Output safety", false),
+
+            output_ceiling: FloatParam::new(
+                "Output ceiling",
+                DEFAULT_SAFETY_CEILING_DB,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Distortion {
+    /// Picks the oversampling factor from the current parameters, oversampling further when
+    /// auto oversampling is on and an aliasing-prone type is heard, and retunes the filters that
+    /// run at the oversampled rate. This only runs on reset, since switching factors mid-stream
+    /// would restart the oversamplers and the limiter's look-ahead.
+    fn select_oversample_factor(&mut self) {
+        let boost = self.params.auto_oversampling.value()
+            && (is_aliasing_prone(&self.params.distortion_type.value())
+                || (self.params.morph.value() > 0.0
+                    && is_aliasing_prone(&self.params.distortion_type_b.value())));
+        self.active_oversample_factor = if boost {
+            self.boosted_oversample_factor
+        } else {
+            self.oversample_factor
+        };
+
+        let fs = self.sample_rate;
+        self.prefilter
+            .set_fc(filter_fc(fs, self.active_oversample_factor));
+        self.postfilter
+            .set_fc(filter_fc(fs, self.active_oversample_factor));
+        let oversampled_fs = fs * self.active_oversample_factor as f32;
+        let dc_filter_cutoff = self.params.dc_filter_cutoff.value();
+        for dc_filter in [
+            &mut self.oversampled_dc_filters.0,
+            &mut self.oversampled_dc_filters.1,
+        ] {
+            dc_filter.set_cutoff(dc_filter_cutoff, oversampled_fs);
         }
     }
 }
@@ -222,12 +659,45 @@ impl Plugin for Distortion {
         let fs = _buffer_config.sample_rate;
         if fs >= 88200. {
             self.oversample_factor = 1;
+            self.boosted_oversample_factor = OVERSAMPLING_FACTOR;
         } else {
             self.oversample_factor = 4;
+            self.boosted_oversample_factor = HIGH_OVERSAMPLING_FACTOR;
         }
 
-        self.prefilter.set_fc(FILTER_CUTOFF_HZ / fs);
-        self.postfilter.set_fc(FILTER_CUTOFF_HZ / fs);
+        self.sample_rate = fs;
+        self.level_matcher
+            .set_time_constant(fs, LEVEL_MATCH_TIME_CONSTANT);
+        let dc_filter_cutoff = self.params.dc_filter_cutoff.value();
+        self.dc_filters.0.set_cutoff(dc_filter_cutoff, fs);
+        self.dc_filters.1.set_cutoff(dc_filter_cutoff, fs);
+
+        // The safety limiter's look-ahead is always in the signal path, so toggling it doesn't
+        // change the latency
+        self.safe_limiter = safe_limiter(fs, self.oversample_factor);
+        self.boosted_safe_limiter = safe_limiter(fs, self.boosted_oversample_factor);
+
+        // Delay the dry signal by the oversampler's and limiter's latency so it lines up with
+        // the wet signal. The latency is always the boosted factor's, so toggling auto
+        // oversampling or changing the distortion type doesn't change it.
+        let oversampling_latency = |factor: usize| match factor {
+            OVERSAMPLING_FACTOR => self.oversampler.latency().round() as usize,
+            HIGH_OVERSAMPLING_FACTOR => self.high_oversampler.latency().round() as usize,
+            _ => 0,
+        };
+        let boosted_latency = oversampling_latency(self.boosted_oversample_factor);
+        self.wet_delay = boosted_latency - oversampling_latency(self.oversample_factor);
+        self.wet_delays = (
+            DelayLine::new(self.wet_delay.max(1)),
+            DelayLine::new(self.wet_delay.max(1)),
+        );
+        self.latency = boosted_latency + safe_lookahead(fs);
+        self.dry_delays = (
+            DelayLine::new(self.latency.max(1)),
+            DelayLine::new(self.latency.max(1)),
+        );
+        _context.set_latency_samples(self.latency as u32);
+        self.bypass.set_sample_rate(fs);
 
         true
     }
@@ -235,6 +705,32 @@ impl Plugin for Distortion {
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.select_oversample_factor();
+        self.level_matcher.reset();
+        self.safe_limiter.reset();
+        self.boosted_safe_limiter.reset();
+        self.oversampler.reset();
+        self.high_oversampler.reset();
+        self.prefilter.reset();
+        self.postfilter.reset();
+        self.one_knob_filter.reset();
+        self.dc_filters.0.reset();
+        self.dc_filters.1.reset();
+        self.oversampled_dc_filters.0.reset();
+        self.oversampled_dc_filters.1.reset();
+        self.dc_filters_in_use = (false, false);
+        for delays in [&mut self.dry_delays, &mut self.wet_delays] {
+            delays.0.reset();
+            delays.1.reset();
+        }
+        for clippers in [&mut self.hard_clippers.0, &mut self.hard_clippers.1] {
+            clippers.0.reset();
+            clippers.1.reset();
+        }
+        self.shaper_inputs = (0.0, 0.0);
+        self.previous_dry = (0.0, 0.0);
+        self.output_safety.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
@@ -243,66 +739,279 @@ impl Plugin for Distortion {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        let bypassed = self.params.bypass.value();
         for mut channel_samples in buffer.iter_samples() {
             let input_gain = self.params.input_gain.smoothed.next();
             let output_gain = self.params.output_gain.smoothed.next();
-            let drive = self.params.drive.smoothed.next();
             let dry_wet_ratio = self.params.dry_wet_ratio.smoothed.next();
             let distortion_type = self.params.distortion_type.value();
+            let fold_offset = self.params.fold_offset.smoothed.next();
+            let fold_gain = self.params.fold_gain.smoothed.next();
+            let distortion_type_b = self.params.distortion_type_b.value();
+            let morph = self.params.morph.smoothed.next();
+
+            // Map each channel's drive for both types, as (type A, type B) pairs
+            let drive_law = self.params.drive_law.value();
+            let (drive_l, drive_r) = stereo_drives(
+                self.params.drive.smoothed.next(),
+                self.params.stereo_drive.smoothed.next(),
+            );
+            let map_drives = |raw: f32| {
+                (
+                    map_drive(raw, &drive_law, &distortion_type),
+                    map_drive(raw, &drive_law, &distortion_type_b),
+                )
+            };
+            let (drive_l, drive_r) = (map_drives(drive_l), map_drives(drive_r));
+            let clipper_quality =
+                clipper_quality_param_to_quality(&self.params.clipper_quality.value());
+            for clippers in [&mut self.hard_clippers.0, &mut self.hard_clippers.1] {
+                clippers.0.set_quality(clipper_quality);
+                clippers.1.set_quality(clipper_quality);
+            }
+
+            // Crossfade between both distortion types. Both are run every sample, so neither
+            // clipper's antialiasing state goes stale while its type isn't heard, and the other
+            // types are delayed by the antialiased clipper's half sample so the two line up.
+            let antialiased = clipper_quality == ClipperQuality::Antiderivative;
+            let distort = |x: f32,
+                           previous: &mut f32,
+                           drives: (f32, f32),
+                           clippers: &mut (HardClipper, HardClipper)| {
+                let delayed = if antialiased {
+                    0.5 * (x + *previous)
+                } else {
+                    x
+                };
+                *previous = x;
+                let input = |distortion_type: &DistortionType| match distortion_type {
+                    DistortionType::HardClipping => x,
+                    _ => delayed,
+                };
+                let distorted_a = distort_sample(
+                    &distortion_type,
+                    drives.0,
+                    fold_offset,
+                    fold_gain,
+                    &mut clippers.0,
+                    input(&distortion_type),
+                );
+                let distorted_b = distort_sample(
+                    &distortion_type_b,
+                    drives.1,
+                    fold_offset,
+                    fold_gain,
+                    &mut clippers.1,
+                    input(&distortion_type_b),
+                );
+                (1.0 - morph) * distorted_a + morph * distorted_b
+            };
             let enable_pre_filter = self.params.enable_pre_filter.value();
             let enable_post_filter = self.params.enable_post_filter.value();
+            let enable_dc_filter = self.params.enable_dc_filter.value();
+            let oversampled_dc_filter = self.params.oversampled_dc_filter.value();
+            self.safe_limiter.set_enabled(self.params.safe.value());
+            self.boosted_safe_limiter
+                .set_enabled(self.params.safe.value());
+
+            // The factor only changes on reset, so the oversamplers and limiters never switch
+            // mid-stream
+            let oversample_factor = self.active_oversample_factor;
+            let boost = oversample_factor != self.oversample_factor;
+
+            // Update DC filters while the cutoff smooths
+            if self.params.dc_filter_cutoff.smoothed.is_smoothing() {
+                let dc_filter_cutoff = self.params.dc_filter_cutoff.smoothed.next();
+                self.dc_filters
+                    .0
+                    .set_cutoff(dc_filter_cutoff, self.sample_rate);
+                self.dc_filters
+                    .1
+                    .set_cutoff(dc_filter_cutoff, self.sample_rate);
+                let oversampled_fs = self.sample_rate * oversample_factor as f32;
+                self.oversampled_dc_filters
+                    .0
+                    .set_cutoff(dc_filter_cutoff, oversampled_fs);
+                self.oversampled_dc_filters
+                    .1
+                    .set_cutoff(dc_filter_cutoff, oversampled_fs);
+            }
 
             let in_l = *channel_samples.get_mut(0).unwrap();
             let in_r = *channel_samples.get_mut(1).unwrap();
 
-            let processed_l = self.dc_filters.0.process(in_l) * input_gain;
-            let processed_r = self.dc_filters.1.process(in_r) * input_gain;
+            let (dry_l, dry_r) = if self.latency > 0 {
+                let delayed = (self.dry_delays.0.read(), self.dry_delays.1.read());
+                self.dry_delays.0.write_and_advance(in_l);
+                self.dry_delays.1.write_and_advance(in_r);
+                delayed
+            } else {
+                (in_l, in_r)
+            };
+
+            // Antialiased hard clipping delays the wet signal by another half sample at the
+            // oversampled rate, so delay the dry signal to match
+            let previous_dry = std::mem::replace(&mut self.previous_dry, (dry_l, dry_r));
+            let (dry_l, dry_r) = if antialiased {
+                let fraction = 0.5 / oversample_factor as f32;
+                (
+                    dry_l + (previous_dry.0 - dry_l) * fraction,
+                    dry_r + (previous_dry.1 - dry_r) * fraction,
+                )
+            } else {
+                (dry_l, dry_r)
+            };
 
-            let (wet_l, wet_r) = if self.oversample_factor == OVERSAMPLING_FACTOR {
-                // Begin upsampling block
-                let mut frame_l = [processed_l, 0., 0., 0.];
-                let mut frame_r = [processed_r, 0., 0., 0.];
+            // Once fully bypassed, only the dry signal's latency compensation keeps running
+            if self.bypass.is_bypassed(bypassed) {
+                *channel_samples.get_mut(0).unwrap() = dry_l;
+                *channel_samples.get_mut(1).unwrap() = dry_r;
+                self.dc_filters_in_use = (false, false);
+                continue;
+            }
 
-                for i in 0..OVERSAMPLING_FACTOR {
-                    // Upsample
-                    frame_l[i] = self.upsampler.0.process(frame_l[i]);
-                    frame_r[i] = self.upsampler.1.process(frame_r[i]);
+            // Switching the DC filter between the base and the oversampled rate leaves the
+            // filter that was switched out holding stale state, so clear it when it's switched
+            // back in
+            let filter_dc_before_gain = enable_dc_filter && !oversampled_dc_filter;
+            let filter_dc_after_shaping = enable_dc_filter && oversampled_dc_filter;
+            if filter_dc_before_gain && !self.dc_filters_in_use.0 {
+                self.dc_filters.0.reset();
+                self.dc_filters.1.reset();
+            }
+            if filter_dc_after_shaping && !self.dc_filters_in_use.1 {
+                self.oversampled_dc_filters.0.reset();
+                self.oversampled_dc_filters.1.reset();
+            }
+            self.dc_filters_in_use = (filter_dc_before_gain, filter_dc_after_shaping);
 
-                    // Apply pre-filtering
-                    if enable_pre_filter {
-                        let prefiltered = self.prefilter.process((frame_l[i], frame_r[i]));
-                        frame_l[i] = prefiltered.0;
-                        frame_r[i] = prefiltered.1;
-                    }
+            let (processed_l, processed_r) = if filter_dc_before_gain {
+                (
+                    self.dc_filters.0.process(in_l) * input_gain,
+                    self.dc_filters.1.process(in_r) * input_gain,
+                )
+            } else {
+                (in_l * input_gain, in_r * input_gain)
+            };
 
-                    // Apply distortion
-                    frame_l[i] = distort_sample(&distortion_type, drive, frame_l[i]);
-                    frame_r[i] = distort_sample(&distortion_type, drive, frame_r[i]);
+            // Filter, distort, and limit a frame at the oversampled rate
+            let prefilter = &mut self.prefilter;
+            let postfilter = &mut self.postfilter;
+            let hard_clippers = &mut self.hard_clippers;
+            let shaper_inputs = &mut self.shaper_inputs;
+            let oversampled_dc_filters = &mut self.oversampled_dc_filters;
+            let mut shape = |mut frame: (f32, f32), limiter: &mut LookaheadLimiter| {
+                // Apply pre-filtering
+                if enable_pre_filter {
+                    frame = prefilter.process(frame);
+                }
 
-                    // Apply post-filtering
-                    if enable_post_filter {
-                        let postfiltered = self.postfilter.process((frame_l[i], frame_r[i]));
-                        frame_l[i] = postfiltered.0;
-                        frame_r[i] = postfiltered.1;
-                    }
+                // Apply distortion
+                frame = (
+                    distort(frame.0, &mut shaper_inputs.0, drive_l, &mut hard_clippers.0),
+                    distort(frame.1, &mut shaper_inputs.1, drive_r, &mut hard_clippers.1),
+                );
 
-                    // Downsample through half-band filter
-                    frame_l[i] = self.downsampler.0.process(frame_l[i]);
-                    frame_r[i] = self.downsampler.1.process(frame_r[i]);
+                // Remove the DC the waveshaper adds while still at the oversampled rate
+                if filter_dc_after_shaping {
+                    frame = (
+                        oversampled_dc_filters.0.process(frame.0),
+                        oversampled_dc_filters.1.process(frame.1),
+                    );
                 }
 
-                (frame_l[0], frame_r[0])
+                // Apply post-filtering
+                if enable_post_filter {
+                    frame = postfilter.process(frame);
+                }
+
+                // Catch inter-sample peaks before the downsampling filters
+                limiter.process(frame)
+            };
+            let limiter = if boost {
+                &mut self.boosted_safe_limiter
+            } else {
+                &mut self.safe_limiter
+            };
+
+            let (wet_l, wet_r) = match oversample_factor {
+                HIGH_OVERSAMPLING_FACTOR => self
+                    .high_oversampler
+                    .process((processed_l, processed_r), |frame| shape(frame, limiter)),
+                OVERSAMPLING_FACTOR => self
+                    .oversampler
+                    .process((processed_l, processed_r), |frame| shape(frame, limiter)),
+                _ => {
+                    let mut frame = (
+                        distort(
+                            processed_l,
+                            &mut self.shaper_inputs.0,
+                            drive_l,
+                            &mut self.hard_clippers.0,
+                        ),
+                        distort(
+                            processed_r,
+                            &mut self.shaper_inputs.1,
+                            drive_r,
+                            &mut self.hard_clippers.1,
+                        ),
+                    );
+                    if filter_dc_after_shaping {
+                        frame = (
+                            self.oversampled_dc_filters.0.process(frame.0),
+                            self.oversampled_dc_filters.1.process(frame.1),
+                        );
+                    }
+                    limiter.process(frame)
+                }
+            };
+
+            // Line the regular oversampling factor up with the boosted factor's latency
+            let (wet_l, wet_r) = if !boost && self.wet_delay > 0 {
+                let delayed = (self.wet_delays.0.read(), self.wet_delays.1.read());
+                self.wet_delays.0.write_and_advance(wet_l);
+                self.wet_delays.1.write_and_advance(wet_r);
+                delayed
             } else {
-                let distorted_l = distort_sample(&distortion_type, drive, processed_l);
-                let distorted_r = distort_sample(&distortion_type, drive, processed_r);
-                (distorted_l, distorted_r)
+                (wet_l, wet_r)
             };
 
-            let out_l = (in_l * (1.0 - dry_wet_ratio)) + (wet_l * dry_wet_ratio);
-            let out_r = (in_r * (1.0 - dry_wet_ratio)) + (wet_r * dry_wet_ratio);
+            // Match the wet signal's loudness to the input so drive and type changes stay level
+            let (wet_l, wet_r) = if self.params.auto_level.value() {
+                self.level_matcher
+                    .process((processed_l, processed_r), (wet_l, wet_r))
+            } else {
+                (wet_l, wet_r)
+            };
+
+            // Shape the distorted tone with the one-knob filter, skipped at its center
+            let (wet_l, wet_r) = match one_knob_filter(self.params.filter.smoothed.next()) {
+                Some((filter_type, cutoff)) => {
+                    self.one_knob_filter.set_biquads(
+                        filter_type,
+                        cutoff / self.sample_rate,
+                        ONE_KNOB_FILTER_Q,
+                        0.0,
+                    );
+                    self.one_knob_filter.process((wet_l, wet_r))
+                }
+                None => (wet_l, wet_r),
+            };
+
+            let out_l = (dry_l * (1.0 - dry_wet_ratio)) + (wet_l * dry_wet_ratio);
+            let out_r = (dry_r * (1.0 - dry_wet_ratio)) + (wet_r * dry_wet_ratio);
+
+            let (out_l, out_r) = (out_l * output_gain, out_r * output_gain);
+            let (out_l, out_r) = self.output_safety.process_switched(
+                self.params.output_safety.value(),
+                self.params.output_ceiling.smoothed.next(),
+                (out_l, out_r),
+            );
+
+            let (out_l, out_r) = self.bypass.tick(bypassed, (dry_l, dry_r), (out_l, out_r));
 
-            *channel_samples.get_mut(0).unwrap() = out_l * output_gain;
-            *channel_samples.get_mut(1).unwrap() = out_r * output_gain;
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
         }
 
         ProcessStatus::Normal
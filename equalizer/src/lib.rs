@@ -1,9 +1,27 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
+use atomic_float::AtomicF32;
 use fx::biquad::{BiquadFilterType, StereoBiquadFilter};
+use fx::bypass::BypassCrossfade;
+use fx::dynamics::{dynamic_eq_gain, EnvelopeFollower};
+use fx::spectrum::{SampleRing, SpectrumAnalyzer};
+use fx::stereo::{decode_mid_side, encode_mid_side};
+use fx::DEFAULT_SAMPLE_RATE;
 use nih_plug::prelude::*;
 
+/// The number of samples per analyzer block. Larger sizes give finer frequency resolution
+/// at the cost of slower updates.
+const ANALYZER_FFT_SIZE: usize = 2048;
+
+/// The most samples processed between filter coefficient updates while parameters smooth. This
+/// is short enough that the steps in between are inaudible
+const COEFFICIENT_UPDATE_INTERVAL: usize = 32;
+
+/// How quickly a dynamic band engages and lets go, in seconds
+const DYNAMIC_ATTACK_SECONDS: f32 = 0.005;
+const DYNAMIC_RELEASE_SECONDS: f32 = 0.1;
+
 /// All possible filter types for this EQ plugin.
 #[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum BiquadFilterTypeParam {
@@ -14,6 +32,7 @@ pub enum BiquadFilterTypeParam {
     ParametricEQ,
     LowShelf,
     HighShelf,
+    AllPass,
 }
 
 /// A matching from the filter type parameter to the implementation's filter type.
@@ -28,13 +47,63 @@ fn eq_type_to_param(filter_type: BiquadFilterTypeParam) -> BiquadFilterType {
         BiquadFilterTypeParam::ParametricEQ => BiquadFilterType::ParametricEQ,
         BiquadFilterTypeParam::LowShelf => BiquadFilterType::LowShelf,
         BiquadFilterTypeParam::HighShelf => BiquadFilterType::HighShelf,
+        BiquadFilterTypeParam::AllPass => BiquadFilterType::AllPass,
     }
 }
 
+/// The filter type that isolates the part of the spectrum a filter type acts on, for band solo.
+/// Peaking, band pass, notch, and all pass filters act around the cutoff, pass filters act on
+/// what they remove, and shelves act on the shelved region.
+fn band_solo_type(filter_type: BiquadFilterTypeParam) -> BiquadFilterType {
+    match filter_type {
+        BiquadFilterTypeParam::LowPass => BiquadFilterType::HighPass,
+        BiquadFilterTypeParam::HighPass => BiquadFilterType::LowPass,
+        BiquadFilterTypeParam::BandPass
+        | BiquadFilterTypeParam::Notch
+        | BiquadFilterTypeParam::ParametricEQ
+        | BiquadFilterTypeParam::AllPass => BiquadFilterType::BandPass,
+        BiquadFilterTypeParam::LowShelf => BiquadFilterType::LowPass,
+        BiquadFilterTypeParam::HighShelf => BiquadFilterType::HighPass,
+    }
+}
+
+/// Which part of the stereo signal the filter processes.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StereoMode {
+    Stereo,
+    Mid,
+    Side,
+}
+
+/// Work done off the audio thread.
+pub enum EqualizerTask {
+    /// Analyzes the latest block of input and publishes its spectrum, unless the plugin was
+    /// reset since the analysis was requested.
+    AnalyzeSpectrum { generation: usize },
+}
+
 pub struct Equalizer {
     params: Arc<EqualizerParams>,
     biquad: StereoBiquadFilter,
+    /// Isolates the band the main filter acts on, for auditioning it with band solo
+    solo_biquad: StereoBiquadFilter,
     should_update_filter: Arc<AtomicBool>,
+    /// Only used on the background thread, which runs the analyzer's FFT
+    analyzer: Arc<Mutex<SpectrumAnalyzer>>,
+    /// The latest input, written on the audio thread and analyzed on the background thread
+    analyzer_input: Arc<SampleRing>,
+    /// How many samples were written to the analyzer's input since the last analysis
+    samples_since_analysis: usize,
+    /// The magnitude of each analyzer bin, in dBFS, to be read by an editor
+    spectrum: Arc<Vec<AtomicF32>>,
+    /// Counts resets, so an analysis requested before a reset doesn't publish stale magnitudes
+    analyzer_generation: Arc<AtomicUsize>,
+    /// Follows the level of the band isolated by the solo filter, for dynamic EQ
+    band_envelope: EnvelopeFollower,
+    /// Whether the filter's gain was last set by the dynamic EQ, to restore the static gain
+    /// once it's turned off
+    was_dynamic: bool,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -50,6 +119,24 @@ struct EqualizerParams {
 
     #[id = "filter-type"]
     pub filter_type: EnumParam<BiquadFilterTypeParam>,
+
+    #[id = "stereo-mode"]
+    pub stereo_mode: EnumParam<StereoMode>,
+
+    #[id = "band-solo"]
+    pub band_solo: BoolParam,
+
+    #[id = "dynamic"]
+    pub dynamic: BoolParam,
+
+    #[id = "threshold"]
+    pub threshold: FloatParam,
+
+    #[id = "analyzer"]
+    pub analyzer: BoolParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Equalizer {
@@ -60,6 +147,19 @@ impl Default for Equalizer {
             params,
             should_update_filter,
             biquad: StereoBiquadFilter::new(),
+            solo_biquad: StereoBiquadFilter::new(),
+            analyzer: Arc::new(Mutex::new(SpectrumAnalyzer::new(ANALYZER_FFT_SIZE))),
+            analyzer_input: Arc::new(SampleRing::new(ANALYZER_FFT_SIZE)),
+            samples_since_analysis: 0,
+            spectrum: Arc::new(
+                (0..ANALYZER_FFT_SIZE / 2 + 1)
+                    .map(|_| AtomicF32::new(util::MINUS_INFINITY_DB))
+                    .collect(),
+            ),
+            analyzer_generation: Arc::new(AtomicUsize::new(0)),
+            band_envelope: EnvelopeFollower::new(DEFAULT_SAMPLE_RATE as f32),
+            was_dynamic: false,
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -124,10 +224,48 @@ impl EqualizerParams {
                     move |_| should_update_filter.store(true, Ordering::SeqCst)
                 }),
             ),
+
+            stereo_mode: EnumParam::new("Stereo mode", StereoMode::Stereo),
+
+            band_solo: BoolParam::new("Band solo", false),
+
+            // Engages the gain only while the band's level is over the threshold
+            dynamic: BoolParam::new("Dynamic", false),
+
+            threshold: FloatParam::new(
+                "Threshold",
+                -20.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            analyzer: BoolParam::new("Analyzer", false),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
         }
     }
 }
 
+/// Publishes a silent spectrum.
+fn clear_spectrum(spectrum: &[AtomicF32]) {
+    for bin in spectrum.iter() {
+        bin.store(util::MINUS_INFINITY_DB, Ordering::Relaxed);
+    }
+}
+
+impl Equalizer {
+    /// Returns the analyzer's magnitudes in dBFS, from DC up to the Nyquist frequency.
+    /// These are only updated while the analyzer is enabled.
+    pub fn spectrum(&self) -> Arc<Vec<AtomicF32>> {
+        self.spectrum.clone()
+    }
+}
+
 impl Plugin for Equalizer {
     const NAME: &'static str = "Equalizer v0.0.13";
     const VENDOR: &'static str = "Renzo Ledesma";
@@ -162,15 +300,48 @@ impl Plugin for Equalizer {
     // More advanced plugins can use this to run expensive background tasks. See the field's
     // documentation for more information. `()` means that the plugin does not have any background
     // tasks.
-    type BackgroundTask = ();
+    type BackgroundTask = EqualizerTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let analyzer = self.analyzer.clone();
+        let analyzer_input = self.analyzer_input.clone();
+        let spectrum = self.spectrum.clone();
+        let analyzer_generation = self.analyzer_generation.clone();
+        Box::new(move |task| match task {
+            EqualizerTask::AnalyzeSpectrum { generation } => {
+                let mut analyzer = analyzer.lock().unwrap();
+                analyzer.analyze(&analyzer_input);
+                if analyzer_generation.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                for (bin, magnitude) in spectrum.iter().zip(analyzer.magnitudes()) {
+                    bin.store(util::gain_to_db(*magnitude), Ordering::Relaxed);
+                }
+
+                // A reset while publishing may have cleared the spectrum before these stores
+                // landed, so clear it again
+                if analyzer_generation.load(Ordering::SeqCst) != generation {
+                    clear_spectrum(&spectrum);
+                }
+            }
+        })
+    }
+
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.biquad.reset();
+        self.solo_biquad.reset();
+        self.analyzer_input.reset();
+        self.samples_since_analysis = 0;
+        self.analyzer_generation.fetch_add(1, Ordering::SeqCst);
+        clear_spectrum(&self.spectrum);
+        self.band_envelope.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
@@ -180,6 +351,16 @@ impl Plugin for Equalizer {
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         let sample_rate = _context.transport().sample_rate;
+        self.bypass.set_sample_rate(sample_rate);
+        self.band_envelope.set_sample_rate(sample_rate);
+        self.band_envelope
+            .set_times(DYNAMIC_ATTACK_SECONDS, DYNAMIC_RELEASE_SECONDS);
+
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
 
         // Check if we should update filter coefficients
         if self
@@ -196,34 +377,115 @@ impl Plugin for Equalizer {
             let gain_db = util::gain_to_db(gain);
             self.biquad
                 .set_biquads(eq_type_to_param(filter_type), fc, q, gain_db);
+            self.solo_biquad
+                .set_biquads(band_solo_type(filter_type), fc, q, 0.0);
         }
 
-        for mut channel_samples in buffer.iter_samples() {
-            // Update parameters while smoothing
+        // Recomputing the filter coefficients is the main cost, so process in short blocks and
+        // update the coefficients once per block, leaving only the filtering per sample
+        for (_, mut block) in buffer.iter_blocks(COEFFICIENT_UPDATE_INTERVAL) {
+            let block_len = block.samples() as u32;
+
+            // Update parameters while smoothing, skipping ahead to the end of the block
             if self.params.cutoff_frequency.smoothed.is_smoothing() {
-                let cutoff_frequency_smoothed = self.params.cutoff_frequency.smoothed.next();
+                let cutoff_frequency_smoothed =
+                    self.params.cutoff_frequency.smoothed.next_step(block_len);
                 let fc = cutoff_frequency_smoothed / sample_rate;
                 self.biquad.set_fc(fc);
+                self.solo_biquad.set_fc(fc);
             }
             if self.params.q.smoothed.is_smoothing() {
-                let q_smoothed = self.params.q.smoothed.next();
+                let q_smoothed = self.params.q.smoothed.next_step(block_len);
                 self.biquad.set_q(q_smoothed);
+                self.solo_biquad.set_q(q_smoothed);
             }
-            if self.params.gain.smoothed.is_smoothing() {
-                let gain_smoothed = self.params.gain.smoothed.next();
+            let dynamic = self.params.dynamic.value();
+            let threshold = self.params.threshold.smoothed.next_step(block_len);
+            if !dynamic && (self.params.gain.smoothed.is_smoothing() || self.was_dynamic) {
+                let gain_smoothed = self.params.gain.smoothed.next_step(block_len);
                 let gain_db = util::gain_to_db(gain_smoothed);
                 self.biquad.set_peak_gain(gain_db);
             }
 
-            // Process input
-            let sample_l = *channel_samples.get_mut(0).unwrap();
-            let sample_r = *channel_samples.get_mut(1).unwrap();
-            let input_samples = (sample_l, sample_r);
-
-            let processed_samples = self.biquad.process(input_samples);
-
-            *channel_samples.get_mut(0).unwrap() = processed_samples.0;
-            *channel_samples.get_mut(1).unwrap() = processed_samples.1;
+            // A dynamic band follows the level of the isolated band, applying the gain only
+            // while it's over the threshold, from the level at the end of the last block
+            if dynamic {
+                let gain_db = dynamic_eq_gain(
+                    util::gain_to_db(self.band_envelope.envelope()),
+                    threshold,
+                    util::gain_to_db(self.params.gain.smoothed.next_step(block_len)),
+                );
+                self.biquad.set_peak_gain(gain_db);
+            }
+            self.was_dynamic = dynamic;
+
+            for mut channel_samples in block.iter_samples() {
+                // Process input
+                let sample_l = *channel_samples.get_mut(0).unwrap();
+                let sample_r = *channel_samples.get_mut(1).unwrap();
+                let input_samples = (sample_l, sample_r);
+
+                // Band solo replaces the output with only the band the filter acts on, from the
+                // selected channel; the solo filter always runs so its state is settled when
+                // toggled
+                let solo_samples = match self.params.stereo_mode.value() {
+                    StereoMode::Stereo => self.solo_biquad.process(input_samples),
+                    StereoMode::Mid => {
+                        let (band_mid, _) =
+                            self.solo_biquad.process(encode_mid_side(input_samples));
+                        decode_mid_side((band_mid, 0.0))
+                    }
+                    StereoMode::Side => {
+                        let (_, band_side) =
+                            self.solo_biquad.process(encode_mid_side(input_samples));
+                        decode_mid_side((0.0, band_side))
+                    }
+                };
+                if dynamic {
+                    self.band_envelope
+                        .process(solo_samples.0.abs().max(solo_samples.1.abs()));
+                }
+
+                // Collect the input for the analyzer, analyzing it on the background thread
+                // whenever a full block is collected
+                if self.params.analyzer.value() {
+                    self.analyzer_input.push((sample_l + sample_r) * 0.5);
+                    self.samples_since_analysis += 1;
+                    if self.samples_since_analysis == ANALYZER_FFT_SIZE {
+                        self.samples_since_analysis = 0;
+                        _context.execute_background(EqualizerTask::AnalyzeSpectrum {
+                            generation: self.analyzer_generation.load(Ordering::SeqCst),
+                        });
+                    }
+                }
+
+                // In mid/side modes, the left filter tracks the mid channel and the right filter
+                // tracks the side channel, and only the selected channel is filtered
+                let processed_samples = match self.params.stereo_mode.value() {
+                    StereoMode::Stereo => self.biquad.process(input_samples),
+                    StereoMode::Mid => {
+                        let (mid, side) = encode_mid_side(input_samples);
+                        let (filtered_mid, _) = self.biquad.process((mid, side));
+                        decode_mid_side((filtered_mid, side))
+                    }
+                    StereoMode::Side => {
+                        let (mid, side) = encode_mid_side(input_samples);
+                        let (_, filtered_side) = self.biquad.process((mid, side));
+                        decode_mid_side((mid, filtered_side))
+                    }
+                };
+
+                let processed_samples = if self.params.band_solo.value() {
+                    solo_samples
+                } else {
+                    processed_samples
+                };
+
+                let (out_l, out_r) = self.bypass.tick(bypassed, input_samples, processed_samples);
+
+                *channel_samples.get_mut(0).unwrap() = out_l;
+                *channel_samples.get_mut(1).unwrap() = out_r;
+            }
         }
 
         ProcessStatus::Normal
@@ -0,0 +1,194 @@
+use fx::{bypass::BypassCrossfade, exciter::Exciter as HarmonicExciter, DEFAULT_SAMPLE_RATE};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+pub struct Exciter {
+    params: Arc<ExciterParams>,
+    exciters: (HarmonicExciter, HarmonicExciter),
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct ExciterParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "frequency"]
+    pub frequency: FloatParam,
+
+    #[id = "drive"]
+    pub drive: FloatParam,
+
+    #[id = "mix"]
+    pub mix: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for Exciter {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(ExciterParams::default()),
+            exciters: (
+                HarmonicExciter::new(DEFAULT_SAMPLE_RATE as f32),
+                HarmonicExciter::new(DEFAULT_SAMPLE_RATE as f32),
+            ),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for ExciterParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // The crossover frequency, above which the signal is excited
+            frequency: FloatParam::new(
+                "Frequency",
+                3_000.0,
+                FloatRange::Skewed {
+                    min: 1_000.0,
+                    max: 12_000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            drive: FloatParam::new("Drive", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            mix: FloatParam::new("Mix", 0.3, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for Exciter {
+    const NAME: &'static str = "Exciter v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.exciters.0.set_sample_rate(_buffer_config.sample_rate);
+        self.exciters.1.set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let frequency = self.params.frequency.smoothed.next();
+            let drive = self.params.drive.smoothed.next();
+            let mix = self.params.mix.smoothed.next();
+            for exciter in [&mut self.exciters.0, &mut self.exciters.1] {
+                exciter.set_frequency(frequency);
+                exciter.set_drive(drive);
+                exciter.set_mix(mix);
+            }
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+            let out_l = self.exciters.0.process(in_l);
+            let out_r = self.exciters.1.process(in_r);
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for Exciter {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("A harmonic exciter");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Filter,
+    ];
+}
+
+impl Vst3Plugin for Exciter {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2__exciter";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Filter];
+}
+
+nih_export_vst3!(Exciter);
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use exciter::Exciter;
+
+fn main() {
+    nih_export_standalone::<Exciter>();
+}
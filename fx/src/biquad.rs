@@ -1,5 +1,12 @@
+use crate::denormal::flush_denormal;
 use std::f32::consts::PI;
 
+/// The smallest Q used for coefficients, since a Q of 0 divides by zero
+const MIN_Q: f32 = 0.01;
+/// The normalized frequency range used for coefficients; at Nyquist the bilinear transform blows up
+const MIN_FC: f32 = 1e-5;
+const MAX_FC: f32 = 0.49;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum BiquadFilterType {
     LowPass,
@@ -9,13 +16,15 @@ pub enum BiquadFilterType {
     ParametricEQ,
     LowShelf,
     HighShelf,
+    AllPass,
 }
 
-/// A biquad filter implementation that supports 7 filter types: low pass, high pass,
-/// band pass, notch, parametric (peaking), low shelf, and high shelf. This implementation is
-/// written in transposed direct form II, with two unit delays.
+/// A biquad filter implementation that supports 8 filter types: low pass, high pass,
+/// band pass, notch, parametric (peaking), low shelf, high shelf, and all pass. This
+/// implementation is written in transposed direct form II, with two unit delays.
 ///
 /// Biquad filter code from: https://www.earlevel.com/main/2012/11/26/biquad-c-source-code/
+#[derive(Debug)]
 pub struct BiquadFilter {
     // Filter type & coefficients
     filter_type: BiquadFilterType,
@@ -87,60 +96,62 @@ impl BiquadFilter {
     }
 
     /// Recalculates coefficients according to the filter's current parameters.
+    /// Q and the center frequency are clamped to ranges where the filter stays stable.
     pub fn calculate_biquad_coefficients(&mut self) {
         let v = 10.0_f32.powf(self.peak_gain.abs() / 20.0);
-        let k = (PI * self.fc).tan();
+        let k = (PI * self.fc.clamp(MIN_FC, MAX_FC)).tan();
+        let q = self.q.max(MIN_Q);
 
         match self.filter_type {
             BiquadFilterType::LowPass => {
-                let norm = (1.0 + k / self.q + k * k).recip();
+                let norm = (1.0 + k / q + k * k).recip();
                 self.a0 = k * k * norm;
                 self.a1 = 2.0 * self.a0;
                 self.a2 = self.a0;
                 self.b1 = 2.0 * (k * k - 1.0) * norm;
-                self.b2 = (1.0 - k / self.q + k * k) * norm;
+                self.b2 = (1.0 - k / q + k * k) * norm;
             }
             BiquadFilterType::HighPass => {
-                let norm = (1.0 + k / self.q + k * k).recip();
+                let norm = (1.0 + k / q + k * k).recip();
                 self.a0 = 1.0 * norm;
                 self.a1 = -2.0 * self.a0;
                 self.a2 = self.a0;
                 self.b1 = 2.0 * (k * k - 1.0) * norm;
-                self.b2 = (1.0 - k / self.q + k * k) * norm;
+                self.b2 = (1.0 - k / q + k * k) * norm;
             }
             BiquadFilterType::BandPass => {
-                let norm = (1.0 + k / self.q + k * k).recip();
-                self.a0 = k / self.q * norm;
+                let norm = (1.0 + k / q + k * k).recip();
+                self.a0 = k / q * norm;
                 self.a1 = 0.0;
                 self.a2 = -self.a0;
                 self.b1 = 2.0 * (k * k - 1.0) * norm;
-                self.b2 = (1.0 - k / self.q + k * k) * norm;
+                self.b2 = (1.0 - k / q + k * k) * norm;
             }
             BiquadFilterType::Notch => {
-                let norm = (1.0 + k / self.q + k * k).recip();
+                let norm = (1.0 + k / q + k * k).recip();
                 self.a0 = (1.0 + k * k) * norm;
                 self.a1 = 2.0 * (k * k - 1.0) * norm;
                 self.a2 = self.a0;
                 self.b1 = self.a1;
-                self.b2 = (1.0 - k / self.q + k * k) * norm;
+                self.b2 = (1.0 - k / q + k * k) * norm;
             }
             BiquadFilterType::ParametricEQ => {
                 if self.peak_gain >= 0.0 {
                     // boost
-                    let norm = (1.0 + self.q.recip() * k + k * k).recip();
-                    self.a0 = (1.0 + v / self.q * k + k * k) * norm;
+                    let norm = (1.0 + q.recip() * k + k * k).recip();
+                    self.a0 = (1.0 + v / q * k + k * k) * norm;
                     self.a1 = 2.0 * (k * k - 1.0) * norm;
-                    self.a2 = (1.0 - v / self.q * k + k * k) * norm;
+                    self.a2 = (1.0 - v / q * k + k * k) * norm;
                     self.b1 = self.a1;
-                    self.b2 = (1.0 - self.q.recip() * k + k * k) * norm;
+                    self.b2 = (1.0 - q.recip() * k + k * k) * norm;
                 } else {
                     // cut
-                    let norm = (1.0 + v / self.q * k + k * k).recip();
-                    self.a0 = (1.0 + self.q.recip() * k + k * k) * norm;
+                    let norm = (1.0 + v / q * k + k * k).recip();
+                    self.a0 = (1.0 + q.recip() * k + k * k) * norm;
                     self.a1 = 2.0 * (k * k - 1.0) * norm;
-                    self.a2 = (1.0 - self.q.recip() * k + k * k) * norm;
+                    self.a2 = (1.0 - q.recip() * k + k * k) * norm;
                     self.b1 = self.a1;
-                    self.b2 = (1.0 - v / self.q * k + k * k) * norm;
+                    self.b2 = (1.0 - v / q * k + k * k) * norm;
                 }
             }
             BiquadFilterType::LowShelf => {
@@ -181,13 +192,29 @@ impl BiquadFilter {
                     self.b2 = (v - (2.0 * v).sqrt() * k + k * k) * norm;
                 }
             }
+            BiquadFilterType::AllPass => {
+                // Unity gain everywhere, with the phase turning through -180 degrees at the
+                // cutoff, more quickly for higher Q
+                let norm = (1.0 + k / q + k * k).recip();
+                self.a0 = (1.0 - k / q + k * k) * norm;
+                self.a1 = 2.0 * (k * k - 1.0) * norm;
+                self.a2 = 1.0;
+                self.b1 = self.a1;
+                self.b2 = self.a0;
+            }
         }
     }
 
+    /// Clears the unit delays, so a ringing filter falls silent without changing its settings.
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
     pub fn process(&mut self, input: f32) -> f32 {
         let output = input * self.a0 + self.z1;
-        self.z1 = input * self.a1 + self.z2 - self.b1 * output;
-        self.z2 = input * self.a2 - self.b2 * output;
+        self.z1 = flush_denormal(input * self.a1 + self.z2 - self.b1 * output);
+        self.z2 = flush_denormal(input * self.a2 - self.b2 * output);
         output
     }
 }
@@ -236,9 +263,117 @@ impl StereoBiquadFilter {
         self.filter_r.set_peak_gain(peak_gain);
     }
 
+    /// Clears both filters' unit delays.
+    pub fn reset(&mut self) {
+        self.filter_l.reset();
+        self.filter_r.reset();
+    }
+
     pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
         let out_l = self.filter_l.process(input.0);
         let out_r = self.filter_r.process(input.1);
         (out_l, out_r)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Measures the steady-state gain of a filter for a sine at a given normalized frequency.
+    ///
+    fn measure_gain(filter: &mut BiquadFilter, fc: f32) -> f32 {
+        let length = 20_000;
+        let (mut input_power, mut output_power) = (0.0, 0.0);
+        for n in 0..length {
+            let input = (2.0 * PI * fc * n as f32).sin();
+            let output = filter.process(input);
+            if n >= length / 2 {
+                input_power += input * input;
+                output_power += output * output;
+            }
+        }
+        (output_power / input_power).sqrt()
+    }
+
+    #[test]
+    fn parametric_eq_at_unity_gain_passes_signal_through() {
+        let mut filter = BiquadFilter::new();
+        filter.set_biquad(BiquadFilterType::ParametricEQ, 0.05, 1.0, 0.0);
+        for n in 0..1_000 {
+            let input = (n as f32 * 0.37).sin();
+            assert!((filter.process(input) - input).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn reset_clears_the_filter_state() {
+        let mut filter = BiquadFilter::new();
+        filter.set_biquad(BiquadFilterType::LowPass, 0.01, 0.707, 0.0);
+        for _ in 0..100 {
+            filter.process(1.0);
+        }
+        filter.reset();
+        assert_eq!(filter.process(0.0), 0.0);
+    }
+
+    #[test]
+    fn parametric_eq_boosts_and_cuts_at_center_frequency() {
+        let fc = 0.05;
+        for peak_gain in [-12.0, 6.0, 12.0] {
+            let mut filter = BiquadFilter::new();
+            filter.set_biquad(BiquadFilterType::ParametricEQ, fc, 1.0, peak_gain);
+            let gain_db = 20.0 * measure_gain(&mut filter, fc).log10();
+            assert!((gain_db - peak_gain).abs() < 0.1);
+        }
+    }
+
+    /// The filter's magnitude and phase at a normalized frequency, from its coefficients.
+    fn frequency_response(filter: &BiquadFilter, fc: f32) -> (f32, f32) {
+        let omega = 2.0 * PI * fc;
+        let (sin, cos) = omega.sin_cos();
+        let (sin2, cos2) = (2.0 * omega).sin_cos();
+        let numerator = (
+            filter.a0 + filter.a1 * cos + filter.a2 * cos2,
+            -filter.a1 * sin - filter.a2 * sin2,
+        );
+        let denominator = (
+            1.0 + filter.b1 * cos + filter.b2 * cos2,
+            -filter.b1 * sin - filter.b2 * sin2,
+        );
+        let magnitude = numerator.0.hypot(numerator.1) / denominator.0.hypot(denominator.1);
+        let phase = numerator.1.atan2(numerator.0) - denominator.1.atan2(denominator.0);
+        (magnitude, phase)
+    }
+
+    #[test]
+    fn all_pass_is_flat_while_phase_rotates() {
+        let fc = 0.05;
+        let mut filter = BiquadFilter::new();
+        filter.set_biquad(BiquadFilterType::AllPass, fc, 0.707, 0.0);
+        for n in 1..100 {
+            let (magnitude, _) = frequency_response(&filter, n as f32 * 0.0049);
+            assert!((magnitude - 1.0).abs() < 1e-4);
+        }
+
+        // The phase barely moves well below the cutoff, and is turned halfway around at it
+        let (_, phase) = frequency_response(&filter, fc / 100.0);
+        assert!(phase.abs() < 0.1);
+        let (_, phase) = frequency_response(&filter, fc);
+        assert!((phase.abs() - PI).abs() < 1e-3);
+
+        // Measured through processing, a sine keeps its level
+        assert!((measure_gain(&mut filter, 0.02) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn out_of_range_q_and_fc_stay_stable() {
+        let mut filter = BiquadFilter::new();
+        filter.set_biquad(BiquadFilterType::ParametricEQ, 0.5, 0.0, 12.0);
+        for n in 0..10_000 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            assert!(filter.process(input).is_finite());
+        }
+    }
+}
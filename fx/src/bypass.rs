@@ -0,0 +1,93 @@
+/// How long the crossfade between the processed and unprocessed signal takes, in seconds
+pub const BYPASS_CROSSFADE_SECONDS: f32 = 0.01;
+
+///
+/// A click-free bypass that linearly crossfades between the processed (wet) and unprocessed (dry)
+/// signal whenever the bypass is toggled.
+///
+/// Once the crossfade into bypass has finished, `is_bypassed` reports it so callers can skip their
+/// processing entirely.
+///
+pub struct BypassCrossfade {
+    step: f32,
+    wet_amount: f32,
+}
+
+impl BypassCrossfade {
+    pub fn new(sample_rate: f32) -> BypassCrossfade {
+        BypassCrossfade {
+            step: (BYPASS_CROSSFADE_SECONDS * sample_rate).recip(),
+            wet_amount: 1.,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.step = (BYPASS_CROSSFADE_SECONDS * sample_rate).recip();
+    }
+
+    /// Jumps straight to the end of the crossfade, e.g. when the plugin is reset.
+    pub fn reset(&mut self, bypassed: bool) {
+        self.wet_amount = if bypassed { 0. } else { 1. };
+    }
+
+    ///
+    /// Returns true when bypassed and the crossfade has finished, meaning the output is
+    /// entirely the unprocessed signal.
+    ///
+    /// # Arguments
+    /// * `bypassed` - whether the bypass is currently engaged
+    ///
+    pub fn is_bypassed(&self, bypassed: bool) -> bool {
+        bypassed && self.wet_amount == 0.
+    }
+
+    ///
+    /// Advances the crossfade by one sample and mixes a stereo frame.
+    ///
+    /// # Arguments
+    /// * `bypassed` - whether the bypass is currently engaged
+    /// * `dry` - the unprocessed frame
+    /// * `wet` - the processed frame
+    ///
+    pub fn tick(&mut self, bypassed: bool, dry: (f32, f32), wet: (f32, f32)) -> (f32, f32) {
+        self.wet_amount = if bypassed {
+            (self.wet_amount - self.step).max(0.)
+        } else {
+            (self.wet_amount + self.step).min(1.)
+        };
+
+        (
+            dry.0 + (wet.0 - dry.0) * self.wet_amount,
+            dry.1 + (wet.1 - dry.1) * self.wet_amount,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bypass_crossfades_over_fade_time() {
+        let sample_rate = 1000.;
+        let fade_length = (BYPASS_CROSSFADE_SECONDS * sample_rate) as usize;
+        let mut bypass = BypassCrossfade::new(sample_rate);
+        let (dry, wet) = ((1., -1.), (0., 0.));
+
+        // Not bypassed, only the processed signal is heard
+        assert_eq!(bypass.tick(false, dry, wet), wet);
+
+        // Engaging the bypass fades smoothly to the unprocessed signal
+        let mut previous = wet;
+        for _ in 0..fade_length {
+            assert!(!bypass.is_bypassed(true));
+            let output = bypass.tick(true, dry, wet);
+            assert!(output.0 > previous.0 && output.0 - previous.0 <= 0.1 + 1e-6);
+            previous = output;
+        }
+        assert!((previous.0 - dry.0).abs() < 1e-6);
+        assert!((previous.1 - dry.1).abs() < 1e-6);
+        assert!(bypass.is_bypassed(true));
+        assert!(!bypass.is_bypassed(false));
+    }
+}
@@ -0,0 +1,465 @@
+use crate::spectrum::{fft, Twiddles};
+use std::fmt;
+
+/// The number of samples in each partition of the impulse response, which sets how often the
+/// FFT convolution runs
+pub const DEFAULT_PARTITION_SIZE: usize = 128;
+
+/// A reason a WAV file couldn't be read as an impulse response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavError {
+    /// The data isn't a RIFF WAVE file.
+    NotWav,
+    /// The file ends before a chunk it declares.
+    Truncated,
+    /// The samples aren't 16, 24, or 32-bit PCM, or 32-bit float.
+    UnsupportedFormat,
+    /// The file has no samples.
+    Empty,
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            WavError::NotWav => "not a WAV file",
+            WavError::Truncated => "the WAV file is truncated",
+            WavError::UnsupportedFormat => {
+                "only 16, 24, and 32-bit PCM and 32-bit float WAV files are supported"
+            }
+            WavError::Empty => "the WAV file has no samples",
+        };
+        f.write_str(message)
+    }
+}
+
+///
+/// A stereo impulse response, such as a recording of a room's response to a click. Mono
+/// impulse responses are used for both channels.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImpulseResponse {
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+    pub sample_rate: f32,
+}
+
+impl ImpulseResponse {
+    ///
+    /// Decodes an impulse response from the bytes of a WAV file. Only the first two channels
+    /// are used.
+    ///
+    /// # Arguments
+    /// * `bytes` - the contents of the WAV file
+    ///
+    pub fn from_wav(bytes: &[u8]) -> Result<ImpulseResponse, WavError> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWav);
+        }
+
+        // Walk the chunks for the format and the samples, which are padded to even lengths
+        let mut format = None;
+        let mut data = None;
+        let mut position = 12;
+        while position + 8 <= bytes.len() {
+            let id = &bytes[position..position + 4];
+            let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap());
+            let start = position + 8;
+            let end = start
+                .checked_add(size as usize)
+                .ok_or(WavError::Truncated)?;
+            if id == b"fmt " {
+                format = Some(bytes.get(start..end).ok_or(WavError::Truncated)?);
+            } else if id == b"data" {
+                // Some writers leave the data size unset, so read to the end of the file
+                data = Some(bytes.get(start..end.min(bytes.len())).unwrap_or(&[]));
+            }
+            position = end + (size as usize & 1);
+        }
+        let format = format.ok_or(WavError::NotWav)?;
+        let data = data.ok_or(WavError::Empty)?;
+        if format.len() < 16 {
+            return Err(WavError::Truncated);
+        }
+
+        // The extensible format keeps the actual format in the first bytes of its subformat
+        let mut format_tag = u16::from_le_bytes([format[0], format[1]]);
+        if format_tag == 0xFFFE && format.len() >= 26 {
+            format_tag = u16::from_le_bytes([format[24], format[25]]);
+        }
+        let channels = u16::from_le_bytes([format[2], format[3]]) as usize;
+        let sample_rate = u32::from_le_bytes(format[4..8].try_into().unwrap()) as f32;
+        let bits_per_sample = u16::from_le_bytes([format[14], format[15]]);
+
+        let bytes_per_sample = bits_per_sample as usize / 8;
+        let decode: fn(&[u8]) -> f32 = match (format_tag, bits_per_sample) {
+            (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0,
+            (1, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.0,
+            (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+            (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => return Err(WavError::UnsupportedFormat),
+        };
+        if channels == 0 || sample_rate <= 0.0 {
+            return Err(WavError::UnsupportedFormat);
+        }
+
+        let frame_size = channels * bytes_per_sample;
+        let frames = data.chunks_exact(frame_size);
+        if frames.len() == 0 {
+            return Err(WavError::Empty);
+        }
+        let left: Vec<f32> = frames.clone().map(decode).collect();
+        let right = if channels > 1 {
+            frames
+                .map(|frame| decode(&frame[bytes_per_sample..]))
+                .collect()
+        } else {
+            left.clone()
+        };
+
+        Ok(ImpulseResponse {
+            left,
+            right,
+            sample_rate,
+        })
+    }
+
+    /// The length of the impulse response, in samples.
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    ///
+    /// Returns the impulse response at another sample rate, with linear interpolation. The
+    /// response is scaled so its level stays the same.
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the new sample rate, in samples per second
+    ///
+    pub fn resampled(&self, sample_rate: f32) -> ImpulseResponse {
+        if sample_rate == self.sample_rate || self.is_empty() {
+            return ImpulseResponse {
+                sample_rate,
+                ..self.clone()
+            };
+        }
+
+        let ratio = self.sample_rate / sample_rate;
+        let length = ((self.len() as f32 / ratio).ceil() as usize).max(1);
+        let resample = |channel: &[f32]| -> Vec<f32> {
+            (0..length)
+                .map(|n| {
+                    let position = n as f32 * ratio;
+                    let index = position.floor() as usize;
+                    let fraction = position.fract();
+                    let x0 = channel.get(index).copied().unwrap_or(0.0);
+                    let x1 = channel.get(index + 1).copied().unwrap_or(0.0);
+                    (x0 + (x1 - x0) * fraction) * ratio
+                })
+                .collect()
+        };
+
+        ImpulseResponse {
+            left: resample(&self.left),
+            right: resample(&self.right),
+            sample_rate,
+        }
+    }
+
+    ///
+    /// Shortens the impulse response to at most a given length, fading out the end.
+    ///
+    /// # Arguments
+    /// * `length` - the longest length, in samples
+    ///
+    pub fn truncate(&mut self, length: usize) {
+        if self.len() <= length {
+            return;
+        }
+        let fade_length = (length / 10).max(1);
+        for channel in [&mut self.left, &mut self.right] {
+            channel.truncate(length);
+            for (n, sample) in channel.iter_mut().rev().take(fade_length).enumerate() {
+                *sample *= n as f32 / fade_length as f32;
+            }
+        }
+    }
+
+    ///
+    /// Scales the impulse response so its louder channel has unit energy, which keeps the level
+    /// of broadband sounds about the same as the input regardless of how loudly it was recorded.
+    ///
+    pub fn normalize(&mut self) {
+        let energy = |channel: &[f32]| channel.iter().map(|x| x * x).sum::<f32>();
+        let energy = energy(&self.left).max(energy(&self.right));
+        if energy > 0.0 {
+            let gain = energy.sqrt().recip();
+            for sample in self.left.iter_mut().chain(self.right.iter_mut()) {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+/// A spectrum as separate real and imaginary parts.
+#[derive(Clone)]
+struct Spectrum {
+    real: Vec<f32>,
+    imag: Vec<f32>,
+}
+
+impl Spectrum {
+    fn new(fft_size: usize) -> Spectrum {
+        Spectrum {
+            real: vec![0.0; fft_size],
+            imag: vec![0.0; fft_size],
+        }
+    }
+
+    /// Sets the spectrum to the FFT of a signal, zero-padded to the FFT size.
+    fn set_from_signal(&mut self, signal: &[f32], twiddles: &Twiddles) {
+        self.real.fill(0.0);
+        self.imag.fill(0.0);
+        self.real[..signal.len()].copy_from_slice(signal);
+        fft(&mut self.real, &mut self.imag, twiddles);
+    }
+}
+
+///
+/// Convolves a signal with an impulse response without latency, using uniformly partitioned
+/// FFT convolution.
+///
+/// The impulse response is cut into partitions. The first partition is convolved directly,
+/// sample by sample, so the output isn't delayed. The rest are convolved block by block in the
+/// frequency domain, where each block's spectrum is kept and multiplied with every later
+/// partition's spectrum, so long impulse responses stay cheap. Each block's result is only
+/// needed a block later, hidden behind the first partition.
+///
+/// All buffers and the FFT's twiddle factors are allocated up front, so the convolver can run on
+/// the audio thread once it's created.
+///
+pub struct Convolver {
+    partition_size: usize,
+    head: Vec<f32>,
+    partitions: Vec<Spectrum>,
+    input: Vec<f32>,
+    position: usize,
+    input_spectra: Vec<Spectrum>,
+    newest_spectrum: usize,
+    accumulator: Spectrum,
+    tail: Vec<f32>,
+    twiddles: Twiddles,
+}
+
+impl Convolver {
+    ///
+    /// # Arguments
+    /// * `impulse_response` - the impulse response to convolve with
+    /// * `partition_size` - the number of samples in each partition; must be a power of two
+    ///
+    pub fn new(impulse_response: &[f32], partition_size: usize) -> Convolver {
+        assert!(partition_size.is_power_of_two());
+        let fft_size = 2 * partition_size;
+        let twiddles = Twiddles::new(fft_size);
+
+        let mut chunks = impulse_response.chunks(partition_size);
+        let head = chunks.next().unwrap_or(&[]).to_vec();
+        let partitions: Vec<Spectrum> = chunks
+            .map(|chunk| {
+                let mut spectrum = Spectrum::new(fft_size);
+                spectrum.set_from_signal(chunk, &twiddles);
+                spectrum
+            })
+            .collect();
+
+        Convolver {
+            partition_size,
+            head,
+            input_spectra: vec![Spectrum::new(fft_size); partitions.len()],
+            partitions,
+            input: vec![0.0; fft_size],
+            position: 0,
+            newest_spectrum: 0,
+            accumulator: Spectrum::new(fft_size),
+            tail: vec![0.0; partition_size],
+            twiddles,
+        }
+    }
+
+    /// Clears the convolver's memory of past input.
+    pub fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.position = 0;
+        for spectrum in self.input_spectra.iter_mut() {
+            spectrum.real.fill(0.0);
+            spectrum.imag.fill(0.0);
+        }
+        self.tail.fill(0.0);
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        // The input holds the previous block followed by the current one
+        let n = self.partition_size + self.position;
+        self.input[n] = input;
+
+        // Convolve the first partition directly
+        let head: f32 = self
+            .head
+            .iter()
+            .enumerate()
+            .map(|(k, h)| h * self.input[n - k])
+            .sum();
+        let output = head + self.tail[self.position];
+
+        self.position += 1;
+        if self.position == self.partition_size {
+            self.position = 0;
+            self.convolve_block();
+        }
+
+        output
+    }
+
+    ///
+    /// Convolves the last two blocks of input with the later partitions, leaving the part of
+    /// the result that lands in the next block in the tail.
+    ///
+    fn convolve_block(&mut self) {
+        let fft_size = self.input.len();
+        if !self.partitions.is_empty() {
+            // Keep the newest block's spectrum in place of the oldest
+            self.newest_spectrum = (self.newest_spectrum + 1) % self.input_spectra.len();
+            self.input_spectra[self.newest_spectrum].set_from_signal(&self.input, &self.twiddles);
+
+            // Partition p is delayed by p blocks, so it meets the spectrum from p - 1 blocks ago
+            let accumulator = &mut self.accumulator;
+            accumulator.real.fill(0.0);
+            accumulator.imag.fill(0.0);
+            let spectra_count = self.input_spectra.len();
+            for (p, partition) in self.partitions.iter().enumerate() {
+                let index = (self.newest_spectrum + spectra_count - p) % spectra_count;
+                let input = &self.input_spectra[index];
+                for k in 0..fft_size {
+                    let (a, b) = (input.real[k], input.imag[k]);
+                    let (c, d) = (partition.real[k], partition.imag[k]);
+                    accumulator.real[k] += a * c - b * d;
+                    accumulator.imag[k] += a * d + b * c;
+                }
+            }
+
+            // Inverse FFT by conjugating before and after a forward FFT. The first half of the
+            // result wraps around, so only the second half is kept
+            for imag in accumulator.imag.iter_mut() {
+                *imag = -*imag;
+            }
+            fft(&mut accumulator.real, &mut accumulator.imag, &self.twiddles);
+            let scale = (fft_size as f32).recip();
+            for (tail, real) in self
+                .tail
+                .iter_mut()
+                .zip(&accumulator.real[self.partition_size..])
+            {
+                *tail = real * scale;
+            }
+        }
+
+        // Slide the current block into the previous block's place
+        self.input.copy_within(self.partition_size.., 0);
+    }
+}
+
+///
+/// Convolves each channel of a stereo signal with its channel of an impulse response.
+///
+pub struct StereoConvolver {
+    left: Convolver,
+    right: Convolver,
+}
+
+impl StereoConvolver {
+    ///
+    /// # Arguments
+    /// * `impulse_response` - the impulse response to convolve with, at the processing rate
+    /// * `partition_size` - the number of samples in each partition; must be a power of two
+    ///
+    pub fn new(impulse_response: &ImpulseResponse, partition_size: usize) -> StereoConvolver {
+        StereoConvolver {
+            left: Convolver::new(&impulse_response.left, partition_size),
+            right: Convolver::new(&impulse_response.right, partition_size),
+        }
+    }
+
+    /// Clears the convolver's memory of past input.
+    pub fn reset(&mut self) {
+        self.left.reset();
+        self.right.reset();
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        (self.left.process(input.0), self.right.process(input.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitioned_convolution_matches_direct_convolution() {
+        let impulse_response: Vec<f32> = (0..1_000)
+            .map(|n| (n as f32 * 0.37).sin() * (-(n as f32) / 300.0).exp())
+            .collect();
+        let input: Vec<f32> = (0..3_000).map(|n| (n as f32 * 0.11).cos()).collect();
+
+        let mut convolver = Convolver::new(&impulse_response, 64);
+        for (n, x) in input.iter().enumerate() {
+            let expected: f32 = impulse_response
+                .iter()
+                .enumerate()
+                .take(n + 1)
+                .map(|(k, h)| h * input[n - k])
+                .sum();
+            assert!((convolver.process(*x) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn wav_files_decode_to_impulse_responses() {
+        // A stereo 16-bit WAV with an impulse on the left and half an impulse on the right
+        let samples: [i16; 4] = [i16::MAX, i16::MAX / 2, 0, 0];
+        let data: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16_u32.to_le_bytes());
+        wav.extend_from_slice(&1_u16.to_le_bytes());
+        wav.extend_from_slice(&2_u16.to_le_bytes());
+        wav.extend_from_slice(&48_000_u32.to_le_bytes());
+        wav.extend_from_slice(&(48_000_u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&4_u16.to_le_bytes());
+        wav.extend_from_slice(&16_u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+
+        let impulse_response = ImpulseResponse::from_wav(&wav).unwrap();
+        assert_eq!(impulse_response.sample_rate, 48_000.0);
+        assert_eq!(impulse_response.len(), 2);
+        assert!((impulse_response.left[0] - 1.0).abs() < 1e-4);
+        assert!((impulse_response.right[0] - 0.5).abs() < 1e-4);
+
+        assert_eq!(
+            ImpulseResponse::from_wav(b"not a wav"),
+            Err(WavError::NotWav)
+        );
+        wav[20] = 2;
+        assert_eq!(
+            ImpulseResponse::from_wav(&wav),
+            Err(WavError::UnsupportedFormat)
+        );
+    }
+}
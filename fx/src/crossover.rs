@@ -0,0 +1,319 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+
+/// Butterworth Q; two cascaded Butterworth filters make a Linkwitz-Riley filter.
+const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// A 4th order Linkwitz-Riley low pass or high pass filter, made of two cascaded
+/// 2nd order Butterworth filters.
+struct LinkwitzRileyFilter {
+    filters: [BiquadFilter; 2],
+}
+
+impl LinkwitzRileyFilter {
+    fn new(filter_type: BiquadFilterType, fc: f32) -> LinkwitzRileyFilter {
+        let mut filters = [BiquadFilter::new(), BiquadFilter::new()];
+        for filter in filters.iter_mut() {
+            filter.set_biquad(filter_type, fc, BUTTERWORTH_Q, 0.0);
+        }
+        LinkwitzRileyFilter { filters }
+    }
+
+    fn set_fc(&mut self, fc: f32) {
+        for filter in self.filters.iter_mut() {
+            filter.set_fc(fc);
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.filters[0].process(input);
+        self.filters[1].process(output)
+    }
+}
+
+/// An allpass filter with the same phase response as a Linkwitz-Riley crossover,
+/// made by summing a low pass and high pass pair.
+struct LinkwitzRileyAllpass {
+    low_pass: LinkwitzRileyFilter,
+    high_pass: LinkwitzRileyFilter,
+}
+
+impl LinkwitzRileyAllpass {
+    fn new(fc: f32) -> LinkwitzRileyAllpass {
+        LinkwitzRileyAllpass {
+            low_pass: LinkwitzRileyFilter::new(BiquadFilterType::LowPass, fc),
+            high_pass: LinkwitzRileyFilter::new(BiquadFilterType::HighPass, fc),
+        }
+    }
+
+    fn set_fc(&mut self, fc: f32) {
+        self.low_pass.set_fc(fc);
+        self.high_pass.set_fc(fc);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.low_pass.process(input) + self.high_pass.process(input)
+    }
+}
+
+/// A Linkwitz-Riley crossover that splits a signal into `N` bands, from low to high, whose
+/// sum reconstructs the input with a flat magnitude response.
+///
+/// The bands are split one crossover frequency at a time, from lowest to highest. Each lower
+/// band then runs through an allpass filter for every higher crossover, so all bands share
+/// the same phase response.
+pub struct Crossover<const N: usize> {
+    low_passes: Vec<LinkwitzRileyFilter>,
+    high_passes: Vec<LinkwitzRileyFilter>,
+    /// Phase compensation for each band below each crossover, as (band, crossover) pairs
+    allpasses: Vec<(usize, usize, LinkwitzRileyAllpass)>,
+}
+
+impl<const N: usize> Crossover<N> {
+    ///
+    /// Creates a new crossover.
+    ///
+    /// # Arguments
+    /// * `frequencies` - the `N - 1` crossover frequencies in ascending order, in Hz
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(frequencies: &[f32], sample_rate: f32) -> Crossover<N> {
+        assert!(N >= 2);
+        assert_eq!(frequencies.len(), N - 1);
+
+        let fcs: Vec<f32> = frequencies.iter().map(|f| f / sample_rate).collect();
+        Crossover {
+            low_passes: fcs
+                .iter()
+                .map(|fc| LinkwitzRileyFilter::new(BiquadFilterType::LowPass, *fc))
+                .collect(),
+            high_passes: fcs
+                .iter()
+                .map(|fc| LinkwitzRileyFilter::new(BiquadFilterType::HighPass, *fc))
+                .collect(),
+            allpasses: (0..N - 1)
+                .flat_map(|crossover| (0..crossover).map(move |band| (band, crossover)))
+                .map(|(band, crossover)| {
+                    (band, crossover, LinkwitzRileyAllpass::new(fcs[crossover]))
+                })
+                .collect(),
+        }
+    }
+
+    ///
+    /// Sets one of the crossover frequencies.
+    ///
+    /// # Arguments
+    /// * `index` - which crossover to set, from 0 (lowest) to `N - 2` (highest)
+    /// * `frequency` - the crossover frequency, in Hz
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn set_frequency(&mut self, index: usize, frequency: f32, sample_rate: f32) {
+        let fc = frequency / sample_rate;
+        self.low_passes[index].set_fc(fc);
+        self.high_passes[index].set_fc(fc);
+        for (_, crossover, allpass) in self.allpasses.iter_mut() {
+            if *crossover == index {
+                allpass.set_fc(fc);
+            }
+        }
+    }
+
+    ///
+    /// Splits an input sample into `N` bands, from lowest to highest.
+    ///
+    pub fn split(&mut self, input: f32) -> [f32; N] {
+        let mut bands = [0.0; N];
+        let mut rest = input;
+
+        for (crossover, (low_pass, high_pass)) in self
+            .low_passes
+            .iter_mut()
+            .zip(self.high_passes.iter_mut())
+            .enumerate()
+        {
+            bands[crossover] = low_pass.process(rest);
+            rest = high_pass.process(rest);
+        }
+        bands[N - 1] = rest;
+
+        for (band, _, allpass) in self.allpasses.iter_mut() {
+            bands[*band] = allpass.process(bands[*band]);
+        }
+
+        bands
+    }
+}
+
+/// A chain of allpass filters, one at each crossover frequency, with the same phase response
+/// as the sum of a `Crossover`'s bands. Running an unsplit signal through it, such as the dry
+/// signal of a multiband effect, keeps it in phase with the recombined bands, so mixing the two
+/// doesn't comb filter around the crossovers.
+pub struct CrossoverAllpass {
+    allpasses: Vec<LinkwitzRileyAllpass>,
+}
+
+impl CrossoverAllpass {
+    ///
+    /// Creates a new crossover allpass.
+    ///
+    /// # Arguments
+    /// * `frequencies` - the crossover frequencies of the matching crossover, in Hz
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(frequencies: &[f32], sample_rate: f32) -> CrossoverAllpass {
+        CrossoverAllpass {
+            allpasses: frequencies
+                .iter()
+                .map(|f| LinkwitzRileyAllpass::new(f / sample_rate))
+                .collect(),
+        }
+    }
+
+    /// Sets one of the crossover frequencies, in Hz, like `Crossover::set_frequency`.
+    pub fn set_frequency(&mut self, index: usize, frequency: f32, sample_rate: f32) {
+        self.allpasses[index].set_fc(frequency / sample_rate);
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.allpasses
+            .iter_mut()
+            .fold(input, |output, allpass| allpass.process(output))
+    }
+}
+
+/// A pair of crossover allpasses for stereo frames.
+pub struct StereoCrossoverAllpass {
+    allpass_l: CrossoverAllpass,
+    allpass_r: CrossoverAllpass,
+}
+
+impl StereoCrossoverAllpass {
+    pub fn new(frequencies: &[f32], sample_rate: f32) -> StereoCrossoverAllpass {
+        StereoCrossoverAllpass {
+            allpass_l: CrossoverAllpass::new(frequencies, sample_rate),
+            allpass_r: CrossoverAllpass::new(frequencies, sample_rate),
+        }
+    }
+
+    pub fn set_frequency(&mut self, index: usize, frequency: f32, sample_rate: f32) {
+        self.allpass_l.set_frequency(index, frequency, sample_rate);
+        self.allpass_r.set_frequency(index, frequency, sample_rate);
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        (
+            self.allpass_l.process(input.0),
+            self.allpass_r.process(input.1),
+        )
+    }
+}
+
+/// A pair of crossovers for splitting stereo frames into `N` bands.
+pub struct StereoCrossover<const N: usize> {
+    crossover_l: Crossover<N>,
+    crossover_r: Crossover<N>,
+}
+
+impl<const N: usize> StereoCrossover<N> {
+    pub fn new(frequencies: &[f32], sample_rate: f32) -> StereoCrossover<N> {
+        StereoCrossover {
+            crossover_l: Crossover::new(frequencies, sample_rate),
+            crossover_r: Crossover::new(frequencies, sample_rate),
+        }
+    }
+
+    pub fn set_frequency(&mut self, index: usize, frequency: f32, sample_rate: f32) {
+        self.crossover_l
+            .set_frequency(index, frequency, sample_rate);
+        self.crossover_r
+            .set_frequency(index, frequency, sample_rate);
+    }
+
+    pub fn split(&mut self, input: (f32, f32)) -> [(f32, f32); N] {
+        let bands_l = self.crossover_l.split(input.0);
+        let bands_r = self.crossover_r.split(input.1);
+
+        let mut bands = [(0.0, 0.0); N];
+        for (band, (band_l, band_r)) in bands.iter_mut().zip(bands_l.into_iter().zip(bands_r)) {
+            *band = (band_l, band_r);
+        }
+        bands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    ///
+    /// Measures the gain of the sum of the crossover's bands for a sine at a given frequency.
+    ///
+    fn measure_summed_gain<const N: usize>(crossover: &mut Crossover<N>, fc: f32) -> f32 {
+        let length = 20_000;
+        let (mut input_power, mut output_power) = (0.0, 0.0);
+        for n in 0..length {
+            let input = (2.0 * PI * fc * n as f32).sin();
+            let output: f32 = crossover.split(input).iter().sum();
+
+            // Skip the first half while the filters settle
+            if n >= length / 2 {
+                input_power += input * input;
+                output_power += output * output;
+            }
+        }
+        (output_power / input_power).sqrt()
+    }
+
+    #[test]
+    fn crossover_bands_sum_flat() {
+        let sample_rate = 44_100.0;
+        for frequency in [50.0, 200.0, 500.0, 1_000.0, 3_000.0, 8_000.0, 15_000.0] {
+            let fc = frequency / sample_rate;
+
+            let mut crossover = Crossover::<2>::new(&[1_000.0], sample_rate);
+            assert!((measure_summed_gain(&mut crossover, fc) - 1.0).abs() < 1e-2);
+
+            let mut crossover = Crossover::<3>::new(&[200.0, 2_000.0], sample_rate);
+            assert!((measure_summed_gain(&mut crossover, fc) - 1.0).abs() < 1e-2);
+
+            let mut crossover = Crossover::<4>::new(&[150.0, 1_000.0, 5_000.0], sample_rate);
+            assert!((measure_summed_gain(&mut crossover, fc) - 1.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn crossover_allpass_matches_summed_bands() {
+        let sample_rate = 44_100.0;
+        let frequencies = [150.0, 1_000.0, 5_000.0];
+        let mut crossover = Crossover::<4>::new(&frequencies, sample_rate);
+        let mut allpass = CrossoverAllpass::new(&frequencies, sample_rate);
+
+        // The recombined bands and the allpassed input stay in phase at every frequency
+        for n in 0..10_000 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            let summed: f32 = crossover.split(input).iter().sum();
+            assert!((summed - allpass.process(input)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn crossover_separates_bands() {
+        let sample_rate = 44_100.0;
+        let mut crossover = Crossover::<3>::new(&[200.0, 2_000.0], sample_rate);
+
+        // A sine well inside the middle band barely leaks into the outer bands
+        let fc = 700.0 / sample_rate;
+        let mut band_powers = [0.0; 3];
+        for n in 0..20_000 {
+            let bands = crossover.split((2.0 * PI * fc * n as f32).sin());
+            if n >= 10_000 {
+                for (power, band) in band_powers.iter_mut().zip(bands) {
+                    *power += band * band;
+                }
+            }
+        }
+        assert!(band_powers[1] > 10.0 * band_powers[0]);
+        assert!(band_powers[1] > 10.0 * band_powers[2]);
+    }
+}
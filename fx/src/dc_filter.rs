@@ -1,8 +1,10 @@
+use std::f32::consts::PI;
+
 /// A basic DC filter for correcting DC offset.
-/// 
+///
 /// Adapted for non-SIMD from Fredemus in va-filter, which is licensed under GPL 3.0:
 /// https://github.com/Fredemus/va-filter
-/// 
+///
 /// Originally copied from Understanding Digital Signal Processing by Richard Lyons
 pub struct DcFilter {
     y0: f32,
@@ -22,6 +24,23 @@ impl Default for DcFilter {
 }
 
 impl DcFilter {
+    ///
+    /// Sets the cutoff frequency of the filter. The default alpha of 0.999 is about 7 Hz at 44.1 kHz.
+    ///
+    /// # Arguments
+    /// * `cutoff` - the cutoff frequency, in Hz
+    /// * `sample_rate` - the sample rate, in samples per second
+    ///
+    pub fn set_cutoff(&mut self, cutoff: f32, sample_rate: f32) {
+        self.alpha = (-2.0 * PI * cutoff / sample_rate).exp();
+    }
+
+    /// Clears the filter's unit delays.
+    pub fn reset(&mut self) {
+        self.x0 = 0.;
+        self.y0 = 0.;
+    }
+
     pub fn process(&mut self, input: f32) -> f32 {
         let y_new = input - self.x0 + self.alpha * self.y0;
         self.x0 = input;
@@ -30,3 +49,71 @@ impl DcFilter {
         y_new
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_cutoff_is_about_seven_hz() {
+        let mut dc_filter = DcFilter::default();
+        let default_alpha = dc_filter.alpha;
+        dc_filter.set_cutoff(7.0, 44_100.0);
+        assert!((dc_filter.alpha - default_alpha).abs() < 1e-5);
+    }
+
+    #[test]
+    fn removes_dc_offset() {
+        let mut dc_filter = DcFilter::default();
+        dc_filter.set_cutoff(20.0, 1_000.0);
+        let mut output = 0.0;
+        for _ in 0..1_000 {
+            output = dc_filter.process(1.0);
+        }
+        assert!(output.abs() < 1e-3);
+    }
+
+    #[test]
+    fn removes_rectifier_offset_at_oversampled_rate() {
+        use crate::oversampling::Oversampler;
+        use crate::spectrum::{fft, Twiddles};
+        use crate::waveshapers::get_fuzzy_rectifier_output;
+
+        const FACTOR: usize = 4;
+        const FFT_SIZE: usize = 8192;
+        const SINE_BIN: usize = 37;
+        let sample_rate = 44_100.;
+        let mut dc_filter = DcFilter::default();
+        dc_filter.set_cutoff(10., sample_rate * FACTOR as f32);
+        let mut oversampler = Oversampler::<FACTOR>::new(8, true);
+
+        // A biased sine through a rectifier, which adds plenty of DC of its own
+        let sine = |n: usize| {
+            0.2 + 0.7 * (2. * PI * (SINE_BIN * n % FFT_SIZE) as f32 / FFT_SIZE as f32).sin()
+        };
+        let mut shape = |x: f32| {
+            oversampler.process(x, |x| dc_filter.process(get_fuzzy_rectifier_output(0.5, x)))
+        };
+
+        // Let the DC filter settle, then measure a whole number of periods
+        for n in 0..4 * FFT_SIZE {
+            shape(sine(n));
+        }
+        let mut real: Vec<f32> = (0..FFT_SIZE).map(|n| shape(sine(n))).collect();
+        let mut imag = vec![0.; FFT_SIZE];
+        fft(&mut real, &mut imag, &Twiddles::new(FFT_SIZE));
+        let power = |bin: usize| real[bin] * real[bin] + imag[bin] * imag[bin];
+
+        // The DC is gone, and filtering at the oversampled rate adds no aliasing
+        let harmonics: f32 = (1..FFT_SIZE / 2)
+            .filter(|bin| bin % SINE_BIN == 0)
+            .map(power)
+            .sum();
+        let aliasing: f32 = (1..FFT_SIZE / 2)
+            .filter(|bin| bin % SINE_BIN != 0)
+            .map(power)
+            .sum();
+        assert!(power(0) < harmonics * 1e-9);
+        assert!(aliasing < harmonics * 1e-6);
+    }
+}
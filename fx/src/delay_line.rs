@@ -1,4 +1,10 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::denormal::flush_denormal;
+use crate::filters::Diffuser;
+use crate::lfo::Lfo;
+use crate::stereo::{decode_mid_side, encode_mid_side};
 use std::f32::consts::PI;
+use std::sync::OnceLock;
 
 ///
 /// Performs cubic interpolation given four adjacent samples
@@ -19,12 +25,165 @@ fn get_cubic_interpolated_value(fpos: f32, xm1: f32, x0: f32, x1: f32, x2: f32)
     (((a * fpos) + b) * fpos + c) * fpos + x0
 }
 
+/// The number of samples the windowed-sinc interpolator reads around each position
+const SINC_TAPS: usize = 8;
+/// The number of fractional positions in the sinc table; positions in between are blended
+const SINC_PHASES: usize = 256;
+
+type SincTable = [[f32; SINC_TAPS]; SINC_PHASES + 1];
+
+///
+/// Returns the windowed-sinc interpolator's weights for every fractional position, computed
+/// once and shared by all delay lines. Each row holds the weights of the samples from 3 before
+/// to 4 after the position, under a Lanczos window and normalized to unity gain at DC. The
+/// Lanczos window keeps the response within about 0.3 dB up to 0.35 times the sample rate.
+///
+fn sinc_table() -> &'static SincTable {
+    static TABLE: OnceLock<SincTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let half_width = (SINC_TAPS / 2) as f32;
+        let mut table = [[0.; SINC_TAPS]; SINC_PHASES + 1];
+        for (phase, weights) in table.iter_mut().enumerate() {
+            let fraction = phase as f32 / SINC_PHASES as f32;
+            for (tap, weight) in weights.iter_mut().enumerate() {
+                // The distance from the read position to this tap's sample
+                let x = tap as f32 - (half_width - 1.) - fraction;
+                let sinc = |x: f32| {
+                    if x == 0. {
+                        1.
+                    } else {
+                        (PI * x).sin() / (PI * x)
+                    }
+                };
+                *weight = sinc(x) * sinc(x / half_width);
+            }
+            let sum: f32 = weights.iter().sum();
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+        }
+        table
+    })
+}
+
+/// How fractional positions are read from a delay buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Reads the nearest sample, which gives modulation a gritty, stepped character.
+    None,
+    /// Blends the two surrounding samples.
+    Linear,
+    /// Fits a cubic through the four surrounding samples, which is smooth and cheap.
+    Cubic,
+    /// Weighs the eight surrounding samples with a windowed sinc, which keeps bright material
+    /// clean under pitch modulation, at several times the cost of cubic.
+    Sinc,
+}
+
+///
+/// Reads the value at time `t` from a circular buffer using the given interpolation.
+///
+/// # Arguments
+/// * `buffer` - the circular buffer to read from
+/// * `t` - the fractional position to read, wrapped to the buffer length
+/// * `interpolation` - how to read between samples
+///
+pub(crate) fn get_interpolated_value_from_buffer(
+    buffer: &[f32],
+    t: f32,
+    interpolation: Interpolation,
+) -> f32 {
+    let time = t % buffer.len() as f32;
+    let inpos = time.floor() as usize;
+    let finpos = time.fract();
+
+    match interpolation {
+        Interpolation::None => buffer[time.round() as usize % buffer.len()],
+        Interpolation::Linear => {
+            let x0 = buffer[inpos];
+            let x1 = buffer[(inpos + 1) % buffer.len()];
+            x0 + (x1 - x0) * finpos
+        }
+        Interpolation::Cubic => {
+            // Get four surrounding samples from buffer
+            let xm1 = buffer[if inpos == 0 { buffer.len() } else { inpos } - 1];
+            let x0 = buffer[inpos];
+            let x1 = buffer[(inpos + 1) % buffer.len()];
+            let x2 = buffer[(inpos + 2) % buffer.len()];
+
+            get_cubic_interpolated_value(finpos, xm1, x0, x1, x2)
+        }
+        Interpolation::Sinc => {
+            // Blend the table rows on either side of the fractional position
+            let table = sinc_table();
+            let phase = finpos * SINC_PHASES as f32;
+            let row = (phase as usize).min(SINC_PHASES - 1);
+            let blend = phase - row as f32;
+
+            // The taps start 3 samples before the position, wrapping around the buffer
+            let length = buffer.len();
+            let first = inpos + length * SINC_TAPS - (SINC_TAPS / 2 - 1);
+            (0..SINC_TAPS)
+                .map(|tap| {
+                    let weight = table[row][tap] + (table[row + 1][tap] - table[row][tap]) * blend;
+                    buffer[(first + tap) % length] * weight
+                })
+                .sum()
+        }
+    }
+}
+
+/// The largest magnitude written back into a feedback loop, about 12 dB above full scale
+const MAX_FEEDBACK_LEVEL: f32 = 4.0;
+
+///
+/// Keeps a value written into a feedback loop finite and bounded. NaN, infinite, and denormal
+/// values are flushed to zero so they can't stick in the buffer, and runaway feedback is clamped.
+///
+fn sanitize_feedback_write(value: f32) -> f32 {
+    if value.is_finite() {
+        flush_denormal(value).clamp(-MAX_FEEDBACK_LEVEL, MAX_FEEDBACK_LEVEL)
+    } else {
+        0.0
+    }
+}
+
+/// The largest feedback when it crosses channels. The crossed loop takes two trips through the
+/// delay lines to return, so it's held just under unity to keep the swirl from building up
+const MAX_CROSS_FEEDBACK: f32 = 0.95;
+
+/// The longest inter-channel delay used for Haas widening, in seconds
+pub const MAX_SPREAD_DELAY_SECONDS: f32 = 0.03;
+/// How much the side channel is boosted at full mid/side spread, on top of unity gain
+const MAX_SPREAD_SIDE_GAIN: f32 = 1.0;
+
+/// The most chorus voices, each reading the delay lines at its own LFO phase
+pub const MAX_CHORUS_VOICES: usize = 3;
+
+/// How the chorus's wet signal is widened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpreadMode {
+    /// Delays the right channel, which sounds wide but can comb filter when summed to mono.
+    Haas,
+    /// Boosts the side channel, which keeps the mono sum unchanged.
+    MidSide,
+}
+
+fn spread_buffer_size(sample_rate: usize) -> usize {
+    (MAX_SPREAD_DELAY_SECONDS * sample_rate as f32) as usize + 2
+}
+
 pub struct StereoDelay {
     buffer_l: Vec<f32>,
     buffer_r: Vec<f32>,
     write_pointer: usize,
     lfo_phase: f32,
     sample_rate: usize,
+    spread: f32,
+    spread_mode: SpreadMode,
+    spread_buffer: Vec<f32>,
+    spread_write_pointer: usize,
+    interpolation: Interpolation,
+    cross_feedback: bool,
+    voices: usize,
 }
 
 impl StereoDelay {
@@ -49,6 +208,13 @@ impl StereoDelay {
             write_pointer: 0,
             lfo_phase: 0.0,
             sample_rate,
+            spread: 0.0,
+            spread_mode: SpreadMode::Haas,
+            spread_buffer: vec![0.0; spread_buffer_size(sample_rate)],
+            spread_write_pointer: 0,
+            interpolation: Interpolation::Cubic,
+            cross_feedback: false,
+            voices: 1,
         }
     }
 
@@ -63,23 +229,90 @@ impl StereoDelay {
         let new_size = (max_delay_time * sample_rate as f32) as usize;
         self.buffer_l.resize(new_size, 0.0);
         self.buffer_r.resize(new_size, 0.0);
+        self.sample_rate = sample_rate;
+        self.spread_buffer
+            .resize(spread_buffer_size(sample_rate), 0.0);
     }
 
     ///
-    /// Calculates value at time `t` using cubic interpolation.
+    /// Clears the circular buffers without reallocating them, and restarts the LFO.
     ///
-    fn get_cubic_interpolated_value_from_buffer(&self, t: f32, buffer: &Vec<f32>) -> f32 {
-        let time = t % buffer.len() as f32;
-        let inpos = time.floor() as usize;
-        let finpos = time.fract();
+    pub fn reset(&mut self) {
+        self.buffer_l.iter_mut().for_each(|sample| *sample = 0.0);
+        self.buffer_r.iter_mut().for_each(|sample| *sample = 0.0);
+        self.spread_buffer
+            .iter_mut()
+            .for_each(|sample| *sample = 0.0);
+        self.write_pointer = 0;
+        self.spread_write_pointer = 0;
+        self.lfo_phase = 0.0;
+    }
 
-        // Get four surrounding samples from buffer
-        let xm1 = buffer[if inpos == 0 { buffer.len() } else { inpos } - 1];
-        let x0 = buffer[inpos];
-        let x1 = buffer[(inpos + 1) % buffer.len()];
-        let x2 = buffer[(inpos + 2) % buffer.len()];
+    ///
+    /// Sets the stereo spread applied to the chorus's wet signal. The dry signal is left as is.
+    ///
+    /// # Arguments
+    /// * `spread` - the amount of widening from 0 (unchanged) to 1
+    /// * `mode` - whether to widen with a Haas delay or a mid/side side boost
+    ///
+    pub fn set_spread(&mut self, spread: f32, mode: SpreadMode) {
+        self.spread = spread;
+        self.spread_mode = mode;
+    }
+
+    ///
+    /// Widens a stereo frame of the wet signal according to the spread settings.
+    ///
+    fn apply_spread(&mut self, wet: (f32, f32)) -> (f32, f32) {
+        // Keep the Haas delay running so switching modes or raising the spread doesn't click
+        let buffer_len = self.spread_buffer.len();
+        self.spread_buffer[self.spread_write_pointer] = wet.1;
+        let write_pointer = self.spread_write_pointer;
+        self.spread_write_pointer = (self.spread_write_pointer + 1) % buffer_len;
+
+        if self.spread <= 0.0 {
+            return wet;
+        }
+
+        match self.spread_mode {
+            SpreadMode::Haas => {
+                let delay = self.spread * MAX_SPREAD_DELAY_SECONDS * self.sample_rate as f32;
+                let read_time = write_pointer as f32 - delay + buffer_len as f32;
+                let index = read_time.floor() as usize;
+                let fraction = read_time.fract();
+                let x0 = self.spread_buffer[index % buffer_len];
+                let x1 = self.spread_buffer[(index + 1) % buffer_len];
+                (wet.0, x0 + (x1 - x0) * fraction)
+            }
+            SpreadMode::MidSide => {
+                let (mid, side) = encode_mid_side(wet);
+                decode_mid_side((mid, side * (1.0 + self.spread * MAX_SPREAD_SIDE_GAIN)))
+            }
+        }
+    }
+
+    ///
+    /// Sets whether the chorus's feedback crosses channels, with the left channel's delayed
+    /// signal fed back into the right delay line and vice versa, for a ping-pong swirl.
+    ///
+    pub fn set_cross_feedback(&mut self, cross_feedback: bool) {
+        self.cross_feedback = cross_feedback;
+    }
+
+    ///
+    /// Sets how many voices the chorus reads from the delay lines, from 1 to 3. The voices' LFOs
+    /// are spread evenly around the cycle, so 2 voices are 180 degrees apart and 3 voices are
+    /// 120 degrees apart, like a classic ensemble, and their outputs are averaged.
+    ///
+    pub fn set_voices(&mut self, voices: usize) {
+        self.voices = voices.clamp(1, MAX_CHORUS_VOICES);
+    }
 
-        get_cubic_interpolated_value(finpos, xm1, x0, x1, x2)
+    ///
+    /// Sets how the modulated read positions are interpolated.
+    ///
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
     }
 
     ///
@@ -87,7 +320,14 @@ impl StereoDelay {
     ///
     fn get_read_time(&self, lfo_phase: f32, lfo_width: f32) -> f32 {
         let phase_component = 2.0 * PI * lfo_phase;
-        let current_delay = lfo_width * (0.5 + 0.5 * phase_component.sin());
+        self.get_modulated_read_time(phase_component.sin(), lfo_width)
+    }
+
+    ///
+    /// Get fractional read time into buffer for a bipolar modulation value between -1 and 1
+    ///
+    fn get_modulated_read_time(&self, modulation: f32, lfo_width: f32) -> f32 {
+        let current_delay = lfo_width * (0.5 + 0.5 * modulation);
         let buffer_len = self.buffer_l.len() as f32;
 
         self.write_pointer as f32 - (current_delay * self.sample_rate as f32) as f32 + buffer_len
@@ -95,12 +335,25 @@ impl StereoDelay {
     }
 
     ///
-    /// Calculate samples from buffer given LFO width in samples.
-    /// Phase shift offsets right read pointer for stereo width.
+    /// Get fractional read times into both buffers.
+    /// Phase shift offsets the right LFO phase for stereo width, where 0.5 is 180 degrees.
     ///
-    fn read_interpolated_samples(&self, lfo_width: f32, phase_shift: f32) -> (f32, f32) {
+    fn get_read_times(&self, lfo_width: f32, phase_shift: f32) -> (f32, f32) {
+        self.get_voice_read_times(lfo_width, 0.0, phase_shift)
+    }
+
+    ///
+    /// Get fractional read times into both buffers for a voice whose LFO is offset by
+    /// `voice_phase`, in cycles.
+    ///
+    fn get_voice_read_times(
+        &self,
+        lfo_width: f32,
+        voice_phase: f32,
+        phase_shift: f32,
+    ) -> (f32, f32) {
         // Recalculate read pointer with respect to write pointer
-        let mut lfo_phase = self.lfo_phase;
+        let mut lfo_phase = self.lfo_phase + voice_phase;
         if lfo_phase >= 1.0 {
             lfo_phase -= 1.0;
         }
@@ -109,12 +362,50 @@ impl StereoDelay {
         let t_l = self.get_read_time(lfo_phase, lfo_width);
         let t_r = self.get_read_time(lfo_phase + phase_shift, lfo_width);
 
-        let out_l = self.get_cubic_interpolated_value_from_buffer(t_l, &self.buffer_l);
-        let out_r = self.get_cubic_interpolated_value_from_buffer(t_r, &self.buffer_r);
+        (t_l, t_r)
+    }
+
+    ///
+    /// Calculate samples from buffer given LFO width in samples.
+    /// Phase shift offsets right read pointer for stereo width.
+    ///
+    fn read_interpolated_samples(&self, lfo_width: f32, phase_shift: f32) -> (f32, f32) {
+        let (t_l, t_r) = self.get_read_times(lfo_width, phase_shift);
+
+        let out_l = get_interpolated_value_from_buffer(&self.buffer_l, t_l, self.interpolation);
+        let out_r = get_interpolated_value_from_buffer(&self.buffer_r, t_r, self.interpolation);
 
         (out_l, out_r)
     }
 
+    ///
+    /// Calculate the average of every chorus voice's samples, each voice reading at its own
+    /// evenly spaced LFO phase.
+    ///
+    fn read_chorus_voices(&self, lfo_width: f32, phase_shift: f32) -> (f32, f32) {
+        let mut sum = (0.0, 0.0);
+        for voice in 0..self.voices {
+            let voice_phase = voice as f32 / self.voices as f32;
+            let (t_l, t_r) = self.get_voice_read_times(lfo_width, voice_phase, phase_shift);
+            sum.0 += get_interpolated_value_from_buffer(&self.buffer_l, t_l, self.interpolation);
+            sum.1 += get_interpolated_value_from_buffer(&self.buffer_r, t_r, self.interpolation);
+        }
+        let scale = (self.voices as f32).recip();
+        (sum.0 * scale, sum.1 * scale)
+    }
+
+    ///
+    /// Processes a stereo frame with chorus.
+    ///
+    /// # Arguments
+    /// * `input` - a stereo frame of input
+    /// * `lfo_frequency` - the frequency of the LFO, in Hz
+    /// * `vibrato_width` - the maximum delay swing of the LFO, in seconds
+    /// * `lfo_phase_right_offset` - the right LFO's phase offset from the left for every voice,
+    ///   in cycles; 0.25 is 90 degrees (quadrature), 1/3 is 120 degrees, and 0.5 is 180 degrees
+    /// * `depth` - the level of the wet signal
+    /// * `feedback` - how much of the delayed signal is written back into the delay lines
+    ///
     pub fn process_with_chorus(
         &mut self,
         input: (f32, f32),
@@ -124,14 +415,23 @@ impl StereoDelay {
         depth: f32,
         feedback: f32,
     ) -> (f32, f32) {
-        let interpolated_samples =
-            self.read_interpolated_samples(vibrato_width, lfo_phase_right_offset);
+        let interpolated_samples = self.read_chorus_voices(vibrato_width, lfo_phase_right_offset);
 
         // Store information in buffers
         let (in_l, in_r) = input;
         let (interpolated_l, interpolated_r) = interpolated_samples;
-        self.buffer_l[self.write_pointer] = in_l + interpolated_l * feedback;
-        self.buffer_r[self.write_pointer] = in_r + interpolated_r * feedback;
+        let (feedback, (interpolated_l, interpolated_r)) = if self.cross_feedback {
+            (
+                feedback.clamp(-MAX_CROSS_FEEDBACK, MAX_CROSS_FEEDBACK),
+                (interpolated_r, interpolated_l),
+            )
+        } else {
+            (feedback, (interpolated_l, interpolated_r))
+        };
+        self.buffer_l[self.write_pointer] =
+            sanitize_feedback_write(in_l + interpolated_l * feedback);
+        self.buffer_r[self.write_pointer] =
+            sanitize_feedback_write(in_r + interpolated_r * feedback);
 
         // Increment write pointer at constant rate
         self.write_pointer += 1;
@@ -147,11 +447,24 @@ impl StereoDelay {
             self.lfo_phase -= 1.0;
         }
 
-        let out_l = in_l + depth * interpolated_samples.0;
-        let out_r = in_r + depth * interpolated_samples.1;
-        (out_l, out_r)
+        // Widen only the wet signal, keeping the dry signal centered
+        let (wet_l, wet_r) = self.apply_spread((
+            depth * interpolated_samples.0,
+            depth * interpolated_samples.1,
+        ));
+        (in_l + wet_l, in_r + wet_r)
     }
 
+    ///
+    /// Processes a stereo frame with vibrato.
+    ///
+    /// # Arguments
+    /// * `input` - a stereo frame of input
+    /// * `lfo_frequency` - the frequency of the LFO, in Hz
+    /// * `vibrato_width` - the maximum delay swing of the LFO, in seconds
+    /// * `lfo_phase_right_offset` - the right LFO's phase offset from the left, in cycles; 0 moves
+    ///   both channels together, 0.25 is 90 degrees, and 0.5 is fully out of phase
+    ///
     pub fn process_with_vibrato(
         &mut self,
         input: (f32, f32),
@@ -183,20 +496,89 @@ impl StereoDelay {
 
         interpolated_samples
     }
+
+    ///
+    /// Processes a stereo frame with vibrato driven by an external modulation source, such as a
+    /// random LFO, instead of the internal sine LFO.
+    ///
+    /// # Arguments
+    /// * `input` - a stereo frame of input
+    /// * `modulation` - bipolar modulation values between -1 and 1 for each channel
+    /// * `vibrato_width` - the maximum delay swing of the modulation, in seconds
+    ///
+    pub fn process_with_modulation(
+        &mut self,
+        input: (f32, f32),
+        modulation: (f32, f32),
+        vibrato_width: f32,
+    ) -> (f32, f32) {
+        let t_l = self.get_modulated_read_time(modulation.0, vibrato_width);
+        let t_r = self.get_modulated_read_time(modulation.1, vibrato_width);
+        let out_l = get_interpolated_value_from_buffer(&self.buffer_l, t_l, self.interpolation);
+        let out_r = get_interpolated_value_from_buffer(&self.buffer_r, t_r, self.interpolation);
+
+        // Store information in buffers
+        let (in_l, in_r) = input;
+        self.buffer_l[self.write_pointer] = in_l;
+        self.buffer_r[self.write_pointer] = in_r;
+
+        // Increment write pointer at constant rate
+        self.write_pointer += 1;
+
+        if self.write_pointer >= self.buffer_l.len() {
+            self.write_pointer = 0;
+        }
+
+        (out_l, out_r)
+    }
 }
 
 pub struct DelayLine {
     circular_buffer: Vec<f32>,
     read_pointer: usize,
     write_pointer: usize,
-    delay_time: usize,
+    delay_time: f32,
     dry_mix: f32,
     wet_mix: f32,
     feedback: f32,
+    target_feedback: f32,
+    feedback_smoothing: f32,
+    damping: f32,
+    damping_state: f32,
     sample_rate: usize,
     lfo_phase: f32,
+    interpolation: Interpolation,
+    hold: bool,
+    hold_amount: f32,
+    diffuser: Diffuser,
+    modulation_lfo: Lfo,
+    modulation_depth: f32,
+    feedback_filter: BiquadFilter,
+    feedback_filter_enabled: bool,
+    feedback_filter_fc: f32,
 }
 
+/// The Q of the low pass filter in the feedback path, which keeps its gain at or below unity so
+/// the feedback stays stable however it's swept
+const FEEDBACK_FILTER_Q: f32 = 0.707;
+/// The highest the feedback filter's cutoff can go, as a fraction of the sample rate
+const MAX_FEEDBACK_FILTER_FC: f32 = 0.45;
+
+/// How long the delay line takes to fade into or out of hold, in seconds
+const HOLD_FADE_SECONDS: f32 = 0.01;
+
+/// The time constant of the feedback's per-sample smoothing, in seconds
+const FEEDBACK_SMOOTHING_SECONDS: f32 = 0.01;
+
+/// The one-pole coefficient that ramps the feedback towards its target at a sample rate
+fn feedback_smoothing_coefficient(sample_rate: usize) -> f32 {
+    1.0 - (-1.0 / (FEEDBACK_SMOOTHING_SECONDS * sample_rate as f32)).exp()
+}
+
+/// The interpolation reads this many samples behind the write pointer, so every delay
+/// is this much longer than the set delay time.
+const INTERPOLATION_DELAY_SAMPLES: f32 = 3.0;
+
 impl DelayLine {
     pub fn new(buffer_length: usize, sample_rate: usize) -> DelayLine {
         let mut circular_buffer = Vec::with_capacity(buffer_length);
@@ -208,9 +590,22 @@ impl DelayLine {
             dry_mix: 0.0,
             wet_mix: 1.0,
             feedback: 0.5,
-            delay_time: 0,
+            target_feedback: 0.5,
+            feedback_smoothing: feedback_smoothing_coefficient(sample_rate),
+            damping: 0.0,
+            damping_state: 0.0,
+            delay_time: 0.0,
             sample_rate,
             lfo_phase: 0.0,
+            interpolation: Interpolation::Cubic,
+            hold: false,
+            hold_amount: 0.0,
+            diffuser: Diffuser::new(sample_rate),
+            modulation_lfo: Lfo::new(sample_rate as f32),
+            modulation_depth: 0.0,
+            feedback_filter: BiquadFilter::new(),
+            feedback_filter_enabled: false,
+            feedback_filter_fc: 0.0,
         }
     }
 
@@ -225,12 +620,154 @@ impl DelayLine {
         let wp = self.write_pointer as f32;
         let buffer_length = self.circular_buffer.len();
         let delay_in_samples = (delay_time / 1000.0) * sample_rate;
-        self.delay_time = delay_in_samples as usize;
+        self.delay_time = delay_in_samples.floor();
         self.read_pointer = (wp - delay_in_samples + buffer_length as f32) as usize % buffer_length;
     }
 
+    ///
+    /// Tunes the delay so the feedback loop resonates at a frequency, like a Karplus-Strong string.
+    /// The delay is fractional and accounts for the interpolation and damping filter delays.
+    ///
+    /// # Arguments
+    /// * `frequency` - The resonant frequency, in Hz
+    /// * `sample_rate` - The sample rate of the system
+    ///
+    pub fn set_resonator_frequency(&mut self, frequency: f32, sample_rate: f32) {
+        // Phase delay of the one-pole damping filter at the resonant frequency
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let damping_delay = if self.damping > 0.0 {
+            (self.damping * omega.sin()).atan2(1.0 - self.damping * omega.cos()) / omega
+        } else {
+            0.0
+        };
+
+        let period = sample_rate / frequency;
+        self.delay_time = (period - damping_delay - INTERPOLATION_DELAY_SAMPLES).max(0.0);
+    }
+
+    ///
+    /// Sets the damping of the low pass filter in the feedback path.
+    ///
+    /// # Arguments
+    /// * `damping` - The amount of damping, from 0 (no filtering) up to, but not including, 1
+    ///
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping;
+    }
+
+    ///
+    /// Sets the feedback the delay line ramps towards. The feedback is smoothed every sample,
+    /// so stepping or automating it never clicks.
+    ///
+    /// # Arguments
+    /// * `feedback` - the amount of the delayed signal fed back into the delay line
+    ///
     pub fn set_feedback(&mut self, feedback: f32) {
+        self.target_feedback = feedback;
+    }
+
+    ///
+    /// Jumps straight to a feedback without ramping, such as before processing starts.
+    ///
+    /// # Arguments
+    /// * `feedback` - the amount of the delayed signal fed back into the delay line
+    ///
+    pub fn reset_feedback(&mut self, feedback: f32) {
         self.feedback = feedback;
+        self.target_feedback = feedback;
+    }
+
+    ///
+    /// Clears the circular buffer and the feedback path's filters without reallocating, so
+    /// restarting playback doesn't replay old echoes. The delay time is kept.
+    ///
+    pub fn reset(&mut self) {
+        self.circular_buffer
+            .iter_mut()
+            .for_each(|sample| *sample = 0.0);
+        self.damping_state = 0.0;
+        self.diffuser.reset();
+        self.feedback_filter.reset();
+        self.modulation_lfo.reset();
+        self.lfo_phase = 0.0;
+    }
+
+    ///
+    /// Sets how much an allpass diffuser in the feedback path smears each repeat, so the echoes
+    /// blur into a reverb-like wash as they recirculate.
+    ///
+    /// # Arguments
+    /// * `diffusion` - the diffusion amount from 0 (clean repeats) to 1
+    ///
+    pub fn set_diffusion(&mut self, diffusion: f32) {
+        self.diffuser.set_diffusion(diffusion);
+    }
+
+    ///
+    /// Sets how many allpass stages the feedback diffuser chains, from 1 to
+    /// `MAX_DIFFUSER_STAGES`. More stages blur each repeat more densely.
+    ///
+    pub fn set_diffusion_stages(&mut self, stages: usize) {
+        self.diffuser.set_stages(stages);
+    }
+
+    ///
+    /// Slowly sweeps the delay time with a sine LFO, bending the pitch of the repeats like a
+    /// worn tape echo. The sweep only lengthens the delay, so no depth keeps the delay steady.
+    ///
+    /// # Arguments
+    /// * `depth` - how far the delay time sweeps, in milliseconds
+    /// * `rate` - how fast the delay time sweeps, in Hz
+    ///
+    pub fn set_modulation(&mut self, depth: f32, rate: f32) {
+        self.modulation_depth = depth;
+        self.modulation_lfo.set_frequency(rate);
+    }
+
+    ///
+    /// Sets the cutoff of a low pass filter in the feedback path, which can be swept every
+    /// sample, such as by a filter envelope. Unlike the damping, which only darkens, the filter
+    /// keeps a flat passband below its cutoff. The coefficients are only recomputed when the
+    /// cutoff changes.
+    ///
+    /// # Arguments
+    /// * `cutoff` - the cutoff in Hz, or `None` to take the filter out of the feedback path
+    ///
+    pub fn set_feedback_filter(&mut self, cutoff: Option<f32>) {
+        match cutoff {
+            Some(cutoff) => {
+                let fc = (cutoff / self.sample_rate as f32).clamp(0.0, MAX_FEEDBACK_FILTER_FC);
+                if self.feedback_filter_enabled {
+                    if fc != self.feedback_filter_fc {
+                        self.feedback_filter.set_fc(fc);
+                    }
+                } else {
+                    // Start from silence rather than whatever was left when it was switched off
+                    self.feedback_filter.reset();
+                    self.feedback_filter.set_biquad(
+                        BiquadFilterType::LowPass,
+                        fc,
+                        FEEDBACK_FILTER_Q,
+                        0.0,
+                    );
+                    self.feedback_filter_enabled = true;
+                }
+                self.feedback_filter_fc = fc;
+            }
+            None => self.feedback_filter_enabled = false,
+        }
+    }
+
+    ///
+    /// Latches the delay line so its current contents loop forever. While held, the feedback is
+    /// exactly 1 and undamped, and no new input is written. Entering and leaving hold fades over
+    /// a short time to avoid clicks.
+    ///
+    /// # Arguments
+    /// * `hold` - whether the delay line is held
+    ///
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
     }
 
     pub fn set_dry_wet(&mut self, dry_mix: f32, wet_mix: f32) {
@@ -258,24 +795,28 @@ impl DelayLine {
     pub fn resize_buffer_with_sample_rate(&mut self, new_size: usize, sample_rate: usize) {
         self.sample_rate = sample_rate;
         self.circular_buffer.resize(new_size, 0.0);
+        self.modulation_lfo.set_sample_rate(sample_rate as f32);
+        self.feedback_smoothing = feedback_smoothing_coefficient(sample_rate);
+
+        // The diffuser's allpass lengths follow the sample rate
+        let (diffusion, stages) = (self.diffuser.diffusion(), self.diffuser.stages());
+        self.diffuser = Diffuser::new(sample_rate);
+        self.diffuser.set_diffusion(diffusion);
+        self.diffuser.set_stages(stages);
     }
 
     ///
-    /// Calculates value at time `t` using cubic interpolation.
+    /// Sets how fractional delay times are interpolated.
     ///
-    fn get_cubic_interpolated_value_from_buffer(&self, t: f32) -> f32 {
-        let buffer = &self.circular_buffer;
-        let time = t % buffer.len() as f32;
-        let inpos = time.floor() as usize;
-        let finpos = time.fract();
-
-        // Get four surrounding samples from buffer
-        let xm1 = buffer[if inpos == 0 { buffer.len() } else { inpos } - 1];
-        let x0 = buffer[inpos];
-        let x1 = buffer[(inpos + 1) % buffer.len()];
-        let x2 = buffer[(inpos + 2) % buffer.len()];
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
 
-        get_cubic_interpolated_value(finpos, xm1, x0, x1, x2)
+    ///
+    /// Calculates value at time `t` using the delay line's interpolation.
+    ///
+    fn get_interpolated_value(&self, t: f32) -> f32 {
+        get_interpolated_value_from_buffer(&self.circular_buffer, t, self.interpolation)
     }
 
     fn get_interpolated_sample(&self, lfo_width: f32, sample_rate: f32, phase_shift: f32) -> f32 {
@@ -289,19 +830,63 @@ impl DelayLine {
         let buffer_len = self.circular_buffer.len() as f32;
         let t = self.write_pointer as f32 - (current_delay * sample_rate) as f32 + buffer_len - 3.0;
 
-        self.get_cubic_interpolated_value_from_buffer(t)
+        self.get_interpolated_value(t)
     }
 
     pub fn process_with_delay(&mut self, input: f32) -> f32 {
+        self.process_with_ducked_delay(input, 1.0)
+    }
+
+    ///
+    /// Processes a sample like `process_with_delay`, scaling the delayed signal in the output
+    /// without affecting what is fed back, so ducked echoes return once the gain recovers.
+    ///
+    /// # Arguments
+    /// * `input` - the input sample
+    /// * `duck_gain` - the gain applied to the wet signal, from 0 to 1
+    ///
+    pub fn process_with_ducked_delay(&mut self, input: f32, duck_gain: f32) -> f32 {
         let buffer_length = self.circular_buffer.len();
-        let t = (self.write_pointer as f32 - self.delay_time as f32 + buffer_length as f32 - 3.0)
+
+        // Lengthen the delay by the modulation, without reading past the oldest sample
+        let max_delay = buffer_length as f32 - INTERPOLATION_DELAY_SAMPLES - 2.0;
+        let modulation_samples = self.modulation_depth / 1000.0
+            * self.sample_rate as f32
+            * 0.5
+            * (1.0 + self.modulation_lfo.tick());
+        let modulation_samples = modulation_samples.min(max_delay - self.delay_time).max(0.0);
+
+        let t = (self.write_pointer as f32 - self.delay_time - modulation_samples
+            + buffer_length as f32
+            - INTERPOLATION_DELAY_SAMPLES)
             % buffer_length as f32;
-        let interpolated_sample = self.get_cubic_interpolated_value_from_buffer(t);
-        let output = self.dry_mix * input + self.wet_mix * interpolated_sample;
+        let interpolated_sample = self.get_interpolated_value(t);
+        let output = self.dry_mix * input + self.wet_mix * interpolated_sample * duck_gain;
+
+        // Damp the feedback signal with a one-pole low pass filter
+        self.damping_state = sanitize_feedback_write(
+            interpolated_sample * (1.0 - self.damping) + self.damping_state * self.damping,
+        );
+        let filtered = if self.feedback_filter_enabled {
+            sanitize_feedback_write(self.feedback_filter.process(self.damping_state))
+        } else {
+            self.damping_state
+        };
+
+        // Fade towards looping the delayed signal as is, without new input
+        let hold_step = (HOLD_FADE_SECONDS * self.sample_rate as f32).recip();
+        self.hold_amount = if self.hold {
+            (self.hold_amount + hold_step).min(1.0)
+        } else {
+            (self.hold_amount - hold_step).max(0.0)
+        };
+        self.feedback += (self.target_feedback - self.feedback) * self.feedback_smoothing;
+        let written = input + self.diffuser.tick(filtered) * self.feedback;
 
-        // Write input signal and feedback signal into buffer
-        self.circular_buffer[self.write_pointer] =
-            input + (self.circular_buffer[t as usize] * self.feedback);
+        // Write input signal and feedback signal into buffer, or loop the delayed signal if held
+        self.circular_buffer[self.write_pointer] = sanitize_feedback_write(
+            written * (1.0 - self.hold_amount) + interpolated_sample * self.hold_amount,
+        );
 
         self.read_pointer += 1;
         self.write_pointer += 1;
@@ -348,3 +933,501 @@ impl DelayLine {
         input + depth * interpolated_sample
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::MAX_DIFFUSER_STAGES;
+    use crate::signal::Sine;
+
+    #[test]
+    fn stereo_delay_modulation_is_in_phase_without_offset() {
+        let mut delay = StereoDelay::new(1.0, 1000);
+        for _ in 0..500 {
+            let (t_l, t_r) = delay.get_read_times(0.1, 0.0);
+            assert_eq!(t_l, t_r);
+            delay.process_with_vibrato((0.0, 0.0), 3.0, 0.1, 0.0);
+        }
+    }
+
+    #[test]
+    fn stereo_delay_modulation_is_opposite_with_half_offset() {
+        let sample_rate = 1000;
+        let width = 0.1;
+        let mut delay = StereoDelay::new(1.0, sample_rate);
+        for _ in 0..500 {
+            // 180 degrees apart, the left and right delays always sum to the full width
+            let (t_l, t_r) = delay.get_read_times(width, 0.5);
+            let undelayed_time = delay.write_pointer as f32 + delay.buffer_l.len() as f32 - 3.0;
+            let delay_l = undelayed_time - t_l;
+            let delay_r = undelayed_time - t_r;
+            assert!((delay_l + delay_r - width * sample_rate as f32).abs() < 1e-2);
+
+            delay.process_with_vibrato((0.0, 0.0), 3.0, width, 0.5);
+        }
+    }
+
+    #[test]
+    fn chorus_voices_are_evenly_spaced_around_the_lfo_cycle() {
+        let sample_rate = 1000;
+        let width = 0.1;
+        let mut delay = StereoDelay::new(1.0, sample_rate);
+        delay.set_voices(3);
+        for _ in 0..500 {
+            // 120 degrees apart, the three voices' delays always average to half the width, and
+            // each right channel is a quarter cycle behind its left
+            let undelayed_time = delay.write_pointer as f32 + delay.buffer_l.len() as f32 - 3.0;
+            let (mut sum_l, mut sum_r) = (0.0, 0.0);
+            for voice in 0..3 {
+                let voice_phase = voice as f32 / 3.0;
+                let (t_l, t_r) = delay.get_voice_read_times(width, voice_phase, 0.25);
+                let (quadrature_l, _) = delay.get_voice_read_times(width, voice_phase + 0.25, 0.0);
+                assert!((t_r - quadrature_l).abs() < 1e-2);
+                sum_l += undelayed_time - t_l;
+                sum_r += undelayed_time - t_r;
+            }
+            let expected = 1.5 * width * sample_rate as f32;
+            assert!((sum_l - expected).abs() < 1e-2);
+            assert!((sum_r - expected).abs() < 1e-2);
+
+            delay.process_with_chorus((0.0, 0.0), 3.0, width, 0.25, 1.0, 0.0);
+        }
+    }
+
+    #[test]
+    fn chorus_spread_delays_wet_right_channel() {
+        let sample_rate = 1000;
+        let mut plain = StereoDelay::new(1.0, sample_rate);
+        let mut spread = StereoDelay::new(1.0, sample_rate);
+        spread.set_spread(0.5, SpreadMode::Haas);
+        let spread_samples = (0.5 * MAX_SPREAD_DELAY_SECONDS * sample_rate as f32) as usize;
+
+        let mut plain_wet_r = vec![];
+        for n in 0..200 {
+            let input = if n == 10 { (1.0, 1.0) } else { (0.0, 0.0) };
+            let plain_output = plain.process_with_chorus(input, 1.0, 0.0, 0.0, 1.0, 0.0);
+            let spread_output = spread.process_with_chorus(input, 1.0, 0.0, 0.0, 1.0, 0.0);
+            plain_wet_r.push(plain_output.1 - input.1);
+
+            // The left channel is untouched, and only the right channel's wet signal is delayed
+            assert_eq!(plain_output.0, spread_output.0);
+            let delayed_wet_r = if n >= spread_samples {
+                plain_wet_r[n - spread_samples]
+            } else {
+                0.0
+            };
+            assert!((spread_output.1 - (input.1 + delayed_wet_r)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn chorus_cross_feedback_crosses_channels() {
+        let mut delay = StereoDelay::new(1.0, 1000);
+        delay.set_cross_feedback(true);
+
+        // An impulse only on the left should come back on the right through the feedback
+        let mut right_energy = 0.0;
+        for n in 0..500 {
+            let input = if n == 0 { (1.0, 0.0) } else { (0.0, 0.0) };
+            let output = delay.process_with_chorus(input, 1.0, 0.01, 0.0, 1.0, 1.0);
+            right_energy += output.1 * output.1;
+            assert!(output.0.abs() <= 1.0 && output.1.abs() <= 1.0);
+        }
+        assert!(right_energy > 0.1);
+    }
+
+    #[test]
+    fn chorus_mid_side_spread_keeps_mono_sum() {
+        let mut plain = StereoDelay::new(1.0, 1000);
+        let mut spread = StereoDelay::new(1.0, 1000);
+        spread.set_spread(1.0, SpreadMode::MidSide);
+
+        for n in 0..500 {
+            let input = ((n as f32 * 0.1).sin(), (n as f32 * 0.37).sin());
+            let plain_output = plain.process_with_chorus(input, 2.0, 0.01, 0.5, 1.0, 0.3);
+            let spread_output = spread.process_with_chorus(input, 2.0, 0.01, 0.5, 1.0, 0.3);
+            let plain_sum = plain_output.0 + plain_output.1;
+            let spread_sum = spread_output.0 + spread_output.1;
+            assert!((plain_sum - spread_sum).abs() < 1e-4);
+        }
+    }
+
+    ///
+    /// Measures the period of a delay line's resonance from the zero crossings of its response
+    /// to a dipole, which doesn't excite the resonance at DC.
+    ///
+    fn measure_resonant_period(delay_line: &mut DelayLine) -> f32 {
+        let output: Vec<f32> = (0..32_768)
+            .map(|n| {
+                let input = match n {
+                    0 => 1.0,
+                    1 => -1.0,
+                    _ => 0.0,
+                };
+                delay_line.process_with_delay(input)
+            })
+            .collect();
+
+        // Measure over the second half, once damping has left only the fundamental
+        let crossings: Vec<f32> = output
+            .windows(2)
+            .enumerate()
+            .skip(output.len() / 2)
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(n, pair)| n as f32 + pair[0] / (pair[0] - pair[1]))
+            .collect();
+        (crossings[crossings.len() - 1] - crossings[0]) / (crossings.len() - 1) as f32
+    }
+
+    #[test]
+    fn resonator_is_tuned_to_frequency() {
+        let sample_rate = 44_100.0;
+        for (frequency, damping) in [(440.0, 0.6), (1_000.0, 0.3), (1_234.5, 0.3)] {
+            let mut delay_line = DelayLine::new(4096, sample_rate as usize);
+            delay_line.set_dry_wet(0.0, 1.0);
+            delay_line.reset_feedback(0.999);
+            delay_line.set_damping(damping);
+            delay_line.set_resonator_frequency(frequency, sample_rate);
+
+            let period = sample_rate / frequency;
+            assert!((measure_resonant_period(&mut delay_line) - period).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn reset_clears_echoes_and_feedback_state() {
+        let sample_rate = 1000;
+        let mut delay_line = DelayLine::new(1000, sample_rate);
+        delay_line.set_dry_wet(0.0, 1.0);
+        delay_line.reset_feedback(0.9);
+        delay_line.set_damping(0.5);
+        delay_line.set_diffusion(1.0);
+        delay_line.set_feedback_filter(Some(100.0));
+        delay_line.set_delay_time(100.0, sample_rate as f32);
+        for n in 0..350 {
+            delay_line.process_with_delay(if n == 0 { 1.0 } else { 0.0 });
+        }
+
+        // Without a reset, the echoes would keep ringing out of the full buffer
+        delay_line.reset();
+        for _ in 0..2_000 {
+            assert_eq!(delay_line.process_with_delay(0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn interpolation_modes_read_between_samples() {
+        let buffer = [0.0, 1.0, 4.0, 9.0];
+        let read = |t, interpolation| get_interpolated_value_from_buffer(&buffer, t, interpolation);
+
+        // Whole positions read the stored samples in every mode
+        for interpolation in [
+            Interpolation::None,
+            Interpolation::Linear,
+            Interpolation::Cubic,
+        ] {
+            assert_eq!(read(2.0, interpolation), 4.0);
+        }
+        assert_eq!(read(1.25, Interpolation::None), 1.0);
+        assert_eq!(read(1.75, Interpolation::None), 4.0);
+        assert_eq!(read(1.5, Interpolation::Linear), 2.5);
+        assert!((read(1.5, Interpolation::Cubic) - 2.25).abs() < 1e-6);
+        // Wraps around the end of the buffer
+        assert_eq!(read(3.5, Interpolation::Linear), 4.5);
+        assert!((read(2.0, Interpolation::Sinc) - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sinc_interpolation_is_flatter_than_cubic() {
+        // Reads sines halfway between samples, where interpolators lose the most high end, and
+        // returns the worst deviation from unity gain in dB
+        let worst_deviation_db = |interpolation| {
+            [0.05, 0.1, 0.2, 0.3, 0.35]
+                .iter()
+                .map(|frequency| {
+                    let buffer: Vec<f32> = (0..1_000)
+                        .map(|n| (2. * PI * frequency * n as f32).sin())
+                        .collect();
+                    // Skip the ends, where the reads wrap around the buffer
+                    let range = 10..buffer.len() - 10;
+                    let count = range.len() as f32;
+                    let power = range
+                        .map(|n| {
+                            get_interpolated_value_from_buffer(
+                                &buffer,
+                                n as f32 + 0.5,
+                                interpolation,
+                            )
+                            .powi(2)
+                        })
+                        .sum::<f32>()
+                        / count;
+
+                    // A full scale sine's power is 1/2
+                    (10. * (2. * power).log10()).abs()
+                })
+                .fold(0.0_f32, f32::max)
+        };
+
+        let cubic = worst_deviation_db(Interpolation::Cubic);
+        let sinc = worst_deviation_db(Interpolation::Sinc);
+        assert!(sinc < 0.5);
+        assert!(sinc < cubic / 4.);
+    }
+
+    #[test]
+    fn held_delay_loops_without_new_input() {
+        let mut delay_line = DelayLine::new(100, 1000);
+        delay_line.set_dry_wet(0.0, 1.0);
+        delay_line.set_feedback(0.5);
+        delay_line.set_damping(0.5);
+        delay_line.set_delay_time(20.0, 1000.0);
+        for n in 0..20 {
+            delay_line.process_with_delay((n as f32 * 0.3).sin());
+        }
+
+        // Once the hold has faded in, the loop repeats exactly and ignores the input
+        delay_line.set_hold(true);
+        for _ in 0..100 {
+            delay_line.process_with_delay(1.0);
+        }
+        let period = (delay_line.delay_time + INTERPOLATION_DELAY_SAMPLES) as usize;
+        let first: Vec<f32> = (0..period)
+            .map(|_| delay_line.process_with_delay(1.0))
+            .collect();
+        for _ in 0..100 {
+            let repeat: Vec<f32> = (0..period)
+                .map(|_| delay_line.process_with_delay(-1.0))
+                .collect();
+            assert_eq!(repeat, first);
+        }
+        assert!(first.iter().any(|sample| sample.abs() > 0.01));
+
+        // Releasing the hold lets the loop decay again
+        delay_line.set_hold(false);
+        for _ in 0..10_000 {
+            delay_line.process_with_delay(0.0);
+        }
+        assert!(delay_line.process_with_delay(0.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn feedback_recovers_from_nan_and_stays_bounded() {
+        let mut delay_line = DelayLine::new(100, 1000);
+        delay_line.set_dry_wet(0.0, 1.0);
+        delay_line.set_feedback(1.2);
+        delay_line.set_delay_time(10.0, 1000.0);
+
+        // Runaway feedback is clamped instead of growing without bound
+        for n in 0..10_000 {
+            let output = delay_line.process_with_delay(if n == 0 { 1.0 } else { 0.0 });
+            assert!(output.abs() <= 2.0 * MAX_FEEDBACK_LEVEL);
+        }
+
+        // A NaN input is flushed before it can circulate, so the loop stays finite
+        delay_line.process_with_delay(f32::NAN);
+        for _ in 0..1_000 {
+            assert!(delay_line.process_with_delay(0.0).is_finite());
+        }
+        assert!(delay_line
+            .circular_buffer
+            .iter()
+            .all(|sample| sample.is_finite()));
+    }
+
+    #[test]
+    fn ducked_echoes_return_when_gain_recovers() {
+        let mut plain = DelayLine::new(100, 1000);
+        let mut ducked = DelayLine::new(100, 1000);
+        for delay_line in [&mut plain, &mut ducked] {
+            delay_line.set_dry_wet(0.0, 1.0);
+            delay_line.set_feedback(0.5);
+            delay_line.set_delay_time(10.0, 1000.0);
+        }
+
+        // Fully ducking the first echo silences it but keeps the later repeats intact
+        for n in 0..40 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            let duck_gain = if n < 15 { 0.0 } else { 1.0 };
+            let (plain_out, ducked_out) = (
+                plain.process_with_delay(input),
+                ducked.process_with_ducked_delay(input, duck_gain),
+            );
+            if n < 15 {
+                assert_eq!(ducked_out, 0.0);
+            } else {
+                assert_eq!(ducked_out, plain_out);
+            }
+        }
+    }
+
+    #[test]
+    fn diffusion_smears_repeats_and_is_clean_at_zero() {
+        let sample_rate = 44_100;
+        let echoes = |diffusion: f32| {
+            let mut delay_line = DelayLine::new(sample_rate, sample_rate);
+            delay_line.set_dry_wet(0.0, 1.0);
+            delay_line.set_feedback(0.8);
+            delay_line.set_delay_time(100.0, sample_rate as f32);
+            delay_line.set_diffusion(diffusion);
+            (0..sample_rate)
+                .map(|n| delay_line.process_with_delay(if n == 0 { 1.0 } else { 0.0 }))
+                .collect::<Vec<f32>>()
+        };
+
+        // Without diffusion, every repeat is a single click, as without a diffuser
+        let clean = echoes(0.0);
+        let audible = |signal: &[f32]| signal.iter().filter(|x| x.abs() > 1e-3).count();
+        assert_eq!(audible(&clean), 9);
+
+        // With diffusion, each repeat spreads over more samples than the last. Every repeat is
+        // 100 ms plus the interpolation's 3 samples apart
+        let diffused = echoes(1.0);
+        let repeat = |n: usize| &diffused[n * 4_413..(n + 1) * 4_413];
+        assert!(audible(repeat(2)) > 10);
+        assert!(audible(repeat(3)) > audible(repeat(2)));
+    }
+
+    #[test]
+    fn diffused_tail_decays_at_every_feedback() {
+        let sample_rate = 44_100;
+        for feedback in [0.3, 0.5, 0.9, 0.99] {
+            for diffusion in [0.1, 0.25, 1.0] {
+                for stages in [1, MAX_DIFFUSER_STAGES] {
+                    let mut delay_line = DelayLine::new(sample_rate, sample_rate);
+                    delay_line.set_dry_wet(0.0, 1.0);
+                    delay_line.set_feedback(feedback);
+                    delay_line.set_delay_time(100.0, sample_rate as f32);
+                    delay_line.set_diffusion(diffusion);
+                    delay_line.set_diffusion_stages(stages);
+
+                    // A second of noise, then silence, comparing the tail's peak a second in
+                    // with its peak ten seconds later
+                    let mut seed: u32 = 1;
+                    let mut peaks = [0.0_f32; 11];
+                    for n in 0..11 * sample_rate {
+                        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                        let input = if n < sample_rate {
+                            seed as f32 / u32::MAX as f32 - 0.5
+                        } else {
+                            0.0
+                        };
+                        let output = delay_line.process_with_delay(input);
+                        let peak = &mut peaks[n / sample_rate];
+                        *peak = peak.max(output.abs());
+                    }
+                    assert!(peaks[10] < peaks[1]);
+                    assert!(peaks[1] < 4.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modulation_wobbles_the_pitch_of_the_repeats() {
+        let sample_rate = 44_100;
+        // The shortest and longest periods of a 1 kHz sine through the delay, in samples
+        let period_range = |depth: f32| {
+            let mut delay_line = DelayLine::new(sample_rate, sample_rate);
+            delay_line.set_dry_wet(0.0, 1.0);
+            delay_line.set_feedback(0.0);
+            delay_line.set_delay_time(50.0, sample_rate as f32);
+            delay_line.set_modulation(depth, 5.0);
+            let output: Vec<f32> = Sine::new(1_000.0, 1.0, sample_rate as f32)
+                .take(sample_rate / 2)
+                .map(|x| delay_line.process_with_delay(x))
+                .collect();
+
+            // Skip the empty delay line, and interpolate each crossing between samples
+            let crossings: Vec<f32> = output
+                .windows(2)
+                .enumerate()
+                .skip(sample_rate / 10)
+                .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+                .map(|(n, pair)| n as f32 + pair[0] / (pair[0] - pair[1]))
+                .collect();
+            let periods: Vec<f32> = crossings.windows(2).map(|pair| pair[1] - pair[0]).collect();
+            let shortest = periods.iter().fold(f32::MAX, |min, x| min.min(*x));
+            let longest = periods.iter().fold(0.0_f32, |max, x| max.max(*x));
+            (shortest, longest)
+        };
+
+        // Without depth, the pitch is steady
+        let (shortest, longest) = period_range(0.0);
+        assert!(longest / shortest < 1.001);
+
+        // Sweeping 2 ms at 5 Hz bends the pitch about 3% either way
+        let (shortest, longest) = period_range(2.0);
+        assert!(longest / shortest > 1.05);
+        assert!(longest / shortest < 1.08);
+    }
+
+    #[test]
+    fn feedback_filter_darkens_each_repeat() {
+        let sample_rate = 44_100;
+        // The energy of each 10 ms repeat of a 10 kHz burst through a delay with full feedback
+        let repeat_energies = |cutoff: Option<f32>| {
+            let mut delay_line = DelayLine::new(sample_rate, sample_rate);
+            delay_line.set_dry_wet(0.0, 1.0);
+            delay_line.reset_feedback(0.9);
+            delay_line.set_delay_time(10.0, sample_rate as f32);
+            delay_line.set_feedback_filter(cutoff);
+            let repeat_length = sample_rate / 100;
+            let output: Vec<f32> = (0..4 * repeat_length)
+                .map(|n| {
+                    let input = if n < 100 {
+                        (2.0 * PI * 10_000.0 * n as f32 / sample_rate as f32).sin()
+                    } else {
+                        0.0
+                    };
+                    delay_line.process_with_delay(input)
+                })
+                .collect();
+            output
+                .chunks(repeat_length)
+                .map(|repeat| repeat.iter().map(|x| x * x).sum::<f32>())
+                .collect::<Vec<f32>>()
+        };
+
+        // Without the filter, bright repeats only fade with the feedback
+        let unfiltered = repeat_energies(None);
+        assert!(unfiltered[3] / unfiltered[1] > 0.5);
+
+        // The first echo hasn't been fed back yet, but a 2 kHz cutoff cuts every repeat after it,
+        // and a cutoff far above the burst leaves them be
+        let filtered = repeat_energies(Some(2_000.0));
+        assert_eq!(filtered[1], unfiltered[1]);
+        assert!(filtered[2] < 0.01 * unfiltered[2]);
+        assert!(filtered[3] < filtered[2]);
+        let open = repeat_energies(Some(20_000.0));
+        assert!((open[3] / unfiltered[3] - 1.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn stepping_feedback_has_no_discontinuity() {
+        let sample_rate = 44_100;
+        let mut delay_line = DelayLine::new(sample_rate, sample_rate);
+        delay_line.set_dry_wet(0.0, 1.0);
+        delay_line.set_feedback(0.0);
+        delay_line.set_delay_time(10.0, sample_rate as f32);
+
+        // Settle on a steady echo of a constant input
+        for _ in 0..sample_rate / 10 {
+            delay_line.process_with_delay(0.5);
+        }
+
+        // Stepping the feedback to 0.9 would jump the echo by 0.45 if applied at once
+        delay_line.set_feedback(0.9);
+        let output: Vec<f32> = (0..sample_rate / 10)
+            .map(|_| delay_line.process_with_delay(0.5))
+            .collect();
+        let largest_step = output
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0_f32, f32::max);
+        assert!(largest_step < 0.01);
+
+        // The feedback still reaches its target, building up the echo
+        assert!(*output.last().unwrap() > 2.0);
+    }
+}
@@ -0,0 +1,49 @@
+///
+/// Flushes denormal (subnormal) values to zero. Recursive state that decays during silence
+/// eventually becomes denormal, which is very slow to compute on some CPUs.
+///
+/// # Arguments
+/// * `value` - the value to flush, usually a filter or feedback state
+///
+pub fn flush_denormal(value: f32) -> f32 {
+    if value.abs() < f32::MIN_POSITIVE {
+        0.0
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biquad::{BiquadFilter, BiquadFilterType};
+    use crate::filters::Comb;
+
+    #[test]
+    fn flush_denormal_only_flushes_subnormals() {
+        assert_eq!(flush_denormal(f32::MIN_POSITIVE / 2.0), 0.0);
+        assert_eq!(flush_denormal(-f32::MIN_POSITIVE / 2.0), 0.0);
+        assert_eq!(flush_denormal(f32::MIN_POSITIVE), f32::MIN_POSITIVE);
+        assert_eq!(flush_denormal(-0.5), -0.5);
+    }
+
+    #[test]
+    fn recursive_state_settles_to_zero_in_silence() {
+        let mut biquad = BiquadFilter::new();
+        biquad.set_biquad(BiquadFilterType::LowPass, 0.01, 0.707, 0.0);
+        let mut comb = Comb::new(37);
+        comb.set_feedback(0.84);
+        comb.set_dampening(0.2);
+
+        // A loud burst followed by a long silent tail never lingers in denormal territory
+        for n in 0..200_000 {
+            let input = if n < 1_000 { 1.0 } else { 0.0 };
+            let outputs = [biquad.process(input), comb.tick(input)];
+            for output in outputs {
+                assert!(output == 0.0 || output.is_normal());
+            }
+        }
+        assert_eq!(biquad.process(0.0), 0.0);
+        assert_eq!(comb.tick(0.0), 0.0);
+    }
+}
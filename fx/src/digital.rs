@@ -10,6 +10,154 @@ pub fn floating_point_quantize(input: f32, constant: f32) -> f32 {
     input + constant - constant
 }
 
+///
+/// Floating point quantization that crossfades between the grids of the powers of two around
+/// `constant`. The grid of `floating_point_quantize` only changes size when the constant crosses
+/// a power of two, so sweeping the constant steps audibly; this version moves between grids
+/// gradually instead. At powers of two it matches `floating_point_quantize` exactly.
+///
+/// # Arguments
+/// * `input` - the sample to quantize
+/// * `constant` - the floating point constant, where larger constants quantize more coarsely
+///
+pub fn floating_point_quantize_interpolated(input: f32, constant: f32) -> f32 {
+    let exponent = constant.log2();
+    let lower_constant = 2_f32.powf(exponent.floor());
+    let interpolation = exponent - exponent.floor();
+
+    let lower = floating_point_quantize(input, lower_constant);
+    let upper = floating_point_quantize(input, lower_constant * 2.0);
+    lower + (upper - lower) * interpolation
+}
+
+/// How a decimator fills in the samples between the ones it keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimationMode {
+    /// Holds each kept sample (a zero-order hold), for harsh, aliased steps.
+    Digital,
+    /// Ramps linearly between kept samples (a first-order hold), which attenuates the aliased
+    /// images for a softer, more analog character, at the cost of one reduced-rate sample of
+    /// delay.
+    Smooth,
+}
+
+///
+/// Reduces the sample rate of a signal by keeping only every `factor`-th sample. The factor can
+/// be fractional, so it can be swept smoothly.
+///
+pub struct Decimator {
+    mode: DecimationMode,
+    factor: f32,
+    phase: f32,
+    held: f32,
+    previous_held: f32,
+}
+
+impl Decimator {
+    pub fn new() -> Decimator {
+        Decimator {
+            mode: DecimationMode::Digital,
+            factor: 1.0,
+            phase: 0.0,
+            held: 0.0,
+            previous_held: 0.0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: DecimationMode) {
+        self.mode = mode;
+    }
+
+    ///
+    /// Sets how much to reduce the sample rate by.
+    ///
+    /// # Arguments
+    /// * `factor` - the reduction factor; 1 passes the input through, and 4 keeps every 4th sample
+    ///
+    pub fn set_factor(&mut self, factor: f32) {
+        self.factor = factor.max(1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.held = 0.0;
+        self.previous_held = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.phase += 1.0;
+        if self.phase >= self.factor {
+            self.phase -= self.factor;
+            self.previous_held = self.held;
+            self.held = input;
+        }
+
+        if self.factor <= 1.0 {
+            return input;
+        }
+        match self.mode {
+            DecimationMode::Digital => self.held,
+            DecimationMode::Smooth => {
+                self.previous_held + (self.held - self.previous_held) * (self.phase / self.factor)
+            }
+        }
+    }
+}
+
+impl Default for Decimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///
+/// A triangular (TPDF) dither source. Adding dither before quantizing turns the hard steps
+/// between quantization levels into noise, which masks a moving bit depth at the cost of a
+/// slightly noisier signal.
+///
+/// Give each channel its own dither with its own seed, so the channels' noise is independent
+/// and spreads across the stereo field instead of collapsing to the center.
+///
+#[derive(Clone)]
+pub struct Dither {
+    seed: u32,
+}
+
+impl Dither {
+    ///
+    /// # Arguments
+    /// * `seed` - a nonzero seed for the random number generator
+    ///
+    pub fn new(seed: u32) -> Dither {
+        // Xorshift is linear, so similar seeds start out with similar streams; scrambling the
+        // seed first keeps neighbouring seeds' streams unrelated from the first draw
+        let mut seed = seed.wrapping_mul(0x9E37_79B9);
+        seed ^= seed >> 16;
+        seed = seed.wrapping_mul(0x85EB_CA6B);
+        seed ^= seed >> 13;
+        Dither { seed: seed.max(1) }
+    }
+
+    /// Draws a random value between -1 and 1 with a xorshift generator.
+    fn next_random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    ///
+    /// Draws a dither offset for quantizing to a bit depth, spanning one quantization step
+    /// either way.
+    ///
+    /// # Arguments
+    /// * `bits` - the bit depth the signal will be quantized to
+    ///
+    pub fn next(&mut self, bits: f32) -> f32 {
+        (self.next_random() + self.next_random()) * 0.5 * 2_f32.powf(-bits)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,6 +189,84 @@ mod tests {
         assert_eq!(expected, outputs);
     }
 
+    #[test]
+    fn smooth_decimation_aliases_less_than_digital() {
+        use crate::biquad::{BiquadFilter, BiquadFilterType};
+
+        ///
+        /// Decimates a low sine by 8, then measures the energy left above a quarter of the
+        /// sample rate, which is all aliased images of the sine.
+        ///
+        fn high_frequency_energy(mode: DecimationMode) -> f32 {
+            let mut decimator = Decimator::new();
+            decimator.set_mode(mode);
+            decimator.set_factor(8.0);
+            let mut high_pass = BiquadFilter::new();
+            high_pass.set_biquad(BiquadFilterType::HighPass, 0.25, 0.707, 0.0);
+
+            (0..44_100)
+                .map(|n| {
+                    let input = (2.0 * std::f32::consts::PI * 500.0 * n as f32 / 44_100.0).sin();
+                    high_pass.process(decimator.process(input)).powi(2)
+                })
+                .sum()
+        }
+
+        let digital = high_frequency_energy(DecimationMode::Digital);
+        let smooth = high_frequency_energy(DecimationMode::Smooth);
+        assert!(smooth < digital * 0.1);
+    }
+
+    #[test]
+    fn decimator_passes_through_without_reduction() {
+        for mode in [DecimationMode::Digital, DecimationMode::Smooth] {
+            let mut decimator = Decimator::new();
+            decimator.set_mode(mode);
+            for n in 0..100 {
+                let input = (n as f32 * 0.37).sin();
+                assert_eq!(decimator.process(input), input);
+            }
+        }
+    }
+
+    #[test]
+    fn dither_spans_one_quantization_step() {
+        let mut dither = Dither::new(1);
+        let bits = 4.;
+        let step = 2_f32.powf(-bits);
+        let offsets: Vec<f32> = (0..10_000).map(|_| dither.next(bits)).collect();
+        assert!(offsets.iter().all(|x| x.abs() <= step));
+        let mean = offsets.iter().sum::<f32>() / offsets.len() as f32;
+        assert!(mean.abs() < step * 0.05);
+    }
+
+    #[test]
+    fn differently_seeded_dithers_are_decorrelated_and_equally_loud() {
+        let bits = 8.;
+        let length = 100_000;
+        let mut dither_l = Dither::new(1);
+        let mut dither_r = Dither::new(2);
+        let (mut power_l, mut power_r, mut cross) = (0., 0., 0.);
+        for n in 0..length {
+            let (l, r) = (dither_l.next(bits), dither_r.next(bits));
+            power_l += l * l;
+            power_r += r * r;
+            cross += l * r;
+
+            // Even the first draws of neighbouring seeds are unrelated
+            if n == 0 {
+                assert!((l - r).abs() > 1e-4);
+            }
+        }
+        let correlation = cross / (power_l * power_r).sqrt();
+        assert!(correlation.abs() < 0.02);
+
+        // Triangular dither spanning one step either way has a variance of a sixth of a step
+        let expected_power = 2_f32.powf(-2. * bits) / 6.;
+        assert!((power_l / length as f32 / expected_power - 1.).abs() < 0.03);
+        assert!((power_r / length as f32 / expected_power - 1.).abs() < 0.03);
+    }
+
     #[test]
     fn test_floating_point_quantize() {
         let inputs = vec![0., 0.1, 0.2, 0.5, 0.87, 1.0];
@@ -53,6 +279,36 @@ mod tests {
         println!("{:?}", outputs);
     }
 
+    #[test]
+    fn interpolated_quantize_matches_at_powers_of_two() {
+        let inputs = [0.0, 0.1, -0.2, 0.5, 0.87, -1.0];
+        for constant in [16.0, 1024.0, 65_536.0] {
+            for input in inputs {
+                assert_eq!(
+                    floating_point_quantize_interpolated(input, constant),
+                    floating_point_quantize(input, constant)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn interpolated_quantize_sweeps_without_steps() {
+        // Sweep the constant across a power of two and compare the largest jump between sweeps
+        let input = 0.13;
+        let constants: Vec<f32> = (0..1_000).map(|i| 60_000.0 + i as f32 * 10.0).collect();
+        let largest_step = |quantize: fn(f32, f32) -> f32| {
+            constants
+                .windows(2)
+                .map(|pair| (quantize(input, pair[1]) - quantize(input, pair[0])).abs())
+                .fold(0.0_f32, f32::max)
+        };
+        assert!(
+            largest_step(floating_point_quantize_interpolated)
+                < largest_step(floating_point_quantize) * 0.1
+        );
+    }
+
     #[test]
     fn test_floating_point_quantize_large_constant() {
         let inputs = vec![0., 0.1, 0.2, 0.5, 0.87, 1.0];
@@ -1,11 +1,60 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::oversampling::Oversampler;
+use crate::waveshapers::{get_saturator_gain, get_saturator_output};
+use std::collections::VecDeque;
 use std::f32::consts::E;
 
 const AVERAGE_FACTOR: f32 = 0.9999;
 
+/// How far the linear detector curve moves in one attack or release time, in dB
+pub const LINEAR_DETECTOR_RANGE_DB: f32 = 10.0;
+
+/// How far above the threshold a dynamic EQ band reaches its full gain, in dB
+pub const DYNAMIC_EQ_RANGE_DB: f32 = 12.0;
+
+/// How much gain reduction drives the compressor's warmth fully, in dB
+const WARMTH_FULL_REDUCTION_DB: f32 = 12.0;
+/// The saturator's drive at full warmth and full gain reduction, gentle enough that rounding off
+/// the peaks keeps the level within about a dB of the clean compression
+const MAX_WARMTH_DRIVE: f32 = 0.2;
+
+/// The Q of the key filter's high pass, flat without a resonant bump
+const KEY_HIGH_PASS_Q: f32 = 0.707;
+/// The Q of the key filter's bell, narrow enough to pick out a region like sibilance
+const KEY_BELL_Q: f32 = 2.0;
+/// The highest the key filter's frequencies can go, as a fraction of the sample rate
+const MAX_KEY_FILTER_FC: f32 = 0.45;
+
+/// The shape of a dynamic range processor's attack and release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectorCurve {
+    /// Moves at a constant rate of `LINEAR_DETECTOR_RANGE_DB` per attack or release time, so
+    /// large changes take longer than small ones.
+    Linear,
+    /// A one-pole (RC) curve in decibels, covering 63% of any change in the attack or release
+    /// time. This is the default.
+    Exponential,
+    /// A one-pole curve on the linear gain instead of decibels, like a capacitor smoothing a
+    /// gain voltage. Gain reduction eases in more gently and lets go faster at first, for a
+    /// softer, more analog feel.
+    Logarithmic,
+}
+
+/// Where a dynamic range processor's detector reads the signal from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionTopology {
+    /// Reads the input, as most digital compressors do. This is the default.
+    Feedforward,
+    /// Reads the already compressed output, as many classic analog compressors do. The gain
+    /// reduction corrects itself as it goes, so it follows the signal more smoothly and
+    /// compresses more gently at the same ratio.
+    Feedback,
+}
+
 /// FIXME: Use with extreme caution and low volumes.
 /// I probably implemented this wrong, because Juan Gil's JUCE version sounds fine but this does not.
-/// 
-/// A dynamic range processor capable of compression and expansion.
+///
+/// A dynamic range processor capable of compression, expansion, and gating.
 ///
 /// Code ported from Juan Gil's compressor-expander implementation, which is licensed under GNU:
 // https://github.com/juandagilc/Audio-Effects/blob/master/Compressor-Expander/Source/PluginProcessor.cpp
@@ -23,6 +72,30 @@ pub struct DynamicRangeProcessor {
     attack: f32,
     release: f32,
     is_expander: bool,
+    detector_curve: DetectorCurve,
+    detection: DetectionTopology,
+    /// The last gain-reduced sample, before makeup gain, for feedback detection
+    feedback_input: f32,
+
+    // Gate parameters & state
+    is_gate: bool,
+    range: f32,
+    hold: f32,
+    hold_counter: usize,
+
+    warmth: f32,
+
+    /// The time constant of the makeup gain's smoothing, in seconds
+    makeup_smoothing: f32,
+    /// The smoothed makeup gain, in dB
+    makeup_gain: f32,
+
+    // Key filter, shaping only what the detector hears
+    key_high_pass: BiquadFilter,
+    key_high_pass_cutoff: Option<f32>,
+    key_bell: BiquadFilter,
+    key_bell_frequency: f32,
+    key_bell_gain: f32,
 }
 
 impl DynamicRangeProcessor {
@@ -40,9 +113,38 @@ impl DynamicRangeProcessor {
             attack: 0.,
             release: 0.,
             is_expander: false,
+            detector_curve: DetectorCurve::Exponential,
+            detection: DetectionTopology::Feedforward,
+            feedback_input: 0.,
+            is_gate: false,
+            range: 0.,
+            hold: 0.,
+            hold_counter: 0,
+            warmth: 0.,
+            makeup_smoothing: 0.,
+            makeup_gain: 0.,
+            key_high_pass: BiquadFilter::new(),
+            key_high_pass_cutoff: None,
+            key_bell: BiquadFilter::new(),
+            key_bell_frequency: 0.,
+            key_bell_gain: 0.,
         }
     }
 
+    /// Clears the level detector and gain computer state.
+    pub fn reset(&mut self) {
+        self.input_level = 0.;
+        self.yl_prev = 0.;
+        self.xg = 0.;
+        self.xl = 0.;
+        self.yg = 0.;
+        self.yl = 0.;
+        self.feedback_input = 0.;
+        self.hold_counter = 0;
+        self.key_high_pass.reset();
+        self.key_bell.reset();
+    }
+
     ///
     /// Update the parameters of the dynamic range processor.
     ///
@@ -68,8 +170,144 @@ impl DynamicRangeProcessor {
         self.is_expander = is_expander;
     }
 
+    ///
+    /// Update the gate parameters of the dynamic range processor. When the gate is enabled,
+    /// it takes precedence over compression and expansion, and uses the threshold, attack,
+    /// and release from `set_parameters`.
+    ///
+    /// # Arguments
+    /// * `is_gate` - when true, attenuate the signal by `range` when input falls below threshold
+    /// * `range` - the maximum amount of attenuation when the gate is closed, in dB
+    /// * `hold` - the amount of time to keep the gate open after input falls below threshold, in seconds
+    ///
+    pub fn set_gate_parameters(&mut self, is_gate: bool, range: f32, hold: f32) {
+        self.is_gate = is_gate;
+        self.range = range;
+        self.hold = hold;
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: usize) {
         self.sample_rate = sample_rate;
+        self.update_key_filter();
+    }
+
+    ///
+    /// Sets a high pass on the detector's input only, so the detector ignores the bass while the
+    /// main signal stays unfiltered.
+    ///
+    /// # Arguments
+    /// * `cutoff` - the cutoff in Hz, or `None` to let the detector hear the full signal
+    ///
+    pub fn set_key_high_pass(&mut self, cutoff: Option<f32>) {
+        if cutoff != self.key_high_pass_cutoff {
+            if self.key_high_pass_cutoff.is_none() {
+                // Start from silence rather than whatever was left when it was switched off
+                self.key_high_pass.reset();
+            }
+            self.key_high_pass_cutoff = cutoff;
+            self.update_key_filter();
+        }
+    }
+
+    ///
+    /// Sets a bell on the detector's input only. Boosting a region makes the processor react
+    /// more to it, such as boosting sibilance to de-ess, while the main signal stays unfiltered.
+    ///
+    /// # Arguments
+    /// * `frequency` - the bell's center frequency, in Hz
+    /// * `gain` - the bell's gain in dB; 0 takes the bell out of the detector's path
+    ///
+    pub fn set_key_bell(&mut self, frequency: f32, gain: f32) {
+        if frequency != self.key_bell_frequency || gain != self.key_bell_gain {
+            if self.key_bell_gain == 0. {
+                self.key_bell.reset();
+            }
+            self.key_bell_frequency = frequency;
+            self.key_bell_gain = gain;
+            self.update_key_filter();
+        }
+    }
+
+    fn update_key_filter(&mut self) {
+        let sample_rate = self.sample_rate as f32;
+        if let Some(cutoff) = self.key_high_pass_cutoff {
+            self.key_high_pass.set_biquad(
+                BiquadFilterType::HighPass,
+                (cutoff / sample_rate).clamp(0., MAX_KEY_FILTER_FC),
+                KEY_HIGH_PASS_Q,
+                0.,
+            );
+        }
+        self.key_bell.set_biquad(
+            BiquadFilterType::ParametricEQ,
+            (self.key_bell_frequency / sample_rate).clamp(0., MAX_KEY_FILTER_FC),
+            KEY_BELL_Q,
+            self.key_bell_gain,
+        );
+    }
+
+    /// Filters the detector's input through the key filter.
+    fn filter_key(&mut self, input: f32) -> f32 {
+        let mut key = input;
+        if self.key_high_pass_cutoff.is_some() {
+            key = self.key_high_pass.process(key);
+        }
+        if self.key_bell_gain != 0. {
+            key = self.key_bell.process(key);
+        }
+        key
+    }
+
+    /// Sets the shape of the attack and release.
+    pub fn set_detector_curve(&mut self, detector_curve: DetectorCurve) {
+        self.detector_curve = detector_curve;
+    }
+
+    ///
+    /// Sets whether the detector reads the input or the compressed output. Feedback detection
+    /// only applies to compression; expansion and gating always read the input, since reading
+    /// their own attenuated output would only deepen the attenuation.
+    ///
+    pub fn set_detection_topology(&mut self, detection: DetectionTopology) {
+        self.detection = detection;
+    }
+
+    ///
+    /// Sets the compressor's analog-style warmth, a gentle saturation that grows with the gain
+    /// reduction, so the harder it compresses the more harmonics it adds. Expansion and gating
+    /// stay clean.
+    ///
+    /// # Arguments
+    /// * `warmth` - the amount of saturation from 0 (clean) to 1, reached at 12 dB of reduction
+    ///
+    pub fn set_warmth(&mut self, warmth: f32) {
+        self.warmth = warmth;
+    }
+
+    ///
+    /// Sets how smoothly the makeup gain follows changes, whether it's set by hand or computed
+    /// from the signal, so fast changes don't zipper.
+    ///
+    /// # Arguments
+    /// * `time` - the one-pole time constant in seconds; 0 applies changes immediately
+    ///
+    pub fn set_makeup_smoothing(&mut self, time: f32) {
+        self.makeup_smoothing = time;
+    }
+
+    ///
+    /// Jumps straight to a makeup gain without smoothing, such as before processing starts.
+    ///
+    /// # Arguments
+    /// * `makeup_gain` - the makeup gain, in dB
+    ///
+    pub fn reset_makeup_gain(&mut self, makeup_gain: f32) {
+        self.makeup_gain = makeup_gain;
+    }
+
+    /// The smoothed makeup gain applied to the last processed frame, in dB.
+    pub fn makeup_gain(&self) -> f32 {
+        self.makeup_gain
     }
 
     fn calculate_alpha_time(&self, tau: f32) -> f32 {
@@ -80,6 +318,35 @@ impl DynamicRangeProcessor {
         }
     }
 
+    ///
+    /// Moves the gain reduction towards a target along the detector curve.
+    ///
+    /// # Arguments
+    /// * `target` - the gain reduction to move towards, in dB
+    /// * `is_attack` - whether to move with the attack time rather than the release time
+    ///
+    fn follow_detector_curve(&self, target: f32, is_attack: bool) -> f32 {
+        let time = if is_attack { self.attack } else { self.release };
+        let alpha = self.calculate_alpha_time(time);
+        match self.detector_curve {
+            DetectorCurve::Linear => {
+                if time == 0. {
+                    target
+                } else {
+                    let step = LINEAR_DETECTOR_RANGE_DB / (time * self.sample_rate as f32);
+                    self.yl_prev + (target - self.yl_prev).clamp(-step, step)
+                }
+            }
+            DetectorCurve::Exponential => alpha * self.yl_prev + (1. - alpha) * target,
+            DetectorCurve::Logarithmic => {
+                let gain_prev = 10.0_f32.powf(-self.yl_prev * 0.05);
+                let gain_target = 10.0_f32.powf(-target * 0.05);
+                let gain = alpha * gain_prev + (1. - alpha) * gain_target;
+                -20. * gain.log10()
+            }
+        }
+    }
+
     ///
     /// Convert stereo (2-channel) buffer to mono
     ///
@@ -98,10 +365,17 @@ impl DynamicRangeProcessor {
     pub fn calculate_control_voltage(&mut self, input: f32, makeup_gain: f32) -> f32 {
         // Get internal parameters
         let threshold = self.threshold;
-        let alpha_attack = self.calculate_alpha_time(self.attack);
-        let alpha_release = self.calculate_alpha_time(self.release);
+        let is_feedback =
+            self.detection == DetectionTopology::Feedback && !self.is_gate && !self.is_expander;
 
-        let input_squared = input.powf(2.);
+        // Feedback detection reads the previous output, since the current one isn't known yet
+        let detector_input = if is_feedback {
+            self.feedback_input
+        } else {
+            input
+        };
+        let detector_input = self.filter_key(detector_input);
+        let input_squared = detector_input.powf(2.);
         self.input_level = if self.is_expander {
             AVERAGE_FACTOR * self.input_level + (1. - AVERAGE_FACTOR) * input_squared
         } else {
@@ -114,7 +388,21 @@ impl DynamicRangeProcessor {
             10. * self.input_level.log10()
         };
 
-        if self.is_expander {
+        if self.is_gate {
+            // Gate; stay open while above threshold and until the hold time has passed
+            if self.xg >= threshold {
+                self.hold_counter = (self.hold * self.sample_rate as f32) as usize;
+                self.xl = 0.;
+            } else if self.hold_counter > 0 {
+                self.hold_counter -= 1;
+                self.xl = 0.;
+            } else {
+                self.xl = self.range;
+            }
+
+            // Attack opens the gate, release closes it
+            self.yl = self.follow_detector_curve(self.xl, self.xl < self.yl_prev);
+        } else if self.is_expander {
             // Expand
             self.yg = if self.xg > threshold {
                 self.xg
@@ -124,11 +412,7 @@ impl DynamicRangeProcessor {
 
             self.xl = self.xg - self.yg;
 
-            self.yl = if self.xl < self.yl_prev {
-                alpha_attack * self.yl_prev + (1. - alpha_attack) * self.xl
-            } else {
-                alpha_release * self.yl_prev + (1. - alpha_release) * self.xl
-            };
+            self.yl = self.follow_detector_curve(self.xl, self.xl < self.yl_prev);
         } else {
             // Compress
             self.yg = if self.xg < threshold {
@@ -139,15 +423,24 @@ impl DynamicRangeProcessor {
 
             self.xl = self.xg - self.yg;
 
-            self.yl = if self.xl > self.yl_prev {
-                alpha_attack * self.yl_prev + (1. - alpha_attack) * self.xl
+            if is_feedback {
+                // Reading the output closes a loop through the gain reduction, so never boost,
+                // which would feed on itself, and never move more than halfway to the target in
+                // one sample, so the loop can't overshoot and ring
+                self.xl = self.xl.max(0.);
+                let yl = self.follow_detector_curve(self.xl, self.xl > self.yl_prev);
+                let max_step = 0.5 * (self.xl - self.yl_prev).abs();
+                self.yl = self.yl_prev + (yl - self.yl_prev).clamp(-max_step, max_step);
             } else {
-                alpha_release * self.yl_prev + (1. - alpha_release) * self.xl
-            };
+                self.yl = self.follow_detector_curve(self.xl, self.xl > self.yl_prev);
+            }
         }
 
         let control_voltage = 10.0_f32.powf((makeup_gain - self.yl) * 0.05);
         self.yl_prev = self.yl;
+        if is_feedback {
+            self.feedback_input = input * 10.0_f32.powf(-self.yl * 0.05);
+        }
 
         control_voltage
     }
@@ -166,69 +459,1055 @@ impl DynamicRangeProcessor {
     ///
     /// # Arguments
     /// * `input_frame` - a stereo frame of input
-    /// * `makeup_gain` - the makeup gain to apply after processing, in dB
+    /// * `makeup_gain` - the makeup gain to smooth towards and apply after processing, in dB
     ///
     pub fn process_input_frame(&mut self, input_frame: (f32, f32), makeup_gain: f32) -> (f32, f32) {
-        // Get internal parameters
-        let threshold = self.threshold;
-        let alpha_attack = self.calculate_alpha_time(self.attack);
-        let alpha_release = self.calculate_alpha_time(self.release);
+        let alpha = self.calculate_alpha_time(self.makeup_smoothing);
+        self.makeup_gain = alpha * self.makeup_gain + (1. - alpha) * makeup_gain;
+        let makeup_gain = self.makeup_gain;
 
         let input = (input_frame.0 + input_frame.1) * 0.5;
+        let control_voltage = self.calculate_control_voltage(input, makeup_gain);
 
-        let input_squared = input.powf(2.);
-        self.input_level = if self.is_expander {
-            AVERAGE_FACTOR * self.input_level + (1. - AVERAGE_FACTOR) * input_squared
+        if self.warmth > 0. && !self.is_gate && !self.is_expander && self.yl > 0. {
+            // Saturate the compressed signal before the makeup gain, so the makeup gain doesn't
+            // change how hard it's driven. Dividing out the saturator's gain at rest keeps the
+            // warmth from undoing the reduction it colors
+            let drive =
+                self.warmth * MAX_WARMTH_DRIVE * (self.yl / WARMTH_FULL_REDUCTION_DB).min(1.);
+            let makeup = 10.0_f32.powf(makeup_gain * 0.05);
+            let reduction = control_voltage / makeup;
+            let output_gain = makeup / get_saturator_gain(drive);
+            (
+                get_saturator_output(drive, input_frame.0 * reduction) * output_gain,
+                get_saturator_output(drive, input_frame.1 * reduction) * output_gain,
+            )
         } else {
-            input_squared
+            (
+                input_frame.0 * control_voltage,
+                input_frame.1 * control_voltage,
+            )
+        }
+    }
+}
+
+/// The most a level matcher will boost or cut, as a gain ratio (about 24 dB)
+const MAX_LEVEL_MATCH_GAIN: f32 = 16.0;
+const LEVEL_MATCH_SILENCE: f32 = 1e-10;
+
+///
+/// Matches the loudness of a processed signal to a reference signal by comparing their running
+/// RMS levels. Useful for making nonlinear processing sound equally loud across its settings.
+///
+pub struct LevelMatcher {
+    reference_power: f32,
+    signal_power: f32,
+    alpha: f32,
+}
+
+impl LevelMatcher {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `time_constant` - how quickly the RMS levels follow the signals, in seconds
+    ///
+    pub fn new(sample_rate: f32, time_constant: f32) -> LevelMatcher {
+        let mut level_matcher = LevelMatcher {
+            reference_power: 0.,
+            signal_power: 0.,
+            alpha: 0.,
         };
+        level_matcher.set_time_constant(sample_rate, time_constant);
+        level_matcher
+    }
 
-        self.xg = if self.input_level <= 0.000001 {
-            -60.
+    pub fn set_time_constant(&mut self, sample_rate: f32, time_constant: f32) {
+        self.alpha = E.recip().powf(sample_rate.recip() / time_constant);
+    }
+
+    pub fn reset(&mut self) {
+        self.reference_power = 0.;
+        self.signal_power = 0.;
+    }
+
+    ///
+    /// Scales a processed stereo frame to match the level of a reference stereo frame.
+    ///
+    /// # Arguments
+    /// * `reference` - the frame whose level should be matched, e.g. the input before processing
+    /// * `signal` - the processed frame to scale
+    ///
+    pub fn process(&mut self, reference: (f32, f32), signal: (f32, f32)) -> (f32, f32) {
+        let reference_power = (reference.0.powi(2) + reference.1.powi(2)) * 0.5;
+        let signal_power = (signal.0.powi(2) + signal.1.powi(2)) * 0.5;
+        self.reference_power =
+            self.alpha * self.reference_power + (1. - self.alpha) * reference_power;
+        self.signal_power = self.alpha * self.signal_power + (1. - self.alpha) * signal_power;
+
+        // Leave silence alone rather than chasing noise
+        let gain = if self.reference_power < LEVEL_MATCH_SILENCE
+            || self.signal_power < LEVEL_MATCH_SILENCE
+        {
+            1.
         } else {
-            10. * self.input_level.log10()
+            (self.reference_power / self.signal_power)
+                .sqrt()
+                .clamp(MAX_LEVEL_MATCH_GAIN.recip(), MAX_LEVEL_MATCH_GAIN)
         };
 
-        if self.is_expander {
-            // Compute gain below threshold (expansion)
-            self.yg = if self.xg > threshold {
-                self.xg
-            } else {
-                threshold + (self.xg - threshold) * self.ratio
-            };
+        (signal.0 * gain, signal.1 * gain)
+    }
+}
 
-            self.xl = self.xg - self.yg;
+/// How many times the true peak detector oversamples, which catches inter-sample peaks to
+/// within about 0.2 dB
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_FILTER_ORDER: usize = 8;
+
+///
+/// A look-ahead peak limiter that keeps a stereo signal under a ceiling. The signal is delayed
+/// by the look-ahead, so gain reduction can ramp down before a peak arrives instead of
+/// clipping it.
+///
+/// Each sample's required gain is held at its minimum over the look-ahead window, released
+/// exponentially, then smoothed with a moving average over the same window. Every gain
+/// averaged into a peak's output is already at or below what that peak needs, so the output
+/// never exceeds the ceiling.
+///
+/// A soft knee starts limiting gently below the ceiling. True peak detection oversamples the
+/// detection path to find the peaks between samples, which would otherwise slip through once
+/// the signal is converted to analog or encoded lossily, while the gain is still applied at the
+/// base rate.
+///
+pub struct LookaheadLimiter {
+    ceiling: f32,
+    knee: f32,
+    enabled: bool,
+    release_alpha: f32,
+    true_peak: bool,
+    true_peak_upsamplers: (
+        Oversampler<TRUE_PEAK_OVERSAMPLING>,
+        Oversampler<TRUE_PEAK_OVERSAMPLING>,
+    ),
+    /// How much later the upsamplers report a peak than it occurs, in whole samples
+    true_peak_delay: usize,
+    /// How many samples the gain is held and averaged over; shorter than the look-ahead when
+    /// detecting true peaks, so the gain still ramps down before each peak despite detecting it
+    /// late
+    gain_window: usize,
+    delay_l: Vec<f32>,
+    delay_r: Vec<f32>,
+    /// Required gains in the look-ahead window as (sample index, gain), rising from the front
+    minimum_gains: VecDeque<(usize, f32)>,
+    released_gain: f32,
+    averaged_gains: Vec<f32>,
+    averaged_sum: f64,
+    index: usize,
+    sample_count: usize,
+}
+
+impl LookaheadLimiter {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `latency` - the look-ahead, in samples; the limiter delays its input by this much
+    ///
+    pub fn new(sample_rate: f32, latency: usize) -> LookaheadLimiter {
+        let window = latency + 1;
+        let upsampler = Oversampler::new(TRUE_PEAK_FILTER_ORDER, false);
+        let mut limiter = LookaheadLimiter {
+            ceiling: 1.,
+            knee: 0.,
+            enabled: true,
+            release_alpha: 0.,
+            true_peak: false,
+            true_peak_upsamplers: (upsampler, upsampler),
+            true_peak_delay: upsampler.upsampling_latency().ceil() as usize + 1,
+            gain_window: window,
+            delay_l: vec![0.; window],
+            delay_r: vec![0.; window],
+            minimum_gains: VecDeque::with_capacity(window),
+            released_gain: 1.,
+            averaged_gains: vec![1.; window],
+            averaged_sum: window as f64,
+            index: 0,
+            sample_count: 0,
+        };
+        limiter.set_release(sample_rate, 0.05);
+        limiter
+    }
+
+    /// The delay the look-ahead adds, in samples.
+    pub fn latency(&self) -> usize {
+        self.delay_l.len() - 1
+    }
+
+    /// Sets the highest level the output can reach, in dBFS.
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = 10.0_f32.powf(ceiling * 0.05);
+    }
+
+    ///
+    /// Sets the width of the soft knee, which eases the gain reduction in from a full knee below
+    /// the ceiling. Centering the knee half a knee below the ceiling keeps the output under it.
+    ///
+    /// # Arguments
+    /// * `knee` - the knee width in dB, where 0 is a hard knee
+    ///
+    pub fn set_knee(&mut self, knee: f32) {
+        self.knee = knee.max(0.);
+    }
+
+    ///
+    /// Turns true peak detection on or off. The upsamplers report peaks a few samples late, so
+    /// true peak detection shortens the gain's ramp by that much, and the look-ahead must be
+    /// longer than that delay to catch every peak.
+    ///
+    pub fn set_true_peak(&mut self, true_peak: bool) {
+        if true_peak == self.true_peak {
+            return;
+        }
+        self.true_peak = true_peak;
 
-            // Ballistics; apply attack or release
-            self.yl = if self.xl < self.yl_prev {
-                alpha_attack * self.yl_prev + (1. - alpha_attack) * self.xl
+        let window = self.delay_l.len();
+        self.gain_window = if true_peak {
+            window.saturating_sub(self.true_peak_delay).max(1)
+        } else {
+            window
+        };
+
+        // Average over the latest gains in the new window
+        self.averaged_sum = (1..=self.gain_window)
+            .map(|age| self.averaged_gains[(self.index + window - age) % window] as f64)
+            .sum();
+    }
+
+    /// Sets how long the gain takes to recover after a peak, in seconds.
+    pub fn set_release(&mut self, sample_rate: f32, release: f32) {
+        self.release_alpha = E.recip().powf(sample_rate.recip() / release);
+    }
+
+    ///
+    /// Turns limiting on or off. The look-ahead delay stays either way, so toggling doesn't
+    /// change the latency, and the gain ramps smoothly in and out.
+    ///
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn reset(&mut self) {
+        self.delay_l.fill(0.);
+        self.delay_r.fill(0.);
+        self.minimum_gains.clear();
+        self.released_gain = 1.;
+        self.averaged_gains.fill(1.);
+        self.averaged_sum = self.gain_window as f64;
+        self.index = 0;
+        self.sample_count = 0;
+        self.true_peak_upsamplers.0.reset();
+        self.true_peak_upsamplers.1.reset();
+    }
+
+    /// The highest level of the input, between samples too when detecting true peaks.
+    fn detect_peak(&mut self, input: (f32, f32)) -> f32 {
+        let sample_peak = input.0.abs().max(input.1.abs());
+        if !self.true_peak {
+            return sample_peak;
+        }
+
+        let upsampled_l = self.true_peak_upsamplers.0.upsample(input.0);
+        let upsampled_r = self.true_peak_upsamplers.1.upsample(input.1);
+        upsampled_l
+            .iter()
+            .chain(upsampled_r.iter())
+            .fold(0., |peak, x| peak.max(x.abs()))
+    }
+
+    /// The gain a peak needs to stay under the ceiling, easing in over the soft knee.
+    fn required_gain(&self, peak: f32) -> f32 {
+        if !self.enabled || peak <= 0. {
+            return 1.;
+        }
+        if self.knee == 0. {
+            return if peak > self.ceiling {
+                self.ceiling / peak
             } else {
-                alpha_release * self.yl_prev + (1. - alpha_release) * self.xl
+                1.
             };
+        }
+
+        // A quadratic knee from a full knee below the ceiling up to the ceiling, above which
+        // peaks are held half a knee under it, so the output never reaches the ceiling
+        let overshoot_db = 20. * (peak / self.ceiling).log10();
+        let gain_db = if overshoot_db <= -self.knee {
+            0.
+        } else if overshoot_db < 0. {
+            -(overshoot_db + self.knee).powi(2) / (2. * self.knee)
         } else {
-            // Compute gain above threshold (compression)
-            self.yg = if self.xg < threshold {
-                self.xg
+            -overshoot_db - self.knee / 2.
+        };
+        10.0_f32.powf(gain_db * 0.05)
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let window = self.delay_l.len();
+
+        // The gain this sample needs to stay under the ceiling
+        let peak = self.detect_peak(input);
+        let required_gain = self.required_gain(peak);
+
+        // Hold the minimum required gain over the look-ahead window
+        while let Some(&(_, gain)) = self.minimum_gains.back() {
+            if gain < required_gain {
+                break;
+            }
+            self.minimum_gains.pop_back();
+        }
+        self.minimum_gains
+            .push_back((self.sample_count, required_gain));
+        while let Some(&(sample, _)) = self.minimum_gains.front() {
+            if sample + self.gain_window > self.sample_count {
+                break;
+            }
+            self.minimum_gains.pop_front();
+        }
+        let held_gain = self.minimum_gains.front().map_or(1., |&(_, gain)| gain);
+
+        // Release towards the held gain, but never stay above it
+        self.released_gain = if held_gain < self.released_gain {
+            held_gain
+        } else {
+            held_gain + (self.released_gain - held_gain) * self.release_alpha
+        };
+
+        // Smooth the gain with a moving average over the gain window
+        let oldest = (self.index + window - self.gain_window) % window;
+        self.averaged_sum += (self.released_gain - self.averaged_gains[oldest]) as f64;
+        self.averaged_gains[self.index] = self.released_gain;
+        let gain = (self.averaged_sum / self.gain_window as f64) as f32;
+
+        // Delay the signal by the look-ahead
+        self.delay_l[self.index] = input.0;
+        self.delay_r[self.index] = input.1;
+        self.index = (self.index + 1) % window;
+        self.sample_count += 1;
+        let delayed = (self.delay_l[self.index], self.delay_r[self.index]);
+
+        // Clamp away any rounding error in the running average
+        let limit = if self.enabled { self.ceiling } else { f32::MAX };
+        (
+            (delayed.0 * gain).clamp(-limit, limit),
+            (delayed.1 * gain).clamp(-limit, limit),
+        )
+    }
+}
+
+///
+/// An attack/release envelope that ducks a signal each time it's triggered, independent of the
+/// signal's level. Useful for sidechain-style pumping keyed by MIDI notes.
+///
+pub struct Ducker {
+    sample_rate: f32,
+    envelope: f32,
+    is_attacking: bool,
+    attack_step: f32,
+    alpha_release: f32,
+}
+
+impl Ducker {
+    pub fn new(sample_rate: f32) -> Ducker {
+        let mut ducker = Ducker {
+            sample_rate,
+            envelope: 0.,
+            is_attacking: false,
+            attack_step: 1.,
+            alpha_release: 0.,
+        };
+        ducker.set_times(0., 0.);
+        ducker
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    ///
+    /// # Arguments
+    /// * `attack` - the amount of time to reach full attenuation after a trigger, in seconds
+    /// * `release` - the amount of time to recover from attenuation, in seconds
+    ///
+    pub fn set_times(&mut self, attack: f32, release: f32) {
+        self.attack_step = if attack > 0. {
+            (attack * self.sample_rate).recip().min(1.)
+        } else {
+            1.
+        };
+        self.alpha_release = if release > 0. {
+            E.recip().powf(self.sample_rate.recip() / release)
+        } else {
+            0.
+        };
+    }
+
+    /// Restarts the envelope's attack from its current level.
+    pub fn trigger(&mut self) {
+        self.is_attacking = true;
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.;
+        self.is_attacking = false;
+    }
+
+    ///
+    /// Advances the envelope by one sample and returns the gain to apply.
+    ///
+    /// # Arguments
+    /// * `depth` - the attenuation at the peak of the envelope, in dB
+    ///
+    pub fn next_gain(&mut self, depth: f32) -> f32 {
+        if self.is_attacking {
+            // Ramp linearly up to full attenuation, then start releasing
+            self.envelope += self.attack_step;
+            if self.envelope >= 1. {
+                self.envelope = 1.;
+                self.is_attacking = false;
+            }
+        } else {
+            self.envelope *= self.alpha_release;
+        }
+
+        10.0_f32.powf(-depth * self.envelope * 0.05)
+    }
+}
+
+///
+/// A peak envelope follower with separate attack and release times, for driving parameters from
+/// the level of a signal, like the cutoff of an auto-wah.
+///
+pub struct EnvelopeFollower {
+    sample_rate: f32,
+    envelope: f32,
+    alpha_attack: f32,
+    alpha_release: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(sample_rate: f32) -> EnvelopeFollower {
+        EnvelopeFollower {
+            sample_rate,
+            envelope: 0.,
+            alpha_attack: 0.,
+            alpha_release: 0.,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    fn calculate_alpha_time(&self, tau: f32) -> f32 {
+        if tau > 0. {
+            E.recip().powf(self.sample_rate.recip() / tau)
+        } else {
+            0.
+        }
+    }
+
+    ///
+    /// # Arguments
+    /// * `attack` - how quickly the envelope rises to a louder level, in seconds
+    /// * `release` - how quickly the envelope falls to a quieter level, in seconds
+    ///
+    pub fn set_times(&mut self, attack: f32, release: f32) {
+        self.alpha_attack = self.calculate_alpha_time(attack);
+        self.alpha_release = self.calculate_alpha_time(release);
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.;
+    }
+
+    /// The envelope as of the last processed sample, as a linear amplitude.
+    pub fn envelope(&self) -> f32 {
+        self.envelope
+    }
+
+    ///
+    /// Follows the level of one input sample and returns the envelope, as a linear amplitude.
+    ///
+    pub fn process(&mut self, input: f32) -> f32 {
+        let level = input.abs();
+        let alpha = if level > self.envelope {
+            self.alpha_attack
+        } else {
+            self.alpha_release
+        };
+        self.envelope = alpha * self.envelope + (1. - alpha) * level;
+        self.envelope
+    }
+}
+
+/// The fast and slow followers' times for detecting transients, in seconds
+const TRANSIENT_FAST_ATTACK: f32 = 0.001;
+const TRANSIENT_FAST_RELEASE: f32 = 0.02;
+const TRANSIENT_SLOW_ATTACK: f32 = 0.03;
+const TRANSIENT_SLOW_RELEASE: f32 = 0.1;
+/// How far the fast follower must jump above the slow one to trigger, in dB, at no and at full
+/// sensitivity
+const MIN_SENSITIVITY_JUMP_DB: f32 = 12.0;
+const MAX_SENSITIVITY_JUMP_DB: f32 = 1.0;
+/// Transients quieter than this never trigger, so noise and tails don't retrigger, in dBFS
+const TRANSIENT_FLOOR_DB: f32 = -60.0;
+/// How long the envelope takes to rise to 1 once triggered, in seconds
+const TRANSIENT_ENVELOPE_ATTACK: f32 = 0.002;
+/// Below this the decaying envelope is snapped to 0, so whatever it drives settles
+const TRANSIENT_ENVELOPE_FLOOR: f32 = 1e-5;
+
+///
+/// An envelope that ramps up to 1 over a couple of milliseconds on every transient of its
+/// input, then decays back to 0, like a synth's filter envelope retriggered by each new note.
+/// Retriggering ramps up from wherever the envelope has decayed to.
+///
+/// Transients are found by comparing a fast envelope follower to a slow one: a sudden rise in
+/// level pulls the fast follower above the slow one. Once triggered, the detector waits for the
+/// followers to meet again before it can retrigger, so one note triggers once.
+///
+pub struct TransientEnvelope {
+    sample_rate: f32,
+    fast: EnvelopeFollower,
+    slow: EnvelopeFollower,
+    jump: f32,
+    armed: bool,
+    attacking: bool,
+    envelope: f32,
+    attack_step: f32,
+    sensitivity: f32,
+    decay_seconds: f32,
+    decay: f32,
+}
+
+impl TransientEnvelope {
+    pub fn new(sample_rate: f32) -> TransientEnvelope {
+        let mut envelope = TransientEnvelope {
+            sample_rate,
+            fast: EnvelopeFollower::new(sample_rate),
+            slow: EnvelopeFollower::new(sample_rate),
+            jump: 0.,
+            armed: true,
+            attacking: false,
+            envelope: 0.,
+            attack_step: 1.,
+            sensitivity: f32::NAN,
+            decay_seconds: f32::NAN,
+            decay: 0.,
+        };
+        envelope.set_sample_rate(sample_rate);
+        envelope.set_sensitivity(0.5);
+        envelope.set_decay(0.3);
+        envelope
+    }
+
+    /// Sets the sample rate, retuning the decay to it.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.attack_step = (TRANSIENT_ENVELOPE_ATTACK * sample_rate).recip().min(1.);
+        let decay_seconds = std::mem::replace(&mut self.decay_seconds, f32::NAN);
+        if !decay_seconds.is_nan() {
+            self.set_decay(decay_seconds);
+        }
+        self.fast.set_sample_rate(sample_rate);
+        self.fast
+            .set_times(TRANSIENT_FAST_ATTACK, TRANSIENT_FAST_RELEASE);
+        self.slow.set_sample_rate(sample_rate);
+        self.slow
+            .set_times(TRANSIENT_SLOW_ATTACK, TRANSIENT_SLOW_RELEASE);
+    }
+
+    ///
+    /// # Arguments
+    /// * `sensitivity` - how small a jump in level triggers the envelope, from 0 (a 12 dB jump)
+    ///   to 1 (a 1 dB jump)
+    ///
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        if sensitivity == self.sensitivity {
+            return;
+        }
+        self.sensitivity = sensitivity;
+        let jump_db = MIN_SENSITIVITY_JUMP_DB
+            + (MAX_SENSITIVITY_JUMP_DB - MIN_SENSITIVITY_JUMP_DB) * sensitivity.clamp(0., 1.);
+        self.jump = 10.0_f32.powf(jump_db * 0.05);
+    }
+
+    ///
+    /// # Arguments
+    /// * `decay` - how long the envelope takes to fall from 1 by 60 dB, in seconds
+    ///
+    pub fn set_decay(&mut self, decay: f32) {
+        if decay == self.decay_seconds {
+            return;
+        }
+        self.decay_seconds = decay;
+        self.decay = if decay > 0. {
+            10.0_f32.powf(-3. / (decay * self.sample_rate))
+        } else {
+            0.
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.fast.reset();
+        self.slow.reset();
+        self.armed = true;
+        self.attacking = false;
+        self.envelope = 0.;
+    }
+
+    ///
+    /// Follows one input sample and returns the envelope, from 0 to 1.
+    ///
+    pub fn process(&mut self, input: f32) -> f32 {
+        let fast = self.fast.process(input);
+        let slow = self.slow.process(input);
+
+        let floor = 10.0_f32.powf(TRANSIENT_FLOOR_DB * 0.05);
+        if self.armed && fast > floor && fast > slow * self.jump {
+            self.attacking = true;
+            self.armed = false;
+        } else if fast <= slow {
+            self.armed = true;
+        }
+
+        if self.attacking {
+            self.envelope = (self.envelope + self.attack_step).min(1.);
+            self.attacking = self.envelope < 1.;
+        } else {
+            self.envelope *= self.decay;
+            if self.envelope < TRANSIENT_ENVELOPE_FLOOR {
+                self.envelope = 0.;
+            }
+        }
+        self.envelope
+    }
+}
+
+///
+/// The gain of a dynamic EQ band for the current level of the band. Below the threshold the
+/// band is flat, and above it the band's gain eases in, reaching its full setting
+/// `DYNAMIC_EQ_RANGE_DB` above the threshold.
+///
+/// # Arguments
+/// * `level` - the level of the band, from an envelope follower on the band-passed signal, in dB
+/// * `threshold` - the level where the band starts to engage, in dB
+/// * `gain` - the band's full boost or cut, in dB
+///
+pub fn dynamic_eq_gain(level: f32, threshold: f32, gain: f32) -> f32 {
+    let amount = ((level - threshold) / DYNAMIC_EQ_RANGE_DB).clamp(0., 1.);
+    gain * amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::Sine;
+    use std::f32::consts::{PI, SQRT_2};
+
+    const WARMTH_TEST_SAMPLE_RATE: usize = 48_000;
+
+    ///
+    /// Compresses a 1 kHz sine for a second and returns the last half second, after the detector
+    /// settles.
+    ///
+    fn compressed_sine(amplitude: f32, warmth: f32) -> Vec<f32> {
+        let sample_rate = WARMTH_TEST_SAMPLE_RATE;
+        let mut processor = DynamicRangeProcessor::new(sample_rate);
+        processor.set_parameters(-20., 0.25, 0.01, 0.1, false);
+        processor.set_warmth(warmth);
+        Sine::new(1_000., amplitude, sample_rate as f32)
+            .take(sample_rate)
+            .map(|x| processor.process_input_frame((x, x), 0.).0)
+            .skip(sample_rate / 2)
+            .collect()
+    }
+
+    ///
+    /// Compresses a 1 kHz sine and returns the ratio of its third harmonic to its fundamental,
+    /// measured after the detector settles.
+    ///
+    fn compressed_third_harmonic(amplitude: f32, warmth: f32) -> f32 {
+        let sample_rate = WARMTH_TEST_SAMPLE_RATE;
+        let output = compressed_sine(amplitude, warmth);
+
+        // Whole periods of both harmonics fit in the half second measured
+        let harmonic = |frequency: f32| {
+            let (mut real, mut imag) = (0., 0.);
+            for (n, x) in output.iter().enumerate() {
+                let phase = 2. * PI * frequency * n as f32 / sample_rate as f32;
+                real += x * phase.cos();
+                imag += x * phase.sin();
+            }
+            real.hypot(imag)
+        };
+        harmonic(3_000.) / harmonic(1_000.)
+    }
+
+    #[test]
+    fn warmth_adds_harmonics_only_while_compressing() {
+        // Below the threshold, the compressor stays clean at any warmth
+        assert_eq!(
+            compressed_third_harmonic(0.05, 1.),
+            compressed_third_harmonic(0.05, 0.)
+        );
+
+        // Well above it, full warmth adds far more harmonics than the compression alone
+        let clean = compressed_third_harmonic(0.9, 0.);
+        let warm = compressed_third_harmonic(0.9, 1.);
+        assert!(warm > 10. * clean);
+    }
+
+    #[test]
+    fn warmth_keeps_the_compressed_level() {
+        let rms = |output: Vec<f32>| {
+            (output.iter().map(|x| x * x).sum::<f32>() / output.len() as f32).sqrt()
+        };
+
+        // Well past full warmth's 12 dB of reduction, the warmth colors the signal without
+        // undoing the reduction
+        let clean = rms(compressed_sine(0.9, 0.));
+        let warm = rms(compressed_sine(0.9, 1.));
+        assert!((20. * (warm / clean).log10()).abs() < 1.);
+    }
+
+    #[test]
+    fn key_filter_shapes_detection_without_filtering_the_signal() {
+        // Returns the peak output of a compressed sine, once the detector has settled
+        let compressed_peak = |frequency: f32, key_high_pass: Option<f32>, key_bell_gain: f32| {
+            let sample_rate = 44_100;
+            let mut processor = DynamicRangeProcessor::new(sample_rate);
+            processor.set_parameters(-20., 0.1, 0.001, 0.05, false);
+            processor.set_key_high_pass(key_high_pass);
+            processor.set_key_bell(6_000., key_bell_gain);
+            Sine::new(frequency, 0.5, sample_rate as f32)
+                .take(sample_rate)
+                .map(|x| processor.process_input_frame((x, x), 0.).0)
+                .skip(sample_rate / 2)
+                .fold(0., |peak: f32, x| peak.max(x.abs()))
+        };
+
+        // Loud bass is compressed, unless the key filter's high pass hides it from the detector,
+        // in which case it passes through at its full level
+        assert!(compressed_peak(50., None, 0.) < 0.25);
+        assert!((compressed_peak(50., Some(1_000.), 0.) - 0.5).abs() < 0.01);
+
+        // Boosting the detector around sibilance compresses it harder than other frequencies
+        let plain = compressed_peak(6_000., None, 0.);
+        let boosted = compressed_peak(6_000., None, 12.);
+        assert!(boosted < plain * 0.8);
+    }
+
+    #[test]
+    fn makeup_gain_is_smoothed_over_the_smoothing_time() {
+        let sample_rate = 1_000;
+        let mut processor = DynamicRangeProcessor::new(sample_rate);
+        processor.set_parameters(0., 1., 0., 0., false);
+
+        // Without smoothing, the makeup gain applies immediately
+        let output = processor.process_input_frame((0.1, 0.1), 20.);
+        assert!((output.0 - 1.).abs() < 1e-4);
+
+        // With smoothing, a jump covers 63% of the way, in dB, in one time constant
+        processor.reset_makeup_gain(0.);
+        processor.set_makeup_smoothing(0.01);
+        let mut previous = 0.;
+        for _ in 0..10 {
+            let output = processor.process_input_frame((0.1, 0.1), 20.);
+            assert!(output.0 > previous);
+            previous = output.0;
+        }
+        assert!((processor.makeup_gain() - 20. * (1. - E.recip())).abs() < 0.1);
+    }
+
+    #[test]
+    fn feedback_detection_settles_without_oscillating() {
+        for detector_curve in [
+            DetectorCurve::Linear,
+            DetectorCurve::Exponential,
+            DetectorCurve::Logarithmic,
+        ] {
+            for ratio in [0., 0.25, 0.5, 1., 4., 20.] {
+                for attack in [0., 0.001, 0.01] {
+                    let mut processor = DynamicRangeProcessor::new(44_100);
+                    processor.set_parameters(-30., ratio, attack, 0.05, false);
+                    processor.set_detector_curve(detector_curve);
+                    processor.set_detection_topology(DetectionTopology::Feedback);
+
+                    // The gain reduction only ever moves one way towards where it settles
+                    let reduction: Vec<f32> = (0..10_000)
+                        .map(|_| -20. * processor.calculate_control_voltage(0.5, 0.).log10())
+                        .collect();
+                    assert!(reduction.iter().all(|x| x.is_finite() && *x >= -1e-3));
+                    assert!(reduction.windows(2).all(|pair| pair[1] >= pair[0] - 1e-4));
+
+                    // Reading the reduced output, it settles where the output's reduction
+                    // sustains itself, which is gentler than feedforward detection
+                    let over = 20. * 0.5_f32.log10() + 30.;
+                    let expected = (1. - ratio).max(0.) * over / (2. - ratio.min(1.));
+                    assert!((reduction[9_999] - expected).abs() < 0.05);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transient_envelope_retriggers_on_each_note_and_decays() {
+        let sample_rate = 44_100.;
+        let mut envelope = TransientEnvelope::new(sample_rate);
+        envelope.set_sensitivity(0.5);
+        envelope.set_decay(0.2);
+
+        // Two notes half a second apart, each a held sine starting from silence. Each ramps the
+        // envelope up to 1 over the attack, never jumping.
+        let note_length = sample_rate as usize / 2;
+        let attack_length = (TRANSIENT_ENVELOPE_ATTACK * sample_rate) as usize;
+        let mut triggers = vec![];
+        let mut previous = 0.;
+        for n in 0..2 * note_length {
+            let gate = if n % note_length < note_length / 2 {
+                0.5
             } else {
-                threshold + (self.xg - threshold) * self.ratio
+                0.
             };
+            let input = gate * (n as f32 * 0.05).sin();
+            let output = envelope.process(input);
+            assert!((0. ..=1.).contains(&output));
+            assert!(output - previous <= 1.01 / attack_length as f32);
+            if output == 1. && previous < 1. {
+                triggers.push(n);
+            }
+            previous = output;
+        }
+        assert_eq!(triggers.len(), 2);
+        assert!(triggers[0] >= attack_length && triggers[0] < 100 + attack_length);
+        assert!((triggers[1] - note_length) < 100 + attack_length);
 
-            self.xl = self.xg - self.yg;
+        // A held note triggers once, then the envelope falls 60 dB over the decay time
+        let mut envelope = TransientEnvelope::new(sample_rate);
+        envelope.set_decay(0.2);
+        let output: Vec<f32> = (0..sample_rate as usize)
+            .map(|n| envelope.process(0.5 * (n as f32 * 0.05).sin()))
+            .collect();
+        assert_eq!(output.iter().filter(|x| **x == 1.).count(), 1);
+        let start = output.iter().position(|x| *x == 1.).unwrap();
+        let decayed = output[start + (0.2 * sample_rate) as usize];
+        assert!((20. * decayed.log10() + 60.).abs() < 0.5);
+    }
 
-            // Ballistics; apply attack or release
-            self.yl = if self.xl > self.yl_prev {
-                alpha_attack * self.yl_prev + (1. - alpha_attack) * self.xl
+    #[test]
+    fn gate_attenuates_below_threshold_by_range() {
+        let mut processor = DynamicRangeProcessor::new(1000);
+        processor.set_parameters(-20., 1., 0., 0., false);
+        processor.set_gate_parameters(true, 40., 0.01);
+
+        // Loud input passes through the open gate
+        let loud = processor.process_input_frame((0.5, 0.5), 0.);
+        assert!((loud.0 - 0.5).abs() < 1e-4);
+
+        // Quiet input is held open for the hold time, then attenuated by the range
+        for _ in 0..10 {
+            let held = processor.process_input_frame((0.01, 0.01), 0.);
+            assert!((held.0 - 0.01).abs() < 1e-6);
+        }
+        let gated = processor.process_input_frame((0.01, 0.01), 0.);
+        assert!((gated.0 - 0.0001).abs() < 1e-6);
+    }
+
+    ///
+    /// Feeds a constant level 10 dB over the threshold into a limiter, and returns the gain
+    /// reduction in dB after each sample.
+    ///
+    fn gain_reduction_step_response(detector_curve: DetectorCurve) -> Vec<f32> {
+        let mut processor = DynamicRangeProcessor::new(1000);
+        processor.set_parameters(-30., 0., 0.1, 0.1, false);
+        processor.set_detector_curve(detector_curve);
+        (0..1000)
+            .map(|_| -20. * processor.calculate_control_voltage(0.1, 0.).log10())
+            .collect()
+    }
+
+    #[test]
+    fn detector_curves_reach_target_in_attack_time() {
+        // The attack is 100 samples, and the target is 10 dB of gain reduction
+        let linear = gain_reduction_step_response(DetectorCurve::Linear);
+        assert!((linear[49] - 5.).abs() < 1e-3);
+        assert!((linear[99] - 10.).abs() < 1e-3);
+
+        let exponential = gain_reduction_step_response(DetectorCurve::Exponential);
+        assert!((exponential[99] - 10. * (1. - E.recip())).abs() < 0.05);
+        assert!((exponential[999] - 10.).abs() < 1e-3);
+
+        // The gain itself covers 63% of the way in the attack time
+        let logarithmic = gain_reduction_step_response(DetectorCurve::Logarithmic);
+        let target_gain = 10.0_f32.powf(-0.5);
+        let expected_gain = 1. - (1. - target_gain) * (1. - E.recip());
+        assert!((logarithmic[99] + 20. * expected_gain.log10()).abs() < 0.05);
+        assert!((logarithmic[999] - 10.).abs() < 1e-3);
+
+        // Gain reduction eases in more gently than with the exponential curve
+        assert!(logarithmic[99] < exponential[99]);
+    }
+
+    #[test]
+    fn lookahead_limiter_stays_under_ceiling() {
+        let mut limiter = LookaheadLimiter::new(1000., 8);
+        limiter.set_ceiling(-6.);
+        let ceiling = 10.0_f32.powf(-6. * 0.05);
+
+        // Quiet input passes through unchanged, delayed by the look-ahead
+        let mut output = vec![];
+        for n in 0..100 {
+            output.push(limiter.process(((n as f32 * 0.3).sin() * 0.25, 0.)));
+        }
+        for (n, frame) in output.iter().enumerate().skip(8) {
+            assert_eq!(frame.0, ((n - 8) as f32 * 0.3).sin() * 0.25);
+        }
+
+        // Pathological input, with isolated huge spikes and loud noise, never gets through
+        let mut seed: u32 = 1;
+        for n in 0..10_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (seed as f32 / u32::MAX as f32) * 2. - 1.;
+            let input = if n % 997 == 0 {
+                (1000., -1000.)
             } else {
-                alpha_release * self.yl_prev + (1. - alpha_release) * self.xl
+                (noise * 4., -noise)
             };
+            let output = limiter.process(input);
+            assert!(output.0.abs() <= ceiling && output.1.abs() <= ceiling);
         }
+    }
 
-        let control_voltage = 10.0_f32.powf((makeup_gain - self.yl) * 0.05);
-        self.yl_prev = self.yl;
+    #[test]
+    fn true_peak_detection_catches_inter_sample_peaks() {
+        // A quarter sample rate tone whose samples all fall 3 dB below its peaks, between them
+        let input: Vec<f32> = (0..4_800)
+            .map(|n| 2. * (PI * n as f32 / 2. + PI / 4.).sin())
+            .collect();
+        let steady_true_peak = |true_peak: bool| {
+            let mut limiter = LookaheadLimiter::new(48_000., 48);
+            limiter.set_true_peak(true_peak);
+            let output: Vec<f32> = input.iter().map(|x| limiter.process((*x, *x)).0).collect();
+            let sample_peak = output[2_400..]
+                .iter()
+                .fold(0., |peak: f32, x| peak.max(x.abs()));
+            sample_peak * SQRT_2
+        };
 
-        (
-            input_frame.0 * control_voltage,
-            input_frame.1 * control_voltage,
-        )
+        // Sample peaks are kept under the ceiling either way, but only true peak detection keeps
+        // the peaks between them under it too
+        assert!(steady_true_peak(false) > 1.4);
+        assert!((steady_true_peak(true) - 1.).abs() < 0.02);
+    }
+
+    #[test]
+    fn soft_knee_eases_in_below_the_ceiling() {
+        let peak_db = |knee: f32, amplitude: f32| {
+            let mut limiter = LookaheadLimiter::new(48_000., 48);
+            limiter.set_knee(knee);
+            let output: Vec<f32> = Sine::new(100., amplitude, 48_000.)
+                .take(9_600)
+                .map(|x| limiter.process((x, x)).0)
+                .collect();
+            let peak = output[4_800..]
+                .iter()
+                .fold(0., |peak: f32, x| peak.max(x.abs()));
+            20. * peak.log10()
+        };
+        let minus_3_db = 10.0_f32.powf(-3. * 0.05);
+
+        // 3 dB under the ceiling, a hard knee leaves the signal alone, while a 6 dB knee is
+        // already reducing it by (3 dB)^2 / 12 dB
+        assert!((peak_db(0., minus_3_db) + 3.).abs() < 0.01);
+        assert!((peak_db(6., minus_3_db) + 3.75).abs() < 0.05);
+
+        // Loud peaks are held half a knee under the ceiling
+        assert!((peak_db(6., 4.) + 3.).abs() < 0.05);
+    }
+
+    #[test]
+    fn level_matcher_matches_reference_rms() {
+        let sample_rate = 1000.;
+        let mut level_matcher = LevelMatcher::new(sample_rate, 0.05);
+
+        let mut output = (0., 0.);
+        for n in 0..2000 {
+            let reference = (n as f32 * 0.3).sin() * 0.25;
+            let signal = (reference * 3., reference * 3.);
+            output = level_matcher.process((reference, reference), signal);
+            if n >= 1000 {
+                // The boosted signal is brought back down to the reference level
+                assert!((output.0 - reference).abs() < 0.02);
+            }
+        }
+        assert_eq!(output.0, output.1);
+    }
+
+    #[test]
+    fn dynamic_eq_gain_engages_above_threshold() {
+        // Flat at and below the threshold
+        assert_eq!(dynamic_eq_gain(-40., -20., -6.), 0.);
+        assert_eq!(dynamic_eq_gain(-20., -20., -6.), 0.);
+
+        // Halfway into the range, half the cut
+        let halfway = -20. + DYNAMIC_EQ_RANGE_DB * 0.5;
+        assert!((dynamic_eq_gain(halfway, -20., -6.) + 3.).abs() < 1e-6);
+
+        // The full cut, and never more, once well above the threshold
+        assert_eq!(dynamic_eq_gain(0., -20., -6.), -6.);
+        assert_eq!(dynamic_eq_gain(0., -20., 6.), 6.);
+    }
+
+    #[test]
+    fn envelope_follower_attacks_fast_and_releases_slowly() {
+        let mut follower = EnvelopeFollower::new(1000.);
+        follower.set_times(0.005, 0.1);
+
+        // Rises to the input level within a few attack times
+        let mut envelope = 0.;
+        for _ in 0..50 {
+            envelope = follower.process(-0.8);
+        }
+        assert!((envelope - 0.8).abs() < 1e-3);
+
+        // Falls by a factor of e over one release time
+        for _ in 0..100 {
+            envelope = follower.process(0.);
+        }
+        assert!((envelope - 0.8 * E.recip()).abs() < 1e-2);
+    }
+
+    #[test]
+    fn ducker_attenuates_by_depth_then_recovers() {
+        let sample_rate = 1000.;
+        let mut ducker = Ducker::new(sample_rate);
+        ducker.set_times(0.01, 0.05);
+
+        // Untriggered, the signal passes through
+        assert_eq!(ducker.next_gain(20.), 1.);
+
+        // After the attack, the signal is attenuated by the full depth
+        ducker.trigger();
+        let mut gain = 1.;
+        for _ in 0..10 {
+            gain = ducker.next_gain(20.);
+        }
+        assert!((gain - 0.1).abs() < 1e-4);
+
+        // Then it recovers over the release
+        for _ in 0..1000 {
+            gain = ducker.next_gain(20.);
+        }
+        assert!((gain - 1.).abs() < 1e-4);
     }
 }
@@ -0,0 +1,142 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::waveshapers::get_saturator_output;
+
+const EXCITER_Q: f32 = 0.707;
+
+///
+/// A harmonic exciter, which high-passes the signal, saturates the highs to generate new
+/// upper harmonics, and mixes the excited highs back in with the dry signal for extra "air".
+///
+/// The excited highs are high-passed again after saturating, so the low end is left unchanged.
+///
+pub struct Exciter {
+    frequency: f32,
+    drive: f32,
+    mix: f32,
+    sample_rate: f32,
+    input_filter: BiquadFilter,
+    harmonics_filter: BiquadFilter,
+}
+
+impl Exciter {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(sample_rate: f32) -> Exciter {
+        let mut exciter = Exciter {
+            frequency: 3_000.0,
+            drive: 0.5,
+            mix: 0.0,
+            sample_rate,
+            input_filter: BiquadFilter::new(),
+            harmonics_filter: BiquadFilter::new(),
+        };
+        exciter.set_sample_rate(sample_rate);
+        exciter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_filters();
+    }
+
+    ///
+    /// Sets the crossover frequency, above which the signal is excited.
+    ///
+    /// # Arguments
+    /// * `frequency` - the crossover frequency, in Hz
+    ///
+    pub fn set_frequency(&mut self, frequency: f32) {
+        if frequency != self.frequency {
+            self.frequency = frequency;
+            self.update_filters();
+        }
+    }
+
+    ///
+    /// # Arguments
+    /// * `drive` - how hard the highs are saturated, from 0 (no harmonics) to 1
+    ///
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive;
+    }
+
+    ///
+    /// # Arguments
+    /// * `mix` - how much of the excited highs are added to the dry signal, from 0 to 1
+    ///
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix;
+    }
+
+    fn update_filters(&mut self) {
+        let fc = self.frequency / self.sample_rate;
+        self.input_filter
+            .set_biquad(BiquadFilterType::HighPass, fc, EXCITER_Q, 0.0);
+        self.harmonics_filter
+            .set_biquad(BiquadFilterType::HighPass, fc, EXCITER_Q, 0.0);
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let highs = self.input_filter.process(input);
+
+        // Filter again so the saturation's intermodulation products don't muddy the low end
+        let excited = get_saturator_output(self.drive, highs);
+        let excited = self.harmonics_filter.process(excited);
+        input + excited * self.mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::Sine;
+
+    const SAMPLE_RATE: f32 = 44_100.0;
+
+    ///
+    /// Measures the power of a signal after a filter, skipping the filter's settling time.
+    ///
+    fn filtered_power(signal: &[f32], filter_type: BiquadFilterType, frequency: f32) -> f32 {
+        let mut filter = BiquadFilter::new();
+        filter.set_biquad(filter_type, frequency / SAMPLE_RATE, EXCITER_Q, 0.0);
+        let filtered: Vec<f32> = signal.iter().map(|x| filter.process(*x)).collect();
+        filtered[4_410..].iter().map(|x| x * x).sum::<f32>() / filtered.len() as f32
+    }
+
+    #[test]
+    fn exciter_adds_highs_and_keeps_lows() {
+        // A bass note plus a tone just above the crossover, whose harmonics land above 5 kHz
+        let input: Vec<f32> = Sine::new(100.0, 0.5, SAMPLE_RATE)
+            .zip(Sine::new(2_000.0, 0.3, SAMPLE_RATE))
+            .map(|(low, high)| low + high)
+            .take(SAMPLE_RATE as usize)
+            .collect();
+
+        let mut exciter = Exciter::new(SAMPLE_RATE);
+        exciter.set_frequency(1_500.0);
+        exciter.set_drive(0.8);
+        exciter.set_mix(1.0);
+        let output: Vec<f32> = input.iter().map(|x| exciter.process(*x)).collect();
+
+        let high_gain = filtered_power(&output, BiquadFilterType::HighPass, 5_000.0)
+            / filtered_power(&input, BiquadFilterType::HighPass, 5_000.0);
+        assert!(high_gain > 2.0);
+
+        let low_gain_db = 10.0
+            * (filtered_power(&output, BiquadFilterType::LowPass, 300.0)
+                / filtered_power(&input, BiquadFilterType::LowPass, 300.0))
+            .log10();
+        assert!(low_gain_db.abs() < 0.1);
+    }
+
+    #[test]
+    fn exciter_without_mix_passes_through() {
+        let mut exciter = Exciter::new(SAMPLE_RATE);
+        exciter.set_drive(1.0);
+        for x in Sine::new(5_000.0, 0.9, SAMPLE_RATE).take(1_000) {
+            assert_eq!(exciter.process(x), x);
+        }
+    }
+}
@@ -2,18 +2,58 @@
 // Code here: https://github.com/irh/freeverb-rs/blob/main/src/freeverb/src/freeverb.rs
 // Ian Hobson's `freeverb-rs` is licensed under MIT License.
 
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::denormal::flush_denormal;
+
 /// A delay line with variable buffer size.
 #[derive(Debug)]
 pub struct DelayLine {
     buffer: Vec<f32>,
     index: usize,
+    /// The delay length in use, up to the buffer's length
+    length: usize,
 }
 
 impl DelayLine {
     pub fn new(length: usize) -> DelayLine {
+        DelayLine::with_capacity(length, length)
+    }
+
+    ///
+    /// Creates a delay line with room for longer delays, so its length can be changed later
+    /// with `set_length` without reallocating.
+    ///
+    /// # Arguments
+    /// * `length` - the delay length, in samples
+    /// * `capacity` - the longest delay length it can be set to, in samples
+    ///
+    pub fn with_capacity(length: usize, capacity: usize) -> DelayLine {
+        let capacity = capacity.max(length);
         DelayLine {
-            buffer: vec![0.; length],
+            buffer: vec![0.; capacity],
             index: 0,
+            length,
+        }
+    }
+
+    ///
+    /// Changes the delay length without reallocating, so it's safe on the audio thread. The
+    /// newly added stretch of a longer delay starts silent.
+    ///
+    /// # Arguments
+    /// * `length` - the delay length, in samples, up to the capacity the delay line was created
+    ///   with
+    ///
+    pub fn set_length(&mut self, length: usize) {
+        let length = length.min(self.buffer.len()).max(1);
+        if length > self.length {
+            self.buffer[self.length..length]
+                .iter_mut()
+                .for_each(|sample| *sample = 0.);
+        }
+        self.length = length;
+        if self.index >= length {
+            self.index = 0;
         }
     }
 
@@ -21,10 +61,34 @@ impl DelayLine {
         self.buffer[self.index]
     }
 
+    ///
+    /// Reads the delay line at a fractional position with linear interpolation, for modulating
+    /// the delay length without resizing the buffer.
+    ///
+    /// # Arguments
+    /// * `offset` - how many samples to shorten the delay by, from 0 (the same as `read`) to
+    ///   just under the delay line's length
+    ///
+    pub fn read_fractional(&self, offset: f32) -> f32 {
+        let length = self.length;
+        let offset = offset.clamp(0., (length - 1) as f32);
+        let whole = offset.floor();
+        let fraction = offset - whole;
+        let current = (self.index + whole as usize) % length;
+        let next = (current + 1) % length;
+        self.buffer[current] * (1. - fraction) + self.buffer[next] * fraction
+    }
+
+    /// Clears the delay line without reallocating its buffer.
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0.);
+        self.index = 0;
+    }
+
     pub fn write_and_advance(&mut self, value: f32) {
         self.buffer[self.index] = value;
 
-        if self.index == self.buffer.len() - 1 {
+        if self.index >= self.length - 1 {
             self.index = 0;
         } else {
             self.index += 1;
@@ -32,19 +96,52 @@ impl DelayLine {
     }
 }
 
+/// The allpass feedback coefficient used by Freeverb
+pub const DEFAULT_ALLPASS_FEEDBACK: f32 = 0.5;
+
 /// An allpass filter with a single delay line.
 #[derive(Debug)]
 pub struct Allpass {
     delay_line: DelayLine,
+    feedback: f32,
 }
 
 impl Allpass {
     pub fn new(delay_length: usize) -> Allpass {
+        Allpass::with_capacity(delay_length, delay_length)
+    }
+
+    ///
+    /// Creates an allpass whose delay length can later be changed with `set_delay_length`, up to
+    /// `capacity`, without reallocating.
+    ///
+    pub fn with_capacity(delay_length: usize, capacity: usize) -> Allpass {
         Allpass {
-            delay_line: DelayLine::new(delay_length),
+            delay_line: DelayLine::with_capacity(delay_length, capacity),
+            feedback: DEFAULT_ALLPASS_FEEDBACK,
         }
     }
 
+    /// Changes the delay length without reallocating, up to the allpass's capacity.
+    pub fn set_delay_length(&mut self, delay_length: usize) {
+        self.delay_line.set_length(delay_length);
+    }
+
+    ///
+    /// Sets the feedback coefficient, which controls how much the allpass diffuses its input.
+    ///
+    /// # Arguments
+    /// * `value` - the feedback coefficient, below 1 to keep the filter stable
+    ///
+    pub fn set_feedback(&mut self, value: f32) {
+        self.feedback = value;
+    }
+
+    /// Clears the allpass's delay line.
+    pub fn reset(&mut self) {
+        self.delay_line.reset();
+    }
+
     ///
     /// Process an input value with output and feedback
     /// calculated in the style of Schroeder's allpass filter.
@@ -53,34 +150,190 @@ impl Allpass {
     pub fn tick(&mut self, input: f32) -> f32 {
         let delayed = self.delay_line.read();
         let output = -input + delayed;
-        let feedback = 0.5;
 
         self.delay_line
-            .write_and_advance(input + delayed * feedback);
+            .write_and_advance(input + delayed * self.feedback);
+        output
+    }
+
+    ///
+    /// Processes an input value with a true Schroeder allpass, whose gain is unity at every
+    /// frequency, unlike `tick`'s Freeverb-style approximation, which boosts some frequencies.
+    /// This keeps the allpass safe inside other feedback loops.
+    ///
+    pub fn tick_unity(&mut self, input: f32) -> f32 {
+        let delayed = self.delay_line.read();
+        let output = -self.feedback * input + delayed;
+
+        self.delay_line
+            .write_and_advance(input + self.feedback * output);
         output
     }
 }
 
+/// Delay lengths of the diffuser's allpass filters at 44.1 kHz; short and mutually prime so
+/// their echoes don't line up
+const DIFFUSER_TUNINGS: [usize; 4] = [142, 107, 379, 277];
+/// The most allpass stages a diffuser can chain
+pub const MAX_DIFFUSER_STAGES: usize = DIFFUSER_TUNINGS.len();
+/// The diffuser's allpass feedback at full diffusion
+const MAX_DIFFUSER_FEEDBACK: f32 = 0.7;
+
+///
+/// A chain of short allpass filters that smears transients before they reach a reverb's comb
+/// filters, smoothing the reverb's initial buildup. Diffusion scales both the allpasses'
+/// feedback and how much of the chain is heard, so no diffusion passes the input through.
+///
+/// The allpasses are true Schroeder allpasses with unity gain, so crossfading them with the
+/// input never boosts the level, and the diffuser is safe inside a feedback loop. Broadband
+/// signals dip by up to about 3 dB at half diffusion, where the smeared signal and the input
+/// are mixed evenly.
+///
+#[derive(Debug)]
+pub struct Diffuser {
+    allpasses: [Allpass; 4],
+    diffusion: f32,
+    /// How many of the allpasses are heard, from the first
+    stages: usize,
+}
+
+impl Diffuser {
+    pub fn new(sr: usize) -> Diffuser {
+        let mut diffuser = Diffuser {
+            allpasses: DIFFUSER_TUNINGS.map(|tuning| Allpass::new(tuning * sr / 44_100)),
+            diffusion: 0.,
+            stages: MAX_DIFFUSER_STAGES,
+        };
+        diffuser.set_diffusion(0.);
+        diffuser
+    }
+
+    ///
+    /// Sets the amount of diffusion.
+    ///
+    /// # Arguments
+    /// * `value` - the diffusion amount from 0 (pass-through) to 1
+    ///
+    pub fn set_diffusion(&mut self, value: f32) {
+        self.diffusion = value;
+        for allpass in self.allpasses.iter_mut() {
+            allpass.set_feedback(value * MAX_DIFFUSER_FEEDBACK);
+        }
+    }
+
+    pub fn diffusion(&self) -> f32 {
+        self.diffusion
+    }
+
+    ///
+    /// Sets how many allpass stages are chained. More stages smear the input more densely.
+    ///
+    /// # Arguments
+    /// * `stages` - the number of stages, from 1 to `MAX_DIFFUSER_STAGES`
+    ///
+    pub fn set_stages(&mut self, stages: usize) {
+        self.stages = stages.clamp(1, MAX_DIFFUSER_STAGES);
+    }
+
+    pub fn stages(&self) -> usize {
+        self.stages
+    }
+
+    /// Clears the allpass filters' delay lines.
+    pub fn reset(&mut self) {
+        for allpass in self.allpasses.iter_mut() {
+            allpass.reset();
+        }
+    }
+
+    pub fn tick(&mut self, input: f32) -> f32 {
+        // Keep every allpass running without diffusion or past the last stage, so turning either
+        // up doesn't replay stale input
+        let mut signal = input;
+        let mut diffused = input;
+        for (stage, allpass) in self.allpasses.iter_mut().enumerate() {
+            signal = allpass.tick_unity(signal);
+            if stage < self.stages {
+                diffused = signal;
+            }
+        }
+        input * (1. - self.diffusion) + diffused * self.diffusion
+    }
+}
+
+///
+/// Mixes a stereo frame into the comb filters' inputs. Mono input sends the sum to both sides;
+/// stereo input sends each channel to its own side, doubled to match the sum's level.
+///
+pub(crate) fn mix_input(input: (f32, f32), stereo_input: bool) -> (f32, f32) {
+    if stereo_input {
+        (input.0 * 2.0, input.1 * 2.0)
+    } else {
+        let mixed = input.0 + input.1;
+        (mixed, mixed)
+    }
+}
+
+/// The most resonance the comb's damping filter can have, keeping its poles inside the unit circle
+const MAX_COMB_RESONANCE: f32 = 0.95;
+/// The tail EQ's default center frequency, in the low mids, in Hz
+pub const DEFAULT_TAIL_EQ_FREQUENCY: f32 = 400.0;
+/// A broad Q for the comb's tail EQ, so it shapes the tone of the tail rather than ringing
+const TAIL_EQ_Q: f32 = 0.7;
+
 ///
 /// A low pass feedback comb filter implemented with a single delay line.
 ///
+/// The low pass is a one-pole filter by default. With resonance, it becomes a two-pole filter
+/// that emphasizes a frequency region, normalized so its peak gain never exceeds unity.
+///
 #[derive(Debug)]
 pub struct Comb {
     delay_line: DelayLine,
     feedback: f32,
     filter_state: f32,
+    filter_state_prev: f32,
     dampening: f32,
     dampening_inverse: f32,
+    resonance: f32,
+    // Two-pole damping filter coefficients, as y[n] = b0 x[n] + a1 y[n-1] - a2 y[n-2]
+    b0: f32,
+    a1: f32,
+    a2: f32,
+    drive: f32,
+    // Peaking EQ in the feedback path; bypassed at 0 dB
+    tail_eq: BiquadFilter,
+    tail_eq_fc: f32,
+    tail_eq_gain: f32,
+    tail_eq_scale: f32,
 }
 
 impl Comb {
     pub fn new(delay_length: usize) -> Comb {
+        Comb::with_capacity(delay_length, delay_length)
+    }
+
+    ///
+    /// Creates a comb whose delay length can later be changed with `set_delay_length`, up to
+    /// `capacity`, without reallocating.
+    ///
+    pub fn with_capacity(delay_length: usize, capacity: usize) -> Comb {
         Comb {
-            delay_line: DelayLine::new(delay_length),
+            delay_line: DelayLine::with_capacity(delay_length, capacity),
             feedback: 0.,
             filter_state: 0.,
+            filter_state_prev: 0.,
             dampening: 0.,
             dampening_inverse: 0.,
+            resonance: 0.,
+            b0: 0.,
+            a1: 0.,
+            a2: 0.,
+            drive: 0.,
+            tail_eq: BiquadFilter::new(),
+            tail_eq_fc: 0.,
+            tail_eq_gain: 0.,
+            tail_eq_scale: 1.,
         }
     }
 
@@ -88,17 +341,125 @@ impl Comb {
         self.feedback = value;
     }
 
+    /// Changes the delay length without reallocating, up to the comb's capacity.
+    pub fn set_delay_length(&mut self, delay_length: usize) {
+        self.delay_line.set_length(delay_length);
+    }
+
     pub fn set_dampening(&mut self, value: f32) {
         self.dampening = value;
         self.dampening_inverse = 1.0 - value;
+        self.update_resonant_filter();
+    }
+
+    ///
+    /// Sets the resonance of the damping filter. At 0, the damping is a plain one-pole low pass.
+    ///
+    /// # Arguments
+    /// * `value` - the resonance amount from 0 to 1
+    ///
+    pub fn set_resonance(&mut self, value: f32) {
+        self.resonance = value.clamp(0., 1.) * MAX_COMB_RESONANCE;
+        self.update_resonant_filter();
+    }
+
+    fn update_resonant_filter(&mut self) {
+        // Adding `resonance * (y[n-1] - y[n-2])` to the one-pole low pass keeps unity gain at DC
+        self.a1 = self.dampening + self.resonance;
+        self.a2 = self.resonance;
+
+        // Find the peak gain by minimizing the denominator's squared magnitude over cos(w)
+        let (a1, a2) = (self.a1, self.a2);
+        let cos_peak = if a2 > 0. {
+            (a1 * (1. + a2) / (4. * a2)).clamp(-1., 1.)
+        } else {
+            1.
+        };
+        let denominator = 1. + a1 * a1 + a2 * a2 - 2. * a1 * (1. + a2) * cos_peak
+            + 2. * a2 * (2. * cos_peak * cos_peak - 1.);
+        let peak_gain = self.dampening_inverse / denominator.sqrt();
+
+        // Normalize so the feedback loop's gain never exceeds the comb's feedback
+        self.b0 = self.dampening_inverse / peak_gain.max(1.);
+    }
+
+    ///
+    /// Sets the amount of soft saturation applied inside the feedback loop. The saturation
+    /// has unity gain for quiet signals, and limits the filter state to roughly `1 / drive`.
+    ///
+    /// # Arguments
+    /// * `drive` - the saturation amount; 0 disables saturation entirely
+    ///
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive;
+    }
+
+    ///
+    /// Sets a peaking EQ inside the feedback loop, which compounds on every pass so the tail's
+    /// tone changes as it decays. Boosts are normalized so the loop's gain never exceeds the
+    /// feedback, keeping the comb stable; they lower everything else instead.
+    ///
+    /// # Arguments
+    /// * `fc` - the center frequency, as a fraction of the sample rate
+    /// * `gain` - the peak gain in dB; 0 bypasses the EQ
+    ///
+    pub fn set_tail_eq(&mut self, fc: f32, gain: f32) {
+        if fc == self.tail_eq_fc && gain == self.tail_eq_gain {
+            return;
+        }
+        self.tail_eq_fc = fc;
+        self.tail_eq_gain = gain;
+        self.tail_eq
+            .set_biquad(BiquadFilterType::ParametricEQ, fc, TAIL_EQ_Q, gain);
+        self.tail_eq_scale = 10.0_f32.powf(-gain.max(0.) / 20.);
+    }
+
+    /// Clears the comb's delay line and the state of its damping filter and tail EQ.
+    pub fn reset(&mut self) {
+        self.delay_line.reset();
+        self.filter_state = 0.;
+        self.filter_state_prev = 0.;
+        self.tail_eq.reset();
     }
 
     pub fn tick(&mut self, input: f32) -> f32 {
         let output = self.delay_line.read();
-        self.filter_state = output * self.dampening_inverse + self.filter_state * self.dampening;
+        self.tick_with_output(input, output)
+    }
+
+    ///
+    /// Processes an input value while modulating the comb's delay length, which smooths the
+    /// metallic ringing of a static tail.
+    ///
+    /// # Arguments
+    /// * `input` - the input value
+    /// * `modulation` - how many samples to shorten the delay by; 0 is the same as `tick`
+    ///
+    pub fn tick_modulated(&mut self, input: f32, modulation: f32) -> f32 {
+        let output = self.delay_line.read_fractional(modulation);
+        self.tick_with_output(input, output)
+    }
+
+    fn tick_with_output(&mut self, input: f32, output: f32) -> f32 {
+        let filter_state_prev = self.filter_state;
+        self.filter_state = if self.resonance > 0. {
+            output * self.b0 + self.filter_state * self.a1 - self.filter_state_prev * self.a2
+        } else {
+            output * self.dampening_inverse + self.filter_state * self.dampening
+        };
+        self.filter_state_prev = filter_state_prev;
+        if self.drive > 0. {
+            self.filter_state = (self.filter_state * self.drive).tanh() / self.drive;
+        }
+        self.filter_state = flush_denormal(self.filter_state);
+        let feedback_signal = if self.tail_eq_gain != 0. {
+            self.tail_eq.process(self.filter_state) * self.tail_eq_scale
+        } else {
+            self.filter_state
+        };
 
         self.delay_line
-            .write_and_advance(input + self.filter_state * self.feedback);
+            .write_and_advance(flush_denormal(input + feedback_signal * self.feedback));
 
         output
     }
@@ -106,7 +467,7 @@ impl Comb {
 
 #[cfg(test)]
 mod tests {
-    use super::DelayLine;
+    use super::{Allpass, Comb, DelayLine, Diffuser};
 
     #[test]
     fn length_10() {
@@ -122,4 +483,178 @@ mod tests {
             line.write_and_advance(0.);
         }
     }
+
+    #[test]
+    fn fractional_reads_interpolate_toward_newer_samples() {
+        let mut line = DelayLine::new(4);
+        for i in 0..4 {
+            line.write_and_advance(i as f32);
+        }
+
+        // The oldest sample is read first, and offsets move toward the newest
+        assert_eq!(line.read_fractional(0.), line.read());
+        assert_eq!(line.read_fractional(1.), 1.);
+        assert_eq!(line.read_fractional(2.5), 2.5);
+        assert_eq!(line.read_fractional(10.), 3.);
+    }
+
+    #[test]
+    fn allpass_feedback_sets_diffusion() {
+        let mut allpass = Allpass::new(4);
+        allpass.set_feedback(0.7);
+
+        // An impulse comes out inverted, then echoes every delay length scaled by the feedback
+        let mut output = vec![];
+        for n in 0..12 {
+            output.push(allpass.tick(if n == 0 { 1.0 } else { 0.0 }));
+        }
+        assert_eq!(output[0], -1.0);
+        assert_eq!(output[4], 1.0);
+        assert!((output[8] - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn diffuser_passes_through_without_diffusion() {
+        let mut dry = Diffuser::new(44_100);
+        let mut diffused = Diffuser::new(44_100);
+        diffused.set_diffusion(1.0);
+
+        // Without diffusion an impulse is untouched; with it, the impulse is smeared into echoes
+        let mut dry_output = vec![];
+        let mut diffused_output = vec![];
+        for n in 0..2_000 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            dry_output.push(dry.tick(input));
+            diffused_output.push(diffused.tick(input));
+        }
+        assert_eq!(dry_output[0], 1.0);
+        assert!(dry_output[1..].iter().all(|x| *x == 0.0));
+        assert!(diffused_output.iter().filter(|x| x.abs() > 1e-4).count() > 20);
+    }
+
+    #[test]
+    fn comb_drive_bounds_runaway_feedback() {
+        let mut comb = Comb::new(10);
+        comb.set_feedback(1.0);
+        comb.set_drive(4.0);
+
+        // With full feedback and constant input, the saturated state stays bounded
+        let mut output = 0.;
+        for _ in 0..10_000 {
+            output = comb.tick(1.0);
+        }
+        assert!(output.is_finite());
+        assert!(output <= 1.0 + 4.0_f32.recip() + 1e-6);
+    }
+
+    #[test]
+    fn comb_is_stable_with_tail_eq_at_full_feedback() {
+        for gain in [-12.0, 12.0, 24.0] {
+            let mut comb = Comb::new(37);
+            comb.set_feedback(1.0);
+            comb.set_tail_eq(0.05, gain);
+
+            // A boost is normalized, so an impulse never builds up in the loop
+            let mut peak: f32 = 0.;
+            for n in 0..100_000 {
+                let input = if n == 0 { 1.0 } else { 0.0 };
+                peak = peak.max(comb.tick(input).abs());
+            }
+            assert!(peak <= 1.0 + 1e-3);
+        }
+    }
+
+    #[test]
+    fn comb_is_stable_at_maximum_resonance_and_feedback() {
+        for dampening in [0.0, 0.2, 0.5, 0.9] {
+            let mut comb = Comb::new(37);
+            comb.set_feedback(1.0);
+            comb.set_dampening(dampening);
+            comb.set_resonance(1.0);
+
+            // An impulse never builds up, since the damping filter's gain never exceeds unity
+            let mut peak: f32 = 0.;
+            for n in 0..100_000 {
+                let input = if n == 0 { 1.0 } else { 0.0 };
+                peak = peak.max(comb.tick(input).abs());
+            }
+            assert!(peak <= 1.0 + 1e-3);
+        }
+    }
+
+    #[test]
+    fn delay_line_length_changes_within_capacity() {
+        let mut delay_line = DelayLine::with_capacity(4, 8);
+        let echo_after = |delay_line: &mut DelayLine| {
+            delay_line.reset();
+            delay_line.write_and_advance(1.0);
+            (1..=8)
+                .find(|_| {
+                    let echo = delay_line.read() == 1.0;
+                    delay_line.write_and_advance(0.0);
+                    echo
+                })
+                .unwrap_or(0)
+        };
+        assert_eq!(echo_after(&mut delay_line), 4);
+
+        delay_line.set_length(7);
+        assert_eq!(echo_after(&mut delay_line), 7);
+
+        // Lengths past the capacity are clamped to it
+        delay_line.set_length(100);
+        assert_eq!(echo_after(&mut delay_line), 8);
+    }
+
+    #[test]
+    fn diffuser_keeps_the_level() {
+        for diffusion in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            let mut diffuser = Diffuser::new(44_100);
+            diffuser.set_diffusion(diffusion);
+
+            // White noise from a linear congruential generator
+            let mut seed: u32 = 1;
+            let (mut input_power, mut output_power) = (0.0, 0.0);
+            for n in 0..88_200 {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let input = seed as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                let output = diffuser.tick(input);
+                if n >= 44_100 {
+                    input_power += input * input;
+                    output_power += output * output;
+                }
+            }
+
+            // Never louder, and only a little quieter where the input and smear are mixed evenly
+            let gain_db = 10.0 * (output_power / input_power).log10();
+            assert!(gain_db < 0.5 && gain_db > -3.5);
+        }
+    }
+
+    #[test]
+    fn comb_and_allpass_are_silent_after_reset() {
+        let mut delay_line = DelayLine::new(10);
+        let mut allpass = Allpass::new(10);
+        let mut comb = Comb::new(10);
+        comb.set_feedback(0.9);
+        comb.set_dampening(0.2);
+        comb.set_resonance(0.5);
+        comb.set_tail_eq(0.05, 6.0);
+        for n in 0..25 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            delay_line.write_and_advance(input);
+            allpass.tick(input);
+            comb.tick(input);
+        }
+
+        delay_line.reset();
+        allpass.reset();
+        comb.reset();
+        for _ in 0..100 {
+            assert_eq!(delay_line.read(), 0.0);
+            delay_line.write_and_advance(0.0);
+            assert_eq!(allpass.tick(0.0), 0.0);
+            assert_eq!(comb.tick(0.0), 0.0);
+        }
+    }
 }
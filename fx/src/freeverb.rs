@@ -2,8 +2,12 @@
 // Code here: https://github.com/irh/freeverb-rs/blob/main/src/freeverb/src/freeverb.rs
 // Ian Hobson's `freeverb-rs` is licensed under MIT License.
 
+use crate::filters::mix_input;
 use crate::filters::Allpass;
 use crate::filters::Comb;
+use crate::filters::Diffuser;
+use crate::filters::DEFAULT_TAIL_EQ_FREQUENCY;
+use crate::lfo::Lfo;
 
 /// Tuning for Freeverb can be found here:
 /// https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
@@ -11,37 +15,72 @@ use crate::filters::Comb;
 const SCALE_WET: f32 = 1.3;
 const SCALE_DAMPING: f32 = 0.4;
 
-const STEREO_SPREAD: usize = 23;
+/// The offset between the left and right filter tunings in the original Freeverb, in samples
+/// at 44.1 kHz, which decorrelates the channels of the tail
+pub const DEFAULT_STEREO_SPREAD: usize = 23;
+/// The widest stereo spread, which the right filters' buffers are sized for so the spread can
+/// change without reallocating
+pub const MAX_STEREO_SPREAD: usize = 200;
 const FIXED_GAIN: f32 = 0.015;
 
 const SCALE_ROOM: f32 = 0.28;
+const SCALE_DRIVE: f32 = 20.0;
 const OFFSET_ROOM: f32 = 0.7;
 
+/// The most the comb filters' delays are shortened by at full modulation depth, in seconds
+const MAX_MODULATION_DEPTH_SECONDS: f32 = 0.001;
+/// The default rate of the comb filters' modulation, slow enough to avoid audible vibrato
+pub const DEFAULT_MODULATION_RATE: f32 = 0.5;
+
 const COMB_TUNING_L1: usize = 1116;
-const COMB_TUNING_R1: usize = 1116 + STEREO_SPREAD;
 const COMB_TUNING_L2: usize = 1118;
-const COMB_TUNING_R2: usize = 1118 + STEREO_SPREAD;
 const COMB_TUNING_L3: usize = 1277;
-const COMB_TUNING_R3: usize = 1277 + STEREO_SPREAD;
 const COMB_TUNING_L4: usize = 1356;
-const COMB_TUNING_R4: usize = 1356 + STEREO_SPREAD;
 const COMB_TUNING_L5: usize = 1422;
-const COMB_TUNING_R5: usize = 1422 + STEREO_SPREAD;
 const COMB_TUNING_L6: usize = 1491;
-const COMB_TUNING_R6: usize = 1491 + STEREO_SPREAD;
 const COMB_TUNING_L7: usize = 1557;
-const COMB_TUNING_R7: usize = 1557 + STEREO_SPREAD;
 const COMB_TUNING_L8: usize = 1617;
-const COMB_TUNING_R8: usize = 1617 + STEREO_SPREAD;
 
 const ALLPASS_TUNING_L1: usize = 225;
 const ALLPASS_TUNING_L2: usize = 556;
 const ALLPASS_TUNING_L3: usize = 441;
 const ALLPASS_TUNING_L4: usize = 341;
-const ALLPASS_TUNING_R1: usize = 225 + STEREO_SPREAD;
-const ALLPASS_TUNING_R2: usize = 556 + STEREO_SPREAD;
-const ALLPASS_TUNING_R3: usize = 441 + STEREO_SPREAD;
-const ALLPASS_TUNING_R4: usize = 341 + STEREO_SPREAD;
+
+const COMB_TUNINGS: [usize; 8] = [
+    COMB_TUNING_L1,
+    COMB_TUNING_L2,
+    COMB_TUNING_L3,
+    COMB_TUNING_L4,
+    COMB_TUNING_L5,
+    COMB_TUNING_L6,
+    COMB_TUNING_L7,
+    COMB_TUNING_L8,
+];
+const ALLPASS_TUNINGS: [usize; 4] = [
+    ALLPASS_TUNING_L1,
+    ALLPASS_TUNING_L2,
+    ALLPASS_TUNING_L3,
+    ALLPASS_TUNING_L4,
+];
+
+/// How many of Freeverb's comb filters are fed the input, which sets the echo density.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombDensity {
+    /// Feeds every other comb, 4 per channel, for a thinner, grainier tail.
+    Sparse,
+    /// Feeds all 8 combs per channel, as in the original Freeverb.
+    Dense,
+}
+
+impl CombDensity {
+    /// The distance between fed combs, so sparse combs are spread across all the tunings
+    fn comb_stride(self) -> usize {
+        match self {
+            CombDensity::Sparse => 2,
+            CombDensity::Dense => 1,
+        }
+    }
+}
 
 pub struct Freeverb {
     combs: [(Comb, Comb); 8],
@@ -53,76 +92,60 @@ pub struct Freeverb {
     input_gain: f32,
     dampening: f32,
     room_size: f32,
+    resonance: f32,
+    tail_eq_frequency: f32,
+    tail_eq_gain: f32,
+    diffusers: (Diffuser, Diffuser),
+    stereo_input: bool,
+    stereo_spread: usize,
+    sample_rate: usize,
+    drive: f32,
+    density: CombDensity,
     frozen: bool,
+    modulation_lfo: Lfo,
+    /// The modulation depth, in samples
+    modulation_depth: f32,
+}
+
+/// The mean delay of the left comb filters, in seconds, which sets how fast the tail decays at
+/// a given feedback.
+pub(crate) fn mean_comb_delay() -> f32 {
+    COMB_TUNINGS.iter().sum::<usize>() as f32 / COMB_TUNINGS.len() as f32 / 44100.
 }
 
 fn adjust_length(length: usize, sr: usize) -> usize {
     (length as f32 * sr as f32 / 44100.) as usize
 }
 
-fn generate_comb_filters(sr: usize) -> [(Comb, Comb); 8] {
-    [
-        (
-            Comb::new(adjust_length(COMB_TUNING_L1, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R1, sr)),
-        ),
+fn generate_comb_filters(sr: usize, stereo_spread: usize) -> [(Comb, Comb); 8] {
+    COMB_TUNINGS.map(|tuning| {
         (
-            Comb::new(adjust_length(COMB_TUNING_L2, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R2, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L3, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R3, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L4, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R4, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L5, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R5, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L6, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R6, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L7, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R7, sr)),
-        ),
-        (
-            Comb::new(adjust_length(COMB_TUNING_L8, sr)),
-            Comb::new(adjust_length(COMB_TUNING_R8, sr)),
-        ),
-    ]
+            Comb::new(adjust_length(tuning, sr)),
+            Comb::with_capacity(
+                adjust_length(tuning + stereo_spread, sr),
+                adjust_length(tuning + MAX_STEREO_SPREAD, sr),
+            ),
+        )
+    })
 }
 
-fn generate_allpass_filters(sr: usize) -> [(Allpass, Allpass); 4] {
-    [
-        (
-            Allpass::new(adjust_length(ALLPASS_TUNING_L1, sr)),
-            Allpass::new(adjust_length(ALLPASS_TUNING_R1, sr)),
-        ),
-        (
-            Allpass::new(adjust_length(ALLPASS_TUNING_L2, sr)),
-            Allpass::new(adjust_length(ALLPASS_TUNING_R2, sr)),
-        ),
-        (
-            Allpass::new(adjust_length(ALLPASS_TUNING_L3, sr)),
-            Allpass::new(adjust_length(ALLPASS_TUNING_R3, sr)),
-        ),
+fn generate_allpass_filters(sr: usize, stereo_spread: usize) -> [(Allpass, Allpass); 4] {
+    ALLPASS_TUNINGS.map(|tuning| {
         (
-            Allpass::new(adjust_length(ALLPASS_TUNING_L4, sr)),
-            Allpass::new(adjust_length(ALLPASS_TUNING_R4, sr)),
-        ),
-    ]
+            Allpass::new(adjust_length(tuning, sr)),
+            Allpass::with_capacity(
+                adjust_length(tuning + stereo_spread, sr),
+                adjust_length(tuning + MAX_STEREO_SPREAD, sr),
+            ),
+        )
+    })
 }
 
 impl Freeverb {
     pub fn new(sr: usize) -> Self {
         let mut freeverb = Freeverb {
-            combs: generate_comb_filters(sr),
-            allpasses: generate_allpass_filters(sr),
+            combs: generate_comb_filters(sr, DEFAULT_STEREO_SPREAD),
+            allpasses: generate_allpass_filters(sr, DEFAULT_STEREO_SPREAD),
             wet_gains: (0., 0.),
             wet: 0.,
             dry: 0.,
@@ -130,8 +153,22 @@ impl Freeverb {
             width: 0.,
             dampening: 0.,
             room_size: 0.,
+            resonance: 0.,
+            tail_eq_frequency: DEFAULT_TAIL_EQ_FREQUENCY,
+            tail_eq_gain: 0.,
+            diffusers: (Diffuser::new(sr), Diffuser::new(sr)),
+            stereo_input: false,
+            stereo_spread: DEFAULT_STEREO_SPREAD,
+            sample_rate: sr,
+            drive: 0.,
+            density: CombDensity::Dense,
             frozen: false,
+            modulation_lfo: Lfo::new(sr as f32),
+            modulation_depth: 0.,
         };
+        freeverb
+            .modulation_lfo
+            .set_frequency(DEFAULT_MODULATION_RATE);
 
         freeverb.set_wet(1.0);
         freeverb.set_width(0.5);
@@ -143,8 +180,59 @@ impl Freeverb {
     }
 
     pub fn generate_filters(&mut self, sr: usize) {
-        self.combs = generate_comb_filters(sr);
-        self.allpasses = generate_allpass_filters(sr);
+        self.sample_rate = sr;
+        self.modulation_lfo.set_sample_rate(sr as f32);
+        self.diffusers = (Diffuser::new(sr), Diffuser::new(sr));
+        self.combs = generate_comb_filters(sr, self.stereo_spread);
+        self.allpasses = generate_allpass_filters(sr, self.stereo_spread);
+    }
+
+    ///
+    /// Clears the reverb's tail without reallocating its filters, so a restart doesn't replay
+    /// stale audio.
+    ///
+    pub fn reset(&mut self) {
+        for (comb_l, comb_r) in self.combs.iter_mut() {
+            comb_l.reset();
+            comb_r.reset();
+        }
+        for (allpass_l, allpass_r) in self.allpasses.iter_mut() {
+            allpass_l.reset();
+            allpass_r.reset();
+        }
+        self.diffusers.0.reset();
+        self.diffusers.1.reset();
+        self.modulation_lfo.reset();
+    }
+
+    ///
+    /// Sets the offset between the left and right filter tunings, which controls how
+    /// decorrelated the channels of the tail are, from 0 (identical) to very wide. The right
+    /// filters' buffers already fit the widest spread, so this only changes their delay
+    /// lengths, without reallocating, and is safe on the audio thread.
+    ///
+    /// # Arguments
+    /// * `stereo_spread` - the offset in samples at 44.1 kHz, up to `MAX_STEREO_SPREAD`;
+    ///   `DEFAULT_STEREO_SPREAD` is the original Freeverb's
+    ///
+    pub fn set_stereo_spread(&mut self, stereo_spread: usize) {
+        let stereo_spread = stereo_spread.min(MAX_STEREO_SPREAD);
+        if stereo_spread == self.stereo_spread {
+            return;
+        }
+        self.stereo_spread = stereo_spread;
+
+        let sr = self.sample_rate;
+        for (combs, tuning) in self.combs.iter_mut().zip(COMB_TUNINGS) {
+            combs
+                .1
+                .set_delay_length(adjust_length(tuning + stereo_spread, sr));
+        }
+        for (allpasses, tuning) in self.allpasses.iter_mut().zip(ALLPASS_TUNINGS) {
+            allpasses
+                .1
+                .set_delay_length(adjust_length(tuning + stereo_spread, sr));
+        }
     }
 
     pub fn set_wet(&mut self, value: f32) {
@@ -173,12 +261,93 @@ impl Freeverb {
         self.update_combs();
     }
 
+    ///
+    /// Sets the amount of saturation inside the comb filters' feedback, adding grit to
+    /// the tail and taming runaway feedback at large room sizes.
+    ///
+    /// # Arguments
+    /// * `value` - the saturation amount from 0 (clean) to 1
+    ///
+    pub fn set_drive(&mut self, value: f32) {
+        self.drive = value * SCALE_DRIVE;
+        self.update_combs();
+    }
+
+    /// Sets the resonance of the comb filters' damping, from 0 (one-pole) to 1.
+    pub fn set_resonance(&mut self, value: f32) {
+        self.resonance = value;
+        self.update_combs();
+    }
+
+    ///
+    /// Sets the EQ inside the comb filters' feedback, shaping how the tail's tone evolves.
+    ///
+    /// # Arguments
+    /// * `frequency` - the center frequency, in Hz
+    /// * `gain` - the gain applied on every pass through the feedback, in dB; 0 is neutral
+    ///
+    pub fn set_tail_eq(&mut self, frequency: f32, gain: f32) {
+        self.tail_eq_frequency = frequency;
+        self.tail_eq_gain = gain;
+        self.update_combs();
+    }
+
+    ///
+    /// Sets the amount of input diffusion before the comb filters, which smooths the grainy
+    /// attack on transients.
+    ///
+    /// # Arguments
+    /// * `value` - the diffusion amount from 0 (off) to 1
+    ///
+    pub fn set_diffusion(&mut self, value: f32) {
+        self.diffusers.0.set_diffusion(value);
+        self.diffusers.1.set_diffusion(value);
+    }
+
+    ///
+    /// Sets whether the input stays stereo, feeding the left and right comb filters from their
+    /// own channels, or is summed to mono first. Mono input gives a centered, even tail from
+    /// any source, while stereo input keeps the source's placement in the tail.
+    ///
+    pub fn set_stereo_input(&mut self, stereo_input: bool) {
+        self.stereo_input = stereo_input;
+    }
+
+    ///
+    /// Sets how many comb filters the input is fed into. All the combs keep running, so
+    /// switching density lets the unfed combs' tails ring out instead of cutting them off,
+    /// and nothing is reallocated.
+    ///
+    pub fn set_density(&mut self, density: CombDensity) {
+        self.density = density;
+    }
+
+    ///
+    /// Sets the modulation of the comb filters' delay lengths. Each comb is modulated by the same
+    /// slow sine at a different phase, which smooths the metallic ringing of a static tail
+    /// and adds a subtle chorus, like the modulated reverbs of classic hardware units.
+    ///
+    /// # Arguments
+    /// * `depth` - the modulation depth from 0 (static, the original Freeverb) to 1
+    /// * `rate` - the modulation rate, in Hz
+    ///
+    pub fn set_modulation(&mut self, depth: f32, rate: f32) {
+        self.modulation_depth = depth * MAX_MODULATION_DEPTH_SECONDS * self.sample_rate as f32;
+        self.modulation_lfo.set_frequency(rate);
+    }
+
     fn update_combs(&mut self) {
-        let (feedback, dampening) = if self.frozen {
-            (1.0, 0.0)
+        let (feedback, dampening, resonance, tail_eq_gain) = if self.frozen {
+            (1.0, 0.0, 0.0, 0.0)
         } else {
-            (self.room_size, self.dampening)
+            (
+                self.room_size,
+                self.dampening,
+                self.resonance,
+                self.tail_eq_gain,
+            )
         };
+        let tail_eq_fc = self.tail_eq_frequency / self.sample_rate as f32;
 
         for combs in self.combs.iter_mut() {
             combs.0.set_feedback(feedback);
@@ -186,6 +355,15 @@ impl Freeverb {
 
             combs.0.set_dampening(dampening);
             combs.1.set_dampening(dampening);
+
+            combs.0.set_resonance(resonance);
+            combs.1.set_resonance(resonance);
+
+            combs.0.set_tail_eq(tail_eq_fc, tail_eq_gain);
+            combs.1.set_tail_eq(tail_eq_fc, tail_eq_gain);
+
+            combs.0.set_drive(self.drive);
+            combs.1.set_drive(self.drive);
         }
     }
 
@@ -197,13 +375,40 @@ impl Freeverb {
     }
 
     pub fn tick(&mut self, input: (f32, f32)) -> (f32, f32) {
-        let input_mixed = (input.0 + input.1) * FIXED_GAIN * self.input_gain;
+        // Make up for the fewer fed combs, whose outputs sum roughly by power
+        let stride = self.density.comb_stride();
+        let input_gain = FIXED_GAIN * self.input_gain * (stride as f32).sqrt();
+        let input_mixed = mix_input(input, self.stereo_input);
+        let input_mixed = (
+            self.diffusers.0.tick(input_mixed.0 * input_gain),
+            self.diffusers.1.tick(input_mixed.1 * input_gain),
+        );
         let mut out = (0.0, 0.0);
 
-        for combs in self.combs.iter_mut() {
-            out.0 += combs.0.tick(input_mixed);
-            out.1 += combs.1.tick(input_mixed);
+        let comb_count = self.combs.len() as f32;
+        for (index, combs) in self.combs.iter_mut().enumerate() {
+            let comb_input = if index % stride == 0 {
+                input_mixed
+            } else {
+                (0.0, 0.0)
+            };
+            if self.modulation_depth > 0. {
+                // Spread the combs' phases across the cycle, with the right side in quadrature
+                let phase = index as f32 / comb_count;
+                let modulation_l = 0.5 * (1. + self.modulation_lfo.value_at(phase));
+                let modulation_r = 0.5 * (1. + self.modulation_lfo.value_at(phase + 0.25));
+                out.0 += combs
+                    .0
+                    .tick_modulated(comb_input.0, modulation_l * self.modulation_depth);
+                out.1 += combs
+                    .1
+                    .tick_modulated(comb_input.1, modulation_r * self.modulation_depth);
+            } else {
+                out.0 += combs.0.tick(comb_input.0);
+                out.1 += combs.1.tick(comb_input.1);
+            }
         }
+        self.modulation_lfo.advance();
         for allpasses in self.allpasses.iter_mut() {
             out.0 = allpasses.0.tick(out.0);
             out.1 = allpasses.1.tick(out.1);
@@ -215,3 +420,131 @@ impl Freeverb {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Feeds an impulse through the reverb, then checks which left combs it reached.
+    ///
+    fn fed_combs(density: CombDensity) -> Vec<bool> {
+        let mut freeverb = Freeverb::new(44_100);
+        freeverb.set_density(density);
+
+        // Stop before the shortest comb starts echoing, so the impulse sits in the fed combs
+        freeverb.tick((1.0, 1.0));
+        for _ in 0..1_000 {
+            assert_eq!(freeverb.tick((0.0, 0.0)), (0.0, 0.0));
+        }
+
+        freeverb
+            .combs
+            .iter_mut()
+            .map(|combs| (0..1_000).any(|_| combs.0.tick(0.0) != 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn stereo_input_keeps_channels_apart() {
+        let mut mono = Freeverb::new(44_100);
+        let mut stereo = Freeverb::new(44_100);
+        stereo.set_stereo_input(true);
+        mono.set_width(1.0);
+        stereo.set_width(1.0);
+
+        // Input only on the left reaches the right tail only when summed to mono
+        let mut mono_right: f32 = 0.;
+        let mut stereo_right: f32 = 0.;
+        for n in 0..10_000 {
+            let input = if n == 0 { (1.0, 0.0) } else { (0.0, 0.0) };
+            mono_right = mono_right.max(mono.tick(input).1.abs());
+            stereo_right = stereo_right.max(stereo.tick(input).1.abs());
+        }
+        assert!(mono_right > 0.0);
+        assert_eq!(stereo_right, 0.0);
+    }
+
+    #[test]
+    fn stereo_spread_decorrelates_channels() {
+        // Returns how far apart the channels of the tail of a mono impulse are
+        let channel_difference = |stereo_spread: usize| {
+            let mut freeverb = Freeverb::new(44_100);
+            freeverb.set_width(1.0);
+            freeverb.set_stereo_spread(stereo_spread);
+            freeverb.set_room_size(0.5);
+            freeverb.set_damping(0.5);
+            (0..10_000)
+                .map(|n| {
+                    let input = if n == 0 { (1.0, 1.0) } else { (0.0, 0.0) };
+                    let (out_l, out_r) = freeverb.tick(input);
+                    (out_l - out_r).abs()
+                })
+                .fold(0.0_f32, f32::max)
+        };
+        assert_eq!(channel_difference(0), 0.0);
+        assert!(channel_difference(DEFAULT_STEREO_SPREAD) > 0.0);
+    }
+
+    #[test]
+    fn stereo_spread_changes_without_regenerating_filters() {
+        let mut regenerated = Freeverb::new(44_100);
+        regenerated.stereo_spread = 150;
+        regenerated.generate_filters(44_100);
+        // Regenerated combs start without their feedback settings
+        regenerated.set_room_size(0.5);
+        let mut respread = Freeverb::new(44_100);
+        respread.set_stereo_spread(150);
+
+        for n in 0..10_000 {
+            let input = if n == 0 { (1.0, 1.0) } else { (0.0, 0.0) };
+            assert_eq!(regenerated.tick(input), respread.tick(input));
+        }
+    }
+
+    #[test]
+    fn modulation_changes_the_tail_and_stays_stable() {
+        let mut static_reverb = Freeverb::new(44_100);
+        let mut modulated_reverb = Freeverb::new(44_100);
+        modulated_reverb.set_modulation(1.0, 2.0);
+
+        // The tails start identical, then drift apart as the comb delays move
+        let mut difference: f32 = 0.;
+        let mut peak: f32 = 0.;
+        for n in 0..44_100 {
+            let input = if n == 0 { (1.0, 1.0) } else { (0.0, 0.0) };
+            let static_out = static_reverb.tick(input);
+            let modulated_out = modulated_reverb.tick(input);
+            difference = difference.max((static_out.0 - modulated_out.0).abs());
+            peak = peak.max(modulated_out.0.abs().max(modulated_out.1.abs()));
+        }
+        assert!(difference > 1e-3);
+        assert!(peak.is_finite() && peak < 1.0);
+    }
+
+    #[test]
+    fn reset_silences_the_tail() {
+        let mut freeverb = Freeverb::new(44_100);
+        freeverb.set_diffusion(1.0);
+        freeverb.set_modulation(1.0, 2.0);
+        freeverb.set_frozen(true);
+        for n in 0..1_000 {
+            freeverb.tick(if n == 0 { (1.0, 1.0) } else { (0.0, 0.0) });
+        }
+
+        // Even a frozen tail, which would otherwise ring forever, falls silent immediately
+        freeverb.reset();
+        for _ in 0..10_000 {
+            assert_eq!(freeverb.tick((0.0, 0.0)), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn sparse_density_feeds_every_other_comb() {
+        assert!(fed_combs(CombDensity::Dense).iter().all(|fed| *fed));
+        assert_eq!(
+            fed_combs(CombDensity::Sparse),
+            [true, false, true, false, true, false, true, false]
+        );
+    }
+}
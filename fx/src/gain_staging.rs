@@ -0,0 +1,133 @@
+//! Gain staging checks: every block should leave the loudness of a signal unchanged at its
+//! neutral settings, so A/B comparisons aren't skewed by level differences.
+
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::bypass::BypassCrossfade;
+use crate::crossover::Crossover;
+use crate::dc_filter::DcFilter;
+use crate::delay_line::{DelayLine, StereoDelay};
+use crate::digital::bitcrush_sample;
+use crate::dynamics::DynamicRangeProcessor;
+use crate::oversampling::Oversampler;
+use crate::signal::PinkNoise;
+use crate::waveshapers::{
+    get_dropout_output, get_fuzzy_rectifier_output, get_saturating_hard_clipper_output,
+    get_saturator_output, get_shockley_diode_rectifier_output, get_wavefolder_output,
+};
+
+const SAMPLE_RATE: usize = 44_100;
+
+/// Two seconds of noise, so slow filters and short delays barely affect the measurement
+const NOISE_LENGTH: usize = 2 * SAMPLE_RATE;
+
+/// The RMS level of the test noise, in dBFS; low enough that waveshapers stay near their
+/// small-signal gain
+const NOISE_LEVEL_DB: f32 = -20.0;
+
+/// How far the output level may drift from the input level, in dB
+const TOLERANCE_DB: f32 = 1.0;
+
+///
+/// Generates pink noise at `NOISE_LEVEL_DB`.
+///
+fn pink_noise() -> Vec<f32> {
+    let noise: Vec<f32> = PinkNoise::new(0x1234_5678).take(NOISE_LENGTH).collect();
+    let scale = 10.0_f32.powf((NOISE_LEVEL_DB - rms_db(&noise)) / 20.0);
+    noise.iter().map(|x| x * scale).collect()
+}
+
+fn rms_db(signal: &[f32]) -> f32 {
+    let power = signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32;
+    10.0 * power.log10()
+}
+
+///
+/// Runs pink noise through a block and asserts its output is as loud as its input.
+///
+/// # Arguments
+/// * `name` - the name of the block, for the failure message
+/// * `process` - the block's processing, one sample at a time
+///
+fn assert_unity_gain(name: &str, mut process: impl FnMut(f32) -> f32) {
+    let input = pink_noise();
+    let output: Vec<f32> = input.iter().map(|x| process(*x)).collect();
+    let difference = rms_db(&output) - rms_db(&input);
+    assert!(
+        difference.abs() <= TOLERANCE_DB,
+        "{} changes the level by {:.2} dB at neutral settings",
+        name,
+        difference
+    );
+}
+
+#[test]
+fn filters_are_unity_gain_at_zero_db() {
+    for filter_type in [
+        BiquadFilterType::ParametricEQ,
+        BiquadFilterType::LowShelf,
+        BiquadFilterType::HighShelf,
+    ] {
+        let mut biquad = BiquadFilter::new();
+        biquad.set_biquad(filter_type, 0.02, 0.707, 0.0);
+        assert_unity_gain(&format!("{:?}", filter_type), |x| biquad.process(x));
+    }
+
+    let mut dc_filter = DcFilter::default();
+    assert_unity_gain("DcFilter", |x| dc_filter.process(x));
+}
+
+#[test]
+fn crossover_bands_sum_to_unity_gain() {
+    let mut crossover: Crossover<3> = Crossover::new(&[200.0, 2_000.0], SAMPLE_RATE as f32);
+    assert_unity_gain("Crossover", |x| crossover.split(x).iter().sum());
+}
+
+#[test]
+fn dynamics_are_unity_gain_at_one_to_one() {
+    for is_expander in [false, true] {
+        let mut processor = DynamicRangeProcessor::new(SAMPLE_RATE);
+        processor.set_parameters(-40.0, 1.0, 0.01, 0.1, is_expander);
+        assert_unity_gain("DynamicRangeProcessor", |x| {
+            processor.process_input_frame((x, x), 0.0).0
+        });
+    }
+}
+
+#[test]
+fn delays_are_unity_gain_without_feedback() {
+    let mut delay_line = DelayLine::new(SAMPLE_RATE, SAMPLE_RATE);
+    delay_line.set_delay_time(10.0, SAMPLE_RATE as f32);
+    delay_line.set_feedback(0.0);
+    delay_line.set_dry_wet(0.0, 1.0);
+    assert_unity_gain("DelayLine", |x| delay_line.process_with_delay(x));
+
+    let mut stereo_delay = StereoDelay::new(0.1, SAMPLE_RATE);
+    assert_unity_gain("StereoDelay", |x| {
+        stereo_delay.process_with_vibrato((x, x), 5.0, 0.005, 0.0).0
+    });
+}
+
+#[test]
+fn bypass_and_oversampling_are_unity_gain() {
+    let mut bypass = BypassCrossfade::new(SAMPLE_RATE as f32);
+    assert_unity_gain("BypassCrossfade", |x| bypass.tick(false, (x, x), (x, x)).0);
+
+    let mut oversampler: Oversampler<4> = Oversampler::new(8, false);
+    assert_unity_gain("Oversampler", |x| oversampler.process(x, |y| y));
+}
+
+// The double soft clipper is left out, since its dead zone around zero is part of its sound
+#[test]
+fn waveshapers_are_unity_gain_without_drive() {
+    assert_unity_gain("Saturator", |x| get_saturator_output(0.0, x));
+    assert_unity_gain("Saturating hard clipper", |x| {
+        get_saturating_hard_clipper_output(0.0, x)
+    });
+    assert_unity_gain("Fuzzy rectifier", |x| get_fuzzy_rectifier_output(0.0, x));
+    assert_unity_gain("Shockley diode rectifier", |x| {
+        get_shockley_diode_rectifier_output(0.0, x)
+    });
+    assert_unity_gain("Dropout", |x| get_dropout_output(0.0, x));
+    assert_unity_gain("Wavefolder", |x| get_wavefolder_output(0.0, 0.0, 1.0, x));
+    assert_unity_gain("Bitcrusher", |x| bitcrush_sample(x, 16.0));
+}
@@ -0,0 +1,271 @@
+use std::f32::consts::PI;
+
+/// Waveforms available to the low frequency oscillator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LfoWaveform {
+    Sine,
+    Triangle,
+    Square,
+    SawUp,
+    SawDown,
+}
+
+///
+/// Calculates the value of a bipolar waveform at a given phase.
+///
+/// # Arguments
+/// * `waveform` - the shape of the waveform
+/// * `phase` - the phase of the waveform, where 1.0 is a full cycle
+///
+pub fn get_waveform_value(waveform: LfoWaveform, phase: f32) -> f32 {
+    let phase = phase - phase.floor();
+    match waveform {
+        LfoWaveform::Sine => (2.0 * PI * phase).sin(),
+        LfoWaveform::Triangle => {
+            // Starts at zero and rises, like the sine
+            if phase < 0.25 {
+                4.0 * phase
+            } else if phase < 0.75 {
+                2.0 - 4.0 * phase
+            } else {
+                4.0 * phase - 4.0
+            }
+        }
+        LfoWaveform::Square => {
+            if phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        LfoWaveform::SawUp => 2.0 * phase - 1.0,
+        LfoWaveform::SawDown => 1.0 - 2.0 * phase,
+    }
+}
+
+/// A low frequency oscillator producing bipolar values between -1 and 1.
+///
+/// The LFO is naive (not band-limited), so it's meant for modulating parameters
+/// rather than being heard directly.
+#[derive(Debug)]
+pub struct Lfo {
+    waveform: LfoWaveform,
+    phase: f32,
+    frequency: f32,
+    sample_rate: f32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32) -> Lfo {
+        Lfo {
+            waveform: LfoWaveform::Sine,
+            phase: 0.0,
+            frequency: 1.0,
+            sample_rate,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Sets the frequency of the LFO, in Hz.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_waveform(&mut self, waveform: LfoWaveform) {
+        self.waveform = waveform;
+    }
+
+    /// Resets the phase of the LFO to the start of its cycle.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    ///
+    /// Jumps to a phase, for locking the LFO to a host's beat position.
+    ///
+    /// # Arguments
+    /// * `phase` - the new phase, where 1.0 is a full cycle
+    ///
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase - phase.floor();
+    }
+
+    ///
+    /// Gets the current value of the LFO without advancing it.
+    ///
+    /// # Arguments
+    /// * `phase_offset` - an offset added to the current phase, where 1.0 is a full cycle
+    ///
+    pub fn value_at(&self, phase_offset: f32) -> f32 {
+        get_waveform_value(self.waveform, self.phase + phase_offset)
+    }
+
+    /// Advances the phase of the LFO by one sample.
+    pub fn advance(&mut self) {
+        self.phase += self.frequency / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+        }
+    }
+
+    /// Gets the current value of the LFO, then advances it by one sample.
+    pub fn tick(&mut self) -> f32 {
+        let value = self.value_at(0.0);
+        self.advance();
+        value
+    }
+}
+
+/// A smooth random LFO producing bipolar values between -1 and 1, for irregular movement
+/// like tape wow and flutter.
+///
+/// A new random target is drawn every cycle, and the output glides between targets with
+/// cosine interpolation, making a band-limited random walk. It doesn't allocate, so it's
+/// safe to run on the audio thread.
+#[derive(Debug)]
+pub struct RandomLfo {
+    /// The targets at the start of the current cycle, the next cycle, and the one after
+    targets: [f32; 3],
+    phase: f32,
+    frequency: f32,
+    sample_rate: f32,
+    seed: u32,
+}
+
+impl RandomLfo {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `seed` - a nonzero seed for the random number generator
+    ///
+    pub fn new(sample_rate: f32, seed: u32) -> RandomLfo {
+        let mut lfo = RandomLfo {
+            targets: [0.0; 3],
+            phase: 0.0,
+            frequency: 1.0,
+            sample_rate,
+            seed: seed.max(1),
+        };
+        lfo.targets = [lfo.next_random(), lfo.next_random(), lfo.next_random()];
+        lfo
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Sets how often the LFO picks a new target, in Hz.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// Draws a random value between -1 and 1 with a xorshift generator.
+    fn next_random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    ///
+    /// Gets the current value of the LFO without advancing it.
+    ///
+    /// # Arguments
+    /// * `phase_offset` - an offset added to the current phase, where 1.0 is a full cycle
+    ///
+    pub fn value_at(&self, phase_offset: f32) -> f32 {
+        let phase_offset = phase_offset - phase_offset.floor();
+        let position = self.phase + phase_offset;
+        let (from, to, fraction) = if position < 1.0 {
+            (self.targets[0], self.targets[1], position)
+        } else {
+            (self.targets[1], self.targets[2], position - 1.0)
+        };
+
+        // Cosine interpolation keeps the glide smooth at each target
+        let smoothed_fraction = 0.5 - 0.5 * (PI * fraction).cos();
+        from + (to - from) * smoothed_fraction
+    }
+
+    /// Advances the LFO by one sample, drawing a new target at the end of each cycle.
+    pub fn advance(&mut self) {
+        self.phase += self.frequency / self.sample_rate;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.targets = [self.targets[1], self.targets[2], self.next_random()];
+        }
+    }
+
+    /// Gets the current value of the LFO, then advances it by one sample.
+    pub fn tick(&mut self) -> f32 {
+        let value = self.value_at(0.0);
+        self.advance();
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms_stay_within_bounds() {
+        let waveforms = [
+            LfoWaveform::Sine,
+            LfoWaveform::Triangle,
+            LfoWaveform::Square,
+            LfoWaveform::SawUp,
+            LfoWaveform::SawDown,
+        ];
+        for waveform in waveforms {
+            for n in -200..200 {
+                let phase = n as f32 / 100.0;
+                assert!(get_waveform_value(waveform, phase).abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn random_lfo_is_bounded_smooth_and_irregular() {
+        let sample_rate = 1000.0;
+        let mut lfo = RandomLfo::new(sample_rate, 1);
+        lfo.set_frequency(5.0);
+
+        let values: Vec<f32> = (0..5000).map(|_| lfo.tick()).collect();
+        assert!(values.iter().all(|value| value.abs() <= 1.0));
+
+        // A glide of at most 2 over a 200 sample cycle never steps further than pi / 200
+        for pair in values.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= PI / 200.0 + 1e-4);
+        }
+
+        // Successive cycles land on different targets
+        let cycle_starts: Vec<f32> = values.iter().step_by(200).copied().collect();
+        assert!(cycle_starts.windows(2).all(|pair| pair[0] != pair[1]));
+
+        // A full cycle of phase offset reads the same value as no offset
+        assert_eq!(lfo.value_at(0.0), lfo.value_at(1.0));
+    }
+
+    #[test]
+    fn lfo_completes_cycle_at_frequency() {
+        let sample_rate = 200.0;
+        let mut lfo = Lfo::new(sample_rate);
+        lfo.set_frequency(2.0);
+
+        // A quarter cycle in should be the peak of the sine
+        for _ in 0..25 {
+            lfo.tick();
+        }
+        assert!((lfo.tick() - 1.0).abs() < 1e-4);
+
+        // Half a second at 2 Hz should land back at the start of the cycle
+        for _ in 0..74 {
+            lfo.tick();
+        }
+        assert!(lfo.tick().abs() < 1e-3);
+    }
+}
@@ -1,14 +1,33 @@
 pub mod biquad;
+pub mod bypass;
+pub mod convolution;
+pub mod crossover;
 pub mod dc_filter;
 pub mod delay_line;
+pub mod denormal;
 pub mod digital;
 pub mod dynamics;
-pub mod freeverb;
+pub mod exciter;
 pub mod filters;
+pub mod freeverb;
+pub mod lfo;
+pub mod metering;
 pub mod moorer_verb;
+pub mod oscillator;
 pub mod oversampling;
+pub mod phaser;
+pub mod pluck;
+pub mod safety;
+pub mod signal;
+pub mod spectrum;
+pub mod stereo;
+pub mod tail_shaper;
+pub mod tape_noise;
 pub mod waveshapers;
 
+#[cfg(test)]
+mod gain_staging;
+
 // Constants for tape-modeled vibrato (wow & flutter)
 pub const MAX_DELAY_TIME_SECONDS: f32 = 5.0;
 pub const WOW_MAX_FREQUENCY_RATIO: f32 = 0.123;
@@ -0,0 +1,277 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+
+/// The K-weighting pre-filter from ITU-R BS.1770: a high shelf modeling the head's acoustic
+/// effect, then a high-pass (the "RLB" curve) that ignores the lowest frequencies
+const K_WEIGHTING_SHELF_HZ: f32 = 1681.974;
+const K_WEIGHTING_SHELF_GAIN_DB: f32 = 3.9998;
+const K_WEIGHTING_HIGH_PASS_HZ: f32 = 38.135;
+const K_WEIGHTING_HIGH_PASS_Q: f32 = 0.5003;
+
+/// Offsets the K-weighting's gain at 1 kHz, so a full scale 1 kHz sine in one channel reads
+/// -3.01 LUFS
+const LOUDNESS_OFFSET_DB: f32 = -0.691;
+
+/// Loudness is measured in overlapping 400 ms blocks, which start every 100 ms
+const HOP_SECONDS: f32 = 0.1;
+const HOPS_PER_BLOCK: usize = 4;
+
+/// Blocks quieter than the absolute gate are ignored by the integrated loudness, in LUFS
+const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+/// Blocks more than this far below the ungated loudness are ignored too, in LU
+const RELATIVE_GATE_LU: f32 = -10.0;
+
+/// The integrated loudness keeps a histogram of block loudnesses instead of every block, so
+/// measuring for any length of time never allocates
+const HISTOGRAM_MAX_LUFS: f32 = 10.0;
+const HISTOGRAM_RESOLUTION_LU: f32 = 0.1;
+const HISTOGRAM_BINS: usize =
+    ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU) as usize;
+
+/// The level reported for silence, in dB
+const SILENCE_DB: f32 = -100.0;
+
+/// Converts a mean square power to decibels.
+fn power_to_db(power: f32) -> f32 {
+    if power > 0.0 {
+        (10.0 * power.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// Converts a K-weighted power summed across channels to LUFS.
+fn power_to_lufs(power: f32) -> f32 {
+    LOUDNESS_OFFSET_DB + power_to_db(power)
+}
+
+///
+/// A stereo loudness meter measuring momentary RMS level and, following ITU-R BS.1770, the
+/// momentary and gated integrated loudness in LUFS.
+///
+/// The K-weighting is approximated with the crate's biquad filters, which match the standard's
+/// filters to within a fraction of a dB across the audible range.
+///
+pub struct LoudnessMeter {
+    sample_rate: f32,
+    shelf_filters: (BiquadFilter, BiquadFilter),
+    high_pass_filters: (BiquadFilter, BiquadFilter),
+    hop_length: usize,
+    hop_position: usize,
+    /// The K-weighted and unweighted powers summed over the current hop
+    hop_power: f32,
+    hop_rms_power: f32,
+    /// The mean powers of the latest hops, which make up the current block
+    hop_powers: [f32; HOPS_PER_BLOCK],
+    hop_rms_powers: [f32; HOPS_PER_BLOCK],
+    hop_index: usize,
+    /// How many hops have been measured, up to a full block
+    hops_measured: usize,
+    momentary_loudness: f32,
+    momentary_rms: f32,
+    histogram_counts: Vec<u32>,
+    histogram_powers: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(sample_rate: f32) -> LoudnessMeter {
+        let mut meter = LoudnessMeter {
+            sample_rate,
+            shelf_filters: (BiquadFilter::new(), BiquadFilter::new()),
+            high_pass_filters: (BiquadFilter::new(), BiquadFilter::new()),
+            hop_length: 1,
+            hop_position: 0,
+            hop_power: 0.0,
+            hop_rms_power: 0.0,
+            hop_powers: [0.0; HOPS_PER_BLOCK],
+            hop_rms_powers: [0.0; HOPS_PER_BLOCK],
+            hop_index: 0,
+            hops_measured: 0,
+            momentary_loudness: SILENCE_DB,
+            momentary_rms: SILENCE_DB,
+            histogram_counts: vec![0; HISTOGRAM_BINS],
+            histogram_powers: vec![0.0; HISTOGRAM_BINS],
+        };
+        meter.set_sample_rate(sample_rate);
+        meter
+    }
+
+    /// Sets the sample rate, recalculating the K-weighting filters and restarting the measurement.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.hop_length = ((HOP_SECONDS * sample_rate).round() as usize).max(1);
+
+        let shelf_fc = K_WEIGHTING_SHELF_HZ / sample_rate;
+        let high_pass_fc = K_WEIGHTING_HIGH_PASS_HZ / sample_rate;
+        for filter in [&mut self.shelf_filters.0, &mut self.shelf_filters.1] {
+            filter.set_biquad(
+                BiquadFilterType::HighShelf,
+                shelf_fc,
+                0.707,
+                K_WEIGHTING_SHELF_GAIN_DB,
+            );
+        }
+        for filter in [&mut self.high_pass_filters.0, &mut self.high_pass_filters.1] {
+            filter.set_biquad(
+                BiquadFilterType::HighPass,
+                high_pass_fc,
+                K_WEIGHTING_HIGH_PASS_Q,
+                0.0,
+            );
+        }
+        self.reset();
+    }
+
+    /// Restarts the measurement, clearing the momentary and integrated loudness.
+    pub fn reset(&mut self) {
+        self.hop_position = 0;
+        self.hop_power = 0.0;
+        self.hop_rms_power = 0.0;
+        self.hop_powers = [0.0; HOPS_PER_BLOCK];
+        self.hop_rms_powers = [0.0; HOPS_PER_BLOCK];
+        self.hop_index = 0;
+        self.hops_measured = 0;
+        self.momentary_loudness = SILENCE_DB;
+        self.momentary_rms = SILENCE_DB;
+        self.histogram_counts.fill(0);
+        self.histogram_powers.fill(0.0);
+    }
+
+    ///
+    /// Measures a stereo frame. The momentary values update every 100 ms.
+    ///
+    pub fn process(&mut self, input: (f32, f32)) {
+        let weighted_l = self
+            .high_pass_filters
+            .0
+            .process(self.shelf_filters.0.process(input.0));
+        let weighted_r = self
+            .high_pass_filters
+            .1
+            .process(self.shelf_filters.1.process(input.1));
+
+        // Loudness sums the channels' powers, while the RMS level averages them
+        self.hop_power += weighted_l * weighted_l + weighted_r * weighted_r;
+        self.hop_rms_power += 0.5 * (input.0 * input.0 + input.1 * input.1);
+        self.hop_position += 1;
+
+        if self.hop_position >= self.hop_length {
+            self.finish_hop();
+        }
+    }
+
+    fn finish_hop(&mut self) {
+        self.hop_powers[self.hop_index] = self.hop_power / self.hop_length as f32;
+        self.hop_rms_powers[self.hop_index] = self.hop_rms_power / self.hop_length as f32;
+        self.hop_index = (self.hop_index + 1) % HOPS_PER_BLOCK;
+        self.hops_measured = (self.hops_measured + 1).min(HOPS_PER_BLOCK);
+        self.hop_position = 0;
+        self.hop_power = 0.0;
+        self.hop_rms_power = 0.0;
+
+        let block_power = self.hop_powers.iter().sum::<f32>() / HOPS_PER_BLOCK as f32;
+        let block_rms_power = self.hop_rms_powers.iter().sum::<f32>() / HOPS_PER_BLOCK as f32;
+        self.momentary_loudness = power_to_lufs(block_power);
+        self.momentary_rms = power_to_db(block_rms_power);
+
+        // Only whole blocks count toward the integrated loudness, and never those under the
+        // absolute gate
+        if self.hops_measured == HOPS_PER_BLOCK && self.momentary_loudness > ABSOLUTE_GATE_LUFS {
+            let bin =
+                ((self.momentary_loudness - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU) as usize;
+            let bin = bin.min(HISTOGRAM_BINS - 1);
+            self.histogram_counts[bin] += 1;
+            self.histogram_powers[bin] += block_power as f64;
+        }
+    }
+
+    /// The unweighted RMS level of the last 400 ms, averaged across channels, in dBFS.
+    pub fn momentary_rms(&self) -> f32 {
+        self.momentary_rms
+    }
+
+    /// The K-weighted loudness of the last 400 ms, in LUFS.
+    pub fn momentary_loudness(&self) -> f32 {
+        self.momentary_loudness
+    }
+
+    ///
+    /// The gated loudness of everything measured since the last reset, in LUFS. Silence and
+    /// blocks more than 10 LU below the overall loudness are left out, so pauses don't drag the
+    /// measurement down.
+    ///
+    pub fn integrated_loudness(&self) -> f32 {
+        // The mean power of the blocks in the bins from `first_bin` up
+        let mean_power_from = |first_bin: usize| {
+            let count: u32 = self.histogram_counts[first_bin..].iter().sum();
+            let power: f64 = self.histogram_powers[first_bin..].iter().sum();
+            if count > 0 {
+                Some((power / count as f64) as f32)
+            } else {
+                None
+            }
+        };
+
+        let ungated_power = match mean_power_from(0) {
+            Some(power) => power,
+            None => return SILENCE_DB,
+        };
+        let relative_gate = power_to_lufs(ungated_power) + RELATIVE_GATE_LU;
+        let first_bin = ((relative_gate - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU)
+            .ceil()
+            .max(0.0) as usize;
+        match mean_power_from(first_bin.min(HISTOGRAM_BINS - 1)) {
+            Some(power) => power_to_lufs(power),
+            None => power_to_lufs(ungated_power),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::Sine;
+
+    const SAMPLE_RATE: f32 = 48_000.0;
+
+    #[test]
+    fn full_scale_sine_in_one_channel_reads_minus_three_lufs() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        for x in Sine::new(1_000.0, 1.0, SAMPLE_RATE).take(SAMPLE_RATE as usize) {
+            meter.process((x, 0.0));
+        }
+        assert!((meter.momentary_loudness() + 3.01).abs() < 0.2);
+        assert!((meter.integrated_loudness() + 3.01).abs() < 0.2);
+
+        // Half the power of a full scale sine, averaged with a silent channel
+        assert!((meter.momentary_rms() + 6.02).abs() < 0.05);
+    }
+
+    #[test]
+    fn integrated_loudness_gates_out_quiet_passages() {
+        let mut loud_only = LoudnessMeter::new(SAMPLE_RATE);
+        let mut with_pauses = LoudnessMeter::new(SAMPLE_RATE);
+        let mut sine = Sine::new(1_000.0, 0.5, SAMPLE_RATE);
+        let mut quiet_sine = Sine::new(1_000.0, 0.001, SAMPLE_RATE);
+        for n in 0..4 * SAMPLE_RATE as usize {
+            let loud = sine.next().unwrap();
+            let quiet = quiet_sine.next().unwrap();
+            loud_only.process((loud, loud));
+
+            // Alternate between the loud sine and silence or a very quiet sine each second
+            let x = match n / SAMPLE_RATE as usize {
+                0 | 2 => loud,
+                1 => 0.0,
+                _ => quiet,
+            };
+            with_pauses.process((x, x));
+        }
+
+        // Only the blocks straddling the pauses, which are partly loud, still count
+        let difference = with_pauses.integrated_loudness() - loud_only.integrated_loudness();
+        assert!(difference.abs() < 1.0);
+    }
+}
@@ -1,5 +1,10 @@
+use crate::filters::mix_input;
 use crate::filters::Allpass;
 use crate::filters::Comb;
+use crate::filters::Diffuser;
+use crate::filters::DEFAULT_TAIL_EQ_FREQUENCY;
+use crate::freeverb;
+use std::f32::consts::PI;
 
 /// Tuning for Moorer's Reverberator can be found here:
 /// http://www.music.mcgill.ca/~gary/courses/papers/Moorer-Reverb-CMJ-1979.pdf
@@ -29,6 +34,9 @@ const FIXED_GAIN: f32 = 0.015;
 const SCALE_ROOM: f32 = 0.28;
 const OFFSET_ROOM: f32 = 0.7;
 
+/// The frequency at which the damping is matched to Freeverb's, in Hz
+const DAMPING_CALIBRATION_HZ: f32 = 4_000.;
+
 // Comb filter tunings (delay length, feedback)
 const COMB_L1_DELAY_LENGTH_MS: f32 = 50.;
 const COMB_R1_DELAY_LENGTH_MS: f32 = 50. + STEREO_SPREAD_MS;
@@ -46,10 +54,159 @@ const COMB_R6_DELAY_LENGTH_MS: f32 = 78. + STEREO_SPREAD_MS;
 const ALLPASS_L_DELAY_LENGTH: f32 = 6.;
 const ALLPASS_R_DELAY_LENGTH: f32 = 6. + STEREO_SPREAD_MS;
 
+// Early reflection tap tunings (delay time, gain), from Moorer's 18-tap example
+const EARLY_REFLECTION_TAPS: [(f32, f32); 18] = [
+    (4.3, 0.841),
+    (21.5, 0.504),
+    (22.5, 0.491),
+    (26.8, 0.379),
+    (27.0, 0.380),
+    (29.8, 0.346),
+    (45.8, 0.289),
+    (48.5, 0.272),
+    (57.2, 0.192),
+    (58.7, 0.193),
+    (59.5, 0.217),
+    (61.2, 0.181),
+    (70.7, 0.180),
+    (70.8, 0.181),
+    (72.6, 0.176),
+    (74.1, 0.142),
+    (75.3, 0.167),
+    (79.7, 0.134),
+];
+const EARLY_REFLECTIONS_GAIN: f32 = 0.25;
+pub const MAX_EARLY_REFLECTIONS_DELAY_MS: f32 = 100.;
+/// How much later a reflection reaches the far channel at full width, in milliseconds
+const EARLY_REFLECTIONS_MAX_HAAS_MS: f32 = 1.5;
+
+///
+/// A tapped delay line producing a stereo pattern of early reflections.
+/// The right channel's taps are offset by the same stereo spread as the reverb tail.
+///
+/// With width, the reflections alternate between arriving from the left and the right: each
+/// tap is panned toward its side with constant power and reaches the far channel slightly
+/// later, spreading the room's image across the stereo field.
+///
+#[derive(Debug)]
+pub struct EarlyReflections {
+    buffer: Vec<f32>,
+    write_index: usize,
+    /// Each tap's delay and gain in the left and right channels, as (delay, gain) pairs
+    taps_l: [(usize, f32); 18],
+    taps_r: [(usize, f32); 18],
+    delay: usize,
+    width: f32,
+    sample_rate: usize,
+}
+
+impl EarlyReflections {
+    pub fn new(sr: usize) -> EarlyReflections {
+        // Leave room for the longest tap, the stereo spread, and the maximum delay
+        let last_tap_ms = EARLY_REFLECTION_TAPS[EARLY_REFLECTION_TAPS.len() - 1].0;
+        let length = ms_to_samples(
+            last_tap_ms
+                + STEREO_SPREAD_MS
+                + EARLY_REFLECTIONS_MAX_HAAS_MS
+                + MAX_EARLY_REFLECTIONS_DELAY_MS,
+            sr,
+        ) + 1;
+
+        let mut early_reflections = EarlyReflections {
+            buffer: vec![0.; length],
+            write_index: 0,
+            taps_l: [(0, 0.); 18],
+            taps_r: [(0, 0.); 18],
+            delay: 0,
+            width: 0.,
+            sample_rate: sr,
+        };
+        early_reflections.update_taps();
+        early_reflections
+    }
+
+    ///
+    /// Sets how widely the reflections are spread across the stereo field.
+    ///
+    /// # Arguments
+    /// * `width` - the width from 0 (the same reflections in both channels) to 1
+    ///
+    pub fn set_width(&mut self, width: f32) {
+        let width = width.clamp(0., 1.);
+        if width != self.width {
+            self.width = width;
+            self.update_taps();
+        }
+    }
+
+    /// Clears the reflections' buffer without reallocating it.
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0.);
+        self.write_index = 0;
+    }
+
+    fn update_taps(&mut self) {
+        let haas_ms = self.width * EARLY_REFLECTIONS_MAX_HAAS_MS;
+        for (index, (time_ms, gain)) in EARLY_REFLECTION_TAPS.into_iter().enumerate() {
+            let gain = gain * EARLY_REFLECTIONS_GAIN;
+            let near_gain = gain * (1. + self.width).sqrt();
+            let far_gain = gain * (1. - self.width).sqrt();
+            let (tap_l, tap_r) = if index % 2 == 0 {
+                // Arrives from the left
+                (
+                    (time_ms, near_gain),
+                    (time_ms + STEREO_SPREAD_MS + haas_ms, far_gain),
+                )
+            } else {
+                // Arrives from the right
+                (
+                    (time_ms + haas_ms, far_gain),
+                    (time_ms + STEREO_SPREAD_MS, near_gain),
+                )
+            };
+            self.taps_l[index] = (ms_to_samples(tap_l.0, self.sample_rate), tap_l.1);
+            self.taps_r[index] = (ms_to_samples(tap_r.0, self.sample_rate), tap_r.1);
+        }
+    }
+
+    ///
+    /// Sets an additional delay before the first reflection.
+    ///
+    /// # Arguments
+    /// * `delay_ms` - the delay in milliseconds, up to `MAX_EARLY_REFLECTIONS_DELAY_MS`
+    ///
+    pub fn set_delay(&mut self, delay_ms: f32) {
+        let delay_ms = delay_ms.clamp(0., MAX_EARLY_REFLECTIONS_DELAY_MS);
+        self.delay = ms_to_samples(delay_ms, self.sample_rate);
+    }
+
+    fn read(&self, delay: usize) -> f32 {
+        let length = self.buffer.len();
+        self.buffer[(self.write_index + length - delay) % length]
+    }
+
+    pub fn tick(&mut self, input: f32) -> (f32, f32) {
+        self.buffer[self.write_index] = input;
+
+        let mut out = (0.0, 0.0);
+        for ((tap_l, gain_l), (tap_r, gain_r)) in self.taps_l.into_iter().zip(self.taps_r) {
+            out.0 += self.read(tap_l + self.delay) * gain_l;
+            out.1 += self.read(tap_r + self.delay) * gain_r;
+        }
+
+        self.write_index = (self.write_index + 1) % self.buffer.len();
+        out
+    }
+}
+
 #[derive(Debug)]
 pub struct MoorerReverb {
     allpasses: (Allpass, Allpass),
     combs: [(Comb, Comb); 6],
+    early_reflections: EarlyReflections,
+    early_reflections_level: f32,
+    early_reflections_delay: f32,
+    early_reflections_width: f32,
     wet_gains: (f32, f32),
     wet: f32,
     width: f32,
@@ -57,9 +214,49 @@ pub struct MoorerReverb {
     input_gain: f32,
     dampening: f32,
     room_size: f32,
+    resonance: f32,
+    tail_eq_frequency: f32,
+    tail_eq_gain: f32,
+    diffusers: (Diffuser, Diffuser),
+    stereo_input: bool,
+    sample_rate: usize,
     frozen: bool,
 }
 
+/// How many times longer the left comb filters are than Freeverb's on average.
+fn comb_delay_ratio() -> f32 {
+    let lengths = [
+        COMB_L1_DELAY_LENGTH_MS,
+        COMB_L2_DELAY_LENGTH_MS,
+        COMB_L3_DELAY_LENGTH_MS,
+        COMB_L4_DELAY_LENGTH_MS,
+        COMB_L5_DELAY_LENGTH_MS,
+        COMB_L6_DELAY_LENGTH_MS,
+    ];
+    let mean_delay = lengths.iter().sum::<f32>() / lengths.len() as f32 / 1000.;
+    mean_delay / freeverb::mean_comb_delay()
+}
+
+///
+/// The power gain of the combs' one-pole damping filter at a normalized angular frequency.
+///
+fn damping_power_gain(dampening: f32, w: f32) -> f32 {
+    (1. - dampening).powi(2) / (1. - 2. * dampening * w.cos() + dampening * dampening)
+}
+
+///
+/// The one-pole damping coefficient with the given power gain at a normalized angular
+/// frequency, solving `damping_power_gain` for the dampening.
+///
+fn dampening_for_power_gain(gain: f32, w: f32) -> f32 {
+    let a = 1. - gain;
+    if a < 1e-6 {
+        return 0.;
+    }
+    let b = 1. - gain * w.cos();
+    (b - (b * b - a * a).max(0.).sqrt()) / a
+}
+
 fn generate_comb_filters(sr: usize) -> [(Comb, Comb); 6] {
     [
         (
@@ -157,6 +354,10 @@ impl MoorerReverb {
         let mut freeverb = MoorerReverb {
             combs: generate_comb_filters(sr),
             allpasses: generate_allpass_filters(sr),
+            early_reflections: EarlyReflections::new(sr),
+            early_reflections_level: 0.,
+            early_reflections_delay: 0.,
+            early_reflections_width: 0.,
             wet_gains: (0., 0.),
             wet: 0.,
             dry: 0.,
@@ -164,6 +365,12 @@ impl MoorerReverb {
             width: 0.,
             dampening: 0.,
             room_size: 0.,
+            resonance: 0.,
+            tail_eq_frequency: DEFAULT_TAIL_EQ_FREQUENCY,
+            tail_eq_gain: 0.,
+            diffusers: (Diffuser::new(sr), Diffuser::new(sr)),
+            stereo_input: false,
+            sample_rate: sr,
             frozen: false,
         };
 
@@ -177,8 +384,54 @@ impl MoorerReverb {
     }
 
     pub fn generate_filters(&mut self, sr: usize) {
+        self.sample_rate = sr;
+        self.diffusers = (Diffuser::new(sr), Diffuser::new(sr));
         self.combs = generate_comb_filters(sr);
         self.allpasses = generate_allpass_filters(sr);
+        self.early_reflections = EarlyReflections::new(sr);
+        self.early_reflections
+            .set_delay(self.early_reflections_delay);
+        self.early_reflections
+            .set_width(self.early_reflections_width);
+    }
+
+    ///
+    /// Clears the reverb's tail and early reflections without reallocating its filters, so a
+    /// restart doesn't replay stale audio.
+    ///
+    pub fn reset(&mut self) {
+        for (comb_l, comb_r) in self.combs.iter_mut() {
+            comb_l.reset();
+            comb_r.reset();
+        }
+        self.allpasses.0.reset();
+        self.allpasses.1.reset();
+        self.diffusers.0.reset();
+        self.diffusers.1.reset();
+        self.early_reflections.reset();
+    }
+
+    /// Sets the level of the early reflections mixed in with the reverb tail, from 0 to 1.
+    pub fn set_early_reflections_level(&mut self, value: f32) {
+        self.early_reflections_level = value;
+    }
+
+    /// Sets the delay before the first early reflection, in milliseconds.
+    pub fn set_early_reflections_delay(&mut self, delay_ms: f32) {
+        self.early_reflections_delay = delay_ms;
+        self.early_reflections.set_delay(delay_ms);
+    }
+
+    ///
+    /// Sets how widely the early reflections are spread across the stereo field, independently
+    /// of the tail's width.
+    ///
+    /// # Arguments
+    /// * `value` - the width from 0 (the same reflections in both channels) to 1
+    ///
+    pub fn set_early_reflections_width(&mut self, value: f32) {
+        self.early_reflections_width = value;
+        self.early_reflections.set_width(value);
     }
 
     pub fn set_wet(&mut self, value: f32) {
@@ -207,12 +460,65 @@ impl MoorerReverb {
         self.update_combs();
     }
 
+    ///
+    /// Sets the room size calibrated against Freeverb's, so the same value decays in about the
+    /// same time. Moorer's combs are about twice as long as Freeverb's, so each pass through them
+    /// loses as much as two passes through Freeverb's would.
+    ///
+    /// # Arguments
+    /// * `value` - the room size as Freeverb takes it, from 0 to 1
+    ///
+    pub fn set_room_size_matching_freeverb(&mut self, value: f32) {
+        let freeverb_feedback = value * SCALE_ROOM + OFFSET_ROOM;
+        self.room_size = freeverb_feedback.powf(comb_delay_ratio());
+        self.update_combs();
+    }
+
+    ///
+    /// Sets the damping calibrated against Freeverb's, so the same value darkens the tail about
+    /// as quickly. The highs lose as much per second at 4 kHz as they do in Freeverb.
+    ///
+    /// # Arguments
+    /// * `value` - the damping as Freeverb takes it, from 0 to 1
+    ///
+    pub fn set_damping_matching_freeverb(&mut self, value: f32) {
+        let w = 2. * PI * DAMPING_CALIBRATION_HZ / self.sample_rate as f32;
+        let freeverb_gain = damping_power_gain(value * SCALE_DAMPING, w);
+        self.dampening = dampening_for_power_gain(freeverb_gain.powf(comb_delay_ratio()), w);
+        self.update_combs();
+    }
+
+    /// Sets the resonance of the comb filters' damping, from 0 (one-pole) to 1.
+    pub fn set_resonance(&mut self, value: f32) {
+        self.resonance = value;
+        self.update_combs();
+    }
+
+    ///
+    /// Sets the EQ inside the comb filters' feedback, shaping how the tail's tone evolves.
+    ///
+    /// # Arguments
+    /// * `frequency` - the center frequency, in Hz
+    /// * `gain` - the gain applied on every pass through the feedback, in dB; 0 is neutral
+    ///
+    pub fn set_tail_eq(&mut self, frequency: f32, gain: f32) {
+        self.tail_eq_frequency = frequency;
+        self.tail_eq_gain = gain;
+        self.update_combs();
+    }
+
     fn update_combs(&mut self) {
-        let (feedback, dampening) = if self.frozen {
-            (1.0, 0.0)
+        let (feedback, dampening, resonance, tail_eq_gain) = if self.frozen {
+            (1.0, 0.0, 0.0, 0.0)
         } else {
-            (self.room_size, self.dampening)
+            (
+                self.room_size,
+                self.dampening,
+                self.resonance,
+                self.tail_eq_gain,
+            )
         };
+        let tail_eq_fc = self.tail_eq_frequency / self.sample_rate as f32;
 
         for combs in self.combs.iter_mut() {
             combs.0.set_feedback(feedback);
@@ -220,9 +526,36 @@ impl MoorerReverb {
 
             combs.0.set_dampening(dampening);
             combs.1.set_dampening(dampening);
+
+            combs.0.set_resonance(resonance);
+            combs.1.set_resonance(resonance);
+
+            combs.0.set_tail_eq(tail_eq_fc, tail_eq_gain);
+            combs.1.set_tail_eq(tail_eq_fc, tail_eq_gain);
         }
     }
 
+    ///
+    /// Sets the amount of input diffusion before the comb filters, which smooths the grainy
+    /// attack on transients.
+    ///
+    /// # Arguments
+    /// * `value` - the diffusion amount from 0 (off) to 1
+    ///
+    pub fn set_diffusion(&mut self, value: f32) {
+        self.diffusers.0.set_diffusion(value);
+        self.diffusers.1.set_diffusion(value);
+    }
+
+    ///
+    /// Sets whether the input stays stereo, feeding the left and right comb filters from their
+    /// own channels, or is summed to mono first. Mono input gives a centered, even tail from
+    /// any source, while stereo input keeps the source's placement in the tail.
+    ///
+    pub fn set_stereo_input(&mut self, stereo_input: bool) {
+        self.stereo_input = stereo_input;
+    }
+
     fn update_wet_gains(&mut self) {
         self.wet_gains = (
             self.wet * (self.width / 2.0 + 0.5),
@@ -231,20 +564,162 @@ impl MoorerReverb {
     }
 
     pub fn tick(&mut self, input: (f32, f32)) -> (f32, f32) {
-        let input_mixed = (input.0 + input.1) * FIXED_GAIN * self.input_gain;
+        let input_mixed = mix_input(input, self.stereo_input);
+        let input_mixed = (
+            self.diffusers
+                .0
+                .tick(input_mixed.0 * FIXED_GAIN * self.input_gain),
+            self.diffusers
+                .1
+                .tick(input_mixed.1 * FIXED_GAIN * self.input_gain),
+        );
         let mut out = (0.0, 0.0);
 
-        let allpassed_l = self.allpasses.0.tick(input_mixed);
-        let allpassed_r = self.allpasses.1.tick(input_mixed);
+        let allpassed_l = self.allpasses.0.tick(input_mixed.0);
+        let allpassed_r = self.allpasses.1.tick(input_mixed.1);
 
         for combs in self.combs.iter_mut() {
             out.0 += combs.0.tick(allpassed_l);
             out.1 += combs.1.tick(allpassed_r);
         }
 
+        // Early reflections follow the wet level, but not the tail's fixed gain or width
+        let reflections = self
+            .early_reflections
+            .tick((input.0 + input.1) * 0.5 * self.input_gain);
+        let reflections_gain = self.early_reflections_level * self.wet / SCALE_WET;
+
         (
-            out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1 + input.0 * self.dry,
-            out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1 + input.1 * self.dry,
+            out.0 * self.wet_gains.0
+                + out.1 * self.wet_gains.1
+                + reflections.0 * reflections_gain
+                + input.0 * self.dry,
+            out.1 * self.wet_gains.0
+                + out.0 * self.wet_gains.1
+                + reflections.1 * reflections_gain
+                + input.1 * self.dry,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn early_reflections_start_after_delay() {
+        let sr = 10_000;
+        let mut early_reflections = EarlyReflections::new(sr);
+        early_reflections.set_delay(10.);
+
+        // The first tap is at 4.3 ms, plus the 10 ms delay
+        let first_tap = ms_to_samples(4.3, sr) + ms_to_samples(10., sr);
+        let mut output = vec![early_reflections.tick(1.)];
+        for _ in 0..first_tap {
+            output.push(early_reflections.tick(0.));
+        }
+
+        assert!(output[..first_tap].iter().all(|frame| frame.0 == 0.));
+        assert!((output[first_tap].0 - 0.841 * EARLY_REFLECTIONS_GAIN).abs() < 1e-6);
+    }
+
+    #[test]
+    fn early_reflections_width_pans_and_delays_taps() {
+        let sr = 10_000;
+        let impulse_response = |width: f32| {
+            let mut early_reflections = EarlyReflections::new(sr);
+            early_reflections.set_width(width);
+            let mut output = vec![early_reflections.tick(1.)];
+            for _ in 0..ms_to_samples(40., sr) {
+                output.push(early_reflections.tick(0.));
+            }
+            output
+        };
+
+        // Without width, the first reflection reaches both channels at the same level
+        let first_tap = ms_to_samples(4.3, sr);
+        let narrow = impulse_response(0.);
+        assert_eq!(narrow[first_tap].0, 0.841 * EARLY_REFLECTIONS_GAIN);
+        let narrow_right = ms_to_samples(4.3 + STEREO_SPREAD_MS, sr);
+        assert_eq!(narrow[narrow_right].1, 0.841 * EARLY_REFLECTIONS_GAIN);
+
+        // At full width, it only comes from the left, and the taps keep their total power
+        let wide = impulse_response(1.);
+        assert!((wide[first_tap].0 - 2_f32.sqrt() * 0.841 * EARLY_REFLECTIONS_GAIN).abs() < 1e-6);
+        assert!(wide[..ms_to_samples(20., sr)]
+            .iter()
+            .all(|frame| frame.1 == 0.));
+        let energy = |output: &[(f32, f32)]| -> f32 {
+            output
+                .iter()
+                .map(|frame| frame.0 * frame.0 + frame.1 * frame.1)
+                .sum()
+        };
+        assert!((energy(&wide) / energy(&narrow) - 1.).abs() < 1e-4);
+    }
+
+    ///
+    /// Estimates a reverb's decay time from its impulse response, by extrapolating the time
+    /// its backward integrated energy takes to fall from -5 to -25 dB out to 60 dB.
+    ///
+    fn decay_time(impulse_response: &[f32], sr: usize) -> f32 {
+        let mut remaining: Vec<f32> = impulse_response
+            .iter()
+            .rev()
+            .scan(0., |energy, x| {
+                *energy += x * x;
+                Some(*energy)
+            })
+            .collect();
+        remaining.reverse();
+        let time_below = |db: f32| {
+            let threshold = remaining[0] * 10_f32.powf(db / 10.);
+            remaining
+                .iter()
+                .position(|energy| *energy < threshold)
+                .unwrap() as f32
+                / sr as f32
+        };
+        3. * (time_below(-25.) - time_below(-5.))
+    }
+
+    #[test]
+    fn calibrated_room_size_and_damping_decay_like_freeverb() {
+        let sr = 44_100;
+        for (room_size, damping) in [(0.2, 0.), (0.5, 0.5), (0.8, 0.2)] {
+            let mut freeverb = freeverb::Freeverb::new(sr);
+            freeverb.set_room_size(room_size);
+            freeverb.set_damping(damping);
+            let mut moorer = MoorerReverb::new(sr);
+            moorer.set_room_size_matching_freeverb(room_size);
+            moorer.set_damping_matching_freeverb(damping);
+
+            let mut freeverb_output = vec![freeverb.tick((1., 1.)).0];
+            let mut moorer_output = vec![moorer.tick((1., 1.)).0];
+            for _ in 0..4 * sr {
+                freeverb_output.push(freeverb.tick((0., 0.)).0);
+                moorer_output.push(moorer.tick((0., 0.)).0);
+            }
+
+            // Uncalibrated, Moorer's longer combs decay about twice as slowly
+            let ratio = decay_time(&moorer_output, sr) / decay_time(&freeverb_output, sr);
+            assert!((ratio - 1.).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn reset_silences_the_tail_and_early_reflections() {
+        let mut moorer = MoorerReverb::new(10_000);
+        moorer.set_diffusion(1.0);
+        moorer.set_early_reflections_level(1.0);
+        moorer.set_frozen(true);
+        for n in 0..1_000 {
+            moorer.tick(if n == 0 { (1., 1.) } else { (0., 0.) });
+        }
+
+        moorer.reset();
+        for _ in 0..10_000 {
+            assert_eq!(moorer.tick((0., 0.)), (0., 0.));
+        }
+    }
+}
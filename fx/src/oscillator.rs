@@ -0,0 +1,184 @@
+use std::f32::consts::PI;
+
+/// Waveforms available to the band-limited oscillator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OscillatorWaveform {
+    Sine,
+    Saw,
+    Square,
+}
+
+///
+/// Calculates the PolyBLEP correction for a discontinuity at the start of a waveform's cycle:
+/// a two-sample polynomial approximation of the band-limited step's residual, which rounds off
+/// the jump so it doesn't alias. Scaled by the jump's height over 2, add it to a naive waveform
+/// that jumps upwards, like a square's rising edge, and subtract it from one that jumps
+/// downwards, like a saw's reset.
+///
+/// Adapted from Martin Finke's PolyBLEP oscillator tutorial:
+/// https://www.martin-finke.de/articles/audio-plugins-018-polyblep-oscillator/
+///
+/// # Arguments
+/// * `phase` - the phase of the waveform, where 1.0 is a full cycle
+/// * `phase_increment` - how far the phase advances each sample
+///
+pub fn poly_blep(phase: f32, phase_increment: f32) -> f32 {
+    if phase < phase_increment {
+        // Just after the discontinuity
+        let t = phase / phase_increment;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - phase_increment {
+        // Just before the discontinuity
+        let t = (phase - 1.0) / phase_increment;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// An oscillator for audible frequencies, producing bipolar values between -1 and 1.
+///
+/// Unlike the naive `Lfo`, the saw and square are band-limited with PolyBLEP, so they can be
+/// heard directly or used as a ring modulator's carrier without audible aliasing.
+#[derive(Debug)]
+pub struct Oscillator {
+    waveform: OscillatorWaveform,
+    phase: f32,
+    frequency: f32,
+    sample_rate: f32,
+    phase_increment: f32,
+}
+
+impl Oscillator {
+    pub fn new(sample_rate: f32) -> Oscillator {
+        let mut oscillator = Oscillator {
+            waveform: OscillatorWaveform::Sine,
+            phase: 0.0,
+            frequency: 440.0,
+            sample_rate,
+            phase_increment: 0.0,
+        };
+        oscillator.update_phase_increment();
+        oscillator
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_phase_increment();
+    }
+
+    /// Sets the frequency of the oscillator, in Hz, up to the Nyquist frequency.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.update_phase_increment();
+    }
+
+    pub fn set_waveform(&mut self, waveform: OscillatorWaveform) {
+        self.waveform = waveform;
+    }
+
+    /// Resets the phase of the oscillator to the start of its cycle.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn update_phase_increment(&mut self) {
+        self.phase_increment = (self.frequency.abs() / self.sample_rate).min(0.5);
+    }
+
+    /// Gets the current value of the oscillator, then advances it by one sample.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> f32 {
+        let phase = self.phase;
+        let dt = self.phase_increment;
+        let value = match self.waveform {
+            OscillatorWaveform::Sine => (2.0 * PI * phase).sin(),
+            OscillatorWaveform::Saw => 2.0 * phase - 1.0 - poly_blep(phase, dt),
+            OscillatorWaveform::Square => {
+                let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+                // Rising by 2 at the start of the cycle, and falling by 2 halfway through
+                let falling_phase = (phase + 0.5) % 1.0;
+                naive + poly_blep(phase, dt) - poly_blep(falling_phase, dt)
+            }
+        };
+
+        self.phase += dt;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lfo::{get_waveform_value, LfoWaveform};
+    use crate::spectrum::{fft, Twiddles};
+
+    const FFT_SIZE: usize = 8192;
+    /// A saw at about 8 kHz at 44.1 kHz, at an exact FFT bin
+    const SAW_BIN: usize = 1531;
+
+    ///
+    /// Returns the power of a periodic signal in its harmonics' bins, and in every other bin,
+    /// which is all aliasing.
+    ///
+    fn harmonic_and_alias_power(mut signal: impl FnMut() -> f32) -> (f32, f32) {
+        let mut real: Vec<f32> = (0..FFT_SIZE).map(|_| signal()).collect();
+        let mut imag = vec![0.0; FFT_SIZE];
+        fft(&mut real, &mut imag, &Twiddles::new(FFT_SIZE));
+
+        let power = |bin: usize| real[bin] * real[bin] + imag[bin] * imag[bin];
+        let harmonics = (1..FFT_SIZE / 2)
+            .filter(|bin| bin % SAW_BIN == 0)
+            .map(power)
+            .sum();
+        let aliasing = (1..FFT_SIZE / 2)
+            .filter(|bin| bin % SAW_BIN != 0)
+            .map(power)
+            .sum();
+        (harmonics, aliasing)
+    }
+
+    #[test]
+    fn poly_blep_saw_bounds_aliasing_at_high_frequencies() {
+        // With the sample rate as the FFT size, the phase increment is exact
+        let mut oscillator = Oscillator::new(FFT_SIZE as f32);
+        oscillator.set_waveform(OscillatorWaveform::Saw);
+        oscillator.set_frequency(SAW_BIN as f32);
+        let (harmonics, aliasing) = harmonic_and_alias_power(|| oscillator.next());
+
+        let mut phase = 0.0;
+        let (_, naive_aliasing) = harmonic_and_alias_power(|| {
+            let value = get_waveform_value(LfoWaveform::SawUp, phase);
+            phase += SAW_BIN as f32 / FFT_SIZE as f32;
+            value
+        });
+
+        // Even this close to Nyquist, the aliasing stays about 17 dB below the harmonics, and
+        // over 13 dB below the naive saw's
+        assert!(aliasing < harmonics * 0.02);
+        assert!(aliasing < naive_aliasing * 0.05);
+    }
+
+    #[test]
+    fn waveforms_stay_bipolar() {
+        for waveform in [
+            OscillatorWaveform::Sine,
+            OscillatorWaveform::Saw,
+            OscillatorWaveform::Square,
+        ] {
+            let mut oscillator = Oscillator::new(44_100.0);
+            oscillator.set_waveform(waveform);
+            oscillator.set_frequency(3_000.0);
+            let mut sum = 0.0;
+            for _ in 0..44_100 {
+                let value = oscillator.next();
+                assert!(value.abs() <= 1.0 + 1e-6);
+                sum += value;
+            }
+            assert!((sum / 44_100.0).abs() < 1e-2);
+        }
+    }
+}
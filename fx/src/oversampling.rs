@@ -30,6 +30,13 @@ impl Default for AllpassFilter {
 }
 
 impl AllpassFilter {
+    fn reset(&mut self) {
+        *self = Self {
+            a: self.a,
+            ..Self::default()
+        };
+    }
+
     fn process(&mut self, input_sample: f32) -> f32 {
         // Shuffle inputs
         self.x2 = self.x1;
@@ -58,6 +65,19 @@ struct AllpassFilterCascade {
 }
 
 impl AllpassFilterCascade {
+    fn group_delay(&self) -> f32 {
+        self.allpass_filters[..self.filter_count]
+            .iter()
+            .map(|allpass| 2.0 * (1.0 - allpass.a) / (1.0 + allpass.a))
+            .sum()
+    }
+
+    fn reset(&mut self) {
+        self.allpass_filters
+            .iter_mut()
+            .for_each(|allpass| allpass.reset());
+    }
+
     fn process(&mut self, input_sample: f32) -> f32 {
         let mut output = input_sample;
         for i in 0..self.filter_count {
@@ -251,6 +271,24 @@ impl HalfbandFilter {
         }
     }
 
+    ///
+    /// Calculates the group delay of the filter at DC, in samples.
+    ///
+    /// Each second-order allpass `(a + z^-2) / (1 + a z^-2)` delays low frequencies by
+    /// `2 (1 - a) / (1 + a)` samples, and the output averages both branches, where the
+    /// second branch is delayed by one more sample.
+    ///
+    pub fn group_delay(&self) -> f32 {
+        (self.filter_a.group_delay() + 1.0 + self.filter_b.group_delay()) * 0.5
+    }
+
+    /// Clears the filter's state.
+    pub fn reset(&mut self) {
+        self.filter_a.reset();
+        self.filter_b.reset();
+        self.old_out = 0.;
+    }
+
     pub fn process(&mut self, input: f32) -> f32 {
         let output = (self.filter_a.process(input) + self.old_out) * 0.5;
         self.old_out = self.filter_b.process(input);
@@ -301,3 +339,246 @@ impl Default for HalfbandFilter {
         }
     }
 }
+
+const MAX_OVERSAMPLING_STAGES: usize = 4;
+
+/// An oversampler that upsamples a signal by a factor of `N`, runs some processing at the
+/// higher sample rate, then downsamples back to the original sample rate.
+///
+/// `N` must be a power of two up to 16. Each factor of two is its own stage: upsampling
+/// zero-stuffs and filters with a halfband filter, and downsampling filters with another
+/// halfband filter before decimating.
+#[derive(Clone, Copy)]
+pub struct Oversampler<const N: usize> {
+    upsamplers: [HalfbandFilter; MAX_OVERSAMPLING_STAGES],
+    downsamplers: [HalfbandFilter; MAX_OVERSAMPLING_STAGES],
+    stages: usize,
+}
+
+impl<const N: usize> Oversampler<N> {
+    ///
+    /// # Arguments
+    /// * `order` - the order of the halfband filters (2, 4, 6, 8, 10, or 12)
+    /// * `steep` - whether to use steeper halfband filters with less stopband attenuation
+    ///
+    pub fn new(order: usize, steep: bool) -> Oversampler<N> {
+        assert!(N.is_power_of_two() && N <= 1 << MAX_OVERSAMPLING_STAGES);
+
+        let halfband_filter = HalfbandFilter::new(order, steep);
+        Oversampler {
+            upsamplers: [halfband_filter; MAX_OVERSAMPLING_STAGES],
+            downsamplers: [halfband_filter; MAX_OVERSAMPLING_STAGES],
+            stages: N.trailing_zeros() as usize,
+        }
+    }
+
+    ///
+    /// Calculates the latency of the oversampler at low frequencies, in samples at the
+    /// original sample rate.
+    ///
+    pub fn latency(&self) -> f32 {
+        (0..self.stages)
+            .map(|stage| {
+                // Each stage runs at twice the rate of the one before it
+                let stage_factor = (2 << stage) as f32;
+                (self.upsamplers[stage].group_delay() + self.downsamplers[stage].group_delay())
+                    / stage_factor
+            })
+            .sum()
+    }
+
+    /// Clears the halfband filters' state.
+    pub fn reset(&mut self) {
+        self.upsamplers
+            .iter_mut()
+            .chain(self.downsamplers.iter_mut())
+            .for_each(|halfband| halfband.reset());
+    }
+
+    ///
+    /// Calculates the latency of the upsampling half alone at low frequencies, in samples at
+    /// the original sample rate.
+    ///
+    pub(crate) fn upsampling_latency(&self) -> f32 {
+        (0..self.stages)
+            .map(|stage| self.upsamplers[stage].group_delay() / (2 << stage) as f32)
+            .sum()
+    }
+
+    ///
+    /// Upsamples a single input sample into `N` samples.
+    ///
+    pub(crate) fn upsample(&mut self, input: f32) -> [f32; N] {
+        let mut frame = [0.0; N];
+        frame[0] = input;
+
+        let mut length = 1;
+        for upsampler in self.upsamplers[..self.stages].iter_mut() {
+            // Zero-stuff in place, back to front
+            for i in (0..length).rev() {
+                frame[2 * i] = frame[i];
+                frame[2 * i + 1] = 0.0;
+            }
+            length *= 2;
+
+            // Doubling makes up for the energy lost to zero-stuffing
+            for sample in frame[..length].iter_mut() {
+                *sample = upsampler.process(*sample) * 2.0;
+            }
+        }
+
+        frame
+    }
+
+    ///
+    /// Downsamples `N` samples into a single output sample.
+    ///
+    fn downsample(&mut self, mut frame: [f32; N]) -> f32 {
+        let mut length = N;
+        for downsampler in self.downsamplers[..self.stages].iter_mut().rev() {
+            for sample in frame[..length].iter_mut() {
+                *sample = downsampler.process(*sample);
+            }
+
+            // Decimate in place, front to back
+            length /= 2;
+            for i in 0..length {
+                frame[i] = frame[2 * i];
+            }
+        }
+
+        frame[0]
+    }
+
+    ///
+    /// Processes a single input sample at `N` times the sample rate.
+    ///
+    /// # Arguments
+    /// * `input` - a single input sample
+    /// * `process_sample` - the processing to apply to each sample at the oversampled rate
+    ///
+    pub fn process(&mut self, input: f32, mut process_sample: impl FnMut(f32) -> f32) -> f32 {
+        let mut frame = self.upsample(input);
+        for sample in frame.iter_mut() {
+            *sample = process_sample(*sample);
+        }
+        self.downsample(frame)
+    }
+}
+
+/// A pair of oversamplers for processing stereo frames at `N` times the sample rate.
+#[derive(Clone, Copy)]
+pub struct StereoOversampler<const N: usize> {
+    oversampler_l: Oversampler<N>,
+    oversampler_r: Oversampler<N>,
+}
+
+impl<const N: usize> StereoOversampler<N> {
+    pub fn new(order: usize, steep: bool) -> StereoOversampler<N> {
+        StereoOversampler {
+            oversampler_l: Oversampler::new(order, steep),
+            oversampler_r: Oversampler::new(order, steep),
+        }
+    }
+
+    /// Calculates the latency of the oversampler, in samples at the original sample rate.
+    pub fn latency(&self) -> f32 {
+        self.oversampler_l.latency()
+    }
+
+    /// Clears both oversamplers' state.
+    pub fn reset(&mut self) {
+        self.oversampler_l.reset();
+        self.oversampler_r.reset();
+    }
+
+    ///
+    /// Processes a stereo frame at `N` times the sample rate.
+    ///
+    /// # Arguments
+    /// * `input` - a stereo frame of input
+    /// * `process_frame` - the processing to apply to each stereo frame at the oversampled rate
+    ///
+    pub fn process(
+        &mut self,
+        input: (f32, f32),
+        mut process_frame: impl FnMut((f32, f32)) -> (f32, f32),
+    ) -> (f32, f32) {
+        let mut frame_l = self.oversampler_l.upsample(input.0);
+        let mut frame_r = self.oversampler_r.upsample(input.1);
+
+        for (sample_l, sample_r) in frame_l.iter_mut().zip(frame_r.iter_mut()) {
+            (*sample_l, *sample_r) = process_frame((*sample_l, *sample_r));
+        }
+
+        (
+            self.oversampler_l.downsample(frame_l),
+            self.oversampler_r.downsample(frame_r),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    ///
+    /// Measures the gain and delay of a low frequency sine through the oversampler.
+    ///
+    fn measure_sine<const N: usize>(oversampler: &mut Oversampler<N>) -> (f32, f32) {
+        let frequency = 0.002;
+        let length = 5_000;
+        let (mut real, mut imag) = (0.0, 0.0);
+        for n in 0..length {
+            let phase = 2.0 * PI * frequency * n as f32;
+            let output = oversampler.process(phase.sin(), |x| x);
+
+            // Skip the first half while the filters settle
+            if n >= length / 2 {
+                real += output * phase.sin();
+                imag += output * phase.cos();
+            }
+        }
+
+        let gain = real.hypot(imag) / (length / 4) as f32;
+        let delay = imag.atan2(real) / (-2.0 * PI * frequency);
+        (gain, delay)
+    }
+
+    #[test]
+    fn oversampler_has_unity_gain_in_passband() {
+        let (gain, _) = measure_sine(&mut Oversampler::<4>::new(8, true));
+        assert!((gain - 1.0).abs() < 1e-2);
+
+        let (gain, _) = measure_sine(&mut Oversampler::<2>::new(12, true));
+        assert!((gain - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn oversampler_latency_matches_measured_delay() {
+        for oversampler in [
+            Oversampler::<4>::new(8, true),
+            Oversampler::<4>::new(12, false),
+        ] {
+            let (_, delay) = measure_sine(&mut { oversampler });
+            assert!((delay - oversampler.latency()).abs() < 1e-1);
+        }
+    }
+
+    #[test]
+    fn stereo_oversampler_matches_mono_oversamplers() {
+        let mut stereo_oversampler = StereoOversampler::<4>::new(8, true);
+        let mut oversampler = Oversampler::<4>::new(8, true);
+
+        for n in 0..500 {
+            let input = (n as f32 * 0.05).sin();
+            let output = stereo_oversampler.process((input, -input), |frame| {
+                (frame.0.clamp(-0.5, 0.5), frame.1.clamp(-0.5, 0.5))
+            });
+            let expected = oversampler.process(input, |x| x.clamp(-0.5, 0.5));
+            assert_eq!(output.0, expected);
+            assert_eq!(output.1, -expected);
+        }
+    }
+}
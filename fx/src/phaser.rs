@@ -0,0 +1,251 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use crate::denormal::flush_denormal;
+use crate::lfo::Lfo;
+
+/// The fewest all-pass stages in the chain
+pub const MIN_PHASER_STAGES: usize = 4;
+/// The most all-pass stages in the chain
+pub const MAX_PHASER_STAGES: usize = 12;
+/// How far the sweep reaches either side of the center frequency at full depth, in octaves
+const MAX_SWEEP_OCTAVES: f32 = 2.0;
+/// The lowest and highest frequencies the stages are swept to, in Hz
+const MIN_SWEEP_FREQUENCY: f32 = 20.0;
+const MAX_SWEEP_FREQUENCY: f32 = 18_000.0;
+/// The Q of each all-pass stage; a low Q spreads each stage's phase shift wide
+const STAGE_Q: f32 = 0.5;
+/// The most feedback around the chain, short of self-oscillation
+pub const MAX_PHASER_FEEDBACK: f32 = 0.95;
+/// How many samples pass between retuning the stages to the sweep. The LFO moves slowly enough
+/// that the steps can't be heard, and retuning every stage every sample is expensive.
+const CONTROL_INTERVAL: usize = 16;
+
+///
+/// A stereo phaser: a chain of all-pass stages swept together by an LFO, mixed with the dry
+/// signal. Where the chain shifts the phase by an odd multiple of 180 degrees, the wet and dry
+/// signals cancel, leaving notches that move through the spectrum as the LFO sweeps. Feeding
+/// the chain's output back into its input sharpens the peaks between the notches, deepening
+/// the effect.
+///
+pub struct Phaser {
+    sample_rate: f32,
+    stages: Vec<(BiquadFilter, BiquadFilter)>,
+    stage_count: usize,
+    lfo: Lfo,
+    center_frequency: f32,
+    depth: f32,
+    feedback: f32,
+    mix: f32,
+    feedback_state: (f32, f32),
+    fc: f32,
+    control_countdown: usize,
+}
+
+impl Phaser {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(sample_rate: f32) -> Phaser {
+        let stages = (0..MAX_PHASER_STAGES)
+            .map(|_| (BiquadFilter::new(), BiquadFilter::new()))
+            .collect();
+        let mut phaser = Phaser {
+            sample_rate,
+            stages,
+            stage_count: MIN_PHASER_STAGES,
+            lfo: Lfo::new(sample_rate),
+            center_frequency: 1_000.0,
+            depth: 0.5,
+            feedback: 0.0,
+            mix: 0.5,
+            feedback_state: (0.0, 0.0),
+            fc: 0.0,
+            control_countdown: 0,
+        };
+        for (stage_l, stage_r) in phaser.stages.iter_mut() {
+            stage_l.set_biquad(BiquadFilterType::AllPass, 0.0, STAGE_Q, 0.0);
+            stage_r.set_biquad(BiquadFilterType::AllPass, 0.0, STAGE_Q, 0.0);
+        }
+        phaser
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.lfo.set_sample_rate(sample_rate);
+    }
+
+    /// Clears the all-pass stages and feedback, and restarts the LFO.
+    pub fn reset(&mut self) {
+        for (stage_l, stage_r) in self.stages.iter_mut() {
+            stage_l.reset();
+            stage_r.reset();
+        }
+        self.feedback_state = (0.0, 0.0);
+        self.lfo.reset();
+        self.control_countdown = 0;
+    }
+
+    /// Sets the rate of the sweep, in Hz.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.lfo.set_frequency(rate);
+    }
+
+    ///
+    /// # Arguments
+    /// * `depth` - how far the sweep reaches, from 0 (still) to 1 (two octaves either side of
+    ///   the center frequency)
+    ///
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    ///
+    /// # Arguments
+    /// * `stages` - the number of all-pass stages, from 4 to 12; more stages make more notches
+    ///
+    pub fn set_stages(&mut self, stages: usize) {
+        let stages = stages.clamp(MIN_PHASER_STAGES, MAX_PHASER_STAGES);
+        // Added stages start from silence rather than from when they were last used
+        for (stage_l, stage_r) in self.stages.iter_mut().take(stages).skip(self.stage_count) {
+            stage_l.reset();
+            stage_r.reset();
+        }
+        self.stage_count = stages;
+    }
+
+    ///
+    /// # Arguments
+    /// * `feedback` - how much of the chain's output is fed back into its input, from -0.95 to
+    ///   0.95; negative feedback moves the peaks to where the notches were
+    ///
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-MAX_PHASER_FEEDBACK, MAX_PHASER_FEEDBACK);
+    }
+
+    ///
+    /// # Arguments
+    /// * `mix` - the balance of the dry and wet signals; the notches are deepest at 0.5
+    ///
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// Sets the frequency the sweep is centered on, in Hz.
+    pub fn set_center_frequency(&mut self, center_frequency: f32) {
+        self.center_frequency = center_frequency;
+    }
+
+    ///
+    /// Retunes every stage to the sweep, only recomputing the coefficients when it has moved.
+    ///
+    fn update_stages(&mut self, lfo: f32) {
+        // Sweep exponentially, so the notches move evenly in pitch
+        let octaves = self.depth * MAX_SWEEP_OCTAVES * lfo;
+        let frequency = (self.center_frequency * octaves.exp2()).clamp(
+            MIN_SWEEP_FREQUENCY,
+            MAX_SWEEP_FREQUENCY.min(0.45 * self.sample_rate),
+        );
+        let fc = frequency / self.sample_rate;
+        if fc == self.fc {
+            return;
+        }
+        self.fc = fc;
+        for (stage_l, stage_r) in self.stages.iter_mut() {
+            stage_l.set_fc(fc);
+            stage_r.set_fc(fc);
+        }
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let lfo = self.lfo.tick();
+        if self.control_countdown == 0 {
+            self.control_countdown = CONTROL_INTERVAL;
+            self.update_stages(lfo);
+        }
+        self.control_countdown -= 1;
+
+        let mut wet = (
+            input.0 + self.feedback * self.feedback_state.0,
+            input.1 + self.feedback * self.feedback_state.1,
+        );
+        for (stage_l, stage_r) in self.stages.iter_mut().take(self.stage_count) {
+            wet = (stage_l.process(wet.0), stage_r.process(wet.1));
+        }
+        self.feedback_state = (flush_denormal(wet.0), flush_denormal(wet.1));
+
+        (
+            input.0 * (1.0 - self.mix) + wet.0 * self.mix,
+            input.1 * (1.0 - self.mix) + wet.1 * self.mix,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 44_100.0;
+
+    /// Measures the steady-state gain of a still phaser for a sine at a frequency, in Hz.
+    fn measure_gain(phaser: &mut Phaser, frequency: f32) -> f32 {
+        phaser.reset();
+        let length = 20_000;
+        let (mut input_power, mut output_power) = (0.0, 0.0);
+        for n in 0..length {
+            let input = (2.0 * PI * frequency * n as f32 / SAMPLE_RATE).sin();
+            let output = phaser.process((input, input)).0;
+            if n >= length / 2 {
+                input_power += input * input;
+                output_power += output * output;
+            }
+        }
+        (output_power / input_power).sqrt()
+    }
+
+    /// The deepest notch of a still phaser between 100 Hz and 10 kHz, as its gain and frequency.
+    fn deepest_notch(phaser: &mut Phaser) -> (f32, f32) {
+        (0..=60)
+            .map(|n| 100.0 * 100_f32.powf(n as f32 / 60.0))
+            .map(|frequency| (measure_gain(phaser, frequency), frequency))
+            .fold((f32::MAX, 0.0), |deepest, notch| {
+                if notch.0 < deepest.0 {
+                    notch
+                } else {
+                    deepest
+                }
+            })
+    }
+
+    #[test]
+    fn mixing_the_all_pass_chain_cuts_notches_that_follow_the_sweep() {
+        let mut phaser = Phaser::new(SAMPLE_RATE);
+        phaser.set_depth(0.0);
+        phaser.set_center_frequency(1_000.0);
+
+        // The chain alone passes everything at unity gain
+        phaser.set_mix(1.0);
+        assert!((measure_gain(&mut phaser, 300.0) - 1.0).abs() < 0.01);
+
+        // Mixed with the dry signal, the chain cuts deep notches, and is in phase at the center
+        phaser.set_mix(0.5);
+        let (notch_gain, notch_frequency) = deepest_notch(&mut phaser);
+        assert!(notch_gain < 0.1);
+        assert!(measure_gain(&mut phaser, 1_000.0) > 0.95);
+
+        // Moving the center moves the notches with it
+        phaser.set_center_frequency(2_000.0);
+        let (_, moved_frequency) = deepest_notch(&mut phaser);
+        assert!((moved_frequency / notch_frequency - 2.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn feedback_sharpens_the_peaks_between_notches() {
+        let mut phaser = Phaser::new(SAMPLE_RATE);
+        phaser.set_depth(0.0);
+        let without_feedback = measure_gain(&mut phaser, 1_000.0);
+        phaser.set_feedback(0.7);
+        let with_feedback = measure_gain(&mut phaser, 1_000.0);
+        assert!(with_feedback > 1.5 * without_feedback);
+    }
+}
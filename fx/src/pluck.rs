@@ -0,0 +1,273 @@
+use crate::delay_line::{get_interpolated_value_from_buffer, Interpolation};
+use crate::denormal::flush_denormal;
+use crate::signal::WhiteNoise;
+use std::f32::consts::PI;
+
+/// The lowest note a string can be tuned to, which sizes its delay buffer, in Hz
+pub const MIN_FREQUENCY: f32 = 20.0;
+/// The shortest loop delay, since cubic interpolation reads two samples past the read position
+const MIN_DELAY_SAMPLES: f32 = 3.0;
+/// The damping filter's pole at full damping, which still leaves the string a little brightness
+const MAX_DAMPING_POLE: f32 = 0.95;
+
+/// What plucks the string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluckExciter {
+    /// A burst of white noise one period long, the classic Karplus-Strong pluck.
+    Noise,
+    /// A single click, which sounds thinner and more like a struck string.
+    Impulse,
+}
+
+///
+/// A plucked string using the Karplus-Strong algorithm: an exciter fills a feedback delay line
+/// tuned to the note's period, and a one-pole low-pass in the feedback damps the upper
+/// harmonics faster than the fundamental, so the tone mellows as it decays.
+///
+/// The delay is shortened by the damping filter's phase delay at the fundamental, and read
+/// with cubic interpolation, so the string stays in tune at any frequency and damping.
+/// https://ccrma.stanford.edu/~jos/pasp/Karplus_Strong_Algorithm.html
+///
+pub struct PluckedString {
+    sample_rate: f32,
+    buffer: Vec<f32>,
+    write_index: usize,
+    frequency: f32,
+    decay_seconds: f32,
+    damping: f32,
+    /// The loop's delay in samples and gain per period, updated with the string's settings
+    delay: f32,
+    feedback: f32,
+    filter_state: f32,
+    exciter: PluckExciter,
+    noise: WhiteNoise,
+    excitation_remaining: usize,
+    excitation_amplitude: f32,
+}
+
+impl PluckedString {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `seed` - a nonzero seed for the noise exciter, so several strings sound different
+    ///
+    pub fn new(sample_rate: f32, seed: u32) -> PluckedString {
+        let mut string = PluckedString {
+            sample_rate,
+            buffer: vec![],
+            write_index: 0,
+            frequency: 440.0,
+            decay_seconds: 2.0,
+            damping: 0.5,
+            delay: MIN_DELAY_SAMPLES,
+            feedback: 0.0,
+            filter_state: 0.0,
+            exciter: PluckExciter::Noise,
+            noise: WhiteNoise::new(seed),
+            excitation_remaining: 0,
+            excitation_amplitude: 0.0,
+        };
+        string.set_sample_rate(sample_rate);
+        string
+    }
+
+    /// Sets the sample rate, resizing the delay buffer and silencing the string.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        let length = (sample_rate / MIN_FREQUENCY).ceil() as usize + MIN_DELAY_SAMPLES as usize;
+        self.buffer = vec![0.0; length];
+        self.reset();
+        self.update_loop();
+    }
+
+    /// Silences the string.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_index = 0;
+        self.filter_state = 0.0;
+        self.excitation_remaining = 0;
+    }
+
+    ///
+    /// # Arguments
+    /// * `decay_seconds` - how long the fundamental takes to decay by 60 dB without damping
+    ///
+    pub fn set_decay(&mut self, decay_seconds: f32) {
+        if decay_seconds != self.decay_seconds {
+            self.decay_seconds = decay_seconds;
+            self.update_loop();
+        }
+    }
+
+    ///
+    /// # Arguments
+    /// * `damping` - how much faster the upper harmonics decay, from 0 (a bright, metallic
+    ///   string) to 1 (a dull, muted one)
+    ///
+    pub fn set_damping(&mut self, damping: f32) {
+        if damping != self.damping {
+            self.damping = damping;
+            self.update_loop();
+        }
+    }
+
+    pub fn set_exciter(&mut self, exciter: PluckExciter) {
+        self.exciter = exciter;
+    }
+
+    ///
+    /// Plucks the string at a new note. The previous note keeps ringing in the loop, as a real
+    /// string would when plucked again.
+    ///
+    /// # Arguments
+    /// * `frequency` - the note's fundamental frequency, in Hz
+    /// * `velocity` - how hard the string is plucked, from 0 to 1
+    ///
+    pub fn pluck(&mut self, frequency: f32, velocity: f32) {
+        self.frequency = frequency;
+        self.update_loop();
+        self.excitation_amplitude = velocity;
+        self.excitation_remaining = match self.exciter {
+            PluckExciter::Noise => (self.sample_rate / self.frequency).round() as usize,
+            PluckExciter::Impulse => 1,
+        };
+    }
+
+    fn damping_pole(&self) -> f32 {
+        self.damping.clamp(0.0, 1.0) * MAX_DAMPING_POLE
+    }
+
+    fn update_loop(&mut self) {
+        let max_frequency = self.sample_rate / (MIN_DELAY_SAMPLES + 1.0);
+        let frequency = self.frequency.clamp(MIN_FREQUENCY, max_frequency);
+        let period = self.sample_rate / frequency;
+
+        // The one-pole low-pass delays the fundamental too, so take that out of the delay line
+        let pole = self.damping_pole();
+        let w = 2.0 * PI * frequency / self.sample_rate;
+        let filter_delay = (pole * w.sin()).atan2(1.0 - pole * w.cos()) / w;
+        self.delay = (period - filter_delay).max(MIN_DELAY_SAMPLES);
+
+        // Lose 60 dB over the decay time, a period at a time
+        let decay_samples = (self.decay_seconds * self.sample_rate).max(1.0);
+        self.feedback = 10_f32.powf(-3.0 * period / decay_samples);
+    }
+
+    pub fn process(&mut self) -> f32 {
+        let excitation = if self.excitation_remaining > 0 {
+            self.excitation_remaining -= 1;
+            let value = match self.exciter {
+                PluckExciter::Noise => self.noise.next().unwrap_or(0.0),
+                PluckExciter::Impulse => 1.0,
+            };
+            value * self.excitation_amplitude
+        } else {
+            0.0
+        };
+
+        let length = self.buffer.len();
+        let read_time = (self.write_index + length) as f32 - self.delay;
+        let delayed =
+            get_interpolated_value_from_buffer(&self.buffer, read_time, Interpolation::Cubic);
+
+        let pole = self.damping_pole();
+        self.filter_state = flush_denormal((1.0 - pole) * delayed + pole * self.filter_state);
+
+        let output = excitation + self.feedback * self.filter_state;
+        self.buffer[self.write_index] = output;
+        self.write_index = (self.write_index + 1) % length;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biquad::{BiquadFilter, BiquadFilterType};
+
+    const SAMPLE_RATE: f32 = 44_100.0;
+
+    fn power(signal: &[f32]) -> f32 {
+        signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32
+    }
+
+    ///
+    /// Finds the period of a signal's fundamental, in samples, by band-passing it around the
+    /// expected frequency and averaging the time between upward zero crossings.
+    ///
+    fn fundamental_period(signal: &[f32], frequency: f32) -> f32 {
+        let mut band_pass = BiquadFilter::new();
+        band_pass.set_biquad(
+            BiquadFilterType::BandPass,
+            frequency / SAMPLE_RATE,
+            5.0,
+            0.0,
+        );
+        let filtered: Vec<f32> = signal.iter().map(|x| band_pass.process(*x)).collect();
+
+        // Skip the filter's settling time, and interpolate each crossing between samples
+        let crossings: Vec<f32> = filtered
+            .windows(2)
+            .enumerate()
+            .skip(filtered.len() / 4)
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(n, pair)| n as f32 + pair[0] / (pair[0] - pair[1]))
+            .collect();
+        (crossings[crossings.len() - 1] - crossings[0]) / (crossings.len() - 1) as f32
+    }
+
+    #[test]
+    fn plucked_string_is_tuned_to_the_note() {
+        for (frequency, damping) in [(220.0, 0.5), (330.0, 0.9), (1_000.0, 0.2)] {
+            let mut string = PluckedString::new(SAMPLE_RATE, 1);
+            string.set_damping(damping);
+            string.pluck(frequency, 1.0);
+            let output: Vec<f32> = (0..8_192).map(|_| string.process()).collect();
+
+            let expected = SAMPLE_RATE / frequency;
+            let period = fundamental_period(&output, frequency);
+            let error_cents = 1_200.0 * (period / expected).log2();
+            assert!(error_cents.abs() < 2.0);
+        }
+    }
+
+    #[test]
+    fn undamped_string_decays_by_sixty_db_over_the_decay_time() {
+        // An exact number of samples per period, so the loop needs no interpolation
+        let mut string = PluckedString::new(SAMPLE_RATE, 1);
+        string.set_damping(0.0);
+        string.set_decay(0.5);
+        string.pluck(SAMPLE_RATE / 210.0, 1.0);
+        let output: Vec<f32> = (0..SAMPLE_RATE as usize)
+            .map(|_| string.process())
+            .collect();
+
+        let start = power(&output[210..2_310]);
+        let end = power(&output[22_260..24_360]);
+        let decay_db = 10.0 * (end / start).log10();
+        assert!((decay_db + 60.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn damping_mellows_the_tone() {
+        // The share of the tail's power above 4 kHz
+        let brightness = |damping: f32| {
+            let mut string = PluckedString::new(SAMPLE_RATE, 1);
+            string.set_damping(damping);
+            string.pluck(220.0, 1.0);
+            let output: Vec<f32> = (0..SAMPLE_RATE as usize / 2)
+                .map(|_| string.process())
+                .collect();
+            let mut high_pass = BiquadFilter::new();
+            high_pass.set_biquad(
+                BiquadFilterType::HighPass,
+                4_000.0 / SAMPLE_RATE,
+                0.707,
+                0.0,
+            );
+            let highs: Vec<f32> = output.iter().map(|x| high_pass.process(*x)).collect();
+            power(&highs[11_025..]) / power(&output[11_025..])
+        };
+        assert!(brightness(0.8) < 0.1 * brightness(0.1));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::denormal::flush_denormal;
+use crate::oversampling::StereoOversampler;
+
+/// How many times the soft clipper oversamples when oversampling is on
+const SAFETY_OVERSAMPLING: usize = 2;
+const SAFETY_FILTER_ORDER: usize = 8;
+/// Where the soft clipper starts bending, as a fraction of the ceiling (about -0.9 dB), so
+/// anything that isn't already running away passes through untouched
+const SOFT_CLIP_KNEE: f32 = 0.9;
+/// The default ceiling, in dBFS
+pub const DEFAULT_SAFETY_CEILING_DB: f32 = -0.3;
+
+///
+/// Soft clips a sample under a ceiling. Samples more than about 0.9 dB below the ceiling pass
+/// through unchanged, and louder samples bend smoothly towards the ceiling along a tanh curve without ever
+/// reaching it.
+///
+/// # Arguments
+/// * `ceiling` - the linear level the output approaches
+/// * `input_sample` - the sample to clip
+///
+pub fn get_safety_clipper_output(ceiling: f32, input_sample: f32) -> f32 {
+    let knee = ceiling * SOFT_CLIP_KNEE;
+    let magnitude = input_sample.abs();
+    if magnitude <= knee {
+        return input_sample;
+    }
+
+    // Starting at a slope of 1 keeps the bend free of a corner at the knee
+    let headroom = ceiling - knee;
+    let clipped = knee + headroom * ((magnitude - knee) / headroom).tanh();
+    clipped.copysign(input_sample)
+}
+
+///
+/// A final safety stage for a plugin's stereo output: a tanh soft clipper under a ceiling,
+/// plus an optional hard ceiling, with non-finite samples silenced and denormals flushed.
+/// Effects like frozen reverbs and high-feedback delays can build far past full scale, and this
+/// keeps them from reaching the host.
+///
+/// Oversampling the soft clipper reduces its aliasing and catches peaks between samples, at the
+/// cost of the oversampler's latency. The downsampling filters can ring slightly past the
+/// ceiling, which the hard ceiling catches.
+///
+pub struct OutputSafety {
+    ceiling_db: f32,
+    ceiling: f32,
+    enabled: bool,
+    hard_ceiling: bool,
+    oversampled: bool,
+    oversampler: StereoOversampler<SAFETY_OVERSAMPLING>,
+}
+
+impl Default for OutputSafety {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputSafety {
+    pub fn new() -> OutputSafety {
+        let mut safety = OutputSafety {
+            ceiling_db: f32::NAN,
+            ceiling: 1.0,
+            enabled: true,
+            hard_ceiling: true,
+            oversampled: false,
+            oversampler: StereoOversampler::new(SAFETY_FILTER_ORDER, false),
+        };
+        safety.set_ceiling(DEFAULT_SAFETY_CEILING_DB);
+        safety
+    }
+
+    /// Sets the highest level the output can reach, in dBFS. The linear ceiling is only
+    /// recomputed when it changes, so this can be called every sample.
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        if ceiling != self.ceiling_db {
+            self.ceiling_db = ceiling;
+            self.ceiling = 10.0_f32.powf(ceiling * 0.05);
+        }
+    }
+
+    /// Sets whether the output is clamped to the ceiling after soft clipping.
+    pub fn set_hard_ceiling(&mut self, hard_ceiling: bool) {
+        self.hard_ceiling = hard_ceiling;
+    }
+
+    /// Sets whether the soft clipper runs at twice the sample rate, which adds latency.
+    pub fn set_oversampled(&mut self, oversampled: bool) {
+        if oversampled != self.oversampled {
+            self.oversampler.reset();
+        }
+        self.oversampled = oversampled;
+    }
+
+    /// The delay the stage adds, in samples; only nonzero when oversampled.
+    pub fn latency(&self) -> f32 {
+        if self.oversampled {
+            self.oversampler.latency()
+        } else {
+            0.0
+        }
+    }
+
+    /// Clears the oversampler's state.
+    pub fn reset(&mut self) {
+        self.oversampler.reset();
+    }
+
+    ///
+    /// Runs the stage as a plugin's switchable output safety, passing the frame through
+    /// untouched while it's off. Switching it back on starts the oversampler from silence.
+    ///
+    /// # Arguments
+    /// * `enabled` - whether the output safety is on
+    /// * `ceiling` - the highest level the output can reach, in dBFS
+    /// * `input` - the plugin's output frame
+    ///
+    pub fn process_switched(
+        &mut self,
+        enabled: bool,
+        ceiling: f32,
+        input: (f32, f32),
+    ) -> (f32, f32) {
+        if enabled && !self.enabled {
+            self.oversampler.reset();
+        }
+        self.enabled = enabled;
+        if !enabled {
+            return input;
+        }
+
+        self.set_ceiling(ceiling);
+        self.process(input)
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        // A non-finite sample would poison the oversampler's filters, so silence it first
+        let sanitize = |sample: f32| if sample.is_finite() { sample } else { 0.0 };
+        let input = (sanitize(input.0), sanitize(input.1));
+
+        let ceiling = self.ceiling;
+        let clip = |frame: (f32, f32)| {
+            (
+                get_safety_clipper_output(ceiling, frame.0),
+                get_safety_clipper_output(ceiling, frame.1),
+            )
+        };
+        let mut output = if self.oversampled {
+            self.oversampler.process(input, clip)
+        } else {
+            clip(input)
+        };
+
+        if self.hard_ceiling {
+            output = (
+                output.0.clamp(-ceiling, ceiling),
+                output.1.clamp(-ceiling, ceiling),
+            );
+        }
+        (flush_denormal(output.0), flush_denormal(output.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safety_clipper_is_transparent_below_the_knee_and_never_reaches_the_ceiling() {
+        let mut safety = OutputSafety::new();
+        safety.set_ceiling(0.0);
+        safety.set_hard_ceiling(false);
+        for n in 0..=100 {
+            let quiet = n as f32 / 100.0 * SOFT_CLIP_KNEE;
+            assert_eq!(safety.process((quiet, -quiet)), (quiet, -quiet));
+        }
+        let mut previous = 0.0;
+        for n in 1..=1_000 {
+            let loud = n as f32 * 0.1;
+            let (output, inverted) = safety.process((loud, -loud));
+            assert!(output <= 1.0 && output >= previous);
+            assert_eq!(inverted, -output);
+            previous = output;
+        }
+
+        // Garbage never gets through
+        assert_eq!(safety.process((f32::NAN, f32::INFINITY)), (0.0, 0.0));
+        assert_eq!(safety.process((f32::MIN_POSITIVE / 2.0, 0.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn hard_ceiling_catches_oversampled_overshoot() {
+        let mut safety = OutputSafety::new();
+        safety.set_oversampled(true);
+        assert!(safety.latency() > 0.0);
+
+        // A loud square wave rings past the ceiling through the downsampling filters
+        let ceiling = 10.0_f32.powf(DEFAULT_SAFETY_CEILING_DB * 0.05);
+        let square = |n: usize| {
+            if (n / 20).is_multiple_of(2) {
+                4.0
+            } else {
+                -4.0
+            }
+        };
+        safety.set_hard_ceiling(false);
+        let peak = (0..2_000)
+            .map(|n| safety.process((square(n), square(n))).0.abs())
+            .fold(0.0, f32::max);
+        assert!(peak > ceiling);
+
+        safety.reset();
+        safety.set_hard_ceiling(true);
+        for n in 0..2_000 {
+            let (output_l, output_r) = safety.process((square(n), square(n)));
+            assert!(output_l.abs() <= ceiling && output_r.abs() <= ceiling);
+        }
+    }
+
+    #[test]
+    fn switched_safety_passes_through_while_off_and_follows_the_ceiling() {
+        let mut safety = OutputSafety::new();
+        assert_eq!(
+            safety.process_switched(false, -6.0, (4.0, -4.0)),
+            (4.0, -4.0)
+        );
+
+        // Just under the ceiling is left alone, while runaway levels are held under it
+        let ceiling = 10.0_f32.powf(-6.0 * 0.05);
+        let quiet = 0.85 * ceiling;
+        assert_eq!(
+            safety.process_switched(true, -6.0, (quiet, -quiet)),
+            (quiet, -quiet)
+        );
+        let (loud, _) = safety.process_switched(true, -6.0, (4.0, -4.0));
+        assert!(loud <= ceiling && loud > 0.99 * ceiling);
+        let (loud, _) = safety.process_switched(true, -12.0, (4.0, -4.0));
+        assert!(loud <= 10.0_f32.powf(-12.0 * 0.05));
+    }
+}
@@ -0,0 +1,230 @@
+//! Reference signal generators for tests, calibration, and test tones. Every generator is an
+//! endless iterator, so `next()` always returns a sample and `take(n)` collects a buffer.
+
+use std::f32::consts::PI;
+
+/// How many random rows the pink noise generator sums, each updating half as often as the last.
+/// Twelve rows reach down to about 10 Hz at 44.1 kHz, without piling up sub-audio rumble
+const PINK_NOISE_ROWS: usize = 12;
+
+///
+/// A sine wave oscillator.
+///
+pub struct Sine {
+    phase: f32,
+    phase_increment: f32,
+    amplitude: f32,
+}
+
+impl Sine {
+    ///
+    /// # Arguments
+    /// * `frequency` - the frequency of the sine, in Hz
+    /// * `amplitude` - the peak amplitude of the sine
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(frequency: f32, amplitude: f32, sample_rate: f32) -> Sine {
+        Sine {
+            phase: 0.0,
+            phase_increment: frequency / sample_rate,
+            amplitude,
+        }
+    }
+}
+
+impl Iterator for Sine {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let output = (2.0 * PI * self.phase).sin() * self.amplitude;
+        self.phase = (self.phase + self.phase_increment).fract();
+        Some(output)
+    }
+}
+
+///
+/// Uniform white noise between -1 and 1, from a xorshift generator. The same seed always
+/// produces the same noise, so tests are repeatable.
+///
+pub struct WhiteNoise {
+    seed: u32,
+}
+
+impl WhiteNoise {
+    ///
+    /// # Arguments
+    /// * `seed` - a nonzero seed for the random number generator
+    ///
+    pub fn new(seed: u32) -> WhiteNoise {
+        WhiteNoise { seed: seed.max(1) }
+    }
+}
+
+impl Iterator for WhiteNoise {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        Some((self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0)
+    }
+}
+
+///
+/// Pink noise between -1 and 1, with equal power per octave (-3 dB per octave).
+///
+/// Uses the Voss-McCartney algorithm, which sums several random rows that each update half
+/// as often as the one before, plus a white noise row for the highest octave:
+/// https://www.firstpr.com.au/dsp/pink-noise/
+///
+pub struct PinkNoise {
+    white_noise: WhiteNoise,
+    rows: [f32; PINK_NOISE_ROWS],
+    running_sum: f32,
+    counter: u32,
+}
+
+impl PinkNoise {
+    ///
+    /// # Arguments
+    /// * `seed` - a nonzero seed for the random number generator
+    ///
+    pub fn new(seed: u32) -> PinkNoise {
+        PinkNoise {
+            white_noise: WhiteNoise::new(seed),
+            rows: [0.0; PINK_NOISE_ROWS],
+            running_sum: 0.0,
+            counter: 0,
+        }
+    }
+}
+
+impl Iterator for PinkNoise {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Each sample updates exactly one row, chosen by the counter's trailing zeros
+        self.counter = self.counter.wrapping_add(1);
+        let row = self.counter.trailing_zeros() as usize;
+        if row < PINK_NOISE_ROWS {
+            let value = self.white_noise.next()?;
+            self.running_sum += value - self.rows[row];
+            self.rows[row] = value;
+        }
+
+        let white = self.white_noise.next()?;
+        Some((self.running_sum + white) / (PINK_NOISE_ROWS + 1) as f32)
+    }
+}
+
+///
+/// A unit impulse, optionally repeating as an impulse train.
+///
+pub struct Impulse {
+    period: Option<usize>,
+    position: usize,
+}
+
+impl Impulse {
+    /// A single impulse followed by silence.
+    pub fn new() -> Impulse {
+        Impulse {
+            period: None,
+            position: 0,
+        }
+    }
+
+    ///
+    /// An impulse train, for measuring how a system responds to repeated clicks.
+    ///
+    /// # Arguments
+    /// * `period` - the number of samples between impulses
+    ///
+    pub fn train(period: usize) -> Impulse {
+        Impulse {
+            period: Some(period.max(1)),
+            position: 0,
+        }
+    }
+}
+
+impl Default for Impulse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for Impulse {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let output = if self.position == 0 { 1.0 } else { 0.0 };
+        self.position = match self.period {
+            Some(period) => (self.position + 1) % period,
+            None => self.position.saturating_add(1),
+        };
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biquad::{BiquadFilter, BiquadFilterType};
+
+    ///
+    /// Measures the power of a signal in a roughly octave-wide band.
+    ///
+    fn band_power(signal: &[f32], fc: f32) -> f32 {
+        let mut band_pass = BiquadFilter::new();
+        band_pass.set_biquad(BiquadFilterType::BandPass, fc, 1.41, 0.0);
+        signal
+            .iter()
+            .map(|x| band_pass.process(*x).powi(2))
+            .sum::<f32>()
+            / signal.len() as f32
+    }
+
+    #[test]
+    fn sine_has_expected_frequency_and_amplitude() {
+        let samples: Vec<f32> = Sine::new(1_000.0, 0.5, 48_000.0).take(48_000).collect();
+        let upward_crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!((999..=1_000).contains(&upward_crossings));
+        let peak = samples.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!((peak - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn white_noise_is_bounded_and_centered() {
+        let samples: Vec<f32> = WhiteNoise::new(1).take(100_000).collect();
+        assert!(samples.iter().all(|x| (-1.0..=1.0).contains(x)));
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!(mean.abs() < 0.01);
+
+        // White noise has twice the power in each octave up
+        let ratio = band_power(&samples, 0.08) / band_power(&samples, 0.005);
+        assert!((ratio.log2() - 4.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn pink_noise_has_equal_power_per_octave() {
+        let samples: Vec<f32> = PinkNoise::new(1).take(200_000).collect();
+        assert!(samples.iter().all(|x| (-1.0..=1.0).contains(x)));
+
+        // Four octaves apart, the bands should hold about the same power
+        let ratio_db = 10.0 * (band_power(&samples, 0.08) / band_power(&samples, 0.005)).log10();
+        assert!(ratio_db.abs() < 2.0);
+    }
+
+    #[test]
+    fn impulse_and_impulse_train() {
+        let impulse: Vec<f32> = Impulse::new().take(5).collect();
+        assert_eq!(impulse, [1.0, 0.0, 0.0, 0.0, 0.0]);
+        let train: Vec<f32> = Impulse::train(3).take(7).collect();
+        assert_eq!(train, [1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+    }
+}
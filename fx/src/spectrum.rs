@@ -0,0 +1,339 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// The twiddle factors of an FFT size, computed once so repeated FFTs don't recompute them.
+pub struct Twiddles {
+    fft_size: usize,
+    real: Vec<f32>,
+    imag: Vec<f32>,
+}
+
+impl Twiddles {
+    ///
+    /// Computes the twiddle factors for FFTs of a size.
+    ///
+    /// # Arguments
+    /// * `fft_size` - the FFT size; must be a power of two
+    ///
+    pub fn new(fft_size: usize) -> Twiddles {
+        assert!(fft_size.is_power_of_two());
+        let (imag, real) = (0..fft_size / 2)
+            .map(|k| (-2.0 * PI * k as f32 / fft_size as f32).sin_cos())
+            .unzip();
+        Twiddles {
+            fft_size,
+            real,
+            imag,
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+}
+
+///
+/// Computes an in-place, iterative radix-2 FFT with precomputed twiddle factors, so it doesn't
+/// allocate.
+///
+/// # Arguments
+/// * `real` - the real part of the input, replaced with the real part of the output
+/// * `imag` - the imaginary part of the input, replaced with the imaginary part of the output
+/// * `twiddles` - the twiddle factors for the slices' length
+///
+pub fn fft(real: &mut [f32], imag: &mut [f32], twiddles: &Twiddles) {
+    let n = real.len();
+    assert_eq!(n, imag.len());
+    assert_eq!(n, twiddles.fft_size());
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imag.swap(i, j);
+        }
+    }
+
+    // Butterflies
+    let mut length = 2;
+    while length <= n {
+        // A stage's twiddle factors are every nth of the full size's
+        let stride = n / length;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (w_real, w_imag) = (twiddles.real[k * stride], twiddles.imag[k * stride]);
+                let even = start + k;
+                let odd = even + length / 2;
+
+                let t_real = real[odd] * w_real - imag[odd] * w_imag;
+                let t_imag = real[odd] * w_imag + imag[odd] * w_real;
+
+                real[odd] = real[even] - t_real;
+                imag[odd] = imag[even] - t_imag;
+                real[even] += t_real;
+                imag[even] += t_imag;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+///
+/// A ring buffer of the latest samples that one thread writes and another reads without
+/// locking, such as the audio thread feeding a spectrum analyzer on a background thread.
+/// Samples are stored as atomics, so a read racing a write may see a mix of old and new samples,
+/// which an analyzer can tolerate.
+///
+pub struct SampleRing {
+    samples: Vec<AtomicU32>,
+    write_index: AtomicUsize,
+}
+
+impl SampleRing {
+    ///
+    /// Creates a new, silent sample ring.
+    ///
+    /// # Arguments
+    /// * `length` - how many of the latest samples are kept
+    ///
+    pub fn new(length: usize) -> SampleRing {
+        SampleRing {
+            samples: (0..length)
+                .map(|_| AtomicU32::new(0.0f32.to_bits()))
+                .collect(),
+            write_index: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Writes a sample over the oldest one.
+    pub fn push(&self, sample: f32) {
+        let index = self.write_index.load(Ordering::Relaxed);
+        self.samples[index].store(sample.to_bits(), Ordering::Relaxed);
+        self.write_index
+            .store((index + 1) % self.samples.len(), Ordering::Release);
+    }
+
+    /// Silences the ring.
+    pub fn reset(&self) {
+        for sample in self.samples.iter() {
+            sample.store(0.0f32.to_bits(), Ordering::Relaxed);
+        }
+        self.write_index.store(0, Ordering::Release);
+    }
+
+    ///
+    /// Copies the latest samples, from oldest to newest.
+    ///
+    /// # Arguments
+    /// * `output` - where to copy the samples to, as long as the ring
+    ///
+    pub fn copy_latest(&self, output: &mut [f32]) {
+        let start = self.write_index.load(Ordering::Acquire);
+        let length = self.samples.len();
+        for (i, sample) in output.iter_mut().take(length).enumerate() {
+            *sample = f32::from_bits(self.samples[(start + i) % length].load(Ordering::Relaxed));
+        }
+    }
+}
+
+/// A spectrum analyzer that collects input samples and computes the magnitude
+/// of each frequency bin once a full block has been collected.
+///
+/// All buffers and the FFT's twiddle factors are computed up front, so the analyzer doesn't
+/// allocate while running. It can collect samples itself with `push`, or analyze the latest
+/// samples of a `SampleRing` with `analyze`, so the FFT can run off the audio thread.
+pub struct SpectrumAnalyzer {
+    input_buffer: Vec<f32>,
+    write_index: usize,
+    window: Vec<f32>,
+    window_sum: f32,
+    twiddles: Twiddles,
+    real: Vec<f32>,
+    imag: Vec<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    ///
+    /// Creates a new spectrum analyzer.
+    ///
+    /// # Arguments
+    /// * `fft_size` - the number of samples per block; must be a power of two
+    ///
+    pub fn new(fft_size: usize) -> SpectrumAnalyzer {
+        assert!(fft_size.is_power_of_two());
+
+        // Hann window
+        let window: Vec<f32> = (0..fft_size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / fft_size as f32).cos())
+            .collect();
+        let window_sum = window.iter().sum();
+
+        SpectrumAnalyzer {
+            input_buffer: vec![0.0; fft_size],
+            write_index: 0,
+            window,
+            window_sum,
+            twiddles: Twiddles::new(fft_size),
+            real: vec![0.0; fft_size],
+            imag: vec![0.0; fft_size],
+            magnitudes: vec![0.0; fft_size / 2 + 1],
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.input_buffer.len()
+    }
+
+    /// Returns the number of magnitude bins, from DC up to the Nyquist frequency.
+    pub fn num_bins(&self) -> usize {
+        self.magnitudes.len()
+    }
+
+    /// Returns the center frequency of a bin, in Hz.
+    pub fn bin_frequency(&self, bin: usize, sample_rate: f32) -> f32 {
+        bin as f32 * sample_rate / self.fft_size() as f32
+    }
+
+    /// Clears the collected input and the magnitudes.
+    pub fn reset(&mut self) {
+        self.input_buffer.fill(0.0);
+        self.magnitudes.fill(0.0);
+        self.write_index = 0;
+    }
+
+    ///
+    /// Pushes an input sample into the analyzer. Returns true when a full block has been
+    /// collected, at which point the magnitudes have been updated.
+    ///
+    /// # Arguments
+    /// * `sample` - a single input sample
+    ///
+    pub fn push(&mut self, sample: f32) -> bool {
+        self.input_buffer[self.write_index] = sample;
+        self.write_index += 1;
+        if self.write_index == self.input_buffer.len() {
+            self.write_index = 0;
+            self.compute();
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// Updates the magnitudes from the latest block of samples in a sample ring.
+    ///
+    /// # Arguments
+    /// * `ring` - the sample ring, as long as the FFT size
+    ///
+    pub fn analyze(&mut self, ring: &SampleRing) {
+        assert_eq!(ring.len(), self.fft_size());
+        ring.copy_latest(&mut self.input_buffer);
+        self.write_index = 0;
+        self.compute();
+    }
+
+    /// Returns the linear amplitude of each bin, where a full-scale sine reads as 1.0.
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+
+    fn compute(&mut self) {
+        for (i, (real, imag)) in self.real.iter_mut().zip(self.imag.iter_mut()).enumerate() {
+            *real = self.input_buffer[i] * self.window[i];
+            *imag = 0.0;
+        }
+
+        fft(&mut self.real, &mut self.imag, &self.twiddles);
+
+        // Scale by the window's gain so a sine's amplitude is read directly
+        let scale = 2.0 / self.window_sum;
+        for (bin, magnitude) in self.magnitudes.iter_mut().enumerate() {
+            *magnitude = self.real[bin].hypot(self.imag[bin]) * scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyzer_finds_sine_amplitude_at_its_bin() {
+        let fft_size = 256;
+        let sample_rate = 256.0;
+        let bin = 16;
+        let mut analyzer = SpectrumAnalyzer::new(fft_size);
+
+        let mut is_ready = false;
+        for n in 0..fft_size {
+            let phase = 2.0 * PI * bin as f32 * n as f32 / sample_rate;
+            is_ready = analyzer.push(0.5 * phase.sin());
+        }
+        assert!(is_ready);
+
+        let magnitudes = analyzer.magnitudes();
+        assert!((magnitudes[bin] - 0.5).abs() < 1e-3);
+        assert!(magnitudes[bin + 4] < 1e-3);
+        assert!((analyzer.bin_frequency(bin, sample_rate) - 16.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn analyzing_a_ring_matches_pushing() {
+        let fft_size = 256;
+        let mut pushed = SpectrumAnalyzer::new(fft_size);
+        let mut analyzed = SpectrumAnalyzer::new(fft_size);
+        let ring = SampleRing::new(fft_size);
+
+        // The ring keeps only the latest block, so an extra half block of older samples drops out
+        let signal = |n: usize| (0.1 * n as f32).sin() * 0.25 + (0.37 * n as f32).cos() * 0.1;
+        for n in 0..fft_size / 2 {
+            ring.push(signal(n));
+        }
+        for n in fft_size / 2..fft_size * 3 / 2 {
+            ring.push(signal(n));
+            pushed.push(signal(n));
+        }
+        analyzed.analyze(&ring);
+
+        for (pushed, analyzed) in pushed.magnitudes().iter().zip(analyzed.magnitudes()) {
+            assert!((pushed - analyzed).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn fft_matches_direct_dft() {
+        let n = 64;
+        let input: Vec<f32> = (0..n).map(|i| ((i * 7) % 11) as f32 - 5.0).collect();
+        let mut real = input.clone();
+        let mut imag = vec![0.0; n];
+        fft(&mut real, &mut imag, &Twiddles::new(n));
+
+        for bin in 0..n {
+            let (mut dft_real, mut dft_imag) = (0.0, 0.0);
+            for (i, x) in input.iter().enumerate() {
+                let angle = -2.0 * PI * (bin * i) as f32 / n as f32;
+                dft_real += x * angle.cos();
+                dft_imag += x * angle.sin();
+            }
+            assert!((real[bin] - dft_real).abs() < 1e-3);
+            assert!((imag[bin] - dft_imag).abs() < 1e-3);
+        }
+    }
+}
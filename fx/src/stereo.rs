@@ -0,0 +1,301 @@
+use crate::biquad::{BiquadFilter, BiquadFilterType};
+use std::f32::consts::{E, FRAC_PI_4};
+
+/// Below this power, the correlation meter treats the signal as silent
+const CORRELATION_SILENCE: f32 = 1e-10;
+
+/// The longest Haas delay a stereo widener can apply, in milliseconds
+pub const MAX_HAAS_DELAY_MS: f32 = 30.0;
+
+/// Butterworth Q for the widener's bass mono filter
+const BASS_MONO_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+///
+/// Encodes a stereo frame from left/right to mid/side, where mid is `(L + R) / 2` and side is
+/// `(L - R) / 2`.
+///
+pub fn encode_mid_side(input: (f32, f32)) -> (f32, f32) {
+    ((input.0 + input.1) * 0.5, (input.0 - input.1) * 0.5)
+}
+
+///
+/// Decodes a stereo frame from mid/side back to left/right, undoing `encode_mid_side`.
+///
+pub fn decode_mid_side(input: (f32, f32)) -> (f32, f32) {
+    (input.0 + input.1, input.0 - input.1)
+}
+
+///
+/// Sums a stereo frame to mono, as it would sound on a mono playback system.
+///
+pub fn collapse_to_mono(input: (f32, f32)) -> f32 {
+    (input.0 + input.1) * 0.5
+}
+
+///
+/// Calculates the left and right gains for a pan position with the equal-power (sine/cosine)
+/// panning law. The gains' squares always sum to 1, so a source keeps the same loudness
+/// anywhere in the stereo field, where linear panning dips by 3 dB in the center.
+///
+/// # Arguments
+/// * `pan` - the pan position, from -1 (hard left) through 0 (center, -3 dB in each channel)
+///   to 1 (hard right)
+///
+pub fn equal_power_pan(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+///
+/// A phase correlation meter for checking mono compatibility. It reports +1 when both channels
+/// are identical, 0 when they're unrelated (or silent), and -1 when they're out of phase and
+/// would cancel when summed to mono.
+///
+pub struct CorrelationMeter {
+    product: f32,
+    power_l: f32,
+    power_r: f32,
+    alpha: f32,
+}
+
+impl CorrelationMeter {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `time_constant` - how quickly the meter follows the signal, in seconds
+    ///
+    pub fn new(sample_rate: f32, time_constant: f32) -> CorrelationMeter {
+        let mut meter = CorrelationMeter {
+            product: 0.,
+            power_l: 0.,
+            power_r: 0.,
+            alpha: 0.,
+        };
+        meter.set_time_constant(sample_rate, time_constant);
+        meter
+    }
+
+    pub fn set_time_constant(&mut self, sample_rate: f32, time_constant: f32) {
+        self.alpha = E.recip().powf(sample_rate.recip() / time_constant);
+    }
+
+    pub fn reset(&mut self) {
+        self.product = 0.;
+        self.power_l = 0.;
+        self.power_r = 0.;
+    }
+
+    ///
+    /// Measures a stereo frame and returns the current correlation, from -1 to 1.
+    ///
+    pub fn process(&mut self, input: (f32, f32)) -> f32 {
+        let (l, r) = input;
+        self.product = self.alpha * self.product + (1. - self.alpha) * l * r;
+        self.power_l = self.alpha * self.power_l + (1. - self.alpha) * l * l;
+        self.power_r = self.alpha * self.power_r + (1. - self.alpha) * r * r;
+        self.correlation()
+    }
+
+    /// The current correlation, from -1 to 1.
+    pub fn correlation(&self) -> f32 {
+        let power = (self.power_l * self.power_r).sqrt();
+        if power < CORRELATION_SILENCE {
+            0.
+        } else {
+            (self.product / power).clamp(-1., 1.)
+        }
+    }
+}
+
+///
+/// A stereo widener that scales the side signal, optionally keeping the low end centered and
+/// widening further with a short Haas delay on the right channel.
+///
+pub struct StereoWidener {
+    width: f32,
+    bass_mono: bool,
+    bass_mono_frequency: f32,
+    bass_mono_filter: BiquadFilter,
+    haas_buffer: Vec<f32>,
+    haas_index: usize,
+    haas_delay: usize,
+    sample_rate: f32,
+}
+
+impl StereoWidener {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    ///
+    pub fn new(sample_rate: f32) -> StereoWidener {
+        let mut widener = StereoWidener {
+            width: 1.0,
+            bass_mono: false,
+            bass_mono_frequency: 0.0,
+            bass_mono_filter: BiquadFilter::new(),
+            haas_buffer: vec![],
+            haas_index: 0,
+            haas_delay: 0,
+            sample_rate,
+        };
+        widener.set_sample_rate(sample_rate);
+        widener
+    }
+
+    /// Sets the sample rate, resizing and clearing the Haas delay buffer.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        let length = (MAX_HAAS_DELAY_MS / 1000.0 * sample_rate).ceil() as usize + 1;
+        self.haas_buffer = vec![0.0; length];
+        self.haas_index = 0;
+        self.bass_mono_filter.set_biquad(
+            BiquadFilterType::HighPass,
+            self.bass_mono_frequency / sample_rate,
+            BASS_MONO_Q,
+            0.0,
+        );
+    }
+
+    ///
+    /// Sets the stereo width.
+    ///
+    /// # Arguments
+    /// * `width` - the amount of side signal; 0 is mono, 1 is unchanged, and 2 is extra wide
+    ///
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width;
+    }
+
+    ///
+    /// Keeps the low end centered by filtering the side signal below a crossover frequency.
+    ///
+    /// # Arguments
+    /// * `enabled` - whether the bass is made mono
+    /// * `frequency` - the crossover frequency, in Hz
+    ///
+    pub fn set_bass_mono(&mut self, enabled: bool, frequency: f32) {
+        self.bass_mono = enabled;
+        if frequency != self.bass_mono_frequency {
+            self.bass_mono_frequency = frequency;
+            self.bass_mono_filter.set_biquad(
+                BiquadFilterType::HighPass,
+                frequency / self.sample_rate,
+                BASS_MONO_Q,
+                0.0,
+            );
+        }
+    }
+
+    ///
+    /// Delays the right channel slightly, which the ear hears as width rather than an echo.
+    ///
+    /// # Arguments
+    /// * `delay_ms` - the delay in milliseconds, up to `MAX_HAAS_DELAY_MS`; 0 turns it off
+    ///
+    pub fn set_haas_delay(&mut self, delay_ms: f32) {
+        let delay = (delay_ms.clamp(0.0, MAX_HAAS_DELAY_MS) / 1000.0 * self.sample_rate).round();
+        self.haas_delay = (delay as usize).min(self.haas_buffer.len() - 1);
+    }
+
+    pub fn reset(&mut self) {
+        self.haas_buffer.fill(0.0);
+        self.haas_index = 0;
+        self.bass_mono_filter.set_biquad(
+            BiquadFilterType::HighPass,
+            self.bass_mono_frequency / self.sample_rate,
+            BASS_MONO_Q,
+            0.0,
+        );
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let (mid, side) = encode_mid_side(input);
+
+        // The filter keeps running while disabled, so enabling it doesn't click
+        let filtered_side = self.bass_mono_filter.process(side);
+        let side = if self.bass_mono { filtered_side } else { side };
+        let (out_l, out_r) = decode_mid_side((mid, side * self.width));
+
+        // Write then read, so no delay reads the current sample
+        let length = self.haas_buffer.len();
+        self.haas_buffer[self.haas_index] = out_r;
+        let out_r = self.haas_buffer[(self.haas_index + length - self.haas_delay) % length];
+        self.haas_index = (self.haas_index + 1) % length;
+
+        (out_l, out_r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_power_pan_keeps_power_constant() {
+        assert_eq!(equal_power_pan(-1.0).1, 0.0);
+        assert!(equal_power_pan(1.0).0.abs() < 1e-7);
+        let center = equal_power_pan(0.0);
+        assert!((center.0 - center.1).abs() < 1e-7);
+        for n in 0..=20 {
+            let (gain_l, gain_r) = equal_power_pan(n as f32 / 10.0 - 1.0);
+            assert!((gain_l * gain_l + gain_r * gain_r - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mid_side_round_trips() {
+        let input = (0.75, -0.25);
+        assert_eq!(encode_mid_side(input), (0.25, 0.5));
+        assert_eq!(decode_mid_side(encode_mid_side(input)), input);
+        assert_eq!(collapse_to_mono(input), 0.25);
+    }
+
+    #[test]
+    fn widener_passes_through_at_unity_width() {
+        let mut widener = StereoWidener::new(44_100.);
+        for n in 0..1000 {
+            let input = ((n as f32 * 0.3).sin(), (n as f32 * 0.71).cos() * 0.5);
+            let output = widener.process(input);
+            assert!((output.0 - input.0).abs() < 1e-6);
+            assert!((output.1 - input.1).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn widener_bass_mono_centers_low_end() {
+        let mut mono = StereoWidener::new(44_100.);
+        mono.set_width(0.);
+        let mut bass_mono = StereoWidener::new(44_100.);
+        bass_mono.set_width(2.);
+        bass_mono.set_bass_mono(true, 200.);
+
+        // A low, out of phase signal is all side, so it cancels below the crossover
+        let mut peak: f32 = 0.;
+        for n in 0..44_100 {
+            let sample = (2. * std::f32::consts::PI * 40. * n as f32 / 44_100.).sin();
+            let output = mono.process((sample, sample * 0.2));
+            assert_eq!(output.0, output.1);
+            let output = bass_mono.process((sample, -sample));
+            if n > 22_050 {
+                peak = peak.max(output.0.abs());
+            }
+        }
+        assert!(peak < 0.1);
+    }
+
+    #[test]
+    fn correlation_meter_reports_mono_and_out_of_phase() {
+        let mut mono = CorrelationMeter::new(1000., 0.05);
+        let mut out_of_phase = CorrelationMeter::new(1000., 0.05);
+        let mut silent = CorrelationMeter::new(1000., 0.05);
+        for n in 0..1000 {
+            let sample = (n as f32 * 0.3).sin() * 0.5;
+            mono.process((sample, sample));
+            out_of_phase.process((sample, -sample));
+            silent.process((0., 0.));
+        }
+        assert!((mono.correlation() - 1.).abs() < 1e-4);
+        assert!((out_of_phase.correlation() + 1.).abs() < 1e-4);
+        assert_eq!(silent.correlation(), 0.);
+    }
+}
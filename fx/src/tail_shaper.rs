@@ -0,0 +1,407 @@
+/// The input level that opens the gate, as a linear amplitude (-40 dBFS)
+const GATE_THRESHOLD: f32 = 0.01;
+/// How quickly the gate opens and closes, in seconds; short, so the gated tail cuts off abruptly
+const GATE_ATTACK_SECONDS: f32 = 0.001;
+const GATE_RELEASE_SECONDS: f32 = 0.01;
+/// The fade at each end of a reversed block, in seconds, so the blocks join without clicks
+const REVERSE_FADE_SECONDS: f32 = 0.005;
+/// How long a capture takes to fade in over the live tail, and back out, in seconds
+const CAPTURE_FADE_SECONDS: f32 = 0.05;
+/// The crossfade where a captured loop wraps back to its start, in seconds
+const CAPTURE_LOOP_CROSSFADE_SECONDS: f32 = 0.1;
+
+/// How a reverb's tail is shaped over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailMode {
+    /// The reverb's natural decay.
+    Natural,
+    /// The tail is cut off a hold time after the input falls silent, like 80s gated drums.
+    Gated,
+    /// Each hold time's worth of tail is played backwards, swelling into the dry signal.
+    Reverse,
+}
+
+///
+/// Shapes a reverb's wet signal into a gated or reversed tail.
+///
+/// Gating follows the dry input, holding the gate open while the input is above -40 dBFS and
+/// for the hold time after. Reversing records the wet signal in blocks of the hold time and
+/// plays each block backwards during the next one. The dry signal is delayed by two blocks, so
+/// every swell finishes before the transient that caused it; `latency` reports that delay.
+///
+pub struct TailShaper {
+    mode: TailMode,
+    sample_rate: f32,
+    hold_samples: usize,
+    // Gate state
+    gate_gain: f32,
+    gate_hold_remaining: usize,
+    // Reverse state; the block length only changes on reset
+    block_length: usize,
+    block_position: usize,
+    recording: Vec<(f32, f32)>,
+    playback: Vec<(f32, f32)>,
+    dry_delay: Vec<(f32, f32)>,
+    dry_delay_index: usize,
+}
+
+impl TailShaper {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `max_hold_seconds` - the longest hold time, which sizes the reverse buffers
+    ///
+    pub fn new(sample_rate: f32, max_hold_seconds: f32) -> TailShaper {
+        let max_hold_samples = ((max_hold_seconds * sample_rate) as usize).max(1);
+        TailShaper {
+            mode: TailMode::Natural,
+            sample_rate,
+            hold_samples: max_hold_samples,
+            gate_gain: 0.,
+            gate_hold_remaining: 0,
+            block_length: max_hold_samples,
+            block_position: 0,
+            recording: vec![(0., 0.); max_hold_samples],
+            playback: vec![(0., 0.); max_hold_samples],
+            dry_delay: vec![(0., 0.); 2 * max_hold_samples],
+            dry_delay_index: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: TailMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.reset();
+        }
+    }
+
+    ///
+    /// Sets the hold time. In reverse mode, this is the length of the reversed blocks, and it
+    /// takes effect on the next reset, so the latency doesn't follow the hold time while it
+    /// moves.
+    ///
+    /// # Arguments
+    /// * `seconds` - the hold time, up to the maximum given to `new`
+    ///
+    pub fn set_hold(&mut self, seconds: f32) {
+        self.hold_samples = ((seconds * self.sample_rate) as usize).clamp(1, self.recording.len());
+    }
+
+    /// Clears the gate and the reverse buffers.
+    pub fn reset(&mut self) {
+        self.gate_gain = 0.;
+        self.gate_hold_remaining = 0;
+        self.block_length = self.hold_samples;
+        self.block_position = 0;
+        self.recording.fill((0., 0.));
+        self.playback.fill((0., 0.));
+        self.dry_delay.fill((0., 0.));
+        self.dry_delay_index = 0;
+    }
+
+    /// The delay added to the dry signal, in samples, which is only nonzero in reverse mode.
+    pub fn latency(&self) -> usize {
+        match self.mode {
+            TailMode::Reverse => 2 * self.block_length,
+            _ => 0,
+        }
+    }
+
+    ///
+    /// Shapes a frame of the reverb's tail.
+    ///
+    /// # Arguments
+    /// * `dry` - the reverb's input frame
+    /// * `wet` - the reverb's output frame
+    ///
+    /// Returns the dry frame, delayed to line up with the wet signal, and the shaped wet frame.
+    ///
+    pub fn process(&mut self, dry: (f32, f32), wet: (f32, f32)) -> ((f32, f32), (f32, f32)) {
+        match self.mode {
+            TailMode::Natural => (dry, wet),
+            TailMode::Gated => (dry, self.gate(dry, wet)),
+            TailMode::Reverse => self.reverse(dry, wet),
+        }
+    }
+
+    fn gate(&mut self, dry: (f32, f32), wet: (f32, f32)) -> (f32, f32) {
+        if dry.0.abs().max(dry.1.abs()) > GATE_THRESHOLD {
+            self.gate_hold_remaining = self.hold_samples;
+        }
+
+        if self.gate_hold_remaining > 0 {
+            self.gate_hold_remaining -= 1;
+            self.gate_gain =
+                (self.gate_gain + (GATE_ATTACK_SECONDS * self.sample_rate).recip()).min(1.);
+        } else {
+            self.gate_gain =
+                (self.gate_gain - (GATE_RELEASE_SECONDS * self.sample_rate).recip()).max(0.);
+        }
+        (wet.0 * self.gate_gain, wet.1 * self.gate_gain)
+    }
+
+    fn reverse(&mut self, dry: (f32, f32), wet: (f32, f32)) -> ((f32, f32), (f32, f32)) {
+        let position = self.block_position;
+        let length = self.block_length;
+        self.recording[position] = wet;
+        let reversed = self.playback[length - 1 - position];
+
+        // Fade in and out at the ends of each block, where the reversed blocks meet
+        let fade_samples = (REVERSE_FADE_SECONDS * self.sample_rate).max(1.);
+        let distance_to_edge = position.min(length - 1 - position) as f32;
+        let window = (distance_to_edge / fade_samples).min(1.);
+        let reversed = (reversed.0 * window, reversed.1 * window);
+
+        // Delay the dry signal by two blocks
+        let delay_length = 2 * length;
+        let delayed_index =
+            (self.dry_delay_index + self.dry_delay.len() - delay_length) % self.dry_delay.len();
+        let delayed_dry = self.dry_delay[delayed_index];
+        self.dry_delay[self.dry_delay_index] = dry;
+        self.dry_delay_index = (self.dry_delay_index + 1) % self.dry_delay.len();
+
+        self.block_position += 1;
+        if self.block_position >= length {
+            std::mem::swap(&mut self.recording, &mut self.playback);
+            self.block_position = 0;
+        }
+
+        (delayed_dry, reversed)
+    }
+}
+
+///
+/// Captures the last stretch of a reverb's tail and loops it as a sustained pad, so the held
+/// texture is the tail at the moment of capture rather than a freeze's ever-building wash.
+///
+/// The wet signal is recorded continuously until held. Holding loops the recording, with an
+/// equal-power crossfade where the loop wraps around, and fades it in over the live tail;
+/// releasing fades back to the live tail, and recording resumes once the loop has faded out.
+///
+pub struct TailCapture {
+    sample_rate: f32,
+    held: bool,
+    /// How much of the output is the loop rather than the live tail, from 0 to 1
+    hold_amount: f32,
+    buffer: Vec<(f32, f32)>,
+    write_index: usize,
+    /// The buffer index of the oldest captured frame, where the loop starts
+    capture_start: usize,
+    loop_position: usize,
+}
+
+impl TailCapture {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `loop_seconds` - how much of the tail is captured and looped
+    ///
+    pub fn new(sample_rate: f32, loop_seconds: f32) -> TailCapture {
+        // The loop needs room for its wraparound crossfade
+        let crossfade_samples = (CAPTURE_LOOP_CROSSFADE_SECONDS * sample_rate) as usize;
+        let length = ((loop_seconds * sample_rate) as usize).max(2 * crossfade_samples + 1);
+        TailCapture {
+            sample_rate,
+            held: false,
+            hold_amount: 0.,
+            buffer: vec![(0., 0.); length],
+            write_index: 0,
+            capture_start: 0,
+            loop_position: 0,
+        }
+    }
+
+    ///
+    /// Captures and loops the tail, or releases the loop back to the live tail. Holding again
+    /// while a released loop is still fading out keeps the old loop.
+    ///
+    /// # Arguments
+    /// * `held` - whether the captured tail is looped
+    ///
+    pub fn set_held(&mut self, held: bool) {
+        if held && !self.held && self.hold_amount == 0. {
+            self.capture_start = self.write_index;
+            self.loop_position = 0;
+        }
+        self.held = held;
+    }
+
+    /// Releases the loop immediately and clears the recording.
+    pub fn reset(&mut self) {
+        self.held = false;
+        self.hold_amount = 0.;
+        self.buffer.fill((0., 0.));
+        self.write_index = 0;
+        self.capture_start = 0;
+        self.loop_position = 0;
+    }
+
+    ///
+    /// Records or loops a frame of the reverb's tail.
+    ///
+    /// # Arguments
+    /// * `wet` - the reverb's output frame
+    ///
+    /// Returns the live tail, the loop, or a crossfade between the two.
+    ///
+    pub fn process(&mut self, wet: (f32, f32)) -> (f32, f32) {
+        let fade_step = (CAPTURE_FADE_SECONDS * self.sample_rate).recip();
+        self.hold_amount = if self.held {
+            (self.hold_amount + fade_step).min(1.)
+        } else {
+            (self.hold_amount - fade_step).max(0.)
+        };
+
+        if self.hold_amount == 0. {
+            self.buffer[self.write_index] = wet;
+            self.write_index = (self.write_index + 1) % self.buffer.len();
+            return wet;
+        }
+
+        // Uncorrelated signals keep a steady level under an equal-power crossfade
+        let looped = self.next_loop_frame();
+        let live_gain = (1. - self.hold_amount).sqrt();
+        let loop_gain = self.hold_amount.sqrt();
+        (
+            wet.0 * live_gain + looped.0 * loop_gain,
+            wet.1 * live_gain + looped.1 * loop_gain,
+        )
+    }
+
+    ///
+    /// Reads the next frame of the loop. The loop plays the capture after its first crossfade's
+    /// worth of frames, and over its last frames fades into those first frames, which lead
+    /// seamlessly back into the start of the loop.
+    ///
+    fn next_loop_frame(&mut self) -> (f32, f32) {
+        let length = self.buffer.len();
+        let crossfade_samples = (CAPTURE_LOOP_CROSSFADE_SECONDS * self.sample_rate) as usize;
+        let loop_length = length - crossfade_samples;
+        let frame_at = |index: usize| self.buffer[(self.capture_start + index) % length];
+
+        let position = self.loop_position;
+        let frame = frame_at(crossfade_samples + position);
+        let crossfade_start = loop_length - crossfade_samples;
+        let output = if position >= crossfade_start {
+            let lead_in = position - crossfade_start;
+            let amount = lead_in as f32 / crossfade_samples as f32;
+            let lead_in_frame = frame_at(lead_in);
+            let (out_gain, in_gain) = ((1. - amount).sqrt(), amount.sqrt());
+            (
+                frame.0 * out_gain + lead_in_frame.0 * in_gain,
+                frame.1 * out_gain + lead_in_frame.1 * in_gain,
+            )
+        } else {
+            frame
+        };
+
+        self.loop_position = (position + 1) % loop_length;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1_000.0;
+
+    #[test]
+    fn natural_mode_passes_through() {
+        let mut shaper = TailShaper::new(SAMPLE_RATE, 1.0);
+        assert_eq!(shaper.latency(), 0);
+        assert_eq!(
+            shaper.process((0.5, -0.5), (0.25, 0.75)),
+            ((0.5, -0.5), (0.25, 0.75))
+        );
+    }
+
+    #[test]
+    fn gate_cuts_the_tail_after_the_hold_time() {
+        let mut shaper = TailShaper::new(SAMPLE_RATE, 1.0);
+        shaper.set_mode(TailMode::Gated);
+        shaper.set_hold(0.1);
+
+        // A 10 ms burst of input, then a constant tail that should be cut off
+        let wet: Vec<f32> = (0..300)
+            .map(|n| {
+                let dry = if n < 10 { 1.0 } else { 0.0 };
+                shaper.process((dry, dry), (1.0, 1.0)).1 .0
+            })
+            .collect();
+
+        // The gate is held open for 100 ms after the input, then closes within 10 ms
+        assert!(wet[5..100].iter().all(|x| *x == 1.0));
+        assert!(wet[120..].iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn reverse_plays_blocks_backwards_before_the_dry_signal() {
+        let mut shaper = TailShaper::new(SAMPLE_RATE, 1.0);
+        shaper.set_mode(TailMode::Reverse);
+        shaper.set_hold(0.1);
+        shaper.reset();
+        assert_eq!(shaper.latency(), 200);
+
+        // A decaying tail starting with the dry transient
+        let mut dry_out = vec![];
+        let mut wet_out = vec![];
+        for n in 0..300 {
+            let dry = if n == 0 { 1.0 } else { 0.0 };
+            let wet = 0.99_f32.powi(n);
+            let (dry, wet) = shaper.process((dry, dry), (wet, wet));
+            dry_out.push(dry.0);
+            wet_out.push(wet.0);
+        }
+
+        // The dry transient is delayed by two blocks, after the reversed tail has swelled in
+        assert_eq!(dry_out.iter().position(|x| *x == 1.0), Some(200));
+        assert!(wet_out[..100].iter().all(|x| *x == 0.0));
+        assert!(wet_out[150] < wet_out[190]);
+        assert_eq!(wet_out[190], 0.99_f32.powi(9));
+    }
+
+    #[test]
+    fn reverse_hold_changes_wait_for_a_reset() {
+        let mut shaper = TailShaper::new(SAMPLE_RATE, 1.0);
+        shaper.set_mode(TailMode::Reverse);
+        shaper.set_hold(0.1);
+        shaper.reset();
+
+        // Moving the hold time mid-stream leaves the block length and latency alone
+        shaper.set_hold(0.3);
+        for _ in 0..500 {
+            shaper.process((0.0, 0.0), (0.0, 0.0));
+        }
+        assert_eq!(shaper.latency(), 200);
+
+        shaper.reset();
+        assert_eq!(shaper.latency(), 600);
+    }
+
+    #[test]
+    fn capture_loops_the_tail_at_the_moment_of_capture() {
+        let mut capture = TailCapture::new(SAMPLE_RATE, 1.0);
+
+        // Record a ramp, then hold it while the live tail goes silent
+        for n in 0..1_500 {
+            assert_eq!(capture.process((n as f32, 0.)), (n as f32, 0.));
+        }
+        capture.set_held(true);
+        let held: Vec<f32> = (0..3_000).map(|_| capture.process((0., 0.)).0).collect();
+
+        // Once faded in, the loop replays the last second after its first 100 frames, which are
+        // crossfaded in over the end of the loop instead, so it repeats every 900 frames
+        assert_eq!(held[60], 660.0);
+        assert!(held[60..800]
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] == 1.0));
+        assert_eq!(held[900], 600.0);
+        assert_eq!(held[1_000], held[100]);
+
+        // Releasing fades back to the live tail, which is silent
+        capture.set_held(false);
+        let released: Vec<f32> = (0..100).map(|_| capture.process((0., 0.)).0).collect();
+        assert!(released[60..].iter().all(|x| *x == 0.0));
+    }
+}
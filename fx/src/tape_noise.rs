@@ -0,0 +1,169 @@
+use crate::biquad::{BiquadFilterType, StereoBiquadFilter};
+use crate::signal::WhiteNoise;
+
+/// The hiss's level at full hiss, in dBFS RMS
+const MAX_HISS_DB: f32 = -42.0;
+/// The hiss is high-passed, since tape noise sits mostly in the upper mids and highs
+const HISS_HIGH_PASS_HZ: f32 = 1_500.0;
+/// How many crackles there are per second on average at full crackle
+const MAX_CRACKLES_PER_SECOND: f32 = 12.0;
+/// The loudest crackle's peak, in dBFS
+const MAX_CRACKLE_DB: f32 = -18.0;
+/// How long a crackle takes to decay by 60 dB, in seconds
+const CRACKLE_DECAY_SECONDS: f32 = 0.002;
+
+///
+/// Stereo noise for aged tape: a constant high-passed hiss, plus crackles of dust and
+/// damage at random times, each a short burst of noise that dies away within a few milliseconds.
+///
+/// Each channel hisses independently, while crackles land on both channels at once, like
+/// damage across the width of the tape.
+///
+pub struct TapeNoise {
+    sample_rate: f32,
+    hiss_gain: f32,
+    crackle_probability: f32,
+    hiss_noise: (WhiteNoise, WhiteNoise),
+    hiss_filter: StereoBiquadFilter,
+    crackle_noise: WhiteNoise,
+    crackle_level: f32,
+    crackle_decay: f32,
+}
+
+impl TapeNoise {
+    ///
+    /// # Arguments
+    /// * `sample_rate` - the sample rate in samples per second
+    /// * `seed` - a nonzero seed for the noise, so different instances sound different
+    ///
+    pub fn new(sample_rate: f32, seed: u32) -> TapeNoise {
+        let mut tape_noise = TapeNoise {
+            sample_rate,
+            hiss_gain: 0.0,
+            crackle_probability: 0.0,
+            hiss_noise: (
+                WhiteNoise::new(seed),
+                WhiteNoise::new(seed.wrapping_mul(0x9E37_79B9)),
+            ),
+            hiss_filter: StereoBiquadFilter::new(),
+            crackle_noise: WhiteNoise::new(seed.wrapping_add(0x5EED)),
+            crackle_level: 0.0,
+            crackle_decay: 0.0,
+        };
+        tape_noise.set_sample_rate(sample_rate);
+        tape_noise
+    }
+
+    /// Sets the sample rate, retuning the hiss filter and crackle decay. The amounts must be set
+    /// again afterwards.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.hiss_filter.set_biquads(
+            BiquadFilterType::HighPass,
+            HISS_HIGH_PASS_HZ / sample_rate,
+            0.707,
+            0.0,
+        );
+        self.crackle_decay = 10_f32.powf(-3.0 / (CRACKLE_DECAY_SECONDS * sample_rate));
+        self.reset();
+    }
+
+    /// Silences any crackle in progress and clears the hiss filter.
+    pub fn reset(&mut self) {
+        self.hiss_filter.reset();
+        self.crackle_level = 0.0;
+    }
+
+    ///
+    /// # Arguments
+    /// * `hiss` - the hiss's level from 0 (silent) to 1 (-42 dBFS RMS)
+    ///
+    pub fn set_hiss(&mut self, hiss: f32) {
+        // White noise between -1 and 1 has an RMS of 1/sqrt(3), and the high-pass keeps about
+        // 93% of its power, so scale it up to the target level
+        let rms = (0.93_f32 / 3.0).sqrt();
+        self.hiss_gain = hiss.clamp(0.0, 1.0) * 10_f32.powf(MAX_HISS_DB / 20.0) / rms;
+    }
+
+    ///
+    /// # Arguments
+    /// * `crackle` - how often crackles happen, from 0 (never) to 1 (12 times a second)
+    ///
+    pub fn set_crackle(&mut self, crackle: f32) {
+        self.crackle_probability =
+            crackle.clamp(0.0, 1.0) * MAX_CRACKLES_PER_SECOND / self.sample_rate;
+    }
+
+    pub fn process(&mut self) -> (f32, f32) {
+        let hiss = if self.hiss_gain > 0.0 {
+            let white = (
+                self.hiss_noise.0.next().unwrap_or(0.0),
+                self.hiss_noise.1.next().unwrap_or(0.0),
+            );
+            let filtered = self.hiss_filter.process(white);
+            (filtered.0 * self.hiss_gain, filtered.1 * self.hiss_gain)
+        } else {
+            (0.0, 0.0)
+        };
+
+        // Start a crackle of random loudness at random times
+        let chance = 0.5 * (self.crackle_noise.next().unwrap_or(0.0) + 1.0);
+        if chance < self.crackle_probability {
+            let loudness = 0.5 * (self.crackle_noise.next().unwrap_or(0.0) + 1.0);
+            self.crackle_level = loudness * 10_f32.powf(MAX_CRACKLE_DB / 20.0);
+        }
+        let crackle = if self.crackle_level > 0.0 {
+            let crackle = self.crackle_level * self.crackle_noise.next().unwrap_or(0.0);
+            self.crackle_level *= self.crackle_decay;
+            if self.crackle_level < 1e-6 {
+                self.crackle_level = 0.0;
+            }
+            crackle
+        } else {
+            0.0
+        };
+
+        (hiss.0 + crackle, hiss.1 + crackle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44_100.0;
+
+    #[test]
+    fn tape_noise_is_silent_until_hiss_or_crackle_is_set() {
+        let mut tape_noise = TapeNoise::new(SAMPLE_RATE, 1);
+        for _ in 0..SAMPLE_RATE as usize {
+            assert_eq!(tape_noise.process(), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn full_hiss_reaches_its_level_and_crackles_come_at_their_rate() {
+        let mut tape_noise = TapeNoise::new(SAMPLE_RATE, 1);
+        tape_noise.set_hiss(1.0);
+        let hiss: Vec<(f32, f32)> = (0..SAMPLE_RATE as usize)
+            .map(|_| tape_noise.process())
+            .collect();
+        let power = hiss.iter().map(|frame| frame.0 * frame.0).sum::<f32>() / hiss.len() as f32;
+        assert!((10.0 * power.log10() - MAX_HISS_DB).abs() < 1.0);
+
+        // Count the crackles over ten seconds by where each one starts out of silence
+        let mut tape_noise = TapeNoise::new(SAMPLE_RATE, 1);
+        tape_noise.set_crackle(1.0);
+        let mut crackles = 0;
+        let mut crackling = false;
+        for _ in 0..10 * SAMPLE_RATE as usize {
+            tape_noise.process();
+            if tape_noise.crackle_level > 0.0 && !crackling {
+                crackles += 1;
+            }
+            crackling = tape_noise.crackle_level > 0.0;
+        }
+        let expected = 10.0 * MAX_CRACKLES_PER_SECOND;
+        assert!((crackles as f32 - expected).abs() < 0.4 * expected);
+    }
+}
@@ -12,12 +12,106 @@ pub fn get_saturator_output(drive: f32, input_sample: f32) -> f32 {
     (1. - 0.3 * drive) * wet
 }
 
+///
+/// The saturator's gain for quiet signals, its slope at rest. Dividing the saturator's output by
+/// it keeps quiet signals at unity, so the drive only rounds off peaks rather than boosting the
+/// level.
+///
+/// # Arguments
+/// * `drive` - the saturator's drive
+///
+pub fn get_saturator_gain(drive: f32) -> f32 {
+    let drive = drive.min(0.99);
+    let k = 2.0 * drive / (1.0 - drive);
+    (1.0 + k) * (1. - 0.3 * drive)
+}
+
 /// Processes an input sample through a standard, static hard clipper, such
 /// that the magnitude of the input sample will never surpass the threshold.
 pub fn get_hard_clipper_output(threshold: f32, input_sample: f32) -> f32 {
     input_sample.clamp(-threshold, threshold)
 }
 
+/// How close consecutive inputs can be before the antialiased hard clipper falls back to
+/// clipping their midpoint, avoiding a division by (nearly) zero.
+const ADAA_TOLERANCE: f32 = 1e-5;
+
+/// How a hard clipper trades CPU for aliasing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipperQuality {
+    /// Clamps each sample directly, whose infinite harmonics alias even when oversampled.
+    Naive,
+    /// First-order antiderivative antialiasing (ADAA), which greatly reduces aliasing at the
+    /// cost of half a sample of delay and a slight roll-off near Nyquist.
+    Antiderivative,
+}
+
+/// The antiderivative of the hard clipper, used for antiderivative antialiasing.
+fn hard_clipper_antiderivative(threshold: f32, x: f32) -> f32 {
+    if x.abs() <= threshold {
+        0.5 * x * x
+    } else {
+        threshold * x.abs() - 0.5 * threshold * threshold
+    }
+}
+
+///
+/// A hard clipper with a selectable quality. In antiderivative mode it outputs the average of
+/// the clipping function between consecutive inputs, found from the difference of its
+/// antiderivative, which integrates away most of the aliasing from the clipper's sharp corners.
+///
+/// Source: Parker, Zavalishin, and Le Bihan, "Reducing the Aliasing of Nonlinear Waveshaping
+/// Using Continuous-Time Convolution" (DAFx-16)
+///
+#[derive(Debug, Clone, Copy)]
+pub struct HardClipper {
+    quality: ClipperQuality,
+    previous_input: f32,
+}
+
+impl HardClipper {
+    pub fn new(quality: ClipperQuality) -> HardClipper {
+        HardClipper {
+            quality,
+            previous_input: 0.,
+        }
+    }
+
+    pub fn set_quality(&mut self, quality: ClipperQuality) {
+        self.quality = quality;
+    }
+
+    pub fn reset(&mut self) {
+        self.previous_input = 0.;
+    }
+
+    ///
+    /// # Arguments
+    /// * `threshold` - the magnitude the output never surpasses
+    /// * `input_sample` - the sample to clip
+    ///
+    pub fn process(&mut self, threshold: f32, input_sample: f32) -> f32 {
+        let previous_input = self.previous_input;
+        self.previous_input = input_sample;
+
+        match self.quality {
+            ClipperQuality::Naive => get_hard_clipper_output(threshold, input_sample),
+            ClipperQuality::Antiderivative => {
+                let difference = input_sample - previous_input;
+                if difference.abs() < ADAA_TOLERANCE {
+                    get_hard_clipper_output(threshold, 0.5 * (input_sample + previous_input))
+                } else {
+                    // The antiderivatives are both taken at the current threshold, so a moving
+                    // threshold can't leave a stale term behind
+                    (hard_clipper_antiderivative(threshold, input_sample)
+                        - hard_clipper_antiderivative(threshold, previous_input))
+                        / difference
+                }
+            }
+        }
+    }
+}
+
 /// Processes an input sample through a saturating, static hard clipper.
 /// Drive parameter increases distortion and reduces threshold.
 ///
@@ -58,8 +152,8 @@ pub fn get_fuzzy_rectifier_output(drive: f32, input_sample: f32) -> f32 {
 ///
 /// Desmos visualization of parameterization: https://www.desmos.com/calculator/wduyw6huen
 pub fn get_shockley_diode_rectifier_output(drive: f32, input_sample: f32) -> f32 {
-    let shockley_diode_output =
-        (0.4 * drive + 0.1) * (E.powf((2. + 2. * drive) * input_sample) - 1.);
+    // The curve's slope at rest is 1 without drive, so quiet signals keep their level
+    let shockley_diode_output = 0.5 * (E.powf((2. + 2. * drive) * input_sample) - 1.);
     // Run hard clipper in series to prevent clipping
     get_saturating_hard_clipper_output(drive, shockley_diode_output)
 }
@@ -132,11 +226,20 @@ pub fn get_double_soft_clipper_output(drive: f32, input_sample: f32) -> f32 {
 
 /// Processes an input sample through a sinusoidal wavefolder.
 /// The drive parameter increases the frequency of the sine curve, causing more distortion.
+/// The fold offset shifts the input before folding for asymmetric harmonics, and the fold gain
+/// scales the input before folding, separately from the sine frequency.
 ///
 /// Desmos: https://www.desmos.com/calculator/zwffvndj7j
-pub fn get_wavefolder_output(drive: f32, input_sample: f32) -> f32 {
+pub fn get_wavefolder_output(
+    drive: f32,
+    fold_offset: f32,
+    fold_gain: f32,
+    input_sample: f32,
+) -> f32 {
     let k = 1. + (drive * 3.);
-    let wet = (2. * PI * k * input_sample).sin();
+    let x = fold_gain * input_sample + fold_offset;
+    // Subtract the folder's output at rest so the offset doesn't add DC
+    let wet = (2. * PI * k * x).sin() - (2. * PI * k * fold_offset).sin();
 
     // Apply dry/wet based on drive to control volume
     let wet = (1. - drive) * input_sample + (drive) * wet;
@@ -161,6 +264,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn saturator_gain_is_the_slope_at_rest() {
+        for drive in [0., 0.3, 0.8, 0.999] {
+            let quiet = 1e-5;
+            let slope = get_saturator_output(drive, quiet) / quiet;
+            assert!((slope / get_saturator_gain(drive) - 1.).abs() < 0.01);
+        }
+    }
+
     #[test]
     fn waveshapers_return_correct_dc_offset() {
         let num_drive_tests = 100;
@@ -180,7 +292,110 @@ mod tests {
             ));
             assert!(relative_eq!(get_dropout_output(drive, 0.), 0.));
             assert!(relative_eq!(get_double_soft_clipper_output(drive, 0.), 0.));
-            assert!(relative_eq!(get_wavefolder_output(drive, 0.), 0.));
+            assert!(relative_eq!(get_wavefolder_output(drive, 0., 1., 0.), 0.));
+            assert!(relative_eq!(get_wavefolder_output(drive, 0.3, 2., 0.), 0.));
+        }
+    }
+
+    #[test]
+    fn wavefolder_offset_folds_asymmetrically() {
+        let drive = 0.5;
+        let input = 0.2;
+
+        // Without an offset, the wavefolder is an odd function
+        let positive = get_wavefolder_output(drive, 0., 1., input);
+        let negative = get_wavefolder_output(drive, 0., 1., -input);
+        assert!(relative_eq!(positive, -negative));
+
+        let positive = get_wavefolder_output(drive, 0.1, 1., input);
+        let negative = get_wavefolder_output(drive, 0.1, 1., -input);
+        assert!((positive + negative).abs() > 1e-3);
+    }
+
+    ///
+    /// Clips a sine at an exact FFT bin and returns the power in every other bin, which is
+    /// all aliasing since the sine's harmonics are all above Nyquist.
+    ///
+    fn hard_clipper_aliasing(quality: ClipperQuality) -> f32 {
+        use crate::spectrum::{fft, Twiddles};
+        use std::f32::consts::PI;
+
+        const FFT_SIZE: usize = 8192;
+        const SINE_BIN: usize = 1531;
+        let mut clipper = HardClipper::new(quality);
+        let sine = |n: usize| (2. * PI * (SINE_BIN * n % FFT_SIZE) as f32 / FFT_SIZE as f32).sin();
+
+        // Run one period first, so the measured period starts from a settled state
+        for n in 0..FFT_SIZE {
+            clipper.process(0.5, sine(n));
+        }
+        let mut real: Vec<f32> = (0..FFT_SIZE)
+            .map(|n| clipper.process(0.5, sine(n)))
+            .collect();
+        let mut imag = vec![0.; FFT_SIZE];
+        fft(&mut real, &mut imag, &Twiddles::new(FFT_SIZE));
+
+        (1..FFT_SIZE / 2)
+            .filter(|bin| *bin != SINE_BIN)
+            .map(|bin| real[bin] * real[bin] + imag[bin] * imag[bin])
+            .sum()
+    }
+
+    #[test]
+    fn antiderivative_hard_clipper_reduces_aliasing() {
+        let naive = hard_clipper_aliasing(ClipperQuality::Naive);
+        let antiderivative = hard_clipper_aliasing(ClipperQuality::Antiderivative);
+        assert!(antiderivative < naive * 0.25);
+    }
+
+    ///
+    /// Folds a sine at an exact FFT bin at `N` times the sample rate and returns the power in
+    /// every bin that isn't one of the sine's harmonics, which is all aliasing.
+    ///
+    fn oversampled_wavefolder_aliasing<const N: usize>() -> f32 {
+        use crate::oversampling::Oversampler;
+        use crate::spectrum::{fft, Twiddles};
+
+        const FFT_SIZE: usize = 8192;
+        const SINE_BIN: usize = 1531;
+        let mut oversampler = Oversampler::<N>::new(8, true);
+        let sine =
+            |n: usize| 0.8 * (2. * PI * (SINE_BIN * n % FFT_SIZE) as f32 / FFT_SIZE as f32).sin();
+        let mut fold = |x: f32| oversampler.process(x, |x| get_wavefolder_output(1., 0., 1., x));
+
+        // Run one period first, so the measured period starts from a settled state
+        for n in 0..FFT_SIZE {
+            fold(sine(n));
+        }
+        let mut real: Vec<f32> = (0..FFT_SIZE).map(|n| fold(sine(n))).collect();
+        let mut imag = vec![0.; FFT_SIZE];
+        fft(&mut real, &mut imag, &Twiddles::new(FFT_SIZE));
+
+        (1..FFT_SIZE / 2)
+            .filter(|bin| bin % SINE_BIN != 0)
+            .map(|bin| real[bin] * real[bin] + imag[bin] * imag[bin])
+            .sum()
+    }
+
+    #[test]
+    fn higher_oversampling_reduces_wavefolder_aliasing() {
+        let aliasing_4x = oversampled_wavefolder_aliasing::<4>();
+        let aliasing_16x = oversampled_wavefolder_aliasing::<16>();
+
+        // An 8 kHz fold reaches far past 4x's Nyquist, and 16x cuts its aliasing by over 40 dB
+        assert!(aliasing_16x < aliasing_4x * 1e-4);
+    }
+
+    #[test]
+    fn antiderivative_hard_clipper_passes_quiet_signals() {
+        // Below the threshold, the average of a line is its midpoint, half a sample late
+        let mut clipper = HardClipper::new(ClipperQuality::Antiderivative);
+        let mut previous = 0.;
+        for n in 0..100 {
+            let input = (n as f32 * 0.1).sin() * 0.4;
+            let output = clipper.process(0.5, input);
+            assert!((output - 0.5 * (input + previous)).abs() < 1e-4);
+            previous = input;
         }
     }
 
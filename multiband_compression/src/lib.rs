@@ -0,0 +1,413 @@
+use fx::{
+    bypass::BypassCrossfade,
+    crossover::{StereoCrossover, StereoCrossoverAllpass},
+    dynamics::DynamicRangeProcessor,
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+const NUM_BANDS: usize = 3;
+const DEFAULT_LOW_CROSSOVER_HZ: f32 = 200.0;
+const DEFAULT_HIGH_CROSSOVER_HZ: f32 = 2_000.0;
+
+pub struct MultibandCompression {
+    params: Arc<MultibandCompressionParams>,
+    crossover: StereoCrossover<NUM_BANDS>,
+    /// Shifts the dry signal's phase to match the recombined bands
+    dry_allpass: StereoCrossoverAllpass,
+    processors: [DynamicRangeProcessor; NUM_BANDS],
+    sample_rate: f32,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct MultibandCompressionParams {
+    #[id = "input-gain"]
+    pub input_gain: FloatParam,
+    #[id = "dry-wet"]
+    pub dry_wet: FloatParam,
+
+    #[id = "low-crossover"]
+    pub low_crossover: FloatParam,
+    #[id = "high-crossover"]
+    pub high_crossover: FloatParam,
+
+    #[id = "low-threshold"]
+    pub low_threshold: FloatParam,
+    #[id = "low-ratio"]
+    pub low_ratio: FloatParam,
+    #[id = "low-attack"]
+    pub low_attack: FloatParam,
+    #[id = "low-release"]
+    pub low_release: FloatParam,
+    #[id = "low-makeup-gain"]
+    pub low_makeup_gain: FloatParam,
+
+    #[id = "mid-threshold"]
+    pub mid_threshold: FloatParam,
+    #[id = "mid-ratio"]
+    pub mid_ratio: FloatParam,
+    #[id = "mid-attack"]
+    pub mid_attack: FloatParam,
+    #[id = "mid-release"]
+    pub mid_release: FloatParam,
+    #[id = "mid-makeup-gain"]
+    pub mid_makeup_gain: FloatParam,
+
+    #[id = "high-threshold"]
+    pub high_threshold: FloatParam,
+    #[id = "high-ratio"]
+    pub high_ratio: FloatParam,
+    #[id = "high-attack"]
+    pub high_attack: FloatParam,
+    #[id = "high-release"]
+    pub high_release: FloatParam,
+    #[id = "high-makeup-gain"]
+    pub high_makeup_gain: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for MultibandCompression {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(MultibandCompressionParams::default()),
+            crossover: StereoCrossover::new(
+                &[DEFAULT_LOW_CROSSOVER_HZ, DEFAULT_HIGH_CROSSOVER_HZ],
+                DEFAULT_SAMPLE_RATE as f32,
+            ),
+            dry_allpass: StereoCrossoverAllpass::new(
+                &[DEFAULT_LOW_CROSSOVER_HZ, DEFAULT_HIGH_CROSSOVER_HZ],
+                DEFAULT_SAMPLE_RATE as f32,
+            ),
+            processors: [
+                DynamicRangeProcessor::new(DEFAULT_SAMPLE_RATE),
+                DynamicRangeProcessor::new(DEFAULT_SAMPLE_RATE),
+                DynamicRangeProcessor::new(DEFAULT_SAMPLE_RATE),
+            ],
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+fn gain_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        util::db_to_gain(0.0),
+        FloatRange::Skewed {
+            min: util::db_to_gain(-30.0),
+            max: util::db_to_gain(30.0),
+            factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_unit(" dB")
+    .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+    .with_string_to_value(formatters::s2v_f32_gain_to_db())
+}
+
+fn crossover_param(name: &str, default: f32, min: f32, max: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Skewed {
+            min,
+            max,
+            factor: FloatRange::skew_factor(-1.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_unit(" Hz")
+    .with_value_to_string(formatters::v2s_f32_rounded(0))
+}
+
+fn threshold_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        0.0,
+        FloatRange::Linear {
+            min: -25.0,
+            max: 0.0,
+        },
+    )
+    .with_unit(" dB")
+    .with_smoother(SmoothingStyle::Exponential(50.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(2))
+}
+
+fn ratio_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        1.0,
+        FloatRange::Skewed {
+            min: 1.0,
+            max: 20.0,
+            factor: FloatRange::skew_factor(-1.6),
+        },
+    )
+    .with_unit(":1")
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(2))
+}
+
+fn attack_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        2.0,
+        FloatRange::Skewed {
+            min: 0.01,
+            max: 100.0,
+            factor: FloatRange::skew_factor(-1.0),
+        },
+    )
+    .with_unit(" ms")
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(2))
+}
+
+fn release_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        30.0,
+        FloatRange::Skewed {
+            min: 1.0,
+            max: 1000.0,
+            factor: FloatRange::skew_factor(-2.0),
+        },
+    )
+    .with_unit(" ms")
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(2))
+}
+
+impl Default for MultibandCompressionParams {
+    fn default() -> Self {
+        Self {
+            input_gain: gain_param("Input gain"),
+
+            dry_wet: FloatParam::new("Dry/wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Exponential(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // The crossover ranges don't overlap, so the low crossover is always below the high
+            low_crossover: crossover_param(
+                "Low crossover",
+                DEFAULT_LOW_CROSSOVER_HZ,
+                20.0,
+                1_000.0,
+            ),
+            high_crossover: crossover_param(
+                "High crossover",
+                DEFAULT_HIGH_CROSSOVER_HZ,
+                1_000.0,
+                16_000.0,
+            ),
+
+            low_threshold: threshold_param("Low threshold"),
+            low_ratio: ratio_param("Low ratio"),
+            low_attack: attack_param("Low attack"),
+            low_release: release_param("Low release"),
+            low_makeup_gain: gain_param("Low makeup gain"),
+
+            mid_threshold: threshold_param("Mid threshold"),
+            mid_ratio: ratio_param("Mid ratio"),
+            mid_attack: attack_param("Mid attack"),
+            mid_release: release_param("Mid release"),
+            mid_makeup_gain: gain_param("Mid makeup gain"),
+
+            high_threshold: threshold_param("High threshold"),
+            high_ratio: ratio_param("High ratio"),
+            high_attack: attack_param("High attack"),
+            high_release: release_param("High release"),
+            high_makeup_gain: gain_param("High makeup gain"),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl MultibandCompressionParams {
+    /// Returns the threshold, ratio, attack, release, and makeup gain parameters of each band,
+    /// from low to high.
+    fn bands(&self) -> [[&FloatParam; 5]; NUM_BANDS] {
+        [
+            [
+                &self.low_threshold,
+                &self.low_ratio,
+                &self.low_attack,
+                &self.low_release,
+                &self.low_makeup_gain,
+            ],
+            [
+                &self.mid_threshold,
+                &self.mid_ratio,
+                &self.mid_attack,
+                &self.mid_release,
+                &self.mid_makeup_gain,
+            ],
+            [
+                &self.high_threshold,
+                &self.high_ratio,
+                &self.high_attack,
+                &self.high_release,
+                &self.high_makeup_gain,
+            ],
+        ]
+    }
+}
+
+impl Plugin for MultibandCompression {
+    const NAME: &'static str = "Multiband Compression v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        let sample_rate = _buffer_config.sample_rate;
+        self.sample_rate = sample_rate;
+        let crossover_frequencies = [
+            self.params.low_crossover.value(),
+            self.params.high_crossover.value(),
+        ];
+        self.crossover = StereoCrossover::new(&crossover_frequencies, sample_rate);
+        self.dry_allpass = StereoCrossoverAllpass::new(&crossover_frequencies, sample_rate);
+        for processor in self.processors.iter_mut() {
+            processor.set_sample_rate(sample_rate as usize);
+        }
+        self.bypass.set_sample_rate(sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Update crossover frequencies while smoothing
+            if self.params.low_crossover.smoothed.is_smoothing() {
+                let frequency = self.params.low_crossover.smoothed.next();
+                self.crossover.set_frequency(0, frequency, self.sample_rate);
+                self.dry_allpass
+                    .set_frequency(0, frequency, self.sample_rate);
+            }
+            if self.params.high_crossover.smoothed.is_smoothing() {
+                let frequency = self.params.high_crossover.smoothed.next();
+                self.crossover.set_frequency(1, frequency, self.sample_rate);
+                self.dry_allpass
+                    .set_frequency(1, frequency, self.sample_rate);
+            }
+
+            let input_gain = self.params.input_gain.smoothed.next();
+            let sample_l = *channel_samples.get_mut(0).unwrap();
+            let sample_r = *channel_samples.get_mut(1).unwrap();
+            let in_l = sample_l * input_gain;
+            let in_r = sample_r * input_gain;
+
+            // Compress each band independently, then sum the bands back together
+            let bands = self.crossover.split((in_l, in_r));
+            let mut frame_out = (0.0, 0.0);
+            for ((processor, band_params), band) in self
+                .processors
+                .iter_mut()
+                .zip(self.params.bands())
+                .zip(bands)
+            {
+                let [threshold, ratio, attack, release, makeup_gain] = band_params;
+                processor.set_parameters(
+                    threshold.smoothed.next(),
+                    ratio.smoothed.next(),
+                    attack.smoothed.next() * 0.001, // convert from ms to s
+                    release.smoothed.next() * 0.001, // convert from ms to s
+                    false,
+                );
+
+                let makeup_gain_db = util::gain_to_db_fast(makeup_gain.smoothed.next());
+                let band_out = processor.process_input_frame(band, makeup_gain_db);
+                frame_out.0 += band_out.0;
+                frame_out.1 += band_out.1;
+            }
+
+            // Apply dry/wet, then output, with the dry signal phase shifted like the recombined
+            // bands so the mix doesn't comb filter around the crossovers
+            let (dry_l, dry_r) = self.dry_allpass.process((in_l, in_r));
+            let dry_wet_ratio = self.params.dry_wet.smoothed.next();
+            let out_l = dry_l * (1. - dry_wet_ratio) + frame_out.0 * dry_wet_ratio;
+            let out_r = dry_r * (1. - dry_wet_ratio) + frame_out.1 * dry_wet_ratio;
+            let (out_l, out_r) = self
+                .bypass
+                .tick(bypassed, (sample_l, sample_r), (out_l, out_r));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for MultibandCompression {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("A three band dynamic range compressor");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Compressor,
+        ClapFeature::Mastering,
+    ];
+}
+
+impl Vst3Plugin for MultibandCompression {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2_mbcompre";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Dynamics];
+}
+
+nih_export_vst3!(MultibandCompression);
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use multiband_compression::MultibandCompression;
+
+fn main() {
+    nih_export_standalone::<MultibandCompression>();
+}
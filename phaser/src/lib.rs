@@ -0,0 +1,222 @@
+use fx::{
+    bypass::BypassCrossfade,
+    phaser::{Phaser as AllPassPhaser, MAX_PHASER_FEEDBACK, MAX_PHASER_STAGES, MIN_PHASER_STAGES},
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+pub struct Phaser {
+    params: Arc<PhaserParams>,
+    phaser: AllPassPhaser,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct PhaserParams {
+    #[id = "rate"]
+    pub rate: FloatParam,
+
+    #[id = "depth"]
+    pub depth: FloatParam,
+
+    #[id = "stages"]
+    pub stages: IntParam,
+
+    #[id = "feedback"]
+    pub feedback: FloatParam,
+
+    #[id = "mix"]
+    pub mix: FloatParam,
+
+    #[id = "center-frequency"]
+    pub center_frequency: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for Phaser {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(PhaserParams::default()),
+            phaser: AllPassPhaser::new(DEFAULT_SAMPLE_RATE as f32),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for PhaserParams {
+    fn default() -> Self {
+        Self {
+            rate: FloatParam::new(
+                "Rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // How far the sweep reaches, up to two octaves either side of the center frequency
+            depth: FloatParam::new("Depth", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Each all-pass stage adds to the phase shift, so more stages make more notches
+            stages: IntParam::new(
+                "Stages",
+                MIN_PHASER_STAGES as i32,
+                IntRange::Linear {
+                    min: MIN_PHASER_STAGES as i32,
+                    max: MAX_PHASER_STAGES as i32,
+                },
+            ),
+
+            // Negative feedback moves the peaks to where the notches were
+            feedback: FloatParam::new(
+                "Feedback",
+                0.0,
+                FloatRange::Linear {
+                    min: -MAX_PHASER_FEEDBACK,
+                    max: MAX_PHASER_FEEDBACK,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // The notches are deepest with an even mix of the dry and wet signals
+            mix: FloatParam::new("Mix", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            center_frequency: FloatParam::new(
+                "Center frequency",
+                1_000.0,
+                FloatRange::Skewed {
+                    min: 100.0,
+                    max: 5_000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for Phaser {
+    const NAME: &'static str = "Phaser v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.phaser.set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.phaser.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Set parameters
+            self.phaser.set_rate(self.params.rate.smoothed.next());
+            self.phaser.set_depth(self.params.depth.smoothed.next());
+            self.phaser.set_stages(self.params.stages.value() as usize);
+            self.phaser
+                .set_feedback(self.params.feedback.smoothed.next());
+            self.phaser.set_mix(self.params.mix.smoothed.next());
+            self.phaser
+                .set_center_frequency(self.params.center_frequency.smoothed.next());
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+
+            let (out_l, out_r) = self.phaser.process((in_l, in_r));
+            let (out_l, out_r) = self.bypass.tick(bypassed, (in_l, in_r), (out_l, out_r));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for Phaser {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> =
+        Some("A multi-stage phaser built on swept all-pass filters");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Phaser,
+    ];
+}
+
+impl Vst3Plugin for Phaser {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2___phaser";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Modulation];
+}
+
+nih_export_vst3!(Phaser);
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use phaser::Phaser;
+
+fn main() {
+    nih_export_standalone::<Phaser>();
+}
\ No newline at end of file
@@ -0,0 +1,255 @@
+use fx::{
+    bypass::BypassCrossfade,
+    pluck::{PluckExciter, PluckedString},
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+/// How many notes can ring at once; a new note takes over the string that was plucked longest ago
+const NUM_VOICES: usize = 8;
+
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExciterParam {
+    Noise,
+    Impulse,
+}
+
+/// A matching from the exciter parameter to the plucked string's exciter.
+fn exciter_param_to_exciter(exciter: ExciterParam) -> PluckExciter {
+    match exciter {
+        ExciterParam::Noise => PluckExciter::Noise,
+        ExciterParam::Impulse => PluckExciter::Impulse,
+    }
+}
+
+pub struct Pluck {
+    params: Arc<PluckParams>,
+    strings: Vec<PluckedString>,
+    next_string: usize,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct PluckParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "level"]
+    pub level: FloatParam,
+
+    #[id = "decay"]
+    pub decay: FloatParam,
+
+    #[id = "damping"]
+    pub damping: FloatParam,
+
+    #[id = "exciter"]
+    pub exciter: EnumParam<ExciterParam>,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for Pluck {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(PluckParams::default()),
+            // Each string gets its own noise seed, so repeated notes don't sound identical
+            strings: (0..NUM_VOICES)
+                .map(|voice| PluckedString::new(DEFAULT_SAMPLE_RATE as f32, voice as u32 + 1))
+                .collect(),
+            next_string: 0,
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for PluckParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // The level of the plucked strings, which are added to the input
+            level: FloatParam::new(
+                "Level",
+                util::db_to_gain(-6.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // How long a note takes to die away by 60 dB
+            decay: FloatParam::new(
+                "Decay",
+                2.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" s")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            damping: FloatParam::new("Damping", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            exciter: EnumParam::new("Exciter", ExciterParam::Noise),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for Pluck {
+    const NAME: &'static str = "Pluck v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        for string in self.strings.iter_mut() {
+            string.set_sample_rate(_buffer_config.sample_rate);
+        }
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        for string in self.strings.iter_mut() {
+            string.reset();
+        }
+        self.next_string = 0;
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        let exciter = exciter_param_to_exciter(self.params.exciter.value());
+        let mut next_event = context.next_event();
+
+        for (sample_id, mut channel_samples) in buffer.iter_samples().enumerate() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let level = self.params.level.smoothed.next();
+            let decay = self.params.decay.smoothed.next();
+            let damping = self.params.damping.smoothed.next();
+            for string in self.strings.iter_mut() {
+                string.set_decay(decay);
+                string.set_damping(damping);
+                string.set_exciter(exciter);
+            }
+
+            // Pluck a string for each note on at this sample
+            while let Some(event) = next_event {
+                if event.timing() > sample_id as u32 {
+                    break;
+                }
+                if let NoteEvent::NoteOn { note, velocity, .. } = event {
+                    self.strings[self.next_string].pluck(util::midi_note_to_freq(note), velocity);
+                    self.next_string = (self.next_string + 1) % NUM_VOICES;
+                }
+                next_event = context.next_event();
+            }
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+            let strings: f32 = self.strings.iter_mut().map(|string| string.process()).sum();
+            let out_l = in_l + strings * level;
+            let out_r = in_r + strings * level;
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for Pluck {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> =
+        Some("A Karplus-Strong plucked string, played with MIDI");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Synthesizer,
+    ];
+}
+
+impl Vst3Plugin for Pluck {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2____pluck";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Synth];
+}
+
+nih_export_vst3!(Pluck);
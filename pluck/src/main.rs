@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use pluck::Pluck;
+
+fn main() {
+    nih_export_standalone::<Pluck>();
+}
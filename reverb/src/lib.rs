@@ -1,6 +1,19 @@
-use fx::{freeverb::Freeverb, moorer_verb::MoorerReverb, DEFAULT_SAMPLE_RATE};
+use fx::{
+    bypass::BypassCrossfade,
+    convolution::{ImpulseResponse, StereoConvolver, DEFAULT_PARTITION_SIZE},
+    filters::DEFAULT_TAIL_EQ_FREQUENCY,
+    freeverb::{
+        CombDensity, Freeverb, DEFAULT_MODULATION_RATE, DEFAULT_STEREO_SPREAD, MAX_STEREO_SPREAD,
+    },
+    moorer_verb::{MoorerReverb, MAX_EARLY_REFLECTIONS_DELAY_MS},
+    safety::{OutputSafety, DEFAULT_SAFETY_CEILING_DB},
+    tail_shaper::{TailCapture, TailMode, TailShaper},
+    waveshapers::{get_saturator_gain, get_saturator_output},
+    DEFAULT_SAMPLE_RATE,
+};
 use nih_plug::prelude::*;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[derive(Enum, Debug, PartialEq, Eq)]
 pub enum ReverbType {
@@ -11,12 +24,99 @@ pub enum ReverbType {
     #[id = "moorer"]
     #[name = "Moorer"]
     Moorer,
+
+    #[id = "convolution"]
+    #[name = "Convolution"]
+    Convolution,
+}
+
+/// Work done off the audio thread.
+pub enum ReverbTask {
+    /// Decodes the impulse response from the WAV file at the current impulse response path and
+    /// prepares a convolver for it.
+    LoadImpulseResponse { sample_rate: f32 },
+}
+
+/// The longest gate hold and reverse block, in seconds
+const MAX_HOLD_SECONDS: f32 = 2.0;
+/// How much of the tail the capture hold loops, in seconds
+const CAPTURE_LOOP_SECONDS: f32 = 2.0;
+/// Longer impulse responses are cut short, in seconds
+const MAX_IMPULSE_RESPONSE_SECONDS: f32 = 10.0;
+/// The environment variable holding the path of the WAV file new instances load their impulse
+/// response from
+const IMPULSE_RESPONSE_ENV_VAR: &str = "REVERB_IMPULSE_RESPONSE";
+/// The saturator's drive at full wet drive, enough to color the tail without crushing its peaks
+const MAX_WET_DRIVE: f32 = 0.5;
+
+///
+/// Reads an impulse response from a WAV file, resampled to the sample rate, shortened to the
+/// longest length, and normalized.
+///
+fn load_impulse_response(path: &str, sample_rate: f32) -> Result<ImpulseResponse, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    let mut impulse_response = ImpulseResponse::from_wav(&bytes)
+        .map_err(|error| error.to_string())?
+        .resampled(sample_rate);
+    impulse_response.truncate((MAX_IMPULSE_RESPONSE_SECONDS * sample_rate) as usize);
+    impulse_response.normalize();
+    Ok(impulse_response)
+}
+
+/// Shapes of the reverb's tail.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TailModeParam {
+    #[id = "natural"]
+    #[name = "Natural"]
+    Natural,
+
+    #[id = "gated"]
+    #[name = "Gated"]
+    Gated,
+
+    #[id = "reverse"]
+    #[name = "Reverse"]
+    Reverse,
+}
+
+/// A matching from the tail mode parameter to the implementation's tail mode.
+fn tail_mode_param_to_mode(mode: TailModeParam) -> TailMode {
+    match mode {
+        TailModeParam::Natural => TailMode::Natural,
+        TailModeParam::Gated => TailMode::Gated,
+        TailModeParam::Reverse => TailMode::Reverse,
+    }
+}
+
+/// Echo densities for Freeverb.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CombDensityParam {
+    Sparse,
+    Dense,
+}
+
+/// A matching from the density parameter to the implementation's comb density.
+fn density_param_to_density(density: CombDensityParam) -> CombDensity {
+    match density {
+        CombDensityParam::Sparse => CombDensity::Sparse,
+        CombDensityParam::Dense => CombDensity::Dense,
+    }
 }
 
 pub struct Reverb {
     params: Arc<ReverbParams>,
     freeverb: Freeverb,
     moorer_reverb: MoorerReverb,
+    convolver: StereoConvolver,
+    /// A convolver prepared on the background thread, swapped with `convolver` when ready, so
+    /// the audio thread never allocates or frees one
+    pending_convolver: Arc<Mutex<Option<StereoConvolver>>>,
+    convolver_ready: Arc<AtomicBool>,
+    tail_shaper: TailShaper,
+    tail_capture: TailCapture,
+    latency: u32,
+    output_safety: OutputSafety,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -36,14 +136,79 @@ struct ReverbParams {
     #[id = "dampening"]
     pub damping: FloatParam,
 
+    #[id = "resonance"]
+    pub resonance: FloatParam,
+
+    #[id = "tail-eq-frequency"]
+    pub tail_eq_frequency: FloatParam,
+
+    #[id = "tail-eq-gain"]
+    pub tail_eq_gain: FloatParam,
+
+    #[id = "diffusion"]
+    pub diffusion: FloatParam,
+
     #[id = "frozen"]
     pub frozen: BoolParam,
 
     #[id = "reverb-type"]
     pub reverb_type: EnumParam<ReverbType>,
 
+    /// The WAV file the convolution reverb loads its impulse response from, saved with the
+    /// plugin's state
+    #[persist = "impulse-response-path"]
+    pub impulse_response_path: Arc<RwLock<String>>,
+
     #[id = "width"]
     pub width: FloatParam,
+
+    #[id = "stereo-input"]
+    pub stereo_input: BoolParam,
+
+    #[id = "early-reflections-level"]
+    pub early_reflections_level: FloatParam,
+
+    #[id = "early-reflections-delay"]
+    pub early_reflections_delay: FloatParam,
+
+    #[id = "early-reflections-width"]
+    pub early_reflections_width: FloatParam,
+
+    #[id = "density"]
+    pub density: EnumParam<CombDensityParam>,
+
+    #[id = "drive"]
+    pub drive: FloatParam,
+
+    #[id = "wet-drive"]
+    pub wet_drive: FloatParam,
+
+    #[id = "spread"]
+    pub spread: IntParam,
+
+    #[id = "modulation-depth"]
+    pub modulation_depth: FloatParam,
+
+    #[id = "modulation-rate"]
+    pub modulation_rate: FloatParam,
+
+    #[id = "tail-mode"]
+    pub tail_mode: EnumParam<TailModeParam>,
+
+    #[id = "hold"]
+    pub hold: FloatParam,
+
+    #[id = "capture-hold"]
+    pub capture_hold: BoolParam,
+
+    #[id = "output-safety"]
+    pub output_safety: BoolParam,
+
+    #[id = "output-ceiling"]
+    pub output_ceiling: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
     // TODO: add a low pass and/or high pass parameter
 }
 
@@ -54,6 +219,14 @@ impl Default for Reverb {
             params: Arc::new(ReverbParams::default()),
             freeverb: Freeverb::new(DEFAULT_SAMPLE_RATE),
             moorer_reverb: MoorerReverb::new(DEFAULT_SAMPLE_RATE),
+            convolver: StereoConvolver::new(&ImpulseResponse::default(), DEFAULT_PARTITION_SIZE),
+            pending_convolver: Arc::new(Mutex::new(None)),
+            convolver_ready: Arc::new(AtomicBool::new(false)),
+            tail_shaper: TailShaper::new(DEFAULT_SAMPLE_RATE as f32, MAX_HOLD_SECONDS),
+            tail_capture: TailCapture::new(DEFAULT_SAMPLE_RATE as f32, CAPTURE_LOOP_SECONDS),
+            latency: 0,
+            output_safety: OutputSafety::new(),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -105,13 +278,168 @@ impl Default for ReverbParams {
                 .with_smoother(SmoothingStyle::Linear(50.0))
                 .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
+            resonance: FloatParam::new("Resonance", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // A peaking EQ inside the comb feedback, applied on every pass so the tail's tone
+            // changes as it decays. 0 dB is neutral
+            tail_eq_frequency: FloatParam::new(
+                "Tail EQ frequency",
+                DEFAULT_TAIL_EQ_FREQUENCY,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 20_000.0,
+                    factor: FloatRange::skew_factor(-2.2),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            tail_eq_gain: FloatParam::new(
+                "Tail EQ gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -6.0,
+                    max: 6.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // An allpass chain before the comb filters that smooths the attack on transients.
+            // Off by default, keeping the original character
+            diffusion: FloatParam::new("Diffusion", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
             frozen: BoolParam::new("Frozen", false),
 
             reverb_type: EnumParam::new("Type", ReverbType::Freeverb),
 
+            // New instances pick up the impulse response from the environment, if it's set
+            impulse_response_path: Arc::new(RwLock::new(
+                std::env::var(IMPULSE_RESPONSE_ENV_VAR).unwrap_or_default(),
+            )),
+
             width: FloatParam::new("Width", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
                 .with_smoother(SmoothingStyle::Linear(50.0))
                 .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Off sums the input to mono before the comb filters, as Freeverb originally does
+            stereo_input: BoolParam::new("Stereo input", false),
+
+            early_reflections_level: FloatParam::new(
+                "Early reflections",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            early_reflections_delay: FloatParam::new(
+                "Early reflections delay",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_EARLY_REFLECTIONS_DELAY_MS,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            // Spreads the early reflections across the stereo field, independently of the tail
+            early_reflections_width: FloatParam::new(
+                "Early reflections width",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            density: EnumParam::new("Density", CombDensityParam::Dense),
+
+            drive: FloatParam::new("Drive", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Saturates the reverb's output rather than its feedback, coloring the tail without
+            // changing how long it decays. 0 is transparent
+            wet_drive: FloatParam::new("Wet drive", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // The offset between Freeverb's left and right filter tunings, in samples at 44.1 kHz
+            spread: IntParam::new(
+                "Spread",
+                DEFAULT_STEREO_SPREAD as i32,
+                IntRange::Linear {
+                    min: 0,
+                    max: MAX_STEREO_SPREAD as i32,
+                },
+            )
+            .with_unit(" samples"),
+
+            // Slowly moves Freeverb's comb delays to smooth the tail. 0 keeps the tail static
+            modulation_depth: FloatParam::new(
+                "Modulation depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            modulation_rate: FloatParam::new(
+                "Modulation rate",
+                DEFAULT_MODULATION_RATE,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 5.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Gated cuts the tail off after the hold time, while reverse plays the tail backwards
+            // in blocks of the hold time, delaying the dry signal so the swells lead into it
+            tail_mode: EnumParam::new("Tail mode", TailModeParam::Natural),
+
+            hold: FloatParam::new(
+                "Hold",
+                300.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: MAX_HOLD_SECONDS * 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // Loops the tail as it was when pressed, unlike frozen, which keeps feeding the reverb
+            capture_hold: BoolParam::new("Capture hold", false),
+
+            // Soft clips the final output under the ceiling, so runaway levels never reach the host
+            output_safety: BoolParam::new("Output safety", false),
+
+            output_ceiling: FloatParam::new(
+                "Output ceiling",
+                DEFAULT_SAFETY_CEILING_DB,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
         }
     }
 }
@@ -120,26 +448,94 @@ impl Reverb {
     fn update_reverbs(&mut self) {
         let room_size_smoothed = &self.params.room_size.smoothed;
         let damping_smoothed = &self.params.damping.smoothed;
+        let resonance_smoothed = &self.params.resonance.smoothed;
+        let tail_eq_frequency_smoothed = &self.params.tail_eq_frequency.smoothed;
+        let tail_eq_gain_smoothed = &self.params.tail_eq_gain.smoothed;
+        let diffusion_smoothed = &self.params.diffusion.smoothed;
         let width_smoothed = &self.params.width.smoothed;
-
-        // Update reverbs while parameters smooth
+        let early_reflections_level_smoothed = &self.params.early_reflections_level.smoothed;
+        let early_reflections_delay_smoothed = &self.params.early_reflections_delay.smoothed;
+        let early_reflections_width_smoothed = &self.params.early_reflections_width.smoothed;
+        let drive_smoothed = &self.params.drive.smoothed;
+        let modulation_depth_smoothed = &self.params.modulation_depth.smoothed;
+        let modulation_rate_smoothed = &self.params.modulation_rate.smoothed;
+
+        // Update reverbs while parameters smooth. Moorer's room size and damping are calibrated
+        // against Freeverb's, so switching types keeps about the same decay and tone.
         if room_size_smoothed.is_smoothing() {
-            self.freeverb.set_room_size(room_size_smoothed.next());
-            self.moorer_reverb.set_room_size(room_size_smoothed.next());
+            let room_size = room_size_smoothed.next();
+            self.freeverb.set_room_size(room_size);
+            self.moorer_reverb
+                .set_room_size_matching_freeverb(room_size);
         }
         if damping_smoothed.is_smoothing() {
-            self.freeverb.set_damping(damping_smoothed.next());
-            self.moorer_reverb.set_damping(damping_smoothed.next());
+            let damping = damping_smoothed.next();
+            self.freeverb.set_damping(damping);
+            self.moorer_reverb.set_damping_matching_freeverb(damping);
+        }
+        if resonance_smoothed.is_smoothing() {
+            self.freeverb.set_resonance(resonance_smoothed.next());
+            self.moorer_reverb.set_resonance(resonance_smoothed.next());
+        }
+        if tail_eq_frequency_smoothed.is_smoothing() || tail_eq_gain_smoothed.is_smoothing() {
+            let frequency = tail_eq_frequency_smoothed.next();
+            let gain = tail_eq_gain_smoothed.next();
+            self.freeverb.set_tail_eq(frequency, gain);
+            self.moorer_reverb.set_tail_eq(frequency, gain);
+        }
+        if diffusion_smoothed.is_smoothing() {
+            let diffusion = diffusion_smoothed.next();
+            self.freeverb.set_diffusion(diffusion);
+            self.moorer_reverb.set_diffusion(diffusion);
         }
         if width_smoothed.is_smoothing() {
             self.freeverb.set_width(width_smoothed.next());
             self.moorer_reverb.set_width(width_smoothed.next());
         }
 
+        let stereo_input = self.params.stereo_input.value();
+        self.freeverb.set_stereo_input(stereo_input);
+        self.moorer_reverb.set_stereo_input(stereo_input);
+
+        // Early reflections are only modeled by Moorer's reverb
+        if early_reflections_level_smoothed.is_smoothing() {
+            self.moorer_reverb
+                .set_early_reflections_level(early_reflections_level_smoothed.next());
+        }
+        if early_reflections_delay_smoothed.is_smoothing() {
+            self.moorer_reverb
+                .set_early_reflections_delay(early_reflections_delay_smoothed.next());
+        }
+        if early_reflections_width_smoothed.is_smoothing() {
+            self.moorer_reverb
+                .set_early_reflections_width(early_reflections_width_smoothed.next());
+        }
+
+        // Comb density, stereo spread, feedback saturation, and modulation are only modeled by
+        // Freeverb
+        self.freeverb
+            .set_density(density_param_to_density(self.params.density.value()));
+        self.freeverb
+            .set_stereo_spread(self.params.spread.value() as usize);
+        if drive_smoothed.is_smoothing() {
+            self.freeverb.set_drive(drive_smoothed.next());
+        }
+        if modulation_depth_smoothed.is_smoothing() || modulation_rate_smoothed.is_smoothing() {
+            self.freeverb.set_modulation(
+                modulation_depth_smoothed.next(),
+                modulation_rate_smoothed.next(),
+            );
+        }
+
+        self.tail_shaper
+            .set_mode(tail_mode_param_to_mode(self.params.tail_mode.value()));
+        self.tail_shaper.set_hold(self.params.hold.value() / 1000.0);
+
         // Check if we should freeze the reverb
         let frozen = self.params.frozen.value();
         self.freeverb.set_frozen(frozen);
         self.moorer_reverb.set_frozen(frozen);
+        self.tail_capture.set_held(self.params.capture_hold.value());
     }
 }
 
@@ -169,12 +565,37 @@ impl Plugin for Reverb {
     // More advanced plugins can use this to run expensive background tasks. See the field's
     // documentation for more information. `()` means that the plugin does not have any background
     // tasks.
-    type BackgroundTask = ();
+    type BackgroundTask = ReverbTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&self) -> TaskExecutor<Self> {
+        let impulse_response_path = self.params.impulse_response_path.clone();
+        let pending_convolver = self.pending_convolver.clone();
+        let convolver_ready = self.convolver_ready.clone();
+        Box::new(move |task| match task {
+            ReverbTask::LoadImpulseResponse { sample_rate } => {
+                let path = impulse_response_path.read().unwrap().clone();
+                if path.is_empty() {
+                    return;
+                }
+                match load_impulse_response(&path, sample_rate) {
+                    Ok(impulse_response) => {
+                        let convolver =
+                            StereoConvolver::new(&impulse_response, DEFAULT_PARTITION_SIZE);
+                        *pending_convolver.lock().unwrap() = Some(convolver);
+                        convolver_ready.store(true, Ordering::SeqCst);
+                    }
+                    Err(error) => {
+                        nih_error!("Couldn't load the impulse response {}: {}", path, error)
+                    }
+                }
+            }
+        })
+    }
+
     fn accepts_bus_config(&self, config: &BusConfig) -> bool {
         // This works with any symmetrical IO layout
         config.num_input_channels == config.num_output_channels && config.num_input_channels > 0
@@ -189,16 +610,69 @@ impl Plugin for Reverb {
         // Resize buffers and perform other potentially expensive initialization operations here.
         // The `reset()` function is always called right after this function. You can remove this
         // function if you do not need it.
+        self.freeverb
+            .set_stereo_spread(self.params.spread.value() as usize);
         self.freeverb
             .generate_filters(_buffer_config.sample_rate as usize);
+        self.freeverb.set_drive(self.params.drive.value());
+        self.freeverb.set_modulation(
+            self.params.modulation_depth.value(),
+            self.params.modulation_rate.value(),
+        );
+        self.freeverb.set_resonance(self.params.resonance.value());
         self.moorer_reverb
             .generate_filters(_buffer_config.sample_rate as usize);
+        self.moorer_reverb
+            .set_resonance(self.params.resonance.value());
+        let (room_size, damping) = (self.params.room_size.value(), self.params.damping.value());
+        self.freeverb.set_room_size(room_size);
+        self.freeverb.set_damping(damping);
+        self.moorer_reverb
+            .set_room_size_matching_freeverb(room_size);
+        self.moorer_reverb.set_damping_matching_freeverb(damping);
+        let (tail_eq_frequency, tail_eq_gain) = (
+            self.params.tail_eq_frequency.value(),
+            self.params.tail_eq_gain.value(),
+        );
+        self.freeverb.set_tail_eq(tail_eq_frequency, tail_eq_gain);
+        self.moorer_reverb
+            .set_tail_eq(tail_eq_frequency, tail_eq_gain);
+        self.freeverb.set_diffusion(self.params.diffusion.value());
+        self.moorer_reverb
+            .set_diffusion(self.params.diffusion.value());
+        self.moorer_reverb
+            .set_early_reflections_level(self.params.early_reflections_level.value());
+        self.moorer_reverb
+            .set_early_reflections_delay(self.params.early_reflections_delay.value());
+        self.moorer_reverb
+            .set_early_reflections_width(self.params.early_reflections_width.value());
+        self.tail_shaper = TailShaper::new(_buffer_config.sample_rate, MAX_HOLD_SECONDS);
+        self.tail_shaper
+            .set_mode(tail_mode_param_to_mode(self.params.tail_mode.value()));
+        self.tail_shaper.set_hold(self.params.hold.value() / 1000.0);
+        self.tail_shaper.reset();
+        self.tail_capture = TailCapture::new(_buffer_config.sample_rate, CAPTURE_LOOP_SECONDS);
+        self.latency = self.tail_shaper.latency() as u32;
+        _context.set_latency_samples(self.latency);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+
+        // Impulse responses are prepared for the sample rate, so reload it
+        _context.execute(ReverbTask::LoadImpulseResponse {
+            sample_rate: _buffer_config.sample_rate,
+        });
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.freeverb.reset();
+        self.moorer_reverb.reset();
+        self.tail_shaper.reset();
+        self.tail_capture.reset();
+        self.convolver.reset();
+        self.output_safety.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
@@ -207,7 +681,35 @@ impl Plugin for Reverb {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Swap in a newly loaded impulse response, leaving the old convolver to be freed off the
+        // audio thread
+        if self.convolver_ready.load(Ordering::SeqCst) {
+            if let Ok(mut pending_convolver) = self.pending_convolver.try_lock() {
+                if let Some(convolver) = pending_convolver.as_mut() {
+                    std::mem::swap(&mut self.convolver, convolver);
+                }
+                self.convolver_ready.store(false, Ordering::SeqCst);
+            }
+        }
+
+        // Once fully bypassed, skip processing entirely and pass the input through, unless the
+        // reverse tail's latency still needs to be applied to the dry signal
+        let bypassed = self.params.bypass.value();
+        let fully_bypassed = self.bypass.is_bypassed(bypassed);
+        if fully_bypassed && self.latency == 0 {
+            return ProcessStatus::Normal;
+        }
+
         for mut channel_samples in buffer.iter_samples() {
+            if fully_bypassed {
+                let in_l = *channel_samples.get_mut(0).unwrap();
+                let in_r = *channel_samples.get_mut(1).unwrap();
+                let ((dry_l, dry_r), _) = self.tail_shaper.process((in_l, in_r), (0., 0.));
+                *channel_samples.get_mut(0).unwrap() = dry_l;
+                *channel_samples.get_mut(1).unwrap() = dry_r;
+                continue;
+            }
+
             // Update reverbs based on parameters
             self.update_reverbs();
 
@@ -223,15 +725,49 @@ impl Plugin for Reverb {
             let frame_out = match self.params.reverb_type.value() {
                 ReverbType::Freeverb => self.freeverb.tick(input),
                 ReverbType::Moorer => self.moorer_reverb.tick(input),
+                ReverbType::Convolution => self.convolver.process(input),
             };
 
+            // Gate or reverse the tail, delaying the dry signal to match
+            let ((in_l, in_r), frame_out) = self.tail_shaper.process((in_l, in_r), frame_out);
+
+            // Loop the captured tail while held
+            let frame_out = self.tail_capture.process(frame_out);
+
+            // Saturate the wet signal for a warm, tape-like tail, dividing out the saturator's
+            // gain so quiet tails keep their level and only peaks get rounded
+            let wet_drive = self.params.wet_drive.smoothed.next() * MAX_WET_DRIVE;
+            let wet_drive_gain = get_saturator_gain(wet_drive);
+            let frame_out = (
+                get_saturator_output(wet_drive, frame_out.0) / wet_drive_gain,
+                get_saturator_output(wet_drive, frame_out.1) / wet_drive_gain,
+            );
+
             // Apply dry/wet, then output
             let dry_wet_ratio = self.params.dry_wet_ratio.smoothed.next();
             let out_l = in_l * (1. - dry_wet_ratio) + frame_out.0 * dry_wet_ratio;
             let out_r = in_r * (1. - dry_wet_ratio) + frame_out.1 * dry_wet_ratio;
 
-            *channel_samples.get_mut(0).unwrap() = out_l * output_gain;
-            *channel_samples.get_mut(1).unwrap() = out_r * output_gain;
+            // A frozen tail can keep building past full scale
+            let (out_l, out_r) = (out_l * output_gain, out_r * output_gain);
+            let (out_l, out_r) = self.output_safety.process_switched(
+                self.params.output_safety.value(),
+                self.params.output_ceiling.smoothed.next(),
+                (out_l, out_r),
+            );
+
+            let (out_l, out_r) = self.bypass.tick(bypassed, (in_l, in_r), (out_l, out_r));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        // Reversing adds latency, so keep the host up to date when the tail mode changes. The
+        // hold time only changes it on reset.
+        let latency = self.tail_shaper.latency() as u32;
+        if latency != self.latency {
+            self.latency = latency;
+            _context.set_latency_samples(latency);
         }
 
         ProcessStatus::Normal
@@ -0,0 +1,201 @@
+use fx::{bypass::BypassCrossfade, lfo::Lfo, DEFAULT_SAMPLE_RATE};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+pub struct RingModulator {
+    params: Arc<RingModulatorParams>,
+    carrier: Lfo,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct RingModulatorParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "dry-wet"]
+    pub dry_wet: FloatParam,
+
+    #[id = "frequency"]
+    pub frequency: FloatParam,
+
+    #[id = "stereo-phase"]
+    pub stereo_phase: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for RingModulator {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(RingModulatorParams::default()),
+            carrier: Lfo::new(DEFAULT_SAMPLE_RATE as f32),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for RingModulatorParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            dry_wet: FloatParam::new("Dry/wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // The carrier runs at audio rate, so the input's partials are shifted up and down by it
+            frequency: FloatParam::new(
+                "Frequency",
+                440.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 5_000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            stereo_phase: FloatParam::new(
+                "Stereo phase",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 360.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for RingModulator {
+    const NAME: &'static str = "Ring Modulator v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.carrier.set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.carrier.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let dry_wet = self.params.dry_wet.smoothed.next();
+            let frequency = self.params.frequency.smoothed.next();
+            let phase_offset = self.params.stereo_phase.smoothed.next() / 360.0;
+
+            // The carrier is a sine, offset in phase on the right channel
+            self.carrier.set_frequency(frequency);
+            let carrier_l = self.carrier.value_at(0.0);
+            let carrier_r = self.carrier.value_at(phase_offset);
+            self.carrier.advance();
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+
+            let out_l = in_l * (1.0 - dry_wet) + in_l * carrier_l * dry_wet;
+            let out_r = in_r * (1.0 - dry_wet) + in_r * carrier_r * dry_wet;
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for RingModulator {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("A ring modulator");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Custom("ring-modulator"),
+    ];
+}
+
+impl Vst3Plugin for RingModulator {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2__ringmod";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Modulation];
+}
+
+nih_export_vst3!(RingModulator);
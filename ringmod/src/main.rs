@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use ringmod::RingModulator;
+
+fn main() {
+    nih_export_standalone::<RingModulator>();
+}
@@ -0,0 +1,246 @@
+use fx::{
+    bypass::BypassCrossfade,
+    lfo::{Lfo, LfoWaveform},
+    DEFAULT_SAMPLE_RATE,
+};
+use nih_plug::prelude::*;
+use std::sync::Arc;
+
+/// All possible LFO waveforms for this tremolo plugin.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LfoWaveformParam {
+    Sine,
+    Triangle,
+    Square,
+    #[name = "Saw up"]
+    SawUp,
+    #[name = "Saw down"]
+    SawDown,
+}
+
+/// A matching from the waveform parameter to the implementation's waveform.
+fn waveform_param_to_lfo(waveform: LfoWaveformParam) -> LfoWaveform {
+    match waveform {
+        LfoWaveformParam::Sine => LfoWaveform::Sine,
+        LfoWaveformParam::Triangle => LfoWaveform::Triangle,
+        LfoWaveformParam::Square => LfoWaveform::Square,
+        LfoWaveformParam::SawUp => LfoWaveform::SawUp,
+        LfoWaveformParam::SawDown => LfoWaveform::SawDown,
+    }
+}
+
+pub struct Tremolo {
+    params: Arc<TremoloParams>,
+    lfo: Lfo,
+    bypass: BypassCrossfade,
+}
+
+#[derive(Params)]
+struct TremoloParams {
+    #[id = "gain"]
+    pub gain: FloatParam,
+
+    #[id = "dry-wet"]
+    pub dry_wet: FloatParam,
+
+    #[id = "rate"]
+    pub rate: FloatParam,
+
+    #[id = "depth"]
+    pub depth: FloatParam,
+
+    #[id = "waveform"]
+    pub waveform: EnumParam<LfoWaveformParam>,
+
+    #[id = "stereo-phase"]
+    pub stereo_phase: FloatParam,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+}
+
+impl Default for Tremolo {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(TremoloParams::default()),
+            lfo: Lfo::new(DEFAULT_SAMPLE_RATE as f32),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
+        }
+    }
+}
+
+impl Default for TremoloParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            dry_wet: FloatParam::new("Dry/wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            rate: FloatParam::new(
+                "Rate",
+                4.0,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            depth: FloatParam::new("Depth", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            waveform: EnumParam::new("Waveform", LfoWaveformParam::Sine),
+
+            stereo_phase: FloatParam::new(
+                "Stereo phase",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 360.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+        }
+    }
+}
+
+impl Plugin for Tremolo {
+    const NAME: &'static str = "Tremolo v0.0.1";
+    const VENDOR: &'static str = "Renzo Ledesma";
+    const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
+    const EMAIL: &'static str = "renzol2@illinois.edu";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[],
+        aux_output_ports: &[],
+
+        names: PortNames::const_default(),
+    }];
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = ();
+    type BackgroundTask = ();
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        _buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.lfo.set_sample_rate(_buffer_config.sample_rate);
+        self.bypass.set_sample_rate(_buffer_config.sample_rate);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.lfo.reset();
+        self.bypass.reset(self.params.bypass.value());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        _context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
+        for mut channel_samples in buffer.iter_samples() {
+            // Get parameters
+            let gain = self.params.gain.smoothed.next();
+            let dry_wet = self.params.dry_wet.smoothed.next();
+            let rate = self.params.rate.smoothed.next();
+            let depth = self.params.depth.smoothed.next();
+            let waveform = self.params.waveform.value();
+            // At 180 degrees, the channels are fully out of phase and the tremolo becomes an auto-panner
+            let phase_offset = self.params.stereo_phase.smoothed.next() / 360.0;
+
+            self.lfo.set_frequency(rate);
+            self.lfo.set_waveform(waveform_param_to_lfo(waveform));
+
+            // Map bipolar LFO values to channel gains between 1 - depth and 1
+            let lfo_l = self.lfo.value_at(0.0);
+            let lfo_r = self.lfo.value_at(phase_offset);
+            self.lfo.advance();
+            let gain_l = 1.0 - depth * (0.5 - 0.5 * lfo_l);
+            let gain_r = 1.0 - depth * (0.5 - 0.5 * lfo_r);
+
+            // Process input
+            let in_l = *channel_samples.get_mut(0).unwrap();
+            let in_r = *channel_samples.get_mut(1).unwrap();
+
+            let out_l = in_l * (1.0 - dry_wet) + in_l * gain_l * dry_wet;
+            let out_r = in_r * (1.0 - dry_wet) + in_r * gain_r * dry_wet;
+
+            let (out_l, out_r) =
+                self.bypass
+                    .tick(bypassed, (in_l, in_r), (out_l * gain, out_r * gain));
+
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for Tremolo {
+    const CLAP_ID: &'static str = "https://renzomledesma.me";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("A tremolo and auto-pan effect");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Tremolo,
+    ];
+}
+
+impl Vst3Plugin for Tremolo {
+    const VST3_CLASS_ID: [u8; 16] = *b"renzol2_tremolo_";
+
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Modulation];
+}
+
+nih_export_vst3!(Tremolo);
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use tremolo::Tremolo;
+
+fn main() {
+    nih_export_standalone::<Tremolo>();
+}
\ No newline at end of file
@@ -1,17 +1,50 @@
 use fx::{
-    delay_line::StereoDelay, DEFAULT_SAMPLE_RATE, FLUTTER_MAX_FREQUENCY_RATIO,
-    FLUTTER_MAX_LFO_FREQUENCY, MAX_DELAY_TIME_SECONDS, WOW_MAX_FREQUENCY_RATIO,
-    WOW_MAX_LFO_FREQUENCY,
+    bypass::BypassCrossfade,
+    delay_line::{Interpolation, StereoDelay},
+    lfo::RandomLfo,
+    tape_noise::TapeNoise,
+    DEFAULT_SAMPLE_RATE, FLUTTER_MAX_FREQUENCY_RATIO, FLUTTER_MAX_LFO_FREQUENCY,
+    MAX_DELAY_TIME_SECONDS, WOW_MAX_FREQUENCY_RATIO, WOW_MAX_LFO_FREQUENCY,
 };
 use nih_plug::prelude::*;
 use std::sync::Arc;
 
 const PARAMETER_MINIMUM: f32 = 0.01;
+const WOW_RANDOM_SEED: u32 = 0x5EED_0001;
+const FLUTTER_RANDOM_SEED: u32 = 0x5EED_0002;
+const TAPE_NOISE_SEED: u32 = 0x5EED_0003;
+/// How far the age macro raises wow and flutter at full age, on top of their own settings
+const AGE_MODULATION_OFFSET: f32 = 0.5;
+
+/// All interpolation qualities for reading the delay lines.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InterpolationParam {
+    #[name = "None (nearest)"]
+    None,
+    Linear,
+    Cubic,
+    #[name = "Sinc (8-tap)"]
+    Sinc,
+}
+
+/// A matching from the interpolation parameter to the implementation's interpolation.
+fn interpolation_param_to_interpolation(interpolation: InterpolationParam) -> Interpolation {
+    match interpolation {
+        InterpolationParam::None => Interpolation::None,
+        InterpolationParam::Linear => Interpolation::Linear,
+        InterpolationParam::Cubic => Interpolation::Cubic,
+        InterpolationParam::Sinc => Interpolation::Sinc,
+    }
+}
 
 pub struct Vibrato {
     params: Arc<VibratoParams>,
     wow_vibrato: StereoDelay,
     flutter_vibrato: StereoDelay,
+    wow_random_lfo: RandomLfo,
+    flutter_random_lfo: RandomLfo,
+    tape_noise: TapeNoise,
+    bypass: BypassCrossfade,
 }
 
 #[derive(Params)]
@@ -25,8 +58,22 @@ struct VibratoParams {
     #[id = "flutter"]
     pub flutter: FloatParam,
 
+    // Keeps the id of the width parameter it replaced, so existing sessions and automation
+    // still find it
     #[id = "width"]
-    pub width: FloatParam,
+    pub stereo_phase: FloatParam,
+
+    #[id = "age"]
+    pub age: FloatParam,
+
+    #[id = "tape-mode"]
+    pub tape_mode: BoolParam,
+
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<InterpolationParam>,
+
+    #[id = "bypass"]
+    pub bypass: BoolParam,
 }
 
 impl Default for Vibrato {
@@ -35,6 +82,10 @@ impl Default for Vibrato {
             params: Arc::new(VibratoParams::default()),
             wow_vibrato: StereoDelay::new(MAX_DELAY_TIME_SECONDS, DEFAULT_SAMPLE_RATE),
             flutter_vibrato: StereoDelay::new(MAX_DELAY_TIME_SECONDS, DEFAULT_SAMPLE_RATE),
+            wow_random_lfo: RandomLfo::new(DEFAULT_SAMPLE_RATE as f32, WOW_RANDOM_SEED),
+            flutter_random_lfo: RandomLfo::new(DEFAULT_SAMPLE_RATE as f32, FLUTTER_RANDOM_SEED),
+            tape_noise: TapeNoise::new(DEFAULT_SAMPLE_RATE as f32, TAPE_NOISE_SEED),
+            bypass: BypassCrossfade::new(DEFAULT_SAMPLE_RATE as f32),
         }
     }
 }
@@ -80,16 +131,33 @@ impl Default for VibratoParams {
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
-            width: FloatParam::new(
-                "Width",
+            // The right LFO's phase offset from the left: 0 degrees is mono, 90 degrees gives the
+            // smoothest rotary motion, and 180 degrees is fully out of phase
+            stereo_phase: FloatParam::new(
+                "Stereo phase",
                 0.0,
                 FloatRange::Linear {
-                    min: PARAMETER_MINIMUM,
-                    max: 1.0,
+                    min: 0.0,
+                    max: 360.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Logarithmic(50.0))
-            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            // One knob from pristine to worn out tape: raises wow and flutter on top of their own
+            // settings, and adds hiss and then crackle
+            age: FloatParam::new("Age", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Drives wow and flutter with smooth random drift instead of sines, like real tape
+            tape_mode: BoolParam::new("Tape mode", false),
+
+            // Nearest gives modulation a gritty, stepped character, cubic is the smoothest
+            interpolation: EnumParam::new("Interpolation", InterpolationParam::Cubic),
+
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
         }
     }
 }
@@ -138,12 +206,23 @@ impl Plugin for Vibrato {
             .resize_buffers(MAX_DELAY_TIME_SECONDS, fs as usize);
         self.flutter_vibrato
             .resize_buffers(MAX_DELAY_TIME_SECONDS, fs as usize);
+        self.wow_random_lfo.set_sample_rate(fs);
+        self.wow_random_lfo.set_frequency(WOW_MAX_LFO_FREQUENCY);
+        self.flutter_random_lfo.set_sample_rate(fs);
+        self.flutter_random_lfo
+            .set_frequency(FLUTTER_MAX_LFO_FREQUENCY);
+        self.tape_noise.set_sample_rate(fs);
+        self.bypass.set_sample_rate(fs);
         true
     }
 
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        self.wow_vibrato.reset();
+        self.flutter_vibrato.reset();
+        self.tape_noise.reset();
+        self.bypass.reset(self.params.bypass.value());
     }
 
     fn process(
@@ -152,14 +231,26 @@ impl Plugin for Vibrato {
         _aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Once fully bypassed, skip processing entirely and pass the input through
+        let bypassed = self.params.bypass.value();
+        if self.bypass.is_bypassed(bypassed) {
+            return ProcessStatus::Normal;
+        }
+
         for mut channel_samples in buffer.iter_samples() {
             // Smoothing is optionally built into the parameters themselves
             let gain = self.params.gain.smoothed.next();
-            let wow = self.params.wow.smoothed.next();
-            let flutter = self.params.flutter.smoothed.next();
-            let width = self.params.width.smoothed.next();
+            let age = self.params.age.smoothed.next();
+            let wow = (self.params.wow.smoothed.next() + age * AGE_MODULATION_OFFSET).min(1.0);
+            let flutter =
+                (self.params.flutter.smoothed.next() + age * AGE_MODULATION_OFFSET).min(1.0);
+            let phase_offset = self.params.stereo_phase.smoothed.next() / 360.0;
+            let tape_mode = self.params.tape_mode.value();
+            let interpolation =
+                interpolation_param_to_interpolation(self.params.interpolation.value());
+            self.wow_vibrato.set_interpolation(interpolation);
+            self.flutter_vibrato.set_interpolation(interpolation);
 
-            let phase_offset = width * 0.5; // only offset right phase by a maximum of 180 degrees
             let sample_l = *channel_samples.get_mut(0).unwrap();
             let sample_r = *channel_samples.get_mut(1).unwrap();
 
@@ -167,26 +258,66 @@ impl Plugin for Vibrato {
 
             // Apply wow
             if wow > PARAMETER_MINIMUM {
-                processed_samples = self.wow_vibrato.process_with_vibrato(
-                    processed_samples,
-                    WOW_MAX_LFO_FREQUENCY,
-                    wow * WOW_MAX_FREQUENCY_RATIO,
-                    phase_offset,
-                );
+                processed_samples = if tape_mode {
+                    let modulation = (
+                        self.wow_random_lfo.value_at(0.0),
+                        self.wow_random_lfo.value_at(phase_offset),
+                    );
+                    self.wow_vibrato.process_with_modulation(
+                        processed_samples,
+                        modulation,
+                        wow * WOW_MAX_FREQUENCY_RATIO,
+                    )
+                } else {
+                    self.wow_vibrato.process_with_vibrato(
+                        processed_samples,
+                        WOW_MAX_LFO_FREQUENCY,
+                        wow * WOW_MAX_FREQUENCY_RATIO,
+                        phase_offset,
+                    )
+                };
             }
 
             // Apply flutter
             if flutter > PARAMETER_MINIMUM {
-                processed_samples = self.flutter_vibrato.process_with_vibrato(
-                    processed_samples,
-                    FLUTTER_MAX_LFO_FREQUENCY,
-                    flutter * FLUTTER_MAX_FREQUENCY_RATIO,
-                    phase_offset,
-                );
+                processed_samples = if tape_mode {
+                    let modulation = (
+                        self.flutter_random_lfo.value_at(0.0),
+                        self.flutter_random_lfo.value_at(phase_offset),
+                    );
+                    self.flutter_vibrato.process_with_modulation(
+                        processed_samples,
+                        modulation,
+                        flutter * FLUTTER_MAX_FREQUENCY_RATIO,
+                    )
+                } else {
+                    self.flutter_vibrato.process_with_vibrato(
+                        processed_samples,
+                        FLUTTER_MAX_LFO_FREQUENCY,
+                        flutter * FLUTTER_MAX_FREQUENCY_RATIO,
+                        phase_offset,
+                    )
+                };
             }
+            self.wow_random_lfo.advance();
+            self.flutter_random_lfo.advance();
+
+            // Hiss fades in with age, and crackle only becomes noticeable on older tape
+            if age > 0.0 {
+                self.tape_noise.set_hiss(age);
+                self.tape_noise.set_crackle(age * age);
+                let noise = self.tape_noise.process();
+                processed_samples = (processed_samples.0 + noise.0, processed_samples.1 + noise.1);
+            }
+
+            let (out_l, out_r) = self.bypass.tick(
+                bypassed,
+                (sample_l, sample_r),
+                (processed_samples.0 * gain, processed_samples.1 * gain),
+            );
 
-            *channel_samples.get_mut(0).unwrap() = processed_samples.0 * gain;
-            *channel_samples.get_mut(1).unwrap() = processed_samples.1 * gain;
+            *channel_samples.get_mut(0).unwrap() = out_l;
+            *channel_samples.get_mut(1).unwrap() = out_r;
         }
 
         ProcessStatus::Normal
@@ -0,0 +1,7 @@
+use nih_plug::prelude::*;
+
+use widener::Widener;
+
+fn main() {
+    nih_export_standalone::<Widener>();
+}